@@ -0,0 +1,152 @@
+use anyhow::{Context, anyhow, bail};
+use reqwest::Client;
+use std::{path::PathBuf, time::Duration};
+
+use crate::profile::Overrides;
+
+pub const PROFILES_FILE: &str = ".exchange-client.toml";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // create the demo users and replay trades.json (the original behaviour)
+    Load,
+    ProfilesList,
+    ProfilesCheck,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    pub profile: Option<String>,
+    pub profiles_path: Option<PathBuf>,
+    pub overrides: Overrides,
+}
+
+pub const USAGE: &str = "usage: client [--profile NAME] [--profiles PATH] [--base-url URL] \
+[--api-key KEY] [--rate ORDERS_PER_SECOND] [load | profiles list | profiles check]";
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Args> {
+    let mut args = args.into_iter();
+    let mut parsed = Args {
+        command: Command::Load,
+        profile: None,
+        profiles_path: None,
+        overrides: Overrides::default(),
+    };
+    let mut words = vec![];
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--profile" => parsed.profile = Some(value()?),
+            "--profiles" => parsed.profiles_path = Some(PathBuf::from(value()?)),
+            "--base-url" => parsed.overrides.base_url = Some(value()?),
+            "--api-key" => parsed.overrides.api_key = Some(value()?),
+            "--rate" => {
+                let rate = value()?;
+                parsed.overrides.orders_per_second = Some(
+                    rate.parse()
+                        .with_context(|| format!("invalid --rate {}", rate))?,
+                );
+            }
+            flag if flag.starts_with("--") => bail!("unknown flag {}\n{}", flag, USAGE),
+            _ => words.push(arg),
+        }
+    }
+
+    parsed.command = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] | ["load"] => Command::Load,
+        ["profiles", "list"] => Command::ProfilesList,
+        ["profiles", "check"] => Command::ProfilesCheck,
+        _ => bail!("unknown command {:?}\n{}", words.join(" "), USAGE),
+    };
+    Ok(parsed)
+}
+
+// Confirms an environment is up by hitting the gateway's health endpoint.
+pub async fn check(client: &Client, base_url: &str) -> anyhow::Result<()> {
+    let response = client
+        .get(format!("{}/health", base_url))
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("health check returned {}", response.status());
+    }
+    Ok(())
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn args(line: &str) -> anyhow::Result<Args> {
+        parse_args(line.split_whitespace().map(String::from))
+    }
+
+    // Answers a single HTTP request with `status` and returns the base url.
+    async fn mock_server(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let read = socket.read(&mut request).await.unwrap();
+            let body = if request[..read].starts_with(b"GET /health ") {
+                r#"{"status":"ok"}"#
+            } else {
+                ""
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(args("").unwrap().command, Command::Load);
+
+        let parsed = args("--profile staging --rate 5 profiles check").unwrap();
+        assert_eq!(parsed.command, Command::ProfilesCheck);
+        assert_eq!(parsed.profile.as_deref(), Some("staging"));
+        assert_eq!(parsed.overrides.orders_per_second, Some(5));
+
+        assert!(args("profiles").is_err());
+        assert!(args("--rate fast").is_err());
+        assert!(args("--profile").is_err());
+        assert!(args("--verbose").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_against_mock_server() {
+        let client = Client::new();
+
+        let healthy = mock_server("200 OK").await;
+        assert!(check(&client, &healthy).await.is_ok());
+
+        let unhealthy = mock_server("503 Service Unavailable").await;
+        let error = check(&client, &unhealthy).await.unwrap_err();
+        assert!(error.to_string().contains("503"));
+
+        // nothing listening
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(check(&client, &closed).await.is_err());
+    }
+}
@@ -1,10 +1,20 @@
-use std::{fs::File, io::BufReader};
+use std::{fs::File, io::BufReader, path::PathBuf};
 
-use reqwest::Client;
+use anyhow::{anyhow, bail};
+use orderbook::{Price, Qty, Side};
+use reqwest::{
+    Client,
+    header::{HeaderMap, HeaderValue},
+};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use tokio::time::{Duration, sleep};
 
+mod cli;
+mod profile;
+
+use cli::{Args, Command};
+use profile::{Overrides, Profile, ProfilesFile, ResolvedProfile};
+
 #[derive(Serialize)]
 struct CreateUserRequest {
     email: String,
@@ -14,16 +24,109 @@ struct CreateUserRequest {
 struct PlaceOrderRequest {
     user: String,
     symbol: String,
-    side: String, // "buy" or "sell"
-    price: Option<i64>,
-    quantity: u32,
+    side: Side,
+    // read as written, e.g. 101.5
+    price: Option<Price>,
+    // whole shares or a decimal string such as "0.5"
+    quantity: Qty,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let client = Client::new();
-    let base_url = "http://localhost:8080"; // adjust to your server
+    let args = cli::parse_args(std::env::args().skip(1))?;
+    let file = load_profiles(&args)?;
+
+    match args.command {
+        Command::Load => {
+            let (name, profile) = select_profile(&file, &args)?;
+            let profile = profile::resolve(&name, &profile, &args.overrides, env_var)?;
+            println!("Using profile {} ({})", profile.name, profile.base_url);
+            load(&profile).await
+        }
+        Command::ProfilesList => {
+            for (name, profile) in &file.profiles {
+                let marker = if file.default.as_ref() == Some(name) {
+                    "*"
+                } else {
+                    " "
+                };
+                let base_url = profile
+                    .base_url
+                    .as_deref()
+                    .unwrap_or(profile::DEFAULT_BASE_URL);
+                println!("{} {} {}", marker, name, base_url);
+            }
+            Ok(())
+        }
+        Command::ProfilesCheck => {
+            let client = Client::new();
+            let mut failed = 0;
+            for (name, profile) in &file.profiles {
+                let result = match profile::resolve(name, profile, &Overrides::default(), env_var) {
+                    Ok(resolved) => cli::check(&client, &resolved.base_url).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => println!("ok     {}", name),
+                    Err(e) => {
+                        failed += 1;
+                        println!("FAILED {}: {:#}", name, e)
+                    }
+                }
+            }
+            if failed > 0 {
+                bail!("{} of {} profiles failed", failed, file.profiles.len());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
 
+// An explicit --profiles path must exist; the default one in $HOME is optional.
+fn load_profiles(args: &Args) -> anyhow::Result<ProfilesFile> {
+    if let Some(path) = &args.profiles_path {
+        return ProfilesFile::load(path);
+    }
+    let Some(home) = std::env::var_os("HOME") else {
+        return Ok(ProfilesFile::default());
+    };
+    let path = PathBuf::from(home).join(cli::PROFILES_FILE);
+    if !path.exists() {
+        return Ok(ProfilesFile::default());
+    }
+    ProfilesFile::load(&path)
+}
+
+// --profile, then the file's default, then a built-in "local" pointing at
+// localhost so the client keeps working without any profiles file.
+fn select_profile(file: &ProfilesFile, args: &Args) -> anyhow::Result<(String, Profile)> {
+    let name = args
+        .profile
+        .clone()
+        .or_else(|| file.default.clone())
+        .unwrap_or_else(|| "local".to_string());
+    match file.profiles.get(&name) {
+        Some(profile) => Ok((name, profile.clone())),
+        None if args.profile.is_none() => Ok((name, Profile::default())),
+        None => Err(anyhow!("no profile named {:?}", name)),
+    }
+}
+
+fn http_client(profile: &ResolvedProfile) -> anyhow::Result<Client> {
+    let mut headers = HeaderMap::new();
+    if let Some(key) = &profile.api_key {
+        headers.insert("x-api-key", HeaderValue::from_str(key)?);
+    }
+    Ok(Client::builder().default_headers(headers).build()?)
+}
+
+async fn load(profile: &ResolvedProfile) -> anyhow::Result<()> {
+    let client = http_client(profile)?;
+    let base_url = &profile.base_url;
     // 1. Create 10 users
     let mut user_ids = vec![];
     for i in 0..10 {
@@ -46,16 +149,10 @@ async fn main() -> anyhow::Result<()> {
     // 2. Load trades from JSON file
     let file = File::open("trades.json")?;
     let reader = BufReader::new(file);
-    let mut trades_json: Vec<Value> = serde_json::from_reader(reader)?;
-    for trade in &mut trades_json {
-        if let Some(price) = trade.get_mut("price") {
-            if let Some(p) = price.as_f64() {
-                *price = Value::from((p * 100.0).round() as u64);
-            }
-        }
+    let mut trades: Vec<PlaceOrderRequest> = serde_json::from_reader(reader)?;
+    if !profile.symbols.is_empty() {
+        trades.retain(|trade| profile.symbols.contains(&trade.symbol));
     }
-
-    let trades: Vec<PlaceOrderRequest> = serde_json::from_value(Value::Array(trades_json))?;
     println!("Loaded {} trades", trades.len());
 
     // 3. Loop through trades and call place_order API
@@ -71,8 +168,11 @@ async fn main() -> anyhow::Result<()> {
             .await?;
         println!("Placed order response: {}", res);
 
-        // Optional: add small delay to simulate real-world traffic
-        sleep(Duration::from_millis(10)).await;
+        // pace orders to the profile's rate to simulate real-world traffic
+        sleep(Duration::from_millis(
+            1000 / profile.orders_per_second as u64,
+        ))
+        .await;
     }
 
     Ok(())
@@ -0,0 +1,186 @@
+use anyhow::{Context, anyhow};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:8080";
+pub const DEFAULT_ORDERS_PER_SECOND: u32 = 100;
+
+// One named environment from the profiles file, e.g.
+//
+//   [profiles.staging]
+//   base_url = "https://staging.example.com"
+//   api_key_env = "STAGING_API_KEY"
+//   symbols = ["AAPL", "MSFT"]
+//   orders_per_second = 20
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+    // name of an env var holding the api key, preferred over `api_key`
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    pub orders_per_second: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProfilesFile {
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl ProfilesFile {
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let file: ProfilesFile = toml::from_str(contents)?;
+        if let Some(default) = &file.default
+            && !file.profiles.contains_key(default)
+        {
+            return Err(anyhow!("default profile {:?} is not defined", default));
+        }
+        Ok(file)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading profiles from {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+}
+
+// Settings a run actually uses, after layering CLI flags over the profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedProfile {
+    pub name: String,
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub symbols: Vec<String>,
+    pub orders_per_second: u32,
+}
+
+// Command-line values that override whatever the profile says.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Overrides {
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+    pub orders_per_second: Option<u32>,
+}
+
+// Precedence, highest first: CLI flag, env var named by `api_key_env` (api key
+// only), profile value, built-in default.
+pub fn resolve(
+    name: &str,
+    profile: &Profile,
+    overrides: &Overrides,
+    env: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<ResolvedProfile> {
+    let api_key = match (&overrides.api_key, &profile.api_key_env) {
+        (Some(key), _) => Some(key.clone()),
+        (None, Some(var)) => Some(
+            env(var)
+                .or_else(|| profile.api_key.clone())
+                .ok_or_else(|| anyhow!("profile {:?} needs env var {}", name, var))?,
+        ),
+        (None, None) => profile.api_key.clone(),
+    };
+
+    let orders_per_second = overrides
+        .orders_per_second
+        .or(profile.orders_per_second)
+        .unwrap_or(DEFAULT_ORDERS_PER_SECOND);
+    if orders_per_second == 0 {
+        return Err(anyhow!("orders_per_second must be positive"));
+    }
+
+    Ok(ResolvedProfile {
+        name: name.to_string(),
+        base_url: overrides
+            .base_url
+            .clone()
+            .or_else(|| profile.base_url.clone())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+            .trim_end_matches('/')
+            .to_string(),
+        api_key,
+        symbols: profile.symbols.clone(),
+        orders_per_second,
+    })
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+        default = "local"
+
+        [profiles.local]
+        base_url = "http://localhost:8080/"
+        api_key = "local-key"
+
+        [profiles.staging]
+        base_url = "https://staging.example.com"
+        api_key_env = "STAGING_API_KEY"
+        symbols = ["AAPL", "MSFT"]
+        orders_per_second = 20
+    "#;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let file = ProfilesFile::parse(PROFILES).unwrap();
+        assert_eq!(file.default.as_deref(), Some("local"));
+        assert_eq!(file.profiles.len(), 2);
+        assert_eq!(file.profiles["staging"].symbols, vec!["AAPL", "MSFT"]);
+        assert_eq!(file.profiles["local"].orders_per_second, None);
+
+        assert!(ProfilesFile::parse("default = \"prod\"").is_err());
+        assert!(ProfilesFile::parse("[profiles.local]\nbase_uri = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_profile_defaults() {
+        let file = ProfilesFile::parse(PROFILES).unwrap();
+        let local = resolve(
+            "local",
+            &file.profiles["local"],
+            &Overrides::default(),
+            no_env,
+        )
+        .unwrap();
+        assert_eq!(local.base_url, "http://localhost:8080");
+        assert_eq!(local.api_key.as_deref(), Some("local-key"));
+        assert_eq!(local.orders_per_second, DEFAULT_ORDERS_PER_SECOND);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let file = ProfilesFile::parse(PROFILES).unwrap();
+        let staging = &file.profiles["staging"];
+        let env = |var: &str| (var == "STAGING_API_KEY").then(|| "from-env".to_string());
+
+        let resolved = resolve("staging", staging, &Overrides::default(), env).unwrap();
+        assert_eq!(resolved.base_url, "https://staging.example.com");
+        assert_eq!(resolved.api_key.as_deref(), Some("from-env"));
+        assert_eq!(resolved.orders_per_second, 20);
+
+        let overrides = Overrides {
+            base_url: Some("http://10.0.0.7:8080".to_string()),
+            api_key: Some("from-flag".to_string()),
+            orders_per_second: Some(5),
+        };
+        let resolved = resolve("staging", staging, &overrides, env).unwrap();
+        assert_eq!(resolved.base_url, "http://10.0.0.7:8080");
+        assert_eq!(resolved.api_key.as_deref(), Some("from-flag"));
+        assert_eq!(resolved.orders_per_second, 5);
+
+        // a profile that names an env var refuses to run without it
+        assert!(resolve("staging", staging, &Overrides::default(), no_env).is_err());
+    }
+}
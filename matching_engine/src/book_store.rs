@@ -0,0 +1,44 @@
+use orderbook::{BOOK_FILE_EXTENSION, OrderBook};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// How often, in seconds, every book is saved to the snapshot directory.
+// Unset or 0 turns saving off, and with it restoring books at startup.
+pub const BOOK_SNAPSHOT_INTERVAL_ENV: &str = "BOOK_SNAPSHOT_INTERVAL_SECS";
+
+pub fn book_snapshot_interval_from_env() -> Option<Duration> {
+    std::env::var(BOOK_SNAPSHOT_INTERVAL_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
+}
+
+fn path(dir: &Path, symbol: &str) -> PathBuf {
+    dir.join(format!("book-{}.{}", symbol, BOOK_FILE_EXTENSION))
+}
+
+// Writes to a side file and renames it into place, so a crash mid-save
+// leaves the previous snapshot whole.
+pub fn save(dir: &Path, book: &OrderBook) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = path(dir, &book.symbol);
+    let partial = path.with_extension("partial");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    book.save(&mut writer)?;
+    writer.into_inner()?.sync_all()?;
+    fs::rename(partial, path)
+}
+
+// None when nothing has been saved for `symbol`.
+pub fn load(dir: &Path, symbol: &str) -> io::Result<Option<OrderBook>> {
+    match File::open(path(dir, symbol)) {
+        Ok(file) => OrderBook::load(BufReader::new(file)).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,290 @@
+use orderbook::{OrderBook, Qty, TradeEvent};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    logging::{MILLIS_PER_DAY, format_day},
+    metrics::LatencyHistogram,
+    versioned::{self, Migration, Versioned},
+};
+
+const CAPACITY_FILE_PREFIX: &str = "capacity";
+// migrations[v] upgrades a version v capacity file to v + 1
+const CAPACITY_MIGRATIONS: &[Migration] = &[
+    // v0 was the bare symbol -> counters map
+    |symbols| serde_json::json!({ "version": 1, "symbols": symbols }),
+];
+
+#[derive(Serialize, Deserialize)]
+struct CapacityFile {
+    symbols: BTreeMap<String, SymbolCounters>,
+}
+
+// One symbol's activity for the day, as persisted between restarts.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SymbolCounters {
+    pub orders: u64,
+    pub trades: u64,
+    pub volume: Qty,
+    pub peak_resting_orders: usize,
+    pub max_levels: usize,
+    pub match_latency: LatencyHistogram,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SymbolCapacity {
+    pub orders: u64,
+    pub trades: u64,
+    pub volume: Qty,
+    pub peak_resting_orders: usize,
+    pub max_levels: usize,
+    // None with no matches, or when past the largest latency bucket
+    pub p99_match_micros: Option<u64>,
+}
+
+impl From<&SymbolCounters> for SymbolCapacity {
+    fn from(counters: &SymbolCounters) -> Self {
+        Self {
+            orders: counters.orders,
+            trades: counters.trades,
+            volume: counters.volume,
+            peak_resting_orders: counters.peak_resting_orders,
+            max_levels: counters.max_levels,
+            p99_match_micros: counters.match_latency.percentile_micros(99.0),
+        }
+    }
+}
+
+// Published on the outbound channel at every stats tick and, with `final`
+// set, once more when the day rolls over.
+#[derive(Serialize, Debug)]
+pub struct CapacityReport {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub date: String,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    pub symbols: BTreeMap<String, SymbolCapacity>,
+}
+
+#[derive(Debug)]
+pub struct CapacityTracker {
+    day: i64,
+    symbols: BTreeMap<String, SymbolCounters>,
+}
+
+impl CapacityTracker {
+    pub fn new(now: i64) -> Self {
+        Self {
+            day: now.div_euclid(MILLIS_PER_DAY),
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    fn path(dir: &Path, day: i64) -> PathBuf {
+        dir.join(format!("{}.{}.json", CAPACITY_FILE_PREFIX, format_day(day)))
+    }
+
+    // Picks up today's counters from a previous run, if there was one. Older
+    // files are upgraded on load and rewritten in the current shape at the
+    // next save.
+    pub fn restore(dir: &Path, now: i64) -> io::Result<Self> {
+        let mut tracker = Self::new(now);
+        match fs::read_to_string(Self::path(dir, tracker.day)) {
+            Ok(contents) => {
+                tracker.symbols = parse_capacity_file(&contents)?;
+                Ok(tracker)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(tracker),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let file = Versioned {
+            version: CAPACITY_MIGRATIONS.len() as u32,
+            data: &CapacityFile {
+                symbols: self.symbols.clone(),
+            },
+        };
+        let contents = serde_json::to_string(&file).map_err(io::Error::other)?;
+        fs::write(Self::path(dir, self.day), contents)
+    }
+
+    pub fn order_received(&mut self, symbol: &str) {
+        self.counters(symbol).orders += 1;
+    }
+
+    pub fn record_match(&mut self, book: &OrderBook, latency: Duration, trades: &[TradeEvent]) {
+        let counters = self.counters(&book.symbol);
+        counters.trades += trades.len() as u64;
+        counters.volume += trades.iter().map(|t| t.quantity).sum::<Qty>();
+        counters.peak_resting_orders = counters.peak_resting_orders.max(book.len());
+        counters.max_levels = counters.max_levels.max(book.level_count());
+        counters.match_latency.record(latency);
+    }
+
+    // Starts a fresh day once `now` has moved past the tracked one, handing
+    // back the final report for the day just closed.
+    pub fn roll(&mut self, now: i64) -> Option<CapacityReport> {
+        let day = now.div_euclid(MILLIS_PER_DAY);
+        if day <= self.day {
+            return None;
+        }
+        let report = self.report(true);
+        *self = Self::new(now);
+        Some(report)
+    }
+
+    pub fn report(&self, is_final: bool) -> CapacityReport {
+        CapacityReport {
+            kind: "capacity_report",
+            date: format_day(self.day),
+            is_final,
+            symbols: self
+                .symbols
+                .iter()
+                .map(|(symbol, counters)| (symbol.clone(), counters.into()))
+                .collect(),
+        }
+    }
+
+    fn counters(&mut self, symbol: &str) -> &mut SymbolCounters {
+        self.symbols.entry(symbol.to_string()).or_default()
+    }
+}
+
+fn parse_capacity_file(contents: &str) -> io::Result<BTreeMap<String, SymbolCounters>> {
+    let document = serde_json::from_str(contents).map_err(io::Error::other)?;
+    let document = versioned::upgrade("capacity", document, CAPACITY_MIGRATIONS)?;
+    let file: CapacityFile = serde_json::from_value(document).map_err(io::Error::other)?;
+    Ok(file.symbols)
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_support::scratch_dir;
+    use orderbook::{Order, Price, Side};
+
+    // 2025-10-15T23:59:00Z
+    const LATE: i64 = 1_760_572_740_000;
+
+    fn trade(shares: u64) -> TradeEvent {
+        TradeEvent {
+            buyer: "a".to_string(),
+            seller: "b".to_string(),
+            symbol: "AAPL".to_string(),
+            quantity: Qty::shares(shares),
+            price: Price::cents(100),
+            trade_id: 1,
+            taker_side: Side::Buy,
+            timestamp: 0,
+            maker_received_at: 0,
+            maker_order_id: 1,
+            maker_remaining: Qty::shares(0),
+            taker_order_id: 0,
+            buyer_tags: Vec::new(),
+            seller_tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_rollover_boundary_and_restore() {
+        let dir = scratch_dir("capacity");
+        let mut book = OrderBook::new("AAPL".to_string());
+        book.add_limit_order(Order::new_limit_order(
+            Qty::shares(5),
+            Some(Price::cents(100)),
+            Side::Buy,
+            "AAPL".to_string(),
+            "a".to_string(),
+        ))
+        .unwrap();
+
+        let mut tracker = CapacityTracker::new(LATE);
+        tracker.order_received("AAPL");
+        tracker.record_match(&book, Duration::from_micros(30), &[trade(4)]);
+        tracker.save(&dir).unwrap();
+
+        // a restart within the day carries on from the saved counters
+        let mut tracker = CapacityTracker::restore(&dir, LATE + 30_000).unwrap();
+        assert!(tracker.roll(LATE + 59_999).is_none());
+        tracker.order_received("AAPL");
+
+        let report = tracker.roll(LATE + 60_000).unwrap();
+        assert!(report.is_final);
+        assert_eq!(report.date, "2025-10-15");
+        assert_eq!(
+            report.symbols["AAPL"],
+            SymbolCapacity {
+                orders: 2,
+                trades: 1,
+                volume: Qty::shares(4),
+                peak_resting_orders: 1,
+                max_levels: 1,
+                p99_match_micros: Some(50),
+            }
+        );
+
+        let today = tracker.report(false);
+        assert_eq!(today.date, "2025-10-16");
+        assert!(today.symbols.is_empty());
+        // nothing saved for the new day yet
+        assert!(
+            CapacityTracker::restore(&dir, LATE + 60_000)
+                .unwrap()
+                .symbols
+                .is_empty()
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_capacity_file_versions() {
+        let counters = r#"{"orders":2,"trades":1,"volume":4,"peak_resting_orders":1,"max_levels":1,"match_latency":{"buckets":[0,1,0,0,0,0,0,0,0],"count":1}}"#;
+        let v0 = format!(r#"{{"AAPL":{}}}"#, counters);
+        let v1 = format!(r#"{{"version":1,"symbols":{{"AAPL":{}}}}}"#, counters);
+
+        let from_v0 = parse_capacity_file(&v0).unwrap();
+        let from_v1 = parse_capacity_file(&v1).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_v0).unwrap(),
+            serde_json::to_value(&from_v1).unwrap()
+        );
+        assert_eq!(from_v0["AAPL"].volume, Qty::shares(4));
+
+        // what save writes is the current version
+        let dir = scratch_dir("capacity-versions");
+        let tracker = CapacityTracker {
+            day: LATE.div_euclid(MILLIS_PER_DAY),
+            symbols: from_v0,
+        };
+        tracker.save(&dir).unwrap();
+        // volume is read from a plain number but written as a decimal string
+        let written = fs::read_to_string(CapacityTracker::path(&dir, tracker.day)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&written).unwrap(),
+            serde_json::from_str::<serde_json::Value>(
+                &v1.replace(r#""volume":4"#, r#""volume":"4""#)
+            )
+            .unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        let error = parse_capacity_file(r#"{"version":2,"symbols":{}}"#).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "capacity file is version 2, this engine reads up to version 1"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+const MAX_MESSAGE_BYTES_ENV: &str = "MAX_MESSAGE_BYTES";
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+// Orders and admin messages are flat objects.
+const MAX_NESTING_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadRejection {
+    Oversized,
+    TooDeep,
+}
+
+impl PayloadRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadRejection::Oversized => "oversized",
+            PayloadRejection::TooDeep => "too_deep",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PayloadLimits {
+    pub max_bytes: usize,
+    pub max_depth: usize,
+}
+
+impl PayloadLimits {
+    pub fn from_env() -> Self {
+        let max_bytes = std::env::var(MAX_MESSAGE_BYTES_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_MESSAGE_BYTES);
+        Self {
+            max_bytes,
+            max_depth: MAX_NESTING_DEPTH,
+        }
+    }
+
+    // Checked before serde sees the payload, so nothing is allocated for a
+    // hostile message and a deep one can't recurse the parser.
+    pub fn check(&self, payload: &str) -> Result<(), PayloadRejection> {
+        if payload.len() > self.max_bytes {
+            return Err(PayloadRejection::Oversized);
+        }
+        if exceeds_depth(payload, self.max_depth) {
+            return Err(PayloadRejection::TooDeep);
+        }
+        Ok(())
+    }
+}
+
+// Byte scan for array/object nesting, skipping over string contents.
+fn exceeds_depth(payload: &str, max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for byte in payload.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
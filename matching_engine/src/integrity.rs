@@ -0,0 +1,87 @@
+use orderbook::{Order, OrderBook};
+use serde::Serialize;
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::versioned::Versioned;
+
+const SNAPSHOT_DIR_ENV: &str = "ENGINE_SNAPSHOT_DIR";
+const DEFAULT_SNAPSHOT_DIR: &str = "snapshots";
+// Nothing reads snapshots back yet; the version is there for when something does.
+// Version 2 writes prices as decimal strings, and book keys with them.
+const SNAPSHOT_VERSION: u32 = 2;
+
+pub fn snapshot_dir_from_env() -> PathBuf {
+    PathBuf::from(
+        std::env::var(SNAPSHOT_DIR_ENV).unwrap_or_else(|_| DEFAULT_SNAPSHOT_DIR.to_string()),
+    )
+}
+
+// Published when a symbol stops matching because its book failed the
+// post-operation check.
+#[derive(Debug, Serialize)]
+pub struct IntegrityHalt {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub seq: u64,
+    pub reason: String,
+    pub last_order: Order,
+    pub snapshot_hash: String,
+    pub snapshot_path: Option<String>,
+}
+
+impl IntegrityHalt {
+    pub fn new(
+        book: &OrderBook,
+        seq: u64,
+        reason: String,
+        last_order: Order,
+        snapshot_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            kind: "integrity_halt",
+            symbol: book.symbol.clone(),
+            seq,
+            reason,
+            last_order,
+            snapshot_hash: format!("{:016x}", snapshot_hash(book)),
+            snapshot_path: snapshot_path.map(|p| p.display().to_string()),
+        }
+    }
+}
+
+// Fingerprint of every resting order, in priority order, so an offline copy
+// can be matched to the halt that produced it.
+pub fn snapshot_hash(book: &OrderBook) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (side, map) in [("bid", book.bids()), ("ask", book.asks())] {
+        side.hash(&mut hasher);
+        for (price, queue) in map {
+            price.hash(&mut hasher);
+            for order in queue {
+                order.user.hash(&mut hasher);
+                order.quantity.hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+// Dumps the whole book as JSON to `<dir>/halt-<symbol>-<seq>.json`.
+pub fn write_emergency_snapshot(dir: &Path, book: &OrderBook, seq: u64) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("halt-{}-{}.json", book.symbol, seq));
+    let snapshot = Versioned {
+        version: SNAPSHOT_VERSION,
+        data: book,
+    };
+    let json = serde_json::to_vec_pretty(&snapshot).map_err(io::Error::other)?;
+    fs::write(&path, json)?;
+    Ok(path)
+}
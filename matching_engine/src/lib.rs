@@ -0,0 +1,3188 @@
+use orderbook::{
+    AddOrderError, BookDelta, CancelError, EarlyCancel, EngineEvent, MatchResult, Order, OrderAck,
+    OrderBook, OrderExpired, OrderState, OrderView, PlacedOrder, Price, Qty, Side, SymbolRules,
+    TimeInForce, TradeEvent, TradingPhase, ValidationError, envelope,
+};
+use redis::{Client, Commands};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+mod book_store;
+mod capacity;
+mod guard;
+mod integrity;
+pub mod logging;
+mod metrics;
+mod risk;
+mod versioned;
+
+use capacity::{CapacityReport, CapacityTracker};
+use guard::PayloadLimits;
+use integrity::IntegrityHalt;
+use metrics::{MessageMetrics, MessageType};
+use risk::{PositionLimit, PositionLimits};
+
+pub const ORDER_INBOUND_CHANNEL: &str = "order_inbound";
+pub const ORDER_OUTBOUND_CHANNEL: &str = "order_outbound";
+pub const ENGINE_ADMIN_CHANNEL: &str = "engine_admin";
+pub const MARKET_DATA_CHANNEL: &str = "market_data";
+// How many levels a side the checksum on each BookUpdate covers.
+pub const BOOK_UPDATE_DEPTH: usize = 10;
+// Resting orders per chunk of a streamed book snapshot; keeps each chunk
+// well inside the gateway's default 64 KiB message limit.
+pub const SNAPSHOT_CHUNK_ORDERS: usize = 100;
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+// how often resting good-till-date orders are checked for expiry when idle
+pub const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// largest quantity any one order may carry, for symbols that don't set their
+// own; unlimited when unset
+pub const MAX_ORDER_QUANTITY_ENV: &str = "MAX_ORDER_QUANTITY";
+
+pub fn max_order_quantity_from_env() -> Option<Qty> {
+    std::env::var(MAX_ORDER_QUANTITY_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
+
+// Published when a book's circuit breaker stops it; it takes nothing new
+// until an admin resumes it.
+#[derive(Debug, Serialize)]
+pub struct SymbolHalted {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub seq: u64,
+    // the trade that tripped the breaker
+    pub price: Price,
+}
+
+// Same shape as a rejected EngineEvent, for orders that never parsed.
+#[derive(Debug, Serialize)]
+pub struct MalformedOrder {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub reason: &'static str,
+    pub order: serde_json::Value,
+}
+
+// The terms an amended order now has; `expires_at` null is good till
+// cancelled.
+#[derive(Debug, Serialize)]
+pub struct OrderAmended {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub order_id: u64,
+    pub price: Price,
+    pub quantity: Qty,
+    pub time_in_force: TimeInForce,
+    pub expires_at: Option<i64>,
+}
+
+// Answers an OpenOrders request: everything the user has resting, across
+// every symbol.
+#[derive(Debug, Serialize)]
+pub struct OpenOrders {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub user: String,
+    pub orders: Vec<OrderView>,
+}
+
+// The level changes one message made to a book, for the market data
+// channel. `seq` counts up from 1 per symbol with no gaps, so a consumer
+// that sees one skipped knows its copy of the book is stale. `checksum` is
+// the book's checksum over BOOK_UPDATE_DEPTH levels once the deltas are
+// applied, for a consumer to check its copy against.
+#[derive(Debug, Serialize)]
+pub struct BookUpdate {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub seq: u64,
+    pub deltas: Vec<BookDelta>,
+    pub checksum: u32,
+}
+
+// A cancel or amend the engine could not apply.
+#[derive(Debug, Serialize)]
+pub struct RequestRejected {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub order_id: u64,
+    pub reason: &'static str,
+}
+
+impl RequestRejected {
+    fn new(kind: &'static str, symbol: String, order_id: u64, reason: &'static str) -> Self {
+        Self {
+            kind,
+            symbol,
+            order_id,
+            reason,
+        }
+    }
+}
+
+// What happens to orders goes out as an EngineEvent, tagged by its own
+// "type"; the engine's reports about itself and its books carry a "type"
+// field of their own.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum OutboundMessage {
+    Event(EngineEvent),
+    Malformed(MalformedOrder),
+    IntegrityHalt(IntegrityHalt),
+    SymbolHalted(SymbolHalted),
+    CapacityReport(CapacityReport),
+    CancelRejected(RequestRejected),
+    Amended(OrderAmended),
+    AmendRejected(RequestRejected),
+    OpenOrders(OpenOrders),
+    SnapshotChunk(SnapshotChunk),
+    SnapshotEnd(SnapshotEnd),
+}
+
+impl OutboundMessage {
+    // The "type" the message carries, for its envelope.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OutboundMessage::Event(event) => event.kind(),
+            OutboundMessage::Malformed(m) => m.kind,
+            OutboundMessage::IntegrityHalt(halt) => halt.kind,
+            OutboundMessage::SymbolHalted(halted) => halted.kind,
+            OutboundMessage::CapacityReport(report) => report.kind,
+            OutboundMessage::CancelRejected(rejected)
+            | OutboundMessage::AmendRejected(rejected) => rejected.kind,
+            OutboundMessage::Amended(amended) => amended.kind,
+            OutboundMessage::OpenOrders(open) => open.kind,
+            OutboundMessage::SnapshotChunk(chunk) => chunk.kind,
+            OutboundMessage::SnapshotEnd(end) => end.kind,
+        }
+    }
+}
+
+impl From<EngineEvent> for OutboundMessage {
+    fn from(event: EngineEvent) -> Self {
+        OutboundMessage::Event(event)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelOrder {
+    pub symbol: String,
+    pub order_id: u64,
+}
+
+// New terms for a resting order; whatever is left out it keeps.
+#[derive(Debug, Deserialize)]
+pub struct AmendOrder {
+    pub symbol: String,
+    pub order_id: u64,
+    #[serde(default)]
+    pub price: Option<Price>,
+    // the remaining quantity; 0 cancels
+    #[serde(default)]
+    pub quantity: Option<Qty>,
+    // a resting order is good till cancelled, and can't be made IOC or FOK
+    #[serde(default)]
+    pub time_in_force: Option<TimeInForce>,
+    // unix millis, which must be in the future; null takes the expiry away
+    #[serde(default, deserialize_with = "orderbook::present")]
+    pub expires_at: Option<Option<i64>>,
+}
+
+// Takes `quantity` off what a resting order has left, keeping its place;
+// taking all of it, or more, cancels the order.
+#[derive(Debug, Deserialize)]
+pub struct ReduceOrder {
+    pub symbol: String,
+    pub order_id: u64,
+    pub quantity: Qty,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenOrdersQuery {
+    pub user: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotRequest {
+    pub symbol: String,
+    // the requester's own id, carried on every message of the stream
+    pub request_id: u64,
+}
+
+// One run of a streamed snapshot's resting orders, in the order
+// OrderBook::snapshot lists them; `index` counts from 0.
+#[derive(Debug, Serialize)]
+pub struct SnapshotChunk {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub request_id: u64,
+    pub index: usize,
+    pub orders: Vec<PlacedOrder>,
+}
+
+// Closes a streamed snapshot: how many chunks came before it and
+// orderbook::orders_checksum over their orders in order. The book is as of
+// the BookUpdate numbered `book_update_seq`, so a consumer can apply the
+// updates after it. `found` is false, with no chunks, for a symbol the
+// engine doesn't trade.
+#[derive(Debug, Serialize)]
+pub struct SnapshotEnd {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub symbol: String,
+    pub request_id: u64,
+    pub found: bool,
+    pub chunks: usize,
+    pub checksum: u32,
+    pub book_update_seq: u64,
+    pub last_order_id: u64,
+}
+
+// Pulls every order the user has in every symbol.
+#[derive(Debug, Deserialize)]
+pub struct MassCancel {
+    pub user: String,
+}
+
+// Requests other than new orders on the inbound channel. New orders carry no
+// "type" tag, so anything that doesn't parse as one of these is tried as an
+// order.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InboundMessage {
+    Cancel(CancelOrder),
+    Amend(AmendOrder),
+    Reduce(ReduceOrder),
+    OpenOrders(OpenOrdersQuery),
+    MassCancel(MassCancel),
+    BookSnapshot(SnapshotRequest),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResumeSymbol {
+    pub symbol: String,
+    // resuming a halted book is never implicit
+    #[serde(rename = "override", default)]
+    pub override_halt: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminSymbol {
+    pub symbol: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminMessage {
+    PositionLimit(PositionLimit),
+    ResumeSymbol(ResumeSymbol),
+    // orders collect without matching until the uncross
+    StartAuction(AdminSymbol),
+    Uncross(AdminSymbol),
+    // cancels everything in the book and halts it until resumed
+    ClearSymbol(AdminSymbol),
+}
+
+#[derive(Debug, Default)]
+struct EngineStats {
+    orders: u64,
+    trades: u64,
+    rejections: u64,
+    volume: Qty,
+}
+
+// A cancel that came before its order had rested the symbol's minimum time,
+// applied by the sweep once it has.
+#[derive(Debug)]
+struct DeferredCancel {
+    symbol: String,
+    order_id: u64,
+    due_at: i64,
+}
+
+// Each symbol with the rules its orders are checked against.
+pub fn default_symbols() -> Vec<(String, SymbolRules)> {
+    [
+        "AAPL", "MSFT", "TSLA", "GOOGL", "META", "INTC", "JPM", "AMZN",
+    ]
+    .into_iter()
+    .map(|symbol| (String::from(symbol), SymbolRules::default()))
+    .collect()
+}
+
+pub struct MatchingEngine {
+    engine_map: HashMap<String, OrderBook>,
+    redis_client: Client,
+    position_limits: PositionLimits,
+    // symbols stopped after failing the post-operation book check
+    halted: HashSet<String>,
+    snapshot_dir: PathBuf,
+    // global sequence number, bumped for every inbound message
+    sequence: u64,
+    // the last BookUpdate seq sent for each symbol
+    market_data_sequence: HashMap<String, u64>,
+    stats: EngineStats,
+    metrics: MessageMetrics,
+    // p99 latency above this gets an alert line in the stats log
+    latency_alert: Duration,
+    payload_limits: PayloadLimits,
+    // per-symbol daily counters for capacity planning, saved alongside the
+    // emergency snapshots
+    capacity: CapacityTracker,
+    // every book is saved this often, and restored at startup; off when unset
+    book_snapshot_interval: Option<Duration>,
+    deferred_cancels: Vec<DeferredCancel>,
+    // how many of each user's cancels were held back, for surveillance
+    deferred_cancels_by_user: HashMap<String, u64>,
+    clock: fn() -> i64,
+}
+
+impl MatchingEngine {
+    pub fn new(symbols: Vec<(String, SymbolRules)>) -> Self {
+        let max_quantity = max_order_quantity_from_env();
+        let mut engine_map = HashMap::new();
+        for (symbol, mut rules) in symbols.into_iter() {
+            rules.max_quantity = rules.max_quantity.or(max_quantity);
+            engine_map.insert(symbol.clone(), OrderBook::with_rules(symbol, rules));
+        }
+        let redis_client = redis::Client::open("redis://127.0.0.1/").unwrap();
+        Self {
+            engine_map,
+            redis_client,
+            position_limits: PositionLimits::new(),
+            halted: HashSet::new(),
+            snapshot_dir: integrity::snapshot_dir_from_env(),
+            sequence: 0,
+            market_data_sequence: HashMap::new(),
+            stats: EngineStats::default(),
+            metrics: MessageMetrics::default(),
+            latency_alert: metrics::latency_alert_from_env(),
+            payload_limits: PayloadLimits::from_env(),
+            capacity: CapacityTracker::new(logging::now_millis()),
+            book_snapshot_interval: book_store::book_snapshot_interval_from_env(),
+            deferred_cancels: Vec::new(),
+            deferred_cancels_by_user: HashMap::new(),
+            clock: logging::now_millis,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let mut conn = self.redis_client.get_connection().unwrap();
+        let mut pub_sub = conn.as_pubsub();
+
+        pub_sub
+            .subscribe(&[ORDER_INBOUND_CHANNEL, ENGINE_ADMIN_CHANNEL])
+            .unwrap();
+        // wake up periodically even when idle so stats keep flowing and
+        // expired orders leave the book on time
+        pub_sub
+            .set_read_timeout(Some(EXPIRY_SWEEP_INTERVAL))
+            .unwrap();
+        info!("Running matching engine...");
+
+        match CapacityTracker::restore(&self.snapshot_dir, (self.clock)()) {
+            Ok(capacity) => self.capacity = capacity,
+            Err(e) => {
+                warn!(event = "capacity_restore_failed", error = %e, "Starting capacity counters afresh")
+            }
+        }
+        if self.book_snapshot_interval.is_some() {
+            self.restore_books();
+        }
+
+        let mut last_stats = Instant::now();
+        let mut last_book_snapshot = Instant::now();
+        loop {
+            if last_stats.elapsed() >= STATS_INTERVAL {
+                self.log_stats();
+                let reports = self.checkpoint_capacity();
+                self.publish(reports);
+                last_stats = Instant::now();
+            }
+            if let Some(interval) = self.book_snapshot_interval
+                && last_book_snapshot.elapsed() >= interval
+            {
+                self.save_books();
+                last_book_snapshot = Instant::now();
+            }
+            let expired = self.expire_orders();
+            self.publish(expired);
+            let cancelled = self.apply_deferred_cancels();
+            self.publish(cancelled);
+            let updates = self.book_updates();
+            self.publish_book_updates(updates);
+
+            let msg = match pub_sub.get_message() {
+                Ok(msg) => msg,
+                Err(e) if e.is_timeout() => continue,
+                Err(e) => panic!("lost the Redis pubsub connection: {}", e),
+            };
+            let payload: String = msg.get_payload().unwrap();
+
+            let messages = self.process_message(msg.get_channel_name(), &payload);
+            self.publish(messages);
+            let updates = self.book_updates();
+            self.publish_book_updates(updates);
+        }
+    }
+
+    fn publish(&mut self, messages: Vec<OutboundMessage>) {
+        for message in messages {
+            let serialzied = self.seal(&message);
+            self.redis_client
+                .publish(ORDER_OUTBOUND_CHANNEL, serialzied)
+                .unwrap()
+        }
+    }
+
+    fn publish_book_updates(&mut self, updates: Vec<BookUpdate>) {
+        for update in updates {
+            let serialized = envelope::seal(update.kind, (self.clock)(), &update);
+            self.redis_client
+                .publish(MARKET_DATA_CHANNEL, serialized)
+                .unwrap()
+        }
+    }
+
+    // The message as it goes out on the outbound channel, in its envelope.
+    pub fn seal(&self, message: &OutboundMessage) -> String {
+        envelope::seal(message.kind(), (self.clock)(), message)
+    }
+
+    // Takes the level changes every book has gathered since the last call,
+    // one update per book that changed, in symbol order. Called after each
+    // message, so an update holds what that message did.
+    pub fn book_updates(&mut self) -> Vec<BookUpdate> {
+        let mut updates: Vec<BookUpdate> = self
+            .engine_map
+            .iter_mut()
+            .filter_map(|(symbol, book)| {
+                let deltas = book.take_deltas();
+                (!deltas.is_empty()).then(|| BookUpdate {
+                    kind: "book_delta",
+                    symbol: symbol.clone(),
+                    seq: 0,
+                    deltas,
+                    checksum: book.checksum(BOOK_UPDATE_DEPTH),
+                })
+            })
+            .collect();
+        updates.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        for update in &mut updates {
+            let seq = self
+                .market_data_sequence
+                .entry(update.symbol.clone())
+                .or_default();
+            *seq += 1;
+            update.seq = *seq;
+        }
+        updates
+    }
+
+    // Persists today's capacity counters and reports them, closing out the
+    // previous day first if midnight has passed.
+    pub fn checkpoint_capacity(&mut self) -> Vec<OutboundMessage> {
+        let mut messages = self.roll_capacity();
+        if let Err(e) = self.capacity.save(&self.snapshot_dir) {
+            warn!(event = "capacity_save_failed", error = %e, "Failed to save capacity counters");
+        }
+        messages.push(OutboundMessage::CapacityReport(self.capacity.report(false)));
+        messages
+    }
+
+    // Saves every book to the snapshot directory, returning how many made
+    // it; one that fails is logged and left for the next round.
+    pub fn save_books(&self) -> usize {
+        let mut saved = 0;
+        for book in self.engine_map.values() {
+            match book_store::save(&self.snapshot_dir, book) {
+                Ok(()) => saved += 1,
+                Err(e) => {
+                    warn!(event = "book_save_failed", symbol = %book.symbol, error = %e, "Failed to save book")
+                }
+            }
+        }
+        saved
+    }
+
+    // Swaps each configured book for its saved copy, if there is one. The
+    // configured rules win over the saved ones, so a changed tick size or
+    // quantity cap takes effect across a restart.
+    pub fn restore_books(&mut self) -> usize {
+        let mut restored = 0;
+        for (symbol, book) in self.engine_map.iter_mut() {
+            match book_store::load(&self.snapshot_dir, symbol) {
+                Ok(Some(saved)) => {
+                    let mut snapshot = saved.snapshot();
+                    snapshot.rules = *book.rules();
+                    *book = OrderBook::from_snapshot(snapshot);
+                    info!(
+                        event = "book_restored",
+                        symbol = %symbol,
+                        resting = book.len(),
+                        "Restored book"
+                    );
+                    restored += 1;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(event = "book_restore_failed", symbol = %symbol, error = %e, "Starting book empty")
+                }
+            }
+        }
+        restored
+    }
+
+    fn roll_capacity(&mut self) -> Vec<OutboundMessage> {
+        let Some(report) = self.capacity.roll((self.clock)()) else {
+            return vec![];
+        };
+        info!(
+            event = "capacity_rollover",
+            seq = self.sequence,
+            date = %report.date,
+            symbols = report.symbols.len(),
+            "Closed capacity counters for the day"
+        );
+        vec![OutboundMessage::CapacityReport(report)]
+    }
+
+    // Single entry point for everything read off the bus, timed per type.
+    // `raw` is the message in its envelope.
+    pub fn process_message(&mut self, channel: &str, raw: &str) -> Vec<OutboundMessage> {
+        let started = Instant::now();
+        // counters for a new day start before its first message is counted
+        let mut reports = self.roll_capacity();
+
+        let (kind, rejected, messages) =
+            match self.payload_limits.check(raw).map(|()| envelope::open(raw)) {
+                Err(rejection) => {
+                    self.sequence += 1;
+                    warn!(
+                        event = "payload_rejected",
+                        seq = self.sequence,
+                        reason = rejection.as_str(),
+                        bytes = raw.len(),
+                        channel,
+                        "Dropped payload before parsing"
+                    );
+                    (MessageType::Oversized, true, vec![])
+                }
+                Ok(Err(e)) => {
+                    self.sequence += 1;
+                    warn!(
+                        event = "envelope_rejected",
+                        seq = self.sequence,
+                        reason = %e,
+                        raw = %raw,
+                        channel,
+                        "Dropped message outside a supported envelope"
+                    );
+                    (MessageType::Invalid, true, vec![])
+                }
+                Ok(Ok(envelope)) => self.process_payload(channel, envelope.payload.get()),
+            };
+
+        self.metrics.record(kind, started.elapsed(), rejected);
+        reports.extend(messages);
+        reports
+    }
+
+    // Reads what came inside an envelope as the channel's messages and
+    // handles it: the message type, whether it was refused, and what it
+    // published.
+    fn process_payload(
+        &mut self,
+        channel: &str,
+        payload: &str,
+    ) -> (MessageType, bool, Vec<OutboundMessage>) {
+        if channel == ENGINE_ADMIN_CHANNEL {
+            match self.process_admin(payload) {
+                Some(messages) => (MessageType::Admin, false, messages),
+                None => (MessageType::Admin, true, vec![]),
+            }
+        } else if let Ok(request) = serde_json::from_str::<InboundMessage>(payload) {
+            let (kind, messages) = match request {
+                InboundMessage::Cancel(cancel) => {
+                    (MessageType::Cancel, self.process_cancel(cancel))
+                }
+                InboundMessage::Amend(amend) => (MessageType::Amend, self.process_amend(amend)),
+                InboundMessage::Reduce(reduce) => (MessageType::Amend, self.process_reduce(reduce)),
+                InboundMessage::MassCancel(cancel) => {
+                    (MessageType::Cancel, self.process_mass_cancel(cancel))
+                }
+                InboundMessage::OpenOrders(query) => {
+                    (MessageType::Query, vec![self.process_open_orders(query)])
+                }
+                InboundMessage::BookSnapshot(request) => {
+                    (MessageType::Query, self.process_snapshot(request))
+                }
+            };
+            let rejected = messages.iter().any(|m| {
+                matches!(
+                    m,
+                    OutboundMessage::CancelRejected(_) | OutboundMessage::AmendRejected(_)
+                )
+            });
+            (kind, rejected, messages)
+        } else {
+            match serde_json::from_str::<Order>(payload) {
+                Ok(order) => {
+                    let messages = self.process_order(order);
+                    let rejected = messages
+                        .iter()
+                        .any(|m| matches!(m, OutboundMessage::Event(EngineEvent::Rejected(_))));
+                    (MessageType::NewOrder, rejected, messages)
+                }
+                Err(e) => {
+                    self.sequence += 1;
+                    warn!(
+                        event = "parse_error",
+                        seq = self.sequence,
+                        error = %e,
+                        raw = %payload,
+                        "Failed to parse order"
+                    );
+                    (MessageType::Invalid, true, unknown_side(payload))
+                }
+            }
+        }
+    }
+
+    pub fn process_order(&mut self, mut order: Order) -> Vec<OutboundMessage> {
+        self.sequence += 1;
+        let seq = self.sequence;
+        self.stats.orders += 1;
+        self.capacity.order_received(&order.symbol);
+
+        if self.halted.contains(&order.symbol) {
+            return self.reject(seq, "symbol_halted", order);
+        }
+        if order.expires_at.is_some_and(|at| at <= (self.clock)()) {
+            return self.reject(seq, "already_expired", order);
+        }
+        // the order on its own first, under the rules of its symbol if there
+        // is one, then against the book
+        let rules = self
+            .engine_map
+            .get(&order.symbol)
+            .map_or_else(SymbolRules::default, |book| *book.rules());
+        if let Err(e) = order.validate(&rules) {
+            return self.reject_invalid(seq, e, order);
+        }
+        let Some(book) = self.engine_map.get(&order.symbol) else {
+            return self.reject(seq, "unknown_symbol", order);
+        };
+        if let Err(e) = book.validate(&order) {
+            return self.reject(seq, add_reason(e), order);
+        }
+        // anything that expired since the last sweep must not trade with this
+        let mut expired = self.expire_book(&order.symbol, seq);
+
+        // trim the order to whatever keeps the user inside their position cap;
+        // a FOK order can't be trimmed, so it has to fit whole. A notional
+        // order is held to the cap by the book as it buys.
+        let wanted = match order.notional {
+            Some(_) => Qty::MAX,
+            None => order.quantity,
+        };
+        let allowed =
+            self.position_limits
+                .allowed_quantity(&order.user, &order.symbol, &order.side, wanted);
+        let allowed = allowed.round_down_to(self.lot_size(&order.symbol));
+        // nor can it be trimmed below its minimum quantity
+        if allowed.is_zero()
+            || (order.time_in_force == TimeInForce::Fok && allowed < wanted)
+            || order.min_quantity.is_some_and(|min| allowed < min)
+        {
+            expired.extend(self.reject(seq, "position_limit", order));
+            return expired;
+        }
+        let max_quantity = (allowed < wanted).then_some(allowed);
+        if let Some(allowed) = max_quantity
+            && order.notional.is_none()
+        {
+            order.quantity = allowed;
+        }
+
+        let symbol = order.symbol.clone();
+        let mut last_order = order.clone();
+        let engine = self.engine_map.get_mut(&symbol).unwrap();
+        let matching = Instant::now();
+        let added = match order.price {
+            _ if order.peg.is_some() => engine.add_pegged_order(order),
+            _ if order.stop_price.is_some() => {
+                engine
+                    .add_stop_order(order)
+                    .map(|(order_id, events)| MatchResult {
+                        events,
+                        order_id: Some(order_id),
+                        ..MatchResult::default()
+                    })
+            }
+            Some(_) => engine.add_limit_order(order),
+            None if order.market_to_limit => engine.add_market_to_limit_order(order),
+            None if order.notional.is_some() => engine.add_notional_order(order, max_quantity),
+            None => engine.add_market_order(order),
+        };
+        let result = match added {
+            Ok(result) => result,
+            Err(e) => {
+                expired.extend(self.reject(seq, add_reason(e), last_order));
+                return expired;
+            }
+        };
+        // market-to-limit and pegged orders are reported at the price the book
+        // gave them, and every order with the id it gave it
+        if result.limit_price.is_some() {
+            last_order.price = result.limit_price;
+        }
+        last_order.order_id = result.order_id.unwrap_or_default();
+        info!(
+            event = "order_accepted",
+            seq,
+            order_id = last_order.order_id,
+            user = %last_order.user,
+            symbol = %last_order.symbol,
+            side = ?last_order.side,
+            quantity = %last_order.quantity,
+            price = ?last_order.price,
+            "Order accepted"
+        );
+        expired.push(
+            EngineEvent::Accepted(OrderAck {
+                order: last_order.clone(),
+            })
+            .into(),
+        );
+
+        // a market or notional order is still without a price here
+        let dropped = match last_order.price {
+            Some(_)
+                if last_order.time_in_force == TimeInForce::Ioc
+                    && !result.remaining_quantity.is_zero() =>
+            {
+                let order = Order {
+                    quantity: result.remaining_quantity,
+                    state: OrderState::Close,
+                    ..last_order.clone()
+                };
+                Some(("ioc", order))
+            }
+            // whatever budget a notional order didn't spend is handed back
+            // the same way
+            None if result
+                .unspent_notional
+                .is_some_and(|left| left.is_positive()) =>
+            {
+                let order = Order {
+                    quantity: Qty::ZERO,
+                    notional: result.unspent_notional,
+                    state: OrderState::Close,
+                    ..last_order.clone()
+                };
+                Some(("notional_unspent", order))
+            }
+            // as is whatever a market order couldn't take inside the collar,
+            // or with nothing left to take
+            None if !result.remaining_quantity.is_zero() => {
+                let order = Order {
+                    quantity: result.remaining_quantity,
+                    state: OrderState::Close,
+                    ..last_order.clone()
+                };
+                let reason = match result.collared {
+                    true => "collar",
+                    false => "no_liquidity",
+                };
+                Some((reason, order))
+            }
+            _ => None,
+        };
+        let mut messages = self.finish_match(&symbol, seq, matching, result.events, last_order);
+        if let Some((reason, order)) = dropped {
+            info!(
+                event = "order_cancelled",
+                seq,
+                order_id = order.order_id,
+                reason,
+                user = %order.user,
+                symbol = %order.symbol,
+                quantity = %order.quantity,
+                notional = ?order.notional,
+                "Order cancelled"
+            );
+            messages.push(EngineEvent::cancelled(reason, order).into());
+        }
+        expired.extend(messages);
+        expired
+    }
+
+    // Trimming to a position cap rounds down to this, so trimmed orders stay
+    // whole lots.
+    fn lot_size(&self, symbol: &str) -> Qty {
+        self.engine_map
+            .get(symbol)
+            .map_or(SymbolRules::default().lot_size, |book| {
+                book.rules().lot_size
+            })
+    }
+
+    // Sweeps expired orders out of every book that is still matching.
+    pub fn expire_orders(&mut self) -> Vec<OutboundMessage> {
+        let symbols: Vec<String> = self
+            .engine_map
+            .keys()
+            .filter(|symbol| !self.halted.contains(*symbol))
+            .cloned()
+            .collect();
+        symbols
+            .iter()
+            .flat_map(|symbol| self.expire_book(symbol, self.sequence))
+            .collect()
+    }
+
+    fn expire_book(&mut self, symbol: &str, seq: u64) -> Vec<OutboundMessage> {
+        let now = (self.clock)();
+        let Some(book) = self.engine_map.get_mut(symbol) else {
+            return vec![];
+        };
+        book.expire_orders(now)
+            .into_iter()
+            .map(|order| {
+                info!(
+                    event = "order_expired",
+                    seq,
+                    order_id = order.order_id,
+                    user = %order.user,
+                    symbol = %order.symbol,
+                    quantity = %order.quantity,
+                    "Order expired"
+                );
+                EngineEvent::Expired(OrderExpired { order }).into()
+            })
+            .collect()
+    }
+
+    fn reject(&mut self, seq: u64, reason: &'static str, order: Order) -> Vec<OutboundMessage> {
+        self.stats.rejections += 1;
+        warn!(
+            event = "order_rejected",
+            seq,
+            reason,
+            user = %order.user,
+            symbol = %order.symbol,
+            quantity = %order.quantity,
+            "Order rejected"
+        );
+        vec![EngineEvent::rejected(reason, order).into()]
+    }
+
+    // A rejection that carries what Order::validate found wrong.
+    fn reject_invalid(
+        &mut self,
+        seq: u64,
+        error: ValidationError,
+        order: Order,
+    ) -> Vec<OutboundMessage> {
+        self.stats.rejections += 1;
+        warn!(
+            event = "order_rejected",
+            seq,
+            reason = error.reason(),
+            error = %error,
+            user = %order.user,
+            symbol = %order.symbol,
+            quantity = %order.quantity,
+            "Order rejected"
+        );
+        vec![EngineEvent::invalid(error, order).into()]
+    }
+
+    // Books the trades from one matching operation and checks the book it
+    // left behind.
+    fn finish_match(
+        &mut self,
+        symbol: &str,
+        seq: u64,
+        matching: Instant,
+        events: Vec<TradeEvent>,
+        last_order: Order,
+    ) -> Vec<OutboundMessage> {
+        let integrity = self.engine_map[symbol].check_top_of_book();
+        let mut messages = self.book_trades(symbol, seq, matching, events);
+        if let Err(reason) = integrity {
+            messages.push(OutboundMessage::IntegrityHalt(
+                self.halt(symbol, seq, reason, last_order),
+            ));
+        }
+        messages
+    }
+
+    fn book_trades(
+        &mut self,
+        symbol: &str,
+        seq: u64,
+        matching: Instant,
+        events: Vec<TradeEvent>,
+    ) -> Vec<OutboundMessage> {
+        let engine = &self.engine_map[symbol];
+        self.capacity
+            .record_match(engine, matching.elapsed(), &events);
+        // a halted book takes no orders, so if these trades happened it was
+        // one of them that tripped the breaker
+        let tripped = match events.last() {
+            Some(last) if engine.is_halted() => Some(last.price),
+            _ => None,
+        };
+
+        let mut messages: Vec<OutboundMessage> = events
+            .into_iter()
+            .map(|event| {
+                self.stats.trades += 1;
+                self.stats.volume += event.quantity;
+                info!(
+                    event = "trade",
+                    seq,
+                    buyer = %event.buyer,
+                    seller = %event.seller,
+                    symbol = %event.symbol,
+                    quantity = %event.quantity,
+                    price = %event.price,
+                    "Trade"
+                );
+                self.position_limits.apply_trade(&event);
+                EngineEvent::Trade(event).into()
+            })
+            .collect();
+
+        // stops the trades set off that didn't fill and couldn't rest
+        let dropped = self
+            .engine_map
+            .get_mut(symbol)
+            .unwrap()
+            .take_dropped_stops();
+        for stop in dropped {
+            let reason = match (stop.order.price, stop.collared) {
+                (Some(_), _) => "ioc",
+                (None, true) => "collar",
+                (None, false) => "no_liquidity",
+            };
+            messages.extend(cancelled(seq, reason, vec![stop.order]));
+        }
+
+        if let Some(price) = tripped {
+            warn!(
+                event = "circuit_breaker_halt",
+                seq, symbol, price = %price, "Circuit breaker halted the symbol"
+            );
+            messages.push(OutboundMessage::SymbolHalted(SymbolHalted {
+                kind: "symbol_halted",
+                symbol: symbol.to_string(),
+                seq,
+                price,
+            }));
+        }
+        messages
+    }
+
+    // Every resting order the user has, by symbol.
+    pub fn orders_for_user(&self, user: &str) -> Vec<OrderView> {
+        let mut symbols: Vec<&String> = self.engine_map.keys().collect();
+        symbols.sort();
+        symbols
+            .into_iter()
+            .flat_map(|symbol| self.engine_map[symbol].orders_for_user(user))
+            .collect()
+    }
+
+    fn process_open_orders(&mut self, query: OpenOrdersQuery) -> OutboundMessage {
+        self.sequence += 1;
+        let orders = self.orders_for_user(&query.user);
+        info!(
+            event = "open_orders",
+            seq = self.sequence,
+            user = %query.user,
+            count = orders.len(),
+            "Open orders requested"
+        );
+        OutboundMessage::OpenOrders(OpenOrders {
+            kind: "open_orders",
+            user: query.user,
+            orders,
+        })
+    }
+
+    // Streams a book's resting orders SNAPSHOT_CHUNK_ORDERS at a time, then
+    // a closing message to check them against.
+    fn process_snapshot(&mut self, request: SnapshotRequest) -> Vec<OutboundMessage> {
+        self.sequence += 1;
+        let (orders, last_order_id) = match self.engine_map.get(&request.symbol) {
+            Some(book) => {
+                let snapshot = book.snapshot();
+                (Some(snapshot.orders), snapshot.last_order_id)
+            }
+            None => (None, 0),
+        };
+        let found = orders.is_some();
+        let orders = orders.unwrap_or_default();
+
+        let mut messages: Vec<OutboundMessage> = orders
+            .chunks(SNAPSHOT_CHUNK_ORDERS)
+            .enumerate()
+            .map(|(index, chunk)| {
+                OutboundMessage::SnapshotChunk(SnapshotChunk {
+                    kind: "snapshot_chunk",
+                    symbol: request.symbol.clone(),
+                    request_id: request.request_id,
+                    index,
+                    orders: chunk.to_vec(),
+                })
+            })
+            .collect();
+        info!(
+            event = "book_snapshot",
+            seq = self.sequence,
+            symbol = %request.symbol,
+            request_id = request.request_id,
+            orders = orders.len(),
+            chunks = messages.len(),
+            "Book snapshot streamed"
+        );
+        messages.push(OutboundMessage::SnapshotEnd(SnapshotEnd {
+            kind: "snapshot_end",
+            book_update_seq: self
+                .market_data_sequence
+                .get(&request.symbol)
+                .copied()
+                .unwrap_or(0),
+            symbol: request.symbol,
+            request_id: request.request_id,
+            found,
+            chunks: messages.len(),
+            checksum: orderbook::orders_checksum(&orders),
+            last_order_id,
+        }));
+        messages
+    }
+
+    // Risk's kill switch. Symbols halted on an integrity failure are left as
+    // they are for whoever investigates.
+    pub fn process_mass_cancel(&mut self, cancel: MassCancel) -> Vec<OutboundMessage> {
+        self.sequence += 1;
+        let seq = self.sequence;
+        let mut symbols: Vec<String> = self
+            .engine_map
+            .keys()
+            .filter(|symbol| !self.halted.contains(*symbol))
+            .cloned()
+            .collect();
+        symbols.sort();
+        let orders: Vec<Order> = symbols
+            .iter()
+            .flat_map(|symbol| {
+                self.engine_map
+                    .get_mut(symbol)
+                    .unwrap()
+                    .cancel_all_for_user(&cancel.user)
+            })
+            .collect();
+        warn!(
+            event = "mass_cancel",
+            seq,
+            user = %cancel.user,
+            count = orders.len(),
+            "Mass cancel"
+        );
+        cancelled(seq, "mass_cancel", orders)
+    }
+
+    // Withdraws a resting order. There is no ownership check yet: anyone who
+    // knows an order's id can cancel it. An order younger than its symbol's
+    // minimum resting time has its cancel held back until it is old enough,
+    // publishing nothing now, or refused.
+    pub fn process_cancel(&mut self, cancel: CancelOrder) -> Vec<OutboundMessage> {
+        self.sequence += 1;
+        let seq = self.sequence;
+        let now = (self.clock)();
+
+        let early = self
+            .engine_map
+            .get(&cancel.symbol)
+            .filter(|_| !self.halted.contains(&cancel.symbol))
+            .and_then(|book| {
+                let rule = book.rules().min_resting?;
+                let order = book.get_order(cancel.order_id)?;
+                let due_at = order.received_at + rule.millis;
+                (now < due_at).then_some((rule.early_cancel, order.user, due_at))
+            });
+        if let Some((EarlyCancel::Defer, user, due_at)) = early {
+            self.defer_cancel(seq, cancel, user, due_at);
+            return vec![];
+        }
+
+        let result = match self.engine_map.get_mut(&cancel.symbol) {
+            _ if self.halted.contains(&cancel.symbol) => Err("symbol_halted"),
+            Some(_) if early.is_some() => Err("min_resting_time"),
+            Some(book) => book.cancel_order(cancel.order_id).map_err(cancel_reason),
+            None => Err("unknown_symbol"),
+        };
+        match result {
+            Ok(order) => cancelled(seq, "requested", vec![order]),
+            Err(reason) => {
+                warn!(
+                    event = "cancel_rejected",
+                    seq,
+                    order_id = cancel.order_id,
+                    symbol = %cancel.symbol,
+                    reason,
+                    "Cancel rejected"
+                );
+                vec![OutboundMessage::CancelRejected(RequestRejected::new(
+                    "cancel_rejected",
+                    cancel.symbol,
+                    cancel.order_id,
+                    reason,
+                ))]
+            }
+        }
+    }
+
+    fn defer_cancel(&mut self, seq: u64, cancel: CancelOrder, user: String, due_at: i64) {
+        let queued = self.deferred_cancels.iter().any(|deferred| {
+            deferred.symbol == cancel.symbol && deferred.order_id == cancel.order_id
+        });
+        if queued {
+            return;
+        }
+        info!(
+            event = "cancel_deferred",
+            seq,
+            order_id = cancel.order_id,
+            user = %user,
+            symbol = %cancel.symbol,
+            due_at,
+            "Cancel held back until the order has rested long enough"
+        );
+        *self.deferred_cancels_by_user.entry(user).or_default() += 1;
+        self.deferred_cancels.push(DeferredCancel {
+            symbol: cancel.symbol,
+            order_id: cancel.order_id,
+            due_at,
+        });
+    }
+
+    // Applies the held-back cancels whose orders have now rested long enough.
+    // Those for a halted symbol wait for it to resume; an order that filled
+    // in the meantime has nothing left to cancel.
+    pub fn apply_deferred_cancels(&mut self) -> Vec<OutboundMessage> {
+        let now = (self.clock)();
+        let (due, waiting) = std::mem::take(&mut self.deferred_cancels)
+            .into_iter()
+            .partition(|deferred| {
+                deferred.due_at <= now && !self.halted.contains(&deferred.symbol)
+            });
+        self.deferred_cancels = waiting;
+
+        let seq = self.sequence;
+        let mut messages = vec![];
+        for deferred in due {
+            let Some(book) = self.engine_map.get_mut(&deferred.symbol) else {
+                continue;
+            };
+            match book.cancel_order(deferred.order_id) {
+                Ok(order) => messages.extend(cancelled(seq, "requested", vec![order])),
+                Err(e) => info!(
+                    event = "deferred_cancel_dropped",
+                    seq,
+                    order_id = deferred.order_id,
+                    symbol = %deferred.symbol,
+                    reason = cancel_reason(e),
+                    "Deferred cancel found nothing to cancel"
+                ),
+            }
+        }
+        messages
+    }
+
+    // Reprices or resizes a resting order, matching it if the new price
+    // crosses. Like cancels, amends are not checked against the order's owner.
+    pub fn process_amend(&mut self, amend: AmendOrder) -> Vec<OutboundMessage> {
+        self.sequence += 1;
+        let seq = self.sequence;
+        // an order that expired since the last sweep can't be amended back
+        // to life
+        let mut messages = if self.halted.contains(&amend.symbol) {
+            vec![]
+        } else {
+            self.expire_book(&amend.symbol, seq)
+        };
+        let now = (self.clock)();
+
+        let found = match self.engine_map.get(&amend.symbol) {
+            _ if self.halted.contains(&amend.symbol) => Err("symbol_halted"),
+            // IOC and FOK say what to do with an order on arrival, which a
+            // resting order is long past
+            _ if matches!(
+                amend.time_in_force,
+                Some(TimeInForce::Ioc | TimeInForce::Fok)
+            ) =>
+            {
+                Err("invalid_time_in_force")
+            }
+            _ if matches!(amend.expires_at, Some(Some(at)) if at <= now) => Err("already_expired"),
+            Some(book) => book
+                .find_order(amend.order_id)
+                .cloned()
+                .and_then(|resting| {
+                    // quantity 0 cancels, which is always allowed. The book
+                    // unpegs a pegged order it moves, so it is checked
+                    // unpegged
+                    let amended = Order {
+                        price: amend.price.or(resting.price),
+                        quantity: amend.quantity.unwrap_or(resting.quantity),
+                        peg: None,
+                        market_to_limit: false,
+                        ..resting.clone()
+                    };
+                    if !amended.quantity.is_zero() {
+                        book.validate(&amended).map_err(CancelError::Rejected)?;
+                    }
+                    Ok((resting, amended))
+                })
+                .map_err(cancel_reason),
+            None => Err("unknown_symbol"),
+        };
+        let (resting, mut amended) = match found {
+            Ok(orders) => orders,
+            Err(reason) => {
+                messages.push(amend_rejected(seq, &amend.symbol, amend.order_id, reason));
+                return messages;
+            }
+        };
+
+        // growing an order is held to the position cap like a new one would
+        // be, but what is already resting is never trimmed
+        if amended.quantity > resting.quantity {
+            let allowed = self.position_limits.allowed_quantity(
+                &resting.user,
+                &amend.symbol,
+                &resting.side,
+                amended.quantity,
+            );
+            amended.quantity = allowed
+                .round_down_to(self.lot_size(&amend.symbol))
+                .max(resting.quantity);
+        }
+
+        let book = self.engine_map.get_mut(&amend.symbol).unwrap();
+        let matching = Instant::now();
+        // a change to the expiry alone keeps the order's place; a new price
+        // or size goes through the book, which can still turn it away, e.g.
+        // an all-or-none order moved to where it would fill only in part
+        let price = amended.price.unwrap_or_default();
+        let events = if amend.price.is_none() && amend.quantity.is_none() {
+            Ok(vec![])
+        } else {
+            book.amend_order(amend.order_id, price, amended.quantity)
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                messages.push(amend_rejected(
+                    seq,
+                    &amend.symbol,
+                    amend.order_id,
+                    cancel_reason(e),
+                ));
+                return messages;
+            }
+        };
+        if let Some(expires_at) = amend.expires_at {
+            amended.expires_at = expires_at;
+            // gone if it traded away in full, and then its expiry is moot
+            let _ = book.set_expiry(amend.order_id, expires_at);
+        }
+        info!(
+            event = "order_amended",
+            seq,
+            order_id = amend.order_id,
+            user = %resting.user,
+            symbol = %amend.symbol,
+            price = %price,
+            quantity = %amended.quantity,
+            expires_at = ?amended.expires_at,
+            "Order amended"
+        );
+
+        messages.push(OutboundMessage::Amended(OrderAmended {
+            kind: "order_amended",
+            symbol: amend.symbol.clone(),
+            order_id: amend.order_id,
+            price,
+            quantity: amended.quantity,
+            time_in_force: amended.time_in_force,
+            expires_at: amended.expires_at,
+        }));
+        messages.extend(self.finish_match(&amend.symbol, seq, matching, events, resting));
+        messages
+    }
+
+    // The amend-down path: shrinks a resting order in place, answered like an
+    // amend, or cancels it once nothing would be left.
+    pub fn process_reduce(&mut self, reduce: ReduceOrder) -> Vec<OutboundMessage> {
+        self.sequence += 1;
+        let seq = self.sequence;
+        let mut messages = if self.halted.contains(&reduce.symbol) {
+            vec![]
+        } else {
+            self.expire_book(&reduce.symbol, seq)
+        };
+
+        let result = match self.engine_map.get_mut(&reduce.symbol) {
+            _ if self.halted.contains(&reduce.symbol) => Err("symbol_halted"),
+            _ if reduce.quantity.is_zero() => Err("zero_quantity"),
+            Some(book) => book
+                .find_order(reduce.order_id)
+                .cloned()
+                .and_then(|resting| {
+                    let left = resting.quantity.saturating_sub(reduce.quantity);
+                    if left.is_zero() {
+                        return book.cancel_order(reduce.order_id);
+                    }
+                    book.reduce_order(reduce.order_id, left)?;
+                    Ok(Order {
+                        quantity: left,
+                        ..resting
+                    })
+                })
+                .map_err(cancel_reason),
+            None => Err("unknown_symbol"),
+        };
+        match result {
+            // cancel_order hands the order back closed
+            Ok(order) if order.state == OrderState::Close => {
+                messages.extend(cancelled(seq, "reduced", vec![order]));
+            }
+            Ok(order) => {
+                info!(
+                    event = "order_reduced",
+                    seq,
+                    order_id = order.order_id,
+                    user = %order.user,
+                    symbol = %order.symbol,
+                    quantity = %order.quantity,
+                    "Order reduced"
+                );
+                messages.push(OutboundMessage::Amended(OrderAmended {
+                    kind: "order_amended",
+                    symbol: order.symbol,
+                    order_id: order.order_id,
+                    price: order.price.unwrap_or_default(),
+                    quantity: order.quantity,
+                    time_in_force: order.time_in_force,
+                    expires_at: order.expires_at,
+                }));
+            }
+            Err(reason) => {
+                messages.push(amend_rejected(seq, &reduce.symbol, reduce.order_id, reason));
+            }
+        }
+        messages
+    }
+
+    // Stops matching `symbol` and leaves a copy of its book for offline
+    // analysis. Trades already produced by the operation still go out.
+    fn halt(&mut self, symbol: &str, seq: u64, reason: String, last_order: Order) -> IntegrityHalt {
+        self.halted.insert(symbol.to_string());
+        let book = &self.engine_map[symbol];
+        let snapshot_path = match integrity::write_emergency_snapshot(&self.snapshot_dir, book, seq)
+        {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!(
+                    event = "snapshot_failed",
+                    seq,
+                    symbol,
+                    error = %e,
+                    "Failed to write emergency snapshot"
+                );
+                None
+            }
+        };
+        let halt = IntegrityHalt::new(book, seq, reason, last_order, snapshot_path);
+        tracing::error!(
+            event = "integrity_halt",
+            seq,
+            symbol,
+            reason = %halt.reason,
+            snapshot_hash = %halt.snapshot_hash,
+            "Symbol halted"
+        );
+        halt
+    }
+
+    // Returns what the message published, or None if it was refused.
+    pub fn process_admin(&mut self, payload: &str) -> Option<Vec<OutboundMessage>> {
+        self.sequence += 1;
+        let seq = self.sequence;
+
+        match serde_json::from_str::<AdminMessage>(payload) {
+            Ok(AdminMessage::PositionLimit(limit)) => {
+                info!(
+                    event = "position_limit_updated",
+                    seq,
+                    user = %limit.user,
+                    symbol = %limit.symbol,
+                    limit = %limit.limit,
+                    "Position limit updated"
+                );
+                self.position_limits.set_limit(limit);
+                Some(vec![])
+            }
+            Ok(AdminMessage::ResumeSymbol(resume)) => {
+                if !resume.override_halt {
+                    warn!(
+                        event = "resume_refused",
+                        seq,
+                        symbol = %resume.symbol,
+                        "Resume requires the override flag"
+                    );
+                    return None;
+                }
+                // lifts an integrity halt and a circuit breaker halt alike
+                let tripped = match self.engine_map.get_mut(&resume.symbol) {
+                    Some(book) if book.is_halted() => {
+                        book.resume();
+                        true
+                    }
+                    _ => false,
+                };
+                if self.halted.remove(&resume.symbol) || tripped {
+                    warn!(
+                        event = "symbol_resumed",
+                        seq,
+                        symbol = %resume.symbol,
+                        "Symbol resumed by admin override"
+                    );
+                }
+                Some(vec![])
+            }
+            Ok(AdminMessage::StartAuction(auction)) => {
+                let Some(book) = self.engine_map.get_mut(&auction.symbol) else {
+                    warn!(event = "unknown_symbol", seq, symbol = %auction.symbol, "Unknown symbol");
+                    return None;
+                };
+                book.start_auction();
+                info!(event = "auction_started", seq, symbol = %auction.symbol, "Auction started");
+                Some(vec![])
+            }
+            Ok(AdminMessage::Uncross(auction)) => {
+                let matching = Instant::now();
+                let Some(book) = self.engine_map.get_mut(&auction.symbol) else {
+                    warn!(event = "unknown_symbol", seq, symbol = %auction.symbol, "Unknown symbol");
+                    return None;
+                };
+                if book.phase() != TradingPhase::Auction {
+                    warn!(event = "uncross_refused", seq, symbol = %auction.symbol, "Symbol is not in an auction");
+                    return None;
+                }
+                let price = book.auction_price();
+                let events = book.uncross();
+                info!(
+                    event = "auction_uncrossed",
+                    seq,
+                    symbol = %auction.symbol,
+                    price = ?price.map(|(price, _)| price),
+                    volume = %price.map_or(Qty::ZERO, |(_, volume)| volume),
+                    "Auction uncrossed"
+                );
+                Some(self.book_trades(&auction.symbol, seq, matching, events))
+            }
+            Ok(AdminMessage::ClearSymbol(clear)) => {
+                let Some(book) = self.engine_map.get_mut(&clear.symbol) else {
+                    warn!(event = "unknown_symbol", seq, symbol = %clear.symbol, "Unknown symbol");
+                    return None;
+                };
+                let orders = book.clear();
+                // held like an integrity halt, so only a resume override
+                // reopens it
+                self.halted.insert(clear.symbol.clone());
+                warn!(
+                    event = "symbol_cleared",
+                    seq,
+                    symbol = %clear.symbol,
+                    count = orders.len(),
+                    "Symbol cleared and halted"
+                );
+                Some(cancelled(seq, "symbol_cleared", orders))
+            }
+            Err(e) => {
+                warn!(
+                    event = "parse_error",
+                    seq,
+                    error = %e,
+                    raw = %payload,
+                    "Failed to parse admin message"
+                );
+                None
+            }
+        }
+    }
+
+    pub fn log_stats(&self) {
+        info!(
+            event = "stats",
+            seq = self.sequence,
+            orders = self.stats.orders,
+            trades = self.stats.trades,
+            rejections = self.stats.rejections,
+            volume = %self.stats.volume,
+            "Engine stats"
+        );
+        for (user, count) in &self.deferred_cancels_by_user {
+            info!(
+                event = "deferred_cancels",
+                seq = self.sequence,
+                user = %user,
+                count,
+                "Cancels held back for minimum resting time"
+            );
+        }
+
+        let alert_micros = self.latency_alert.as_micros() as u64;
+        for (kind, stats) in &self.metrics.by_type {
+            let p99 = stats.latency.percentile_micros(99.0);
+            info!(
+                event = "message_stats",
+                seq = self.sequence,
+                kind = kind.as_str(),
+                count = stats.count,
+                rejected = stats.rejected,
+                rejection_rate = stats.rejection_rate(),
+                p50_micros = stats.latency.percentile_micros(50.0),
+                p99_micros = p99,
+                "Message stats"
+            );
+            // None means the p99 sample overflowed the largest bucket
+            if stats.count > 0 && p99.is_none_or(|p99| p99 > alert_micros) {
+                warn!(
+                    event = "latency_alert",
+                    seq = self.sequence,
+                    kind = kind.as_str(),
+                    p99_micros = p99,
+                    threshold_micros = alert_micros,
+                    "p99 latency above threshold"
+                );
+            }
+        }
+    }
+}
+
+// An order whose side is neither Buy nor Sell can't become an Order, but it
+// is still rejected back to its sender rather than silently dropped.
+fn unknown_side(payload: &str) -> Vec<OutboundMessage> {
+    let Ok(order) = serde_json::from_str::<serde_json::Value>(payload) else {
+        return vec![];
+    };
+    match order.get("side").map(Side::deserialize) {
+        Some(Ok(_)) => vec![],
+        _ if order.get("user").is_none() => vec![],
+        _ => vec![OutboundMessage::Malformed(MalformedOrder {
+            kind: "rejected",
+            reason: "unknown_side",
+            order,
+        })],
+    }
+}
+
+fn cancelled(seq: u64, reason: &'static str, orders: Vec<Order>) -> Vec<OutboundMessage> {
+    orders
+        .into_iter()
+        .map(|order| {
+            info!(
+                event = "order_cancelled",
+                seq,
+                order_id = order.order_id,
+                reason,
+                user = %order.user,
+                symbol = %order.symbol,
+                quantity = %order.quantity,
+                "Order cancelled"
+            );
+            EngineEvent::cancelled(reason, order).into()
+        })
+        .collect()
+}
+
+fn add_reason(error: AddOrderError) -> &'static str {
+    match error {
+        AddOrderError::Invalid(e) => e.reason(),
+        AddOrderError::SymbolMismatch => "symbol_mismatch",
+        AddOrderError::Halted => "circuit_breaker",
+        AddOrderError::AuctionOpen => "auction_open",
+        AddOrderError::Unfillable => "fok_unfilled",
+        AddOrderError::AllOrNoneUnfilled => "all_or_none_unfilled",
+        AddOrderError::BelowMinQuantity => "min_quantity_unfilled",
+        AddOrderError::WouldCross => "post_only_would_cross",
+        AddOrderError::InvalidPegPrice => "invalid_price",
+        AddOrderError::NoLiquidity => "no_liquidity",
+    }
+}
+
+fn amend_rejected(seq: u64, symbol: &str, order_id: u64, reason: &'static str) -> OutboundMessage {
+    warn!(
+        event = "amend_rejected",
+        seq, order_id, symbol, reason, "Amend rejected"
+    );
+    OutboundMessage::AmendRejected(RequestRejected::new(
+        "amend_rejected",
+        symbol.to_string(),
+        order_id,
+        reason,
+    ))
+}
+
+fn cancel_reason(error: CancelError) -> &'static str {
+    match error {
+        CancelError::UnknownOrder(_) => "unknown_order",
+        CancelError::NotResting(_) => "not_resting",
+        CancelError::Rejected(e) => add_reason(e),
+        CancelError::WouldGrow(_) => "quantity_increase",
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orderbook::{CircuitBreaker, MinRestingTime, Side};
+
+    fn limit_order(user: &str, side: Side, quantity: u64, price: i64) -> Order {
+        Order::new_limit_order(
+            Qty::shares(quantity),
+            Some(Price::cents(price)),
+            side,
+            String::from("AAPL"),
+            user.to_string(),
+        )
+    }
+
+    // the message in the envelope the gateway sends it in
+    fn sealed(payload: &str) -> String {
+        let payload = serde_json::value::RawValue::from_string(payload.to_string()).unwrap();
+        envelope::seal("test", 0, &payload)
+    }
+
+    // the order rested without trading
+    fn only_accepted(messages: &[OutboundMessage]) -> bool {
+        matches!(messages, [OutboundMessage::Event(EngineEvent::Accepted(_))])
+    }
+
+    fn filled(messages: &[OutboundMessage]) -> Qty {
+        messages
+            .iter()
+            .map(|m| match m {
+                OutboundMessage::Event(EngineEvent::Trade(t)) => t.quantity,
+                _ => Qty::ZERO,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_interleaved_orders_jointly_capped() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_admin(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":10}"#);
+
+        engine.process_order(limit_order("maker", Side::Sell, 100, 100));
+
+        // each buy of 6 is within the cap on its own
+        let first = engine.process_order(limit_order("a", Side::Buy, 6, 100));
+        assert_eq!(filled(&first), Qty::shares(6));
+
+        // but the second is trimmed to the remaining headroom
+        let second = engine.process_order(limit_order("a", Side::Buy, 6, 100));
+        assert_eq!(filled(&second), Qty::shares(4));
+        assert_eq!(
+            engine.position_limits.position("a", "AAPL"),
+            Qty::shares(10).units() as i64
+        );
+
+        let third = engine.process_order(limit_order("a", Side::Buy, 1, 100));
+        match third.as_slice() {
+            [OutboundMessage::Event(EngineEvent::Rejected(rejected))] => {
+                assert_eq!(rejected.reason, "position_limit");
+                assert_eq!(rejected.order.user, "a");
+            }
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cancel_message() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let order = serde_json::to_string(&limit_order("maker", Side::Sell, 10, 100)).unwrap();
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order));
+        engine.process_order(limit_order("a", Side::Buy, 4, 100));
+
+        let cancel = r#"{"type":"cancel","symbol":"AAPL","order_id":1}"#;
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(cancel));
+        match messages.as_slice() {
+            [OutboundMessage::Event(EngineEvent::Cancelled(cancelled))] => {
+                assert_eq!(cancelled.order.order_id, 1);
+                assert_eq!(cancelled.order.quantity, Qty::shares(6));
+            }
+            other => panic!("expected a cancellation, got {:?}", other),
+        }
+        assert_eq!(engine.engine_map["AAPL"].best_ask(), None);
+
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(cancel));
+        let json = serde_json::to_value(&messages).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "type": "cancel_rejected",
+                "symbol": "AAPL",
+                "order_id": 1,
+                "reason": "not_resting",
+            }])
+        );
+        let rejected = engine.process_message(
+            ORDER_INBOUND_CHANNEL,
+            &sealed(r#"{"type":"cancel","symbol":"NOPE","order_id":1}"#),
+        );
+        assert!(
+            matches!(rejected.as_slice(), [OutboundMessage::CancelRejected(r)] if r.reason == "unknown_symbol")
+        );
+
+        let stats = &engine.metrics.by_type[&MessageType::Cancel];
+        assert_eq!((stats.count, stats.rejected), (3, 2));
+    }
+
+    #[test]
+    fn test_cancel_before_min_resting_time() {
+        let rules = |early_cancel| SymbolRules {
+            min_resting: Some(MinRestingTime {
+                millis: 500,
+                early_cancel,
+            }),
+            ..SymbolRules::default()
+        };
+        let mut engine = MatchingEngine::new(vec![
+            (String::from("AAPL"), rules(EarlyCancel::Defer)),
+            (String::from("MSFT"), rules(EarlyCancel::Reject)),
+        ]);
+        for book in engine.engine_map.values_mut() {
+            book.set_clock(|| 1_000);
+        }
+        engine.process_order(limit_order("a", Side::Buy, 10, 100));
+        engine.process_order(limit_order("a", Side::Buy, 10, 99));
+        engine.process_order(Order {
+            symbol: String::from("MSFT"),
+            ..limit_order("a", Side::Buy, 10, 100)
+        });
+        let cancel = |symbol: &str, order_id| CancelOrder {
+            symbol: String::from(symbol),
+            order_id,
+        };
+
+        engine.clock = || 1_499;
+        assert!(engine.process_cancel(cancel("AAPL", 1)).is_empty());
+        assert!(engine.apply_deferred_cancels().is_empty());
+        assert_eq!(
+            engine.engine_map["AAPL"].best_bid(),
+            Some((Price::cents(100), Qty::shares(10)))
+        );
+        assert!(matches!(
+            engine.process_cancel(cancel("MSFT", 1)).as_slice(),
+            [OutboundMessage::CancelRejected(r)] if r.reason == "min_resting_time"
+        ));
+
+        engine.clock = || 1_500;
+        assert!(matches!(
+            engine.process_cancel(cancel("AAPL", 2)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Cancelled(c))] if c.order.order_id == 2
+        ));
+        assert!(matches!(
+            engine.process_cancel(cancel("MSFT", 1)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Cancelled(c))] if c.order.order_id == 1
+        ));
+        match engine.apply_deferred_cancels().as_slice() {
+            [OutboundMessage::Event(EngineEvent::Cancelled(cancelled))] => {
+                assert_eq!(cancelled.order.order_id, 1);
+                assert_eq!(cancelled.reason, "requested");
+            }
+            other => panic!("expected the deferred cancel, got {:?}", other),
+        }
+        assert_eq!(engine.engine_map["AAPL"].best_bid(), None);
+        assert!(engine.apply_deferred_cancels().is_empty());
+        assert_eq!(engine.deferred_cancels_by_user["a"], 1);
+    }
+
+    #[test]
+    fn test_ioc_remainder_reported() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("maker", Side::Sell, 3, 100));
+
+        let ioc = Order {
+            time_in_force: TimeInForce::Ioc,
+            ..limit_order("a", Side::Buy, 5, 100)
+        };
+        let messages = engine.process_order(ioc);
+        match messages.as_slice() {
+            [
+                OutboundMessage::Event(EngineEvent::Accepted(_)),
+                OutboundMessage::Event(EngineEvent::Trade(trade)),
+                OutboundMessage::Event(EngineEvent::Cancelled(cancelled)),
+            ] => {
+                assert_eq!(trade.quantity, Qty::shares(3));
+                assert_eq!(cancelled.reason, "ioc");
+                assert_eq!(
+                    (cancelled.order.order_id, cancelled.order.quantity),
+                    (2, Qty::shares(2))
+                );
+            }
+            other => panic!(
+                "expected a trade and the dropped remainder, got {:?}",
+                other
+            ),
+        }
+        assert_eq!(engine.engine_map["AAPL"].len(), 0);
+    }
+
+    #[test]
+    fn test_invalid_orders_rejected() {
+        let rules = SymbolRules {
+            max_quantity: Some(Qty::shares(100)),
+            ..SymbolRules::default()
+        };
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), rules)]);
+        let reason = |messages: Vec<OutboundMessage>| match messages.as_slice() {
+            [OutboundMessage::Event(EngineEvent::Rejected(rejected))] => rejected.reason.clone(),
+            other => panic!("expected a rejection, got {:?}", other),
+        };
+
+        assert_eq!(
+            reason(engine.process_order(limit_order("a", Side::Buy, 0, 100))),
+            "zero_quantity"
+        );
+        assert_eq!(
+            reason(engine.process_order(limit_order("a", Side::Buy, 5, 0))),
+            "invalid_price"
+        );
+        assert_eq!(
+            reason(engine.process_order(limit_order("a", Side::Buy, 101, 100))),
+            "quantity_too_large"
+        );
+        assert_eq!(
+            reason(engine.process_order(limit_order("", Side::Buy, 5, 100))),
+            "empty_user"
+        );
+        // checked before the book is looked for
+        let mut unlisted = limit_order("a", Side::Buy, 0, 100);
+        unlisted.symbol = "MSFT".to_string();
+        assert_eq!(reason(engine.process_order(unlisted)), "zero_quantity");
+        let mut unlisted = limit_order("a", Side::Buy, 5, 100);
+        unlisted.symbol = "MSFT".to_string();
+        assert_eq!(reason(engine.process_order(unlisted)), "unknown_symbol");
+
+        match engine
+            .process_order(limit_order("a", Side::Buy, 101, 100))
+            .as_slice()
+        {
+            [OutboundMessage::Event(EngineEvent::Rejected(rejected))] => assert_eq!(
+                rejected.error,
+                Some(ValidationError::TooLarge {
+                    quantity: Qty::shares(101),
+                    max_quantity: Qty::shares(100),
+                })
+            ),
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+
+        let payload = r#"{"symbol":"AAPL","side":"Hold","quantity":5,"price":100,"user":"a"}"#;
+        match engine
+            .process_message(ORDER_INBOUND_CHANNEL, &sealed(payload))
+            .as_slice()
+        {
+            [OutboundMessage::Malformed(malformed)] => {
+                assert_eq!(malformed.reason, "unknown_side");
+                assert_eq!(malformed.order["user"], "a");
+            }
+            other => panic!("expected an unknown side rejection, got {:?}", other),
+        }
+        assert_eq!(engine.engine_map["AAPL"].len(), 0);
+
+        // sides are read in lowercase too
+        let payload = r#"{"symbol":"AAPL","side":"sell","quantity":5,"price":100,"user":"a"}"#;
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(payload));
+        assert_eq!(engine.engine_map["AAPL"].len(), 1);
+    }
+
+    #[test]
+    fn test_notional_order_reports_unspent() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_admin(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":4}"#);
+        engine.process_order(limit_order("maker", Side::Sell, 10, 150));
+
+        let notional = Order {
+            notional: Some(Price::cents(1000)),
+            ..Order::new_market_order(
+                Qty::shares(0),
+                Side::Buy,
+                String::from("AAPL"),
+                String::from("a"),
+            )
+        };
+        let messages = engine.process_order(notional);
+        match messages.as_slice() {
+            [
+                OutboundMessage::Event(EngineEvent::Accepted(accepted)),
+                OutboundMessage::Event(EngineEvent::Trade(trade)),
+                OutboundMessage::Event(EngineEvent::Cancelled(cancelled)),
+            ] => {
+                // the order's id ties its ack, trade and cancel together
+                assert_eq!(accepted.order.order_id, 2);
+                assert_eq!(trade.taker_order_id, 2);
+                assert_eq!(cancelled.order.order_id, 2);
+                // 1000 affords 6 shares, but the position cap allows only 4
+                assert_eq!(trade.quantity, Qty::shares(4));
+                assert_eq!(cancelled.reason, "notional_unspent");
+                assert_eq!(cancelled.order.notional, Some(Price::cents(400)));
+            }
+            other => panic!("expected a trade and the unspent budget, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fok_rejections() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_admin(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":4}"#);
+        engine.process_order(limit_order("maker", Side::Sell, 3, 100));
+        let fok = |user: &str, quantity| Order {
+            time_in_force: TimeInForce::Fok,
+            ..limit_order(user, Side::Buy, quantity, 100)
+        };
+        let reason = |messages: Vec<OutboundMessage>| match messages.as_slice() {
+            [OutboundMessage::Event(EngineEvent::Rejected(rejected))] => rejected.reason.clone(),
+            other => panic!("expected a rejection, got {:?}", other),
+        };
+
+        // would have been trimmed to 4
+        assert_eq!(reason(engine.process_order(fok("a", 5))), "position_limit");
+        assert_eq!(reason(engine.process_order(fok("b", 5))), "fok_unfilled");
+        assert_eq!(engine.engine_map["AAPL"].len(), 1);
+        assert_eq!(filled(&engine.process_order(fok("b", 3))), Qty::shares(3));
+    }
+
+    #[test]
+    fn test_expired_order_never_matches() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.clock = || 1_000;
+        let gtd = Order {
+            expires_at: Some(1_500),
+            ..limit_order("maker", Side::Sell, 5, 100)
+        };
+        engine.process_order(gtd);
+
+        // the crossing order arrives in the same tick the maker expires,
+        // before any timer sweep has run
+        engine.clock = || 1_500;
+        let messages = engine.process_order(limit_order("a", Side::Buy, 5, 100));
+        match messages.as_slice() {
+            [
+                OutboundMessage::Event(EngineEvent::Expired(expired)),
+                OutboundMessage::Event(EngineEvent::Accepted(accepted)),
+            ] => {
+                assert_eq!(expired.order.user, "maker");
+                assert_eq!(accepted.order.order_id, 2);
+            }
+            other => panic!("expected only the expiry, got {:?}", other),
+        }
+        assert_eq!(filled(&messages), Qty::shares(0));
+        let bids: Vec<&str> = engine.engine_map["AAPL"]
+            .iter_bids()
+            .map(|o| o.user.as_str())
+            .collect();
+        assert_eq!(bids, ["a"]);
+        assert!(engine.expire_orders().is_empty());
+
+        let late = Order {
+            expires_at: Some(1_500),
+            ..limit_order("b", Side::Sell, 5, 100)
+        };
+        let rejected = engine.process_order(late);
+        assert!(
+            matches!(rejected.as_slice(), [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "already_expired")
+        );
+    }
+
+    #[test]
+    fn test_stop_order_fires_on_trade() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("b1", Side::Buy, 5, 100));
+        engine.process_order(limit_order("b2", Side::Buy, 5, 95));
+        let stop = Order {
+            stop_price: Some(Price::cents(100)),
+            ..Order::new_market_order(
+                Qty::shares(5),
+                Side::Sell,
+                String::from("AAPL"),
+                String::from("stopper"),
+            )
+        };
+        assert!(only_accepted(&engine.process_order(stop)));
+
+        let messages = engine.process_order(limit_order("s", Side::Sell, 2, 100));
+        let sellers: Vec<(&str, Qty)> = messages[1..]
+            .iter()
+            .map(|m| match m {
+                OutboundMessage::Event(EngineEvent::Trade(t)) => (t.seller.as_str(), t.quantity),
+                other => panic!("expected only trades after the ack, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            sellers,
+            vec![
+                ("s", Qty::shares(2)),
+                ("stopper", Qty::shares(3)),
+                ("stopper", Qty::shares(2))
+            ]
+        );
+        assert_eq!(engine.stats.trades, 3);
+    }
+
+    #[test]
+    fn test_post_only_at_touch_rejected() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("maker", Side::Sell, 5, 101));
+        let post_only = |price| Order {
+            post_only: true,
+            ..limit_order("mm", Side::Buy, 5, price)
+        };
+
+        let messages = engine.process_order(post_only(101));
+        assert!(
+            matches!(messages.as_slice(), [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "post_only_would_cross")
+        );
+        assert!(only_accepted(&engine.process_order(post_only(100))));
+        assert_eq!(engine.engine_map["AAPL"].len(), 2);
+    }
+
+    #[test]
+    fn test_amend_message() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_admin(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":8}"#);
+        engine.process_order(limit_order("maker", Side::Sell, 10, 101));
+        engine.process_order(limit_order("a", Side::Buy, 5, 100));
+
+        // repriced across the spread and grown past the cap of 8
+        let amend = r#"{"type":"amend","symbol":"AAPL","order_id":2,"price":101,"quantity":12}"#;
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(amend));
+        match messages.as_slice() {
+            [
+                OutboundMessage::Amended(amended),
+                OutboundMessage::Event(EngineEvent::Trade(trade)),
+            ] => {
+                assert_eq!(amended.quantity, Qty::shares(8));
+                assert_eq!(
+                    (trade.buyer.as_str(), trade.quantity),
+                    ("a", Qty::shares(8))
+                );
+            }
+            other => panic!("expected an amend and a trade, got {:?}", other),
+        }
+        assert_eq!(
+            engine.position_limits.position("a", "AAPL"),
+            Qty::shares(8).units() as i64
+        );
+
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(amend));
+        assert!(
+            matches!(messages.as_slice(), [OutboundMessage::AmendRejected(r)] if r.reason == "not_resting")
+        );
+        let stats = &engine.metrics.by_type[&MessageType::Amend];
+        assert_eq!((stats.count, stats.rejected), (2, 1));
+    }
+
+    #[test]
+    fn test_amend_time_in_force_and_expiry() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.clock = || 1_000;
+        engine.process_order(limit_order("a", Side::Buy, 5, 100));
+        engine.process_order(limit_order("b", Side::Buy, 5, 100));
+        let amend =
+            |terms: &str| format!(r#"{{"type":"amend","symbol":"AAPL","order_id":1{terms}}}"#);
+        let mut send = |terms: &str| {
+            let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&amend(terms)));
+            match messages.as_slice() {
+                [OutboundMessage::Amended(amended)] => Ok((
+                    amended.price,
+                    amended.quantity,
+                    amended.time_in_force,
+                    amended.expires_at,
+                )),
+                [OutboundMessage::AmendRejected(rejected)] => Err(rejected.reason),
+                other => panic!("expected an amend ack or rejection, got {:?}", other),
+            }
+        };
+        let terms = |expires_at| {
+            Ok((
+                Price::cents(100),
+                Qty::shares(5),
+                TimeInForce::Gtc,
+                expires_at,
+            ))
+        };
+
+        // good till cancelled to good till date, then extended
+        assert_eq!(send(r#","expires_at":2000"#), terms(Some(2_000)));
+        assert_eq!(send(r#","expires_at":3000"#), terms(Some(3_000)));
+        // IOC and FOK make no sense for an order already resting; GTC does
+        assert_eq!(
+            send(r#","time_in_force":"IOC""#),
+            Err("invalid_time_in_force")
+        );
+        assert_eq!(
+            send(r#","time_in_force":"FOK""#),
+            Err("invalid_time_in_force")
+        );
+        assert_eq!(send(r#","time_in_force":"GTC""#), terms(Some(3_000)));
+        // a deadline that has already passed
+        assert_eq!(send(r#","expires_at":1000"#), Err("already_expired"));
+
+        // none of that cost the order its place
+        let first = |engine: &MatchingEngine| {
+            engine.engine_map["AAPL"]
+                .iter_bids()
+                .next()
+                .map(|o| o.order_id)
+        };
+        assert_eq!(first(&engine), Some(1));
+        engine.clock = || 2_000;
+        assert!(engine.expire_orders().is_empty());
+        engine.clock = || 3_000;
+        assert_eq!(engine.expire_orders().len(), 1);
+        assert_eq!(first(&engine), Some(2));
+
+        // and back from good till date to good till cancelled
+        let gtd = r#"{"type":"amend","symbol":"AAPL","order_id":2,"expires_at":4000}"#;
+        let gtc = r#"{"type":"amend","symbol":"AAPL","order_id":2,"expires_at":null}"#;
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(gtd));
+        match engine
+            .process_message(ORDER_INBOUND_CHANNEL, &sealed(gtc))
+            .as_slice()
+        {
+            [OutboundMessage::Amended(amended)] => assert_eq!(amended.expires_at, None),
+            other => panic!("expected an amend ack, got {:?}", other),
+        }
+        engine.clock = || 5_000;
+        assert!(engine.expire_orders().is_empty());
+        assert_eq!(first(&engine), Some(2));
+    }
+
+    #[test]
+    fn test_reduce_message() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("a", Side::Buy, 10, 100));
+        engine.process_order(limit_order("b", Side::Buy, 10, 100));
+        let reduce = |order_id, quantity| {
+            sealed(&format!(
+                r#"{{"type":"reduce","symbol":"AAPL","order_id":{order_id},"quantity":{quantity}}}"#
+            ))
+        };
+
+        // 10 less 4 keeps its place at the front
+        match engine
+            .process_message(ORDER_INBOUND_CHANNEL, &reduce(1, 4))
+            .as_slice()
+        {
+            [OutboundMessage::Amended(amended)] => {
+                assert_eq!(amended.quantity, Qty::shares(6));
+                assert_eq!(amended.price, Price::cents(100));
+            }
+            other => panic!("expected an amend ack, got {:?}", other),
+        }
+        let sell = limit_order("s", Side::Sell, 2, 100);
+        let trades = engine.process_order(sell);
+        assert!(
+            trades.iter().any(
+                |m| matches!(m, OutboundMessage::Event(EngineEvent::Trade(t)) if t.buyer == "a")
+            )
+        );
+
+        // 4 left, so taking 5 cancels what there is
+        match engine
+            .process_message(ORDER_INBOUND_CHANNEL, &reduce(1, 5))
+            .as_slice()
+        {
+            [OutboundMessage::Event(EngineEvent::Cancelled(cancelled))] => {
+                assert_eq!(cancelled.reason, "reduced");
+                assert_eq!(cancelled.order.quantity, Qty::shares(4));
+            }
+            other => panic!("expected a cancel, got {:?}", other),
+        }
+
+        // a reduce that arrives after the order filled finds nothing
+        engine.process_order(limit_order("s", Side::Sell, 10, 100));
+        assert!(matches!(
+            engine.process_message(ORDER_INBOUND_CHANNEL, &reduce(2, 1)).as_slice(),
+            [OutboundMessage::AmendRejected(r)] if r.reason == "not_resting"
+        ));
+        assert!(engine.engine_map["AAPL"].is_empty());
+    }
+
+    #[test]
+    fn test_amend_all_or_none_into_thin_book() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let mut aon = limit_order("a", Side::Buy, 10, 100);
+        aon.all_or_none = true;
+        engine.process_order(aon);
+        engine.process_order(limit_order("maker", Side::Sell, 3, 102));
+
+        let amend = r#"{"type":"amend","symbol":"AAPL","order_id":1,"price":"1.02","quantity":10}"#;
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(amend));
+        assert!(
+            matches!(messages.as_slice(), [OutboundMessage::AmendRejected(r)] if r.reason == "all_or_none_unfilled"),
+            "{:?}",
+            messages
+        );
+        let prices: Vec<Price> = engine
+            .orders_for_user("a")
+            .iter()
+            .map(|o| o.price)
+            .collect();
+        assert_eq!(prices, [Price::cents(100)]);
+    }
+
+    #[test]
+    fn test_tick_size_per_symbol() {
+        let tick_5 = SymbolRules {
+            tick_size: Price::cents(5),
+            ..SymbolRules::default()
+        };
+        let mut engine = MatchingEngine::new(vec![
+            (String::from("AAPL"), SymbolRules::default()),
+            (String::from("TSLA"), tick_5),
+        ]);
+        let tsla = |price| Order {
+            symbol: String::from("TSLA"),
+            ..limit_order("a", Side::Buy, 5, price)
+        };
+        assert!(only_accepted(&engine.process_order(limit_order(
+            "a",
+            Side::Buy,
+            5,
+            102
+        ))));
+        assert!(only_accepted(&engine.process_order(tsla(100))));
+        assert!(matches!(
+            engine.process_order(tsla(102)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "off_tick"
+        ));
+
+        let amend = r#"{"type":"amend","symbol":"TSLA","order_id":1,"price":102,"quantity":5}"#;
+        assert!(matches!(
+            engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(amend)).as_slice(),
+            [OutboundMessage::AmendRejected(r)] if r.reason == "off_tick"
+        ));
+        assert_eq!(
+            engine.engine_map["TSLA"].best_bid(),
+            Some((Price::cents(100), Qty::shares(5)))
+        );
+    }
+
+    #[test]
+    fn test_lot_size_per_symbol() {
+        let rules = SymbolRules {
+            lot_size: Qty::shares(10),
+            ..SymbolRules::default()
+        };
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), rules)]);
+        engine.process_admin(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":25}"#);
+        assert!(matches!(
+            engine.process_order(limit_order("a", Side::Buy, 15, 100)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "odd_lot"
+        ));
+
+        // the position cap trims 30 down to whole lots, not to 25
+        engine.process_order(limit_order("a", Side::Buy, 30, 100));
+        assert_eq!(
+            engine.engine_map["AAPL"].best_bid(),
+            Some((Price::cents(100), Qty::shares(20)))
+        );
+    }
+
+    #[test]
+    fn test_market_collar_remainder_reported() {
+        let rules = SymbolRules {
+            market_collar_bps: Some(500),
+            ..SymbolRules::default()
+        };
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), rules)]);
+        engine.process_order(limit_order("maker", Side::Sell, 5, 100));
+        engine.process_order(limit_order("maker", Side::Sell, 5, 110));
+
+        let market = Order::new_market_order(
+            Qty::shares(8),
+            Side::Buy,
+            String::from("AAPL"),
+            String::from("a"),
+        );
+        match engine.process_order(market).as_slice() {
+            [
+                OutboundMessage::Event(EngineEvent::Accepted(_)),
+                OutboundMessage::Event(EngineEvent::Trade(trade)),
+                OutboundMessage::Event(EngineEvent::Cancelled(cancelled)),
+            ] => {
+                assert_eq!(
+                    (trade.price, trade.quantity),
+                    (Price::cents(100), Qty::shares(5))
+                );
+                assert_eq!(cancelled.reason, "collar");
+                assert_eq!(cancelled.order.quantity, Qty::shares(3));
+            }
+            other => panic!(
+                "expected a trade and the collared remainder, got {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn test_unfilled_market_remainder_reported() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let market = |user: &str, quantity| {
+            Order::new_market_order(
+                Qty::shares(quantity),
+                Side::Buy,
+                String::from("AAPL"),
+                user.to_string(),
+            )
+        };
+        let cancel = |message: &OutboundMessage| match message {
+            OutboundMessage::Event(EngineEvent::Cancelled(cancelled)) => (
+                cancelled.reason.clone(),
+                cancelled.order.user.clone(),
+                cancelled.order.quantity,
+            ),
+            other => panic!("expected a cancel, got {:?}", other),
+        };
+
+        // nothing to trade against
+        let messages = engine.process_order(market("a", 4));
+        assert!(matches!(
+            messages[0],
+            OutboundMessage::Event(EngineEvent::Accepted(_))
+        ));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            cancel(&messages[1]),
+            (
+                String::from("no_liquidity"),
+                String::from("a"),
+                Qty::shares(4)
+            )
+        );
+
+        // a stop the trade sets off finds the asks gone
+        let stop = Order {
+            stop_price: Some(Price::cents(100)),
+            ..market("stop", 5)
+        };
+        assert!(only_accepted(&engine.process_order(stop)));
+        engine.process_order(limit_order("maker", Side::Sell, 1, 100));
+        let messages = engine.process_order(market("t", 1));
+        assert_eq!(filled(&messages), Qty::shares(1));
+        assert_eq!(
+            cancel(messages.last().unwrap()),
+            (
+                String::from("no_liquidity"),
+                String::from("stop"),
+                Qty::shares(5)
+            )
+        );
+    }
+
+    #[test]
+    fn test_market_to_limit_rests_at_first_level() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let mtl = || Order {
+            market_to_limit: true,
+            ..Order::new_market_order(
+                Qty::shares(8),
+                Side::Buy,
+                String::from("AAPL"),
+                String::from("a"),
+            )
+        };
+        assert!(matches!(
+            engine.process_order(mtl()).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "no_liquidity"
+        ));
+
+        engine.process_order(limit_order("maker", Side::Sell, 5, 100));
+        engine.process_order(limit_order("maker", Side::Sell, 5, 110));
+        match engine.process_order(mtl()).as_slice() {
+            [
+                OutboundMessage::Event(EngineEvent::Accepted(accepted)),
+                OutboundMessage::Event(EngineEvent::Trade(trade)),
+            ] => {
+                assert_eq!(accepted.order.price, Some(Price::cents(100)));
+                assert_eq!(
+                    (trade.price, trade.quantity),
+                    (Price::cents(100), Qty::shares(5))
+                );
+            }
+            other => panic!("expected an ack and one trade, got {:?}", other),
+        }
+        let book = &engine.engine_map["AAPL"];
+        assert_eq!(book.best_bid(), Some((Price::cents(100), Qty::shares(3))));
+        assert_eq!(book.best_ask(), Some((Price::cents(110), Qty::shares(5))));
+    }
+
+    #[test]
+    fn test_pegged_order_acked_at_working_price() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("a", Side::Buy, 5, 100));
+        let payload = r#"{"user":"p","side":"Buy","quantity":2,"price":null,"symbol":"AAPL","peg":{"offset":"0.01"}}"#;
+        match engine
+            .process_message(ORDER_INBOUND_CHANNEL, &sealed(payload))
+            .as_slice()
+        {
+            [OutboundMessage::Event(EngineEvent::Accepted(accepted))] => {
+                assert_eq!(accepted.order.price, Some(Price::cents(99)));
+            }
+            other => panic!("expected an ack, got {:?}", other),
+        }
+        engine.process_order(limit_order("b", Side::Buy, 1, 101));
+        let prices: Vec<Price> = engine
+            .orders_for_user("p")
+            .iter()
+            .map(|o| o.price)
+            .collect();
+        assert_eq!(prices, [Price::cents(100)]);
+    }
+
+    #[test]
+    fn test_amend_unpegs_order() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("a", Side::Buy, 5, 100));
+        let payload = r#"{"user":"p","side":"Buy","quantity":2,"price":null,"symbol":"AAPL","peg":{"offset":"0.01"}}"#;
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(payload));
+
+        let amend = r#"{"type":"amend","symbol":"AAPL","order_id":2,"price":"0.98","quantity":2}"#;
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(amend));
+        assert!(
+            matches!(messages.as_slice(), [OutboundMessage::Amended(a)] if a.price == Price::cents(98)),
+            "{:?}",
+            messages
+        );
+        // no longer follows the best bid
+        engine.process_order(limit_order("b", Side::Buy, 1, 101));
+        let prices: Vec<Price> = engine
+            .orders_for_user("p")
+            .iter()
+            .map(|o| o.price)
+            .collect();
+        assert_eq!(prices, [Price::cents(98)]);
+    }
+
+    #[test]
+    fn test_min_quantity_from_json() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("maker", Side::Sell, 4, 100));
+        let order = |min: u64| {
+            format!(
+                r#"{{"user":"a","side":"Buy","quantity":6,"price":"1","symbol":"AAPL","min_quantity":{min}}}"#
+            )
+        };
+        let reason = |messages: Vec<OutboundMessage>| match messages.as_slice() {
+            [OutboundMessage::Event(EngineEvent::Rejected(rejected))] => rejected.reason.clone(),
+            other => panic!("expected a rejection, got {:?}", other),
+        };
+        assert_eq!(
+            reason(engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order(5)))),
+            "min_quantity_unfilled"
+        );
+        assert_eq!(
+            reason(engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order(7)))),
+            "invalid_min_quantity"
+        );
+        let filled_quantity =
+            filled(&engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order(4))));
+        assert_eq!(filled_quantity, Qty::shares(4));
+    }
+
+    #[test]
+    fn test_circuit_breaker_halt_and_resume() {
+        let rules = SymbolRules {
+            circuit_breaker: Some(CircuitBreaker {
+                move_bps: 500,
+                window_millis: 60_000,
+            }),
+            ..SymbolRules::default()
+        };
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), rules)]);
+        engine.process_order(limit_order("maker", Side::Sell, 1, 100));
+        engine.process_order(limit_order("maker", Side::Sell, 5, 110));
+        engine.process_order(limit_order("a", Side::Buy, 1, 100));
+
+        // 100 to 110 is a 10% move against a 5% breaker
+        match engine
+            .process_order(limit_order("a", Side::Buy, 1, 110))
+            .as_slice()
+        {
+            [
+                OutboundMessage::Event(EngineEvent::Accepted(_)),
+                OutboundMessage::Event(EngineEvent::Trade(_)),
+                OutboundMessage::SymbolHalted(halted),
+            ] => assert_eq!(
+                (halted.symbol.as_str(), halted.price),
+                ("AAPL", Price::cents(110))
+            ),
+            other => panic!("expected a trade and a halt, got {:?}", other),
+        }
+        assert!(matches!(
+            engine.process_order(limit_order("a", Side::Buy, 1, 110)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "circuit_breaker"
+        ));
+        assert_eq!(
+            engine.engine_map["AAPL"].best_ask(),
+            Some((Price::cents(110), Qty::shares(4)))
+        );
+
+        assert!(
+            engine
+                .process_admin(r#"{"type":"resume_symbol","symbol":"AAPL","override":true}"#)
+                .is_some()
+        );
+        assert_eq!(
+            filled(&engine.process_order(limit_order("a", Side::Buy, 1, 110))),
+            Qty::shares(1)
+        );
+    }
+
+    #[test]
+    fn test_book_updates_sequenced_per_symbol() {
+        let mut engine = MatchingEngine::new(vec![
+            (String::from("AAPL"), SymbolRules::default()),
+            (String::from("MSFT"), SymbolRules::default()),
+        ]);
+        let level = |side, cents, shares| BookDelta {
+            side,
+            price: Price::cents(cents),
+            quantity: Qty::shares(shares),
+            orders: usize::from(shares > 0),
+        };
+        let updates = |engine: &mut MatchingEngine| -> Vec<(String, u64, Vec<BookDelta>)> {
+            engine
+                .book_updates()
+                .into_iter()
+                .map(|u| (u.symbol, u.seq, u.deltas))
+                .collect()
+        };
+
+        engine.process_order(limit_order("a", Side::Buy, 5, 100));
+        engine.process_order(Order {
+            symbol: String::from("MSFT"),
+            ..limit_order("b", Side::Sell, 3, 200)
+        });
+        assert_eq!(
+            updates(&mut engine),
+            vec![
+                (String::from("AAPL"), 1, vec![level(Side::Buy, 100, 5)]),
+                (String::from("MSFT"), 1, vec![level(Side::Sell, 200, 3)]),
+            ]
+        );
+
+        // the taker fills in full without resting, so only the level it hit
+        // changes
+        engine.process_order(limit_order("c", Side::Sell, 2, 100));
+        assert_eq!(
+            updates(&mut engine),
+            vec![(String::from("AAPL"), 2, vec![level(Side::Buy, 100, 3)])]
+        );
+
+        // a rejected order changes nothing, so no seq is used up
+        engine.process_order(limit_order("d", Side::Buy, 0, 100));
+        assert!(engine.book_updates().is_empty());
+
+        let cancel = r#"{"type":"cancel","symbol":"AAPL","order_id":1}"#;
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(cancel));
+        let update = engine.book_updates().pop().unwrap();
+        assert_eq!(update.seq, 3);
+        assert_eq!(
+            update.checksum,
+            engine.engine_map["AAPL"].checksum(BOOK_UPDATE_DEPTH)
+        );
+        let json = serde_json::to_value(&update).unwrap();
+        assert_eq!(json["type"], "book_delta");
+        assert_eq!(
+            json["deltas"],
+            serde_json::json!([{ "side": "Buy", "price": "1", "quantity": "0", "orders": 0 }])
+        );
+    }
+
+    #[test]
+    fn test_auction_uncross_publishes_trades() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let uncross = r#"{"type":"uncross","symbol":"AAPL"}"#;
+        assert!(engine.process_admin(uncross).is_none());
+        assert!(
+            engine
+                .process_message(
+                    ENGINE_ADMIN_CHANNEL,
+                    &sealed(r#"{"type":"start_auction","symbol":"AAPL"}"#)
+                )
+                .is_empty()
+        );
+
+        assert!(only_accepted(&engine.process_order(limit_order(
+            "maker",
+            Side::Sell,
+            5,
+            100
+        ))));
+        assert!(only_accepted(&engine.process_order(limit_order(
+            "a",
+            Side::Buy,
+            3,
+            101
+        ))));
+        let market = Order::new_market_order(
+            Qty::shares(1),
+            Side::Buy,
+            String::from("AAPL"),
+            String::from("a"),
+        );
+        assert!(matches!(
+            engine.process_order(market).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "auction_open"
+        ));
+
+        match engine
+            .process_message(ENGINE_ADMIN_CHANNEL, &sealed(uncross))
+            .as_slice()
+        {
+            [OutboundMessage::Event(EngineEvent::Trade(trade))] => {
+                assert_eq!(
+                    (trade.price, trade.quantity),
+                    (Price::cents(100), Qty::shares(3))
+                );
+                assert_eq!(
+                    (trade.buyer.as_str(), trade.seller.as_str()),
+                    ("a", "maker")
+                );
+            }
+            other => panic!("expected one auction trade, got {:?}", other),
+        }
+        assert_eq!(engine.stats.trades, 1);
+        assert_eq!(
+            filled(&engine.process_order(limit_order("a", Side::Buy, 2, 100))),
+            Qty::shares(2)
+        );
+    }
+
+    #[test]
+    fn test_open_orders_across_symbols() {
+        let mut engine = MatchingEngine::new(vec![
+            (String::from("MSFT"), SymbolRules::default()),
+            (String::from("AAPL"), SymbolRules::default()),
+        ]);
+        engine.process_order(Order::new_limit_order(
+            Qty::shares(2),
+            Some(Price::cents(300)),
+            Side::Sell,
+            String::from("MSFT"),
+            String::from("a"),
+        ));
+        engine.process_order(limit_order("a", Side::Buy, 5, 99));
+        engine.process_order(limit_order("b", Side::Buy, 5, 99));
+        engine.process_order(limit_order("a", Side::Sell, 3, 101));
+
+        let query = r#"{"type":"open_orders","user":"a"}"#;
+        match engine
+            .process_message(ORDER_INBOUND_CHANNEL, &sealed(query))
+            .as_slice()
+        {
+            [OutboundMessage::OpenOrders(open)] => {
+                let orders: Vec<(&str, Price, Qty)> = open
+                    .orders
+                    .iter()
+                    .map(|o| (o.symbol.as_str(), o.price, o.remaining_quantity))
+                    .collect();
+                assert_eq!(
+                    orders,
+                    vec![
+                        ("AAPL", Price::cents(99), Qty::shares(5)),
+                        ("AAPL", Price::cents(101), Qty::shares(3)),
+                        ("MSFT", Price::cents(300), Qty::shares(2))
+                    ]
+                );
+            }
+            other => panic!("expected the user's open orders, got {:?}", other),
+        }
+        assert_eq!(engine.metrics.by_type[&MessageType::Query].count, 1);
+    }
+
+    #[test]
+    fn test_book_snapshot_streamed_in_chunks() {
+        let mut engine = MatchingEngine::new(default_symbols());
+        for price in 1..=250 {
+            engine.process_order(limit_order("a", Side::Buy, 1, price));
+        }
+        engine.book_updates();
+
+        let request = r#"{"type":"book_snapshot","symbol":"AAPL","request_id":7}"#;
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(request));
+        let (end, chunks) = messages.split_last().unwrap();
+        let mut orders = vec![];
+        for (i, message) in chunks.iter().enumerate() {
+            let OutboundMessage::SnapshotChunk(chunk) = message else {
+                panic!("expected a chunk, got {:?}", message);
+            };
+            assert_eq!((chunk.request_id, chunk.index), (7, i));
+            orders.extend(chunk.orders.iter().cloned());
+        }
+        assert_eq!(chunks.len(), 250_usize.div_ceil(SNAPSHOT_CHUNK_ORDERS));
+        assert_eq!(orders.len(), 250);
+        assert_eq!(orders[249].price, Price::cents(250));
+        match end {
+            OutboundMessage::SnapshotEnd(end) => {
+                assert!(end.found);
+                assert_eq!(end.chunks, chunks.len());
+                assert_eq!(end.checksum, orderbook::orders_checksum(&orders));
+                assert_eq!(end.book_update_seq, engine.market_data_sequence["AAPL"]);
+                assert_eq!(end.last_order_id, 250);
+            }
+            other => panic!("expected the end of the snapshot, got {:?}", other),
+        }
+
+        let unknown = r#"{"type":"book_snapshot","symbol":"NOPE","request_id":8}"#;
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(unknown));
+        assert!(matches!(
+            messages.as_slice(),
+            [OutboundMessage::SnapshotEnd(end)] if !end.found && end.chunks == 0
+        ));
+    }
+
+    #[test]
+    fn test_mass_cancel_across_symbols() {
+        let mut engine = MatchingEngine::new(vec![
+            (String::from("AAPL"), SymbolRules::default()),
+            (String::from("MSFT"), SymbolRules::default()),
+        ]);
+        engine.process_order(limit_order("a", Side::Buy, 5, 99));
+        engine.process_order(limit_order("b", Side::Buy, 5, 99));
+        engine.process_order(limit_order("a", Side::Sell, 3, 101));
+        engine.process_order(Order::new_limit_order(
+            Qty::shares(2),
+            Some(Price::cents(300)),
+            Side::Sell,
+            String::from("MSFT"),
+            String::from("a"),
+        ));
+
+        let messages = engine.process_message(
+            ORDER_INBOUND_CHANNEL,
+            &sealed(r#"{"type":"mass_cancel","user":"a"}"#),
+        );
+        let cancelled: Vec<(&str, &str, Qty)> = messages
+            .iter()
+            .map(|m| match m {
+                OutboundMessage::Event(EngineEvent::Cancelled(c)) => {
+                    (c.reason.as_str(), c.order.symbol.as_str(), c.order.quantity)
+                }
+                other => panic!("expected only cancellations, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            cancelled,
+            vec![
+                ("mass_cancel", "AAPL", Qty::shares(5)),
+                ("mass_cancel", "AAPL", Qty::shares(3)),
+                ("mass_cancel", "MSFT", Qty::shares(2)),
+            ]
+        );
+        assert!(engine.orders_for_user("a").is_empty());
+        assert_eq!(engine.orders_for_user("b").len(), 1);
+    }
+
+    #[test]
+    fn test_clear_symbol() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("a", Side::Buy, 5, 99));
+        engine.process_order(limit_order("b", Side::Sell, 3, 101));
+
+        let messages = engine.process_message(
+            ENGINE_ADMIN_CHANNEL,
+            &sealed(r#"{"type":"clear_symbol","symbol":"AAPL"}"#),
+        );
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|m| matches!(
+            m,
+            OutboundMessage::Event(EngineEvent::Cancelled(c))
+                if c.reason == "symbol_cleared" && c.order.state == OrderState::Close
+        )));
+        assert_eq!(engine.engine_map["AAPL"].len(), 0);
+        assert!(matches!(
+            engine.process_order(limit_order("a", Side::Buy, 5, 99)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "symbol_halted"
+        ));
+
+        engine.process_admin(r#"{"type":"resume_symbol","symbol":"AAPL","override":true}"#);
+        assert!(only_accepted(&engine.process_order(limit_order(
+            "a",
+            Side::Buy,
+            5,
+            99
+        ))));
+    }
+
+    #[test]
+    fn test_priceless_limit_order_is_rejected() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.process_order(limit_order("maker", Side::Sell, 5, 101));
+
+        // post-only with no price; the engine carries on with the next order
+        let payload = r#"{"user":"a","side":"Buy","quantity":5,"symbol":"AAPL","state":"Open","post_only":true}"#;
+        assert!(matches!(
+            engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(payload)).as_slice(),
+            [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "missing_price"
+        ));
+        assert_eq!(
+            engine.engine_map["AAPL"].best_ask(),
+            Some((Price::cents(101), Qty::shares(5)))
+        );
+        assert_eq!(
+            filled(&engine.process_order(limit_order("a", Side::Buy, 5, 101))),
+            Qty::shares(5)
+        );
+    }
+
+    #[test]
+    fn test_message_metrics_per_type() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let order = |user: &str, side: Side, qty: u64| {
+            serde_json::to_string(&limit_order(user, side, qty, 100)).unwrap()
+        };
+
+        engine.process_message(
+            ENGINE_ADMIN_CHANNEL,
+            &sealed(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":5}"#),
+        );
+        engine.process_message(
+            ENGINE_ADMIN_CHANNEL,
+            &sealed(r#"{"type":"resume_symbol","symbol":"AAPL"}"#),
+        );
+        engine.process_message(
+            ORDER_INBOUND_CHANNEL,
+            &sealed(&order("maker", Side::Sell, 10)),
+        );
+        let filled_order =
+            engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order("a", Side::Buy, 5)));
+        assert_eq!(filled(&filled_order), Qty::shares(5));
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order("a", Side::Buy, 1)));
+        engine.process_message(ORDER_INBOUND_CHANNEL, "not json");
+        engine.process_message(ORDER_INBOUND_CHANNEL, &"x".repeat(1 << 20));
+        engine.process_message(ENGINE_ADMIN_CHANNEL, &"[".repeat(100_000));
+
+        let stats = |kind| &engine.metrics.by_type[&kind];
+        assert_eq!(stats(MessageType::NewOrder).count, 3);
+        assert_eq!(stats(MessageType::NewOrder).rejected, 1);
+        assert_eq!(stats(MessageType::Admin).count, 2);
+        assert_eq!(stats(MessageType::Admin).rejected, 1);
+        assert_eq!(stats(MessageType::Invalid).count, 1);
+        assert_eq!(stats(MessageType::Invalid).rejection_rate(), 1.0);
+        assert_eq!(stats(MessageType::Oversized).count, 2);
+        assert!(
+            stats(MessageType::NewOrder)
+                .latency
+                .percentile_micros(50.0)
+                .is_some()
+        );
+        assert_eq!(engine.sequence, 8);
+    }
+
+    #[test]
+    fn test_messages_travel_in_envelopes() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.clock = || 1_760_486_400_000;
+        let order = serde_json::to_value(limit_order("maker", Side::Sell, 10, 100)).unwrap();
+
+        // a bare order from before envelopes, and one in an envelope too old
+        let bare = order.to_string();
+        let too_old = serde_json::json!({ "v": 0, "type": "order", "ts": 0, "payload": order });
+        for raw in [bare, too_old.to_string()] {
+            assert!(
+                engine
+                    .process_message(ORDER_INBOUND_CHANNEL, &raw)
+                    .is_empty()
+            );
+        }
+        assert_eq!(engine.metrics.by_type[&MessageType::Invalid].count, 2);
+        assert_eq!(engine.engine_map["AAPL"].best_ask(), None);
+
+        // a newer envelope is read for what this engine knows of it
+        let newer = serde_json::json!({
+            "v": 2, "type": "order", "ts": 0, "route": "fast", "payload": order,
+        });
+        let messages = engine.process_message(ORDER_INBOUND_CHANNEL, &newer.to_string());
+        assert!(only_accepted(&messages));
+
+        let sent: serde_json::Value = serde_json::from_str(&engine.seal(&messages[0])).unwrap();
+        assert_eq!(sent["v"], envelope::ENVELOPE_VERSION);
+        assert_eq!(sent["type"], "order_accepted");
+        assert_eq!(sent["ts"], 1_760_486_400_000i64);
+        assert_eq!(sent["payload"]["type"], "order_accepted");
+        assert_eq!(sent["payload"]["order"]["user"], "maker");
+    }
+
+    #[test]
+    fn test_capacity_report_closes_day_before_first_order() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        let dir = logging::test_support::scratch_dir("capacity-engine");
+        engine.snapshot_dir = dir.clone();
+        // 2025-10-15T23:59:00Z, then a minute later
+        engine.clock = || 1_760_572_740_000;
+        engine.capacity = CapacityTracker::new((engine.clock)());
+        let order = |user: &str, side: Side| {
+            serde_json::to_string(&limit_order(user, side, 5, 100)).unwrap()
+        };
+
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order("maker", Side::Sell)));
+        engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order("a", Side::Buy)));
+        let checkpoint = engine.checkpoint_capacity();
+        assert!(matches!(
+            checkpoint.as_slice(),
+            [OutboundMessage::CapacityReport(r)] if !r.is_final && r.symbols["AAPL"].volume == Qty::shares(5)
+        ));
+
+        engine.clock = || 1_760_572_800_000;
+        let messages =
+            engine.process_message(ORDER_INBOUND_CHANNEL, &sealed(&order("a", Side::Buy)));
+        match messages.as_slice() {
+            [
+                OutboundMessage::CapacityReport(report),
+                OutboundMessage::Event(EngineEvent::Accepted(_)),
+            ] => {
+                assert!(report.is_final);
+                assert_eq!(report.date, "2025-10-15");
+                assert_eq!(report.symbols["AAPL"].orders, 2);
+                assert_eq!(report.symbols["AAPL"].trades, 1);
+            }
+            other => panic!("expected the closing report, got {:?}", other),
+        }
+        let today = engine.capacity.report(false);
+        assert_eq!(today.date, "2025-10-16");
+        assert_eq!(today.symbols["AAPL"].orders, 1);
+        assert_eq!(today.symbols["AAPL"].peak_resting_orders, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_integrity_halt_and_override_resume() {
+        let dir = logging::test_support::scratch_dir("halt");
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.snapshot_dir = dir.clone();
+
+        engine.process_order(limit_order("maker", Side::Sell, 10, 101));
+        // corrupt the book behind the matcher's back so it is crossed; the
+        // maps are private, so go round through its serialized form
+        let mut book = serde_json::to_value(&engine.engine_map["AAPL"]).unwrap();
+        book["bid_map"]["1.05"] = serde_json::json!([limit_order("ghost", Side::Buy, 5, 105)]);
+        engine
+            .engine_map
+            .insert(String::from("AAPL"), serde_json::from_value(book).unwrap());
+
+        let messages = engine.process_order(limit_order("a", Side::Buy, 1, 90));
+        let halt = match messages.as_slice() {
+            [
+                OutboundMessage::Event(EngineEvent::Accepted(_)),
+                OutboundMessage::IntegrityHalt(halt),
+            ] => halt,
+            other => panic!("expected an integrity halt, got {:?}", other),
+        };
+        assert_eq!(halt.symbol, "AAPL");
+        assert_eq!(halt.seq, 2);
+        assert_eq!(halt.reason, "crossed book: best bid 1.05 >= best ask 1.01");
+        assert_eq!(halt.last_order.user, "a");
+        assert_eq!(halt.snapshot_hash.len(), 16);
+        let snapshot: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join("halt-AAPL-2.json")).unwrap()).unwrap();
+        assert_eq!(snapshot["version"], 2);
+        assert_eq!(snapshot["bid_map"]["1.05"][0]["user"], "ghost");
+
+        let rejected = engine.process_order(limit_order("b", Side::Sell, 1, 200));
+        assert!(
+            matches!(rejected.as_slice(), [OutboundMessage::Event(EngineEvent::Rejected(r))] if r.reason == "symbol_halted")
+        );
+
+        engine.process_admin(r#"{"type":"resume_symbol","symbol":"AAPL"}"#);
+        assert!(engine.halted.contains("AAPL"));
+        engine.process_admin(r#"{"type":"resume_symbol","symbol":"AAPL","override":true}"#);
+        assert!(!engine.halted.contains("AAPL"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_event_log_for_scripted_session() {
+        let dir = logging::test_support::scratch_dir("session");
+        let config = logging::LogConfig {
+            dir: dir.clone(),
+            max_bytes: 1 << 20,
+            console_level: tracing::level_filters::LevelFilter::OFF,
+        };
+
+        let subscriber = logging::subscriber(&config).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            let mut engine =
+                MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+            engine
+                .process_admin(r#"{"type":"position_limit","user":"a","symbol":"AAPL","limit":5}"#);
+            engine.process_order(limit_order("maker", Side::Sell, 10, 100));
+            engine.process_order(limit_order("a", Side::Buy, 5, 100));
+            engine.process_order(limit_order("a", Side::Buy, 1, 100));
+            engine.process_message(ENGINE_ADMIN_CHANNEL, &sealed("{}"));
+            engine.log_stats();
+        });
+
+        let lines = logging::test_support::read_lines(&dir);
+        let events: Vec<&str> = lines.iter().map(|l| l["event"].as_str().unwrap()).collect();
+        assert_eq!(
+            events,
+            vec![
+                "position_limit_updated",
+                "order_accepted",
+                "order_accepted",
+                "trade",
+                "order_rejected",
+                "parse_error",
+                "stats",
+                "message_stats",
+            ]
+        );
+
+        let seqs: Vec<u64> = lines.iter().map(|l| l["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, vec![1, 2, 3, 3, 4, 5, 5, 5]);
+
+        let trade = &lines[3];
+        assert_eq!(trade["buyer"], "a");
+        assert_eq!(trade["quantity"], "5");
+        assert_eq!(lines[4]["reason"], "position_limit");
+        assert_eq!(lines[6]["volume"], "5");
+        assert_eq!(lines[7]["kind"], "admin");
+        assert_eq!(lines[7]["rejected"], 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_books_survive_restart() {
+        let dir = logging::test_support::scratch_dir("books");
+        let symbols = || {
+            vec![
+                (String::from("AAPL"), SymbolRules::default()),
+                (String::from("MSFT"), SymbolRules::default()),
+            ]
+        };
+        let mut engine = MatchingEngine::new(symbols());
+        engine.snapshot_dir = dir.clone();
+        engine.process_order(limit_order("a", Side::Sell, 5, 101));
+        engine.process_order(limit_order("b", Side::Sell, 5, 102));
+        engine.process_order(limit_order("c", Side::Buy, 2, 101));
+        assert_eq!(engine.save_books(), 2);
+
+        let capped = SymbolRules {
+            max_quantity: Some(Qty::shares(100)),
+            ..SymbolRules::default()
+        };
+        let mut restarted = MatchingEngine::new(vec![
+            (String::from("AAPL"), capped),
+            (String::from("MSFT"), SymbolRules::default()),
+            (String::from("TSLA"), SymbolRules::default()),
+        ]);
+        restarted.snapshot_dir = dir.clone();
+        assert_eq!(restarted.restore_books(), 2);
+        assert_eq!(restarted.engine_map["AAPL"].rules(), &capped);
+
+        let fills = |messages: Vec<OutboundMessage>| -> Vec<(String, Price, Qty, u64)> {
+            messages
+                .into_iter()
+                .filter_map(|m| match m {
+                    OutboundMessage::Event(EngineEvent::Trade(t)) => {
+                        Some((t.seller, t.price, t.quantity, t.maker_order_id))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        let sweep = || limit_order("d", Side::Buy, 7, 102);
+        let original = fills(engine.process_order(sweep()));
+        assert_eq!(original, fills(restarted.process_order(sweep())));
+        assert_eq!(
+            original,
+            vec![
+                (String::from("a"), Price::cents(101), Qty::shares(3), 1),
+                (String::from("b"), Price::cents(102), Qty::shares(4), 2),
+            ]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejection_serializes_with_type_tag() {
+        let message: OutboundMessage =
+            EngineEvent::rejected("position_limit", limit_order("a", Side::Buy, 1, 100)).into();
+        let json: serde_json::Value = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["type"], "rejected");
+        assert_eq!(json["reason"], "position_limit");
+        assert_eq!(json["order"]["user"], "a");
+    }
+
+    #[test]
+    fn test_order_lifecycle_published_as_tagged_events() {
+        let mut engine = MatchingEngine::new(vec![(String::from("AAPL"), SymbolRules::default())]);
+        engine.clock = || 1_000;
+        let gtd = Order {
+            expires_at: Some(2_000),
+            ..limit_order("maker", Side::Sell, 5, 100)
+        };
+        engine.process_order(gtd);
+        let types = |messages: &[OutboundMessage]| -> Vec<String> {
+            messages
+                .iter()
+                .map(|m| serde_json::to_value(m).unwrap()["type"].to_string())
+                .collect()
+        };
+
+        let messages = engine.process_order(limit_order("a", Side::Buy, 2, 100));
+        assert_eq!(types(&messages), [r#""order_accepted""#, r#""trade""#]);
+        let trade = serde_json::to_string(&messages[1]).unwrap();
+        match serde_json::from_str(&trade).unwrap() {
+            EngineEvent::Trade(trade) => assert_eq!(trade.quantity, Qty::shares(2)),
+            other => panic!("expected a trade, got {:?}", other),
+        }
+
+        engine.clock = || 2_000;
+        let messages = engine.expire_orders();
+        assert_eq!(types(&messages), [r#""order_expired""#]);
+        let json = serde_json::to_value(&messages[0]).unwrap();
+        assert_eq!(json["order"]["quantity"], "3");
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{Layer, Registry, fmt, layer::SubscriberExt};
+
+const LOG_DIR_ENV: &str = "ENGINE_LOG_DIR";
+const LOG_MAX_BYTES_ENV: &str = "ENGINE_LOG_MAX_BYTES";
+const LOG_LEVEL_ENV: &str = "ENGINE_LOG_LEVEL";
+
+const DEFAULT_LOG_DIR: &str = "logs";
+const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const LOG_FILE_PREFIX: &str = "engine";
+pub(crate) const MILLIS_PER_DAY: i64 = 86_400_000;
+
+pub struct LogConfig {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+    pub console_level: LevelFilter,
+}
+
+impl LogConfig {
+    pub fn from_env() -> Self {
+        let dir = std::env::var(LOG_DIR_ENV).unwrap_or_else(|_| DEFAULT_LOG_DIR.to_string());
+        let max_bytes = std::env::var(LOG_MAX_BYTES_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let console_level = std::env::var(LOG_LEVEL_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(LevelFilter::INFO);
+        Self {
+            dir: PathBuf::from(dir),
+            max_bytes,
+            console_level,
+        }
+    }
+}
+
+// The JSON-lines file gets every engine event regardless of how chatty the
+// console is configured to be.
+pub fn subscriber(config: &LogConfig) -> io::Result<impl tracing::Subscriber + Send + Sync> {
+    let file = RotatingFile::new(&config.dir, LOG_FILE_PREFIX, config.max_bytes, now_millis)?;
+    let file_layer = fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(false)
+        .with_writer(Mutex::new(file))
+        .with_filter(LevelFilter::INFO);
+    let console_layer = fmt::layer()
+        .with_target(false)
+        .with_filter(config.console_level);
+    Ok(Registry::default().with(file_layer).with(console_layer))
+}
+
+pub fn init(config: &LogConfig) -> io::Result<()> {
+    tracing::subscriber::set_global_default(subscriber(config)?).map_err(io::Error::other)
+}
+
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// Appends to `<prefix>.<YYYY-MM-DD>.<n>.jsonl`, starting a new file when the
+// UTC day changes or the current one would grow past `max_bytes`.
+pub struct RotatingFile {
+    dir: PathBuf,
+    prefix: String,
+    max_bytes: u64,
+    clock: fn() -> i64,
+    day: i64,
+    index: u32,
+    written: u64,
+    file: File,
+}
+
+impl RotatingFile {
+    pub fn new(dir: &Path, prefix: &str, max_bytes: u64, clock: fn() -> i64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let day = clock().div_euclid(MILLIS_PER_DAY);
+        let (index, written, file) = Self::open_latest(dir, prefix, day)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            prefix: prefix.to_string(),
+            max_bytes,
+            clock,
+            day,
+            index,
+            written,
+            file,
+        })
+    }
+
+    fn path(dir: &Path, prefix: &str, day: i64, index: u32) -> PathBuf {
+        dir.join(format!("{}.{}.{}.jsonl", prefix, format_day(day), index))
+    }
+
+    // Resume the newest file for the day so restarts keep appending.
+    fn open_latest(dir: &Path, prefix: &str, day: i64) -> io::Result<(u32, u64, File)> {
+        let mut index = 0;
+        while Self::path(dir, prefix, day, index + 1).exists() {
+            index += 1;
+        }
+        let path = Self::path(dir, prefix, day, index);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok((index, written, file))
+    }
+
+    fn rotate_if_needed(&mut self, incoming: u64) -> io::Result<()> {
+        let day = (self.clock)().div_euclid(MILLIS_PER_DAY);
+        if day != self.day {
+            self.day = day;
+            (self.index, self.written, self.file) =
+                Self::open_latest(&self.dir, &self.prefix, day)?;
+        }
+        if self.written > 0 && self.written + incoming > self.max_bytes {
+            self.index += 1;
+            let path = Self::path(&self.dir, &self.prefix, self.day, self.index);
+            self.file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.written = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rotate_if_needed(buf.len() as u64)?;
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Formats days since the unix epoch as YYYY-MM-DD (UTC).
+pub(crate) fn format_day(day: i64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = day + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use std::path::PathBuf;
+
+    // A fresh, empty directory under the system temp dir.
+    pub fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("engine-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    pub fn read_lines(dir: &PathBuf) -> Vec<serde_json::Value> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .flat_map(|p| {
+                std::fs::read_to_string(p)
+                    .unwrap()
+                    .lines()
+                    .map(|l| serde_json::from_str(l).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::test_support::*;
+    use super::*;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    const OCT_15_2025: i64 = 1_760_486_400_000;
+    static NOW: AtomicI64 = AtomicI64::new(OCT_15_2025);
+
+    fn fake_now() -> i64 {
+        NOW.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_rotates_by_size_and_day() {
+        let dir = scratch_dir("rotation");
+        let mut file = RotatingFile::new(&dir, "engine", 20, fake_now).unwrap();
+
+        file.write_all(b"0123456789\n").unwrap();
+        file.write_all(b"0123456789\n").unwrap(); // would exceed 20 bytes
+        file.write_all(b"short\n").unwrap();
+
+        NOW.store(OCT_15_2025 + MILLIS_PER_DAY, Ordering::SeqCst);
+        file.write_all(b"next day\n").unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "engine.2025-10-15.0.jsonl",
+                "engine.2025-10-15.1.jsonl",
+                "engine.2025-10-16.0.jsonl",
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("engine.2025-10-15.1.jsonl")).unwrap(),
+            "0123456789\nshort\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
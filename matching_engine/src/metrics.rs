@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+const LATENCY_ALERT_ENV: &str = "ENGINE_LATENCY_ALERT_MICROS";
+const DEFAULT_LATENCY_ALERT_MICROS: u64 = 5_000;
+
+// Upper bounds of the latency buckets in microseconds; anything slower lands
+// in a final overflow bucket.
+const BUCKET_BOUNDS_MICROS: [u64; 8] = [10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000];
+
+pub fn latency_alert_from_env() -> Duration {
+    let micros = std::env::var(LATENCY_ALERT_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_LATENCY_ALERT_MICROS);
+    Duration::from_micros(micros)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageType {
+    NewOrder,
+    Cancel,
+    Amend,
+    // read-only requests such as a user's open orders
+    Query,
+    Admin,
+    // payloads that could not be parsed at all
+    Invalid,
+    // turned away by the size/depth guard before parsing
+    Oversized,
+}
+
+impl MessageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageType::NewOrder => "new_order",
+            MessageType::Cancel => "cancel",
+            MessageType::Amend => "amend",
+            MessageType::Query => "query",
+            MessageType::Admin => "admin",
+            MessageType::Invalid => "invalid",
+            MessageType::Oversized => "oversized",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKET_BOUNDS_MICROS.len() + 1],
+    count: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        let bucket = BUCKET_BOUNDS_MICROS
+            .iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MICROS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+    }
+
+    // Upper bound of the bucket holding the `percentile`th sample, or None
+    // when empty or when it falls in the overflow bucket.
+    pub fn percentile_micros(&self, percentile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKET_BOUNDS_MICROS.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TypeStats {
+    pub count: u64,
+    pub rejected: u64,
+    pub latency: LatencyHistogram,
+}
+
+impl TypeStats {
+    pub fn rejection_rate(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.rejected as f64 / self.count as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MessageMetrics {
+    pub by_type: BTreeMap<MessageType, TypeStats>,
+}
+
+impl MessageMetrics {
+    pub fn record(&mut self, kind: MessageType, latency: Duration, rejected: bool) {
+        let stats = self.by_type.entry(kind).or_default();
+        stats.count += 1;
+        stats.rejected += rejected as u64;
+        stats.latency.record(latency);
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile_micros(99.0), None);
+
+        for _ in 0..98 {
+            histogram.record(Duration::from_micros(40));
+        }
+        histogram.record(Duration::from_micros(700));
+        histogram.record(Duration::from_millis(2));
+
+        assert_eq!(histogram.percentile_micros(50.0), Some(50));
+        assert_eq!(histogram.percentile_micros(99.0), Some(1_000));
+        assert_eq!(histogram.percentile_micros(100.0), Some(5_000));
+
+        histogram.record(Duration::from_secs(1));
+        assert_eq!(histogram.percentile_micros(100.0), None);
+    }
+}
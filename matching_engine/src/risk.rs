@@ -0,0 +1,171 @@
+use orderbook::{Qty, Side, TradeEvent};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct PositionLimit {
+    pub user: String,
+    pub symbol: String,
+    pub limit: Qty,
+}
+
+// Net filled position per user per symbol, as seen from the engine's own
+// trades, checked against the caps pushed by the API. Positions are counted
+// in Qty units so fractional fills net out exactly.
+#[derive(Debug, Default)]
+pub struct PositionLimits {
+    limits: HashMap<(String, String), Qty>,
+    positions: HashMap<(String, String), i64>,
+}
+
+impl PositionLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_limit(&mut self, limit: PositionLimit) {
+        self.limits.insert((limit.user, limit.symbol), limit.limit);
+    }
+
+    // In Qty units; negative when short.
+    pub fn position(&self, user: &str, symbol: &str) -> i64 {
+        self.positions
+            .get(&(user.to_string(), symbol.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // How much of an incoming order may go through without the user's net
+    // position moving beyond the cap in either direction. Users without a
+    // configured cap are unrestricted.
+    pub fn allowed_quantity(&self, user: &str, symbol: &str, side: &Side, quantity: Qty) -> Qty {
+        let Some(&limit) = self.limits.get(&(user.to_string(), symbol.to_string())) else {
+            return quantity;
+        };
+        let limit = limit.units() as i128;
+        let position = self.position(user, symbol) as i128;
+        let headroom = match side {
+            Side::Buy => limit - position,
+            Side::Sell => limit + position,
+        };
+        quantity.min(Qty::from_units(headroom.max(0) as u64))
+    }
+
+    pub fn apply_trade(&mut self, event: &TradeEvent) {
+        let quantity = event.quantity.units() as i64;
+        *self
+            .positions
+            .entry((event.buyer.clone(), event.symbol.clone()))
+            .or_insert(0) += quantity;
+        *self
+            .positions
+            .entry((event.seller.clone(), event.symbol.clone()))
+            .or_insert(0) -= quantity;
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orderbook::{Price, qty::QTY_SCALE};
+
+    fn trade(buyer: &str, seller: &str, shares: u64) -> TradeEvent {
+        TradeEvent {
+            buyer: buyer.to_string(),
+            seller: seller.to_string(),
+            symbol: String::from("AAPL"),
+            quantity: Qty::shares(shares),
+            price: Price::cents(100),
+            trade_id: 1,
+            taker_side: Side::Buy,
+            timestamp: 0,
+            maker_received_at: 0,
+            maker_order_id: 1,
+            maker_remaining: Qty::shares(0),
+            taker_order_id: 0,
+            buyer_tags: Vec::new(),
+            seller_tags: Vec::new(),
+        }
+    }
+
+    fn cap(user: &str, limit: u64) -> PositionLimit {
+        PositionLimit {
+            user: user.to_string(),
+            symbol: String::from("AAPL"),
+            limit: Qty::shares(limit),
+        }
+    }
+
+    #[test]
+    fn test_uncapped_user_is_unrestricted() {
+        let limits = PositionLimits::new();
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Buy, Qty::shares(1000)),
+            Qty::shares(1000)
+        );
+    }
+
+    #[test]
+    fn test_headroom_tracks_fills_on_both_sides() {
+        let mut limits = PositionLimits::new();
+        limits.set_limit(cap("a", 10));
+
+        limits.apply_trade(&trade("a", "b", 6));
+        assert_eq!(limits.position("a", "AAPL"), 6 * QTY_SCALE as i64);
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Buy, Qty::shares(6)),
+            Qty::shares(4)
+        );
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Sell, Qty::shares(20)),
+            Qty::shares(16)
+        );
+
+        limits.apply_trade(&trade("b", "a", 16));
+        assert_eq!(limits.position("a", "AAPL"), -10 * QTY_SCALE as i64);
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Sell, Qty::shares(1)),
+            Qty::ZERO
+        );
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Buy, Qty::shares(25)),
+            Qty::shares(20)
+        );
+    }
+
+    #[test]
+    fn test_lowered_cap_blocks_further_exposure() {
+        let mut limits = PositionLimits::new();
+        limits.apply_trade(&trade("a", "b", 8));
+        limits.set_limit(cap("a", 5));
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Buy, Qty::shares(1)),
+            Qty::ZERO
+        );
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Sell, Qty::shares(20)),
+            Qty::shares(13)
+        );
+    }
+
+    #[test]
+    fn test_fractional_fills_net_exactly() {
+        let mut limits = PositionLimits::new();
+        limits.set_limit(cap("a", 1));
+        let mut half = trade("a", "b", 0);
+        half.quantity = Qty::from_units(QTY_SCALE / 2);
+
+        limits.apply_trade(&half);
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Buy, Qty::shares(1)),
+            half.quantity
+        );
+        limits.apply_trade(&half);
+        assert_eq!(limits.position("a", "AAPL"), QTY_SCALE as i64);
+        assert_eq!(
+            limits.allowed_quantity("a", "AAPL", &Side::Buy, half.quantity),
+            Qty::ZERO
+        );
+    }
+}
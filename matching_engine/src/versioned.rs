@@ -0,0 +1,47 @@
+use serde::Serialize;
+use std::io;
+
+// Every file the engine writes carries a top-level "version". Files from
+// before versioning have none and count as version 0.
+#[derive(Serialize)]
+pub struct Versioned<'a, T> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub data: &'a T,
+}
+
+pub fn version_of(document: &serde_json::Value) -> u32 {
+    document
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .map_or(0, |v| v as u32)
+}
+
+// A step upgrading a document from one version to the next.
+pub type Migration = fn(serde_json::Value) -> serde_json::Value;
+
+// Runs `document` through `migrations[v..]`, where `migrations[v]` upgrades
+// version v to v + 1, so it comes out at `migrations.len()`. Documents
+// written by a newer engine are refused rather than half-parsed.
+pub fn upgrade(
+    kind: &str,
+    mut document: serde_json::Value,
+    migrations: &[Migration],
+) -> io::Result<serde_json::Value> {
+    let version = version_of(&document) as usize;
+    if version > migrations.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} file is version {}, this engine reads up to version {}",
+                kind,
+                version,
+                migrations.len()
+            ),
+        ));
+    }
+    for migrate in &migrations[version..] {
+        document = migrate(document);
+    }
+    Ok(document)
+}
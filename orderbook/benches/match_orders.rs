@@ -0,0 +1,49 @@
+// Matching against a deep book: 10k ask levels of one order each, hit by a
+// buy that takes the best level, one that sweeps a hundred levels and one
+// that sweeps them all. Run with `cargo bench -p orderbook`.
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use orderbook::{Order, OrderBook, Price, Qty, Side};
+use std::hint::black_box;
+
+const LEVELS: i64 = 10_000;
+
+fn order(side: Side, shares: u64, price: Price, user: &str) -> Order {
+    Order::new_limit_order(
+        Qty::shares(shares),
+        Some(price),
+        side,
+        "AAPL".to_string(),
+        user.to_string(),
+    )
+}
+
+fn deep_book() -> OrderBook {
+    let mut book = OrderBook::new(String::from("AAPL"));
+    for level in 0..LEVELS {
+        book.add_limit_order(order(Side::Sell, 1, Price::cents(10_000 + level), "maker"))
+            .unwrap();
+    }
+    book
+}
+
+fn match_orders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_orders_10k_levels");
+    for (name, levels) in [("best_level", 1), ("sweep_100", 100), ("sweep_all", LEVELS)] {
+        let limit = Price::cents(10_000 + levels - 1);
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                deep_book,
+                |mut book| {
+                    let taker = order(Side::Buy, levels as u64, limit, "taker");
+                    black_box(book.add_limit_order(taker).unwrap());
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, match_orders);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+// Compares two serialized order book snapshots (e.g. the engine's emergency
+// snapshots) and prints where they diverge. Exits 1 when they differ.
+//
+//   bookdiff [--json] left.json right.json
+
+use orderbook::{OrderBook, diff::diff_books};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: bookdiff [--json] <left.json> <right.json>";
+
+fn load(path: &str) -> Result<OrderBook, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn main() -> ExitCode {
+    let mut json = false;
+    let mut paths = vec![];
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ => paths.push(arg),
+        }
+    }
+    let [left, right] = paths.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let (left, right) = match (load(left), load(right)) {
+        (Ok(left), Ok(right)) => (left, right),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let diff = diff_books(&left, &right);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        print!("{}", diff);
+    }
+
+    if diff.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,272 @@
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt};
+
+use crate::{Order, OrderBook, Price, PriceMap, Qty};
+
+// A resting order as far as a diff is concerned. Ids depend on the order a
+// book saw its requests in, so the user and remaining quantity identify
+// orders within a level instead.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RestingOrder {
+    pub user: String,
+    pub quantity: Qty,
+}
+
+impl From<&Order> for RestingOrder {
+    fn from(order: &Order) -> Self {
+        Self {
+            user: order.user.clone(),
+            quantity: order.quantity,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LevelDiff {
+    OnlyInLeft {
+        quantity: Qty,
+        orders: usize,
+    },
+    OnlyInRight {
+        quantity: Qty,
+        orders: usize,
+    },
+    QuantityMismatch {
+        left: Qty,
+        right: Qty,
+    },
+    // same total, different queue
+    SequenceMismatch {
+        left: Vec<RestingOrder>,
+        right: Vec<RestingOrder>,
+    },
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LevelEntry {
+    pub side: &'static str,
+    pub price: Price,
+    #[serde(flatten)]
+    pub diff: LevelDiff,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SideTotals {
+    pub left: Qty,
+    pub right: Qty,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BookDiff {
+    pub levels: Vec<LevelEntry>,
+    pub bids: SideTotals,
+    pub asks: SideTotals,
+}
+
+impl BookDiff {
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
+
+fn total(queue: &[RestingOrder]) -> Qty {
+    queue.iter().map(|o| o.quantity).sum()
+}
+
+fn level(map: &PriceMap, price: Price) -> Option<Vec<RestingOrder>> {
+    map.get(&price)
+        .map(|queue| queue.iter().map(RestingOrder::from).collect())
+}
+
+fn diff_side(side: &'static str, left: &PriceMap, right: &PriceMap, out: &mut Vec<LevelEntry>) {
+    let prices: BTreeSet<Price> = left.keys().chain(right.keys()).copied().collect();
+    for price in prices {
+        let diff = match (level(left, price), level(right, price)) {
+            (Some(l), None) => LevelDiff::OnlyInLeft {
+                quantity: total(&l),
+                orders: l.len(),
+            },
+            (None, Some(r)) => LevelDiff::OnlyInRight {
+                quantity: total(&r),
+                orders: r.len(),
+            },
+            (Some(l), Some(r)) if total(&l) != total(&r) => LevelDiff::QuantityMismatch {
+                left: total(&l),
+                right: total(&r),
+            },
+            (Some(l), Some(r)) if l != r => LevelDiff::SequenceMismatch { left: l, right: r },
+            _ => continue,
+        };
+        out.push(LevelEntry { side, price, diff });
+    }
+}
+
+fn side_total(map: &PriceMap) -> Qty {
+    map.values().flatten().map(|o| o.quantity).sum()
+}
+
+pub fn diff_books(left: &OrderBook, right: &OrderBook) -> BookDiff {
+    let mut levels = Vec::new();
+    diff_side("bid", &left.bid_map, &right.bid_map, &mut levels);
+    diff_side("ask", &left.ask_map, &right.ask_map, &mut levels);
+    BookDiff {
+        levels,
+        bids: SideTotals {
+            left: side_total(&left.bid_map),
+            right: side_total(&right.bid_map),
+        },
+        asks: SideTotals {
+            left: side_total(&left.ask_map),
+            right: side_total(&right.ask_map),
+        },
+    }
+}
+
+fn format_queue(queue: &[RestingOrder]) -> String {
+    queue
+        .iter()
+        .map(|o| format!("{}:{}", o.user, o.quantity))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl fmt::Display for BookDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.levels {
+            write!(f, "{} {}: ", entry.side, entry.price)?;
+            match &entry.diff {
+                LevelDiff::OnlyInLeft { quantity, orders } => {
+                    writeln!(f, "only in left ({} across {} orders)", quantity, orders)?
+                }
+                LevelDiff::OnlyInRight { quantity, orders } => {
+                    writeln!(f, "only in right ({} across {} orders)", quantity, orders)?
+                }
+                LevelDiff::QuantityMismatch { left, right } => {
+                    writeln!(f, "quantity {} != {}", left, right)?
+                }
+                LevelDiff::SequenceMismatch { left, right } => {
+                    writeln!(f, "order sequence differs")?;
+                    writeln!(f, "  left:  {}", format_queue(left))?;
+                    writeln!(f, "  right: {}", format_queue(right))?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "net bids {} vs {}, net asks {} vs {}",
+            self.bids.left, self.bids.right, self.asks.left, self.asks.right
+        )
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Side;
+
+    fn book(orders: &[(Side, i64, u64, &str)]) -> OrderBook {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for (side, price, qty, user) in orders {
+            book.add_limit_order(Order::new_limit_order(
+                Qty::shares(*qty),
+                Some(Price::cents(*price)),
+                side.clone(),
+                String::from("AAPL"),
+                user.to_string(),
+            ))
+            .unwrap();
+        }
+        book
+    }
+
+    #[test]
+    fn test_identical_books() {
+        let orders = [(Side::Buy, 100, 5, "a"), (Side::Sell, 101, 5, "b")];
+        let diff = diff_books(&book(&orders), &book(&orders));
+        assert!(diff.is_empty());
+        assert_eq!(
+            diff.bids,
+            SideTotals {
+                left: Qty::shares(5),
+                right: Qty::shares(5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_reordered_level() {
+        let left = book(&[(Side::Buy, 100, 5, "a"), (Side::Buy, 100, 5, "b")]);
+        let right = book(&[(Side::Buy, 100, 5, "b"), (Side::Buy, 100, 5, "a")]);
+        let diff = diff_books(&left, &right);
+        assert_eq!(diff.levels.len(), 1);
+        assert!(matches!(
+            &diff.levels[0].diff,
+            LevelDiff::SequenceMismatch { left, .. } if left[0].user == "a"
+        ));
+        assert_eq!(
+            diff.to_string(),
+            "bid 1: order sequence differs\n  left:  a:5 b:5\n  right: b:5 a:5\nnet bids 10 vs 10, net asks 0 vs 0\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_and_mismatched_levels() {
+        let left = book(&[
+            (Side::Buy, 99, 3, "a"),
+            (Side::Sell, 101, 5, "b"),
+            (Side::Sell, 102, 7, "c"),
+        ]);
+        let right = book(&[(Side::Sell, 101, 4, "b"), (Side::Sell, 103, 2, "d")]);
+        let diff = diff_books(&left, &right);
+        assert_eq!(
+            diff.levels,
+            vec![
+                LevelEntry {
+                    side: "bid",
+                    price: Price::cents(99),
+                    diff: LevelDiff::OnlyInLeft {
+                        quantity: Qty::shares(3),
+                        orders: 1
+                    },
+                },
+                LevelEntry {
+                    side: "ask",
+                    price: Price::cents(101),
+                    diff: LevelDiff::QuantityMismatch {
+                        left: Qty::shares(5),
+                        right: Qty::shares(4),
+                    },
+                },
+                LevelEntry {
+                    side: "ask",
+                    price: Price::cents(102),
+                    diff: LevelDiff::OnlyInLeft {
+                        quantity: Qty::shares(7),
+                        orders: 1
+                    },
+                },
+                LevelEntry {
+                    side: "ask",
+                    price: Price::cents(103),
+                    diff: LevelDiff::OnlyInRight {
+                        quantity: Qty::shares(2),
+                        orders: 1
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            diff.asks,
+            SideTotals {
+                left: Qty::shares(12),
+                right: Qty::shares(6),
+            }
+        );
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["levels"][1]["kind"], "quantity_mismatch");
+        assert_eq!(json["levels"][1]["price"], "1.01");
+    }
+}
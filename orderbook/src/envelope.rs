@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::fmt;
+
+// The version this build writes. A newer version may only add fields,
+// which older readers skip, so anything from the first version up reads; a
+// change old readers can't follow needs a new message type instead.
+pub const ENVELOPE_VERSION: u32 = 1;
+const FIRST_ENVELOPE_VERSION: u32 = 1;
+
+// What every message on the Redis channels travels in:
+// { "v": 1, "type": "...", "ts": ..., "payload": {...} }. "type" names the
+// payload for logs and routing; the payload is the message as it was sent
+// before envelopes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub v: u32,
+    #[serde(rename = "type")]
+    pub kind: String,
+    // unix millis when the sender wrapped it
+    pub ts: i64,
+    pub payload: T,
+}
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    Malformed(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Malformed(e) => write!(f, "malformed envelope: {e}"),
+            EnvelopeError::UnsupportedVersion(v) => write!(
+                f,
+                "envelope version {v} is not supported, the first is {FIRST_ENVELOPE_VERSION}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+// Wraps `payload` in an envelope of the current version.
+pub fn seal<T: Serialize + ?Sized>(kind: &str, ts: i64, payload: &T) -> String {
+    let envelope = Envelope {
+        v: ENVELOPE_VERSION,
+        kind: kind.to_string(),
+        ts,
+        payload,
+    };
+    serde_json::to_string(&envelope).unwrap()
+}
+
+// Unwraps a message, leaving its payload unparsed for the receiver to read
+// as whatever it expects there. The version is checked first, so a message
+// from an unsupported version is refused as that rather than as whatever
+// else about it doesn't fit.
+pub fn open(raw: &str) -> Result<Envelope<Box<RawValue>>, EnvelopeError> {
+    #[derive(Deserialize)]
+    struct Version {
+        v: u32,
+    }
+
+    let Version { v } = serde_json::from_str(raw).map_err(EnvelopeError::Malformed)?;
+    if v < FIRST_ENVELOPE_VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(v));
+    }
+    serde_json::from_str(raw).map_err(EnvelopeError::Malformed)
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EngineEvent, Order, Qty, Side};
+
+    fn order() -> Order {
+        Order::new_limit_order(
+            Qty::shares(5),
+            Some("101.5".parse().unwrap()),
+            Side::Buy,
+            "AAPL".to_string(),
+            "a".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let raw = seal("order", 1_760_486_400_000, &order());
+        let json: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(json["v"], 1);
+        assert_eq!(json["type"], "order");
+        assert_eq!(json["ts"], 1_760_486_400_000i64);
+        assert_eq!(json["payload"]["price"], "101.5");
+
+        let envelope = open(&raw).unwrap();
+        assert_eq!(envelope.v, ENVELOPE_VERSION);
+        assert_eq!(envelope.kind, "order");
+        assert_eq!(envelope.ts, 1_760_486_400_000);
+        let read: Order = serde_json::from_str(envelope.payload.get()).unwrap();
+        assert_eq!(read.quantity, Qty::shares(5));
+
+        // a tagged payload keeps its own tag
+        let raw = seal("rejected", 0, &EngineEvent::rejected("off_tick", order()));
+        let envelope = open(&raw).unwrap();
+        match serde_json::from_str(envelope.payload.get()).unwrap() {
+            EngineEvent::Rejected(rejected) => assert_eq!(rejected.reason, "off_tick"),
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_newer_version_with_extra_fields_reads() {
+        let raw = r#"{"v":2,"type":"order","ts":7,"trace_id":"abc","payload":{"symbol":"AAPL","side":"Sell","quantity":"3","price":"100","user":"b","venue":"x"}}"#;
+        let envelope = open(raw).unwrap();
+        assert_eq!(
+            (envelope.v, envelope.kind.as_str(), envelope.ts),
+            (2, "order", 7)
+        );
+        let read: Order = serde_json::from_str(envelope.payload.get()).unwrap();
+        assert_eq!(read.side, Side::Sell);
+        assert_eq!(read.quantity, Qty::shares(3));
+    }
+
+    #[test]
+    fn test_unsupported_and_malformed_refused() {
+        let open_err = |raw: &str| open(raw).unwrap_err().to_string();
+        assert_eq!(
+            open_err(r#"{"v":0,"type":"order","ts":0,"payload":{}}"#),
+            "envelope version 0 is not supported, the first is 1"
+        );
+        // a bare message from before envelopes, or an envelope missing a part
+        for raw in [
+            r#"{"symbol":"AAPL","side":"Buy"}"#,
+            r#"{"v":1,"type":"order","ts":0}"#,
+            r#"{"v":"1","type":"order","ts":0,"payload":{}}"#,
+            "not json",
+        ] {
+            assert!(
+                matches!(open(raw), Err(EnvelopeError::Malformed(_))),
+                "{raw}"
+            );
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Order, TradeEvent, ValidationError};
+
+// Everything that can happen to an order, as the engine publishes it. The
+// "type" field names the variant; a reader that meets a type it doesn't
+// know gets Unknown rather than an error, so new variants can be published
+// before every consumer understands them.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineEvent {
+    Trade(TradeEvent),
+    #[serde(rename = "order_accepted")]
+    Accepted(OrderAck),
+    Rejected(OrderReject),
+    #[serde(rename = "order_cancelled")]
+    Cancelled(CancelAck),
+    #[serde(rename = "order_expired")]
+    Expired(OrderExpired),
+    #[serde(other, skip_serializing)]
+    Unknown,
+}
+
+// The order as the book took it: trimmed to any position cap, and with the
+// id it was given. Sent before any trades it makes on arrival.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderAck {
+    pub order: Order,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderReject {
+    pub reason: String,
+    pub order: Order,
+    // for an order that failed Order::validate, what was wrong with it;
+    // `reason` is its code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ValidationError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelAck {
+    // "requested" for cancel messages, "ioc" for the unfilled part of an IOC
+    // order, "notional_unspent" for what a notional order's budget didn't
+    // buy, "collar" for what a market order couldn't take inside the price
+    // collar, "no_liquidity" for what a market order found nothing to trade
+    // against for, "reduced" for an order reduced by all it had left,
+    // "mass_cancel" for everything a mass cancel pulled,
+    // "symbol_cleared" for everything in a book an admin cleared
+    pub reason: String,
+    // what was left of the order when it came off the book
+    pub order: Order,
+}
+
+// A good-till-date order swept out of the book at its expiry.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderExpired {
+    pub order: Order,
+}
+
+impl From<TradeEvent> for EngineEvent {
+    fn from(trade: TradeEvent) -> Self {
+        EngineEvent::Trade(trade)
+    }
+}
+
+impl EngineEvent {
+    // The "type" it is published under.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineEvent::Trade(_) => "trade",
+            EngineEvent::Accepted(_) => "order_accepted",
+            EngineEvent::Rejected(_) => "rejected",
+            EngineEvent::Cancelled(_) => "order_cancelled",
+            EngineEvent::Expired(_) => "order_expired",
+            EngineEvent::Unknown => "unknown",
+        }
+    }
+
+    pub fn rejected(reason: &str, order: Order) -> Self {
+        EngineEvent::Rejected(OrderReject {
+            reason: reason.to_string(),
+            order,
+            error: None,
+        })
+    }
+
+    pub fn invalid(error: ValidationError, order: Order) -> Self {
+        EngineEvent::Rejected(OrderReject {
+            reason: error.reason().to_string(),
+            order,
+            error: Some(error),
+        })
+    }
+
+    pub fn cancelled(reason: &str, order: Order) -> Self {
+        EngineEvent::Cancelled(CancelAck {
+            reason: reason.to_string(),
+            order,
+        })
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Qty, Side};
+
+    fn order() -> Order {
+        Order::new_limit_order(
+            Qty::shares(5),
+            Some("101.5".parse().unwrap()),
+            Side::Buy,
+            "AAPL".to_string(),
+            "a".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_tagged_round_trip() {
+        let json = serde_json::to_value(EngineEvent::rejected("off_tick", order())).unwrap();
+        assert_eq!(json["type"], "rejected");
+        assert_eq!(json["reason"], "off_tick");
+        assert_eq!(json["order"]["price"], "101.5");
+        assert!(json.get("error").is_none());
+
+        let lot_size = Qty::shares(10);
+        let invalid = EngineEvent::invalid(
+            ValidationError::OddLot {
+                quantity: Qty::shares(5),
+                lot_size,
+            },
+            order(),
+        );
+        let json = serde_json::to_value(&invalid).unwrap();
+        assert_eq!(json["reason"], "odd_lot");
+        assert_eq!(json["error"]["code"], "odd_lot");
+        assert_eq!(json["error"]["lot_size"], "10");
+        match serde_json::from_value(json).unwrap() {
+            EngineEvent::Rejected(rejected) => assert_eq!(
+                rejected.error,
+                Some(ValidationError::OddLot {
+                    quantity: Qty::shares(5),
+                    lot_size,
+                })
+            ),
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+
+        for (event, tag) in [
+            (
+                EngineEvent::Accepted(OrderAck { order: order() }),
+                "order_accepted",
+            ),
+            (EngineEvent::cancelled("ioc", order()), "order_cancelled"),
+            (
+                EngineEvent::Expired(OrderExpired { order: order() }),
+                "order_expired",
+            ),
+        ] {
+            let json = serde_json::to_string(&event).unwrap();
+            assert!(json.starts_with(&format!(r#"{{"type":"{tag}""#)), "{json}");
+            assert_eq!(event.kind(), tag);
+            let read: EngineEvent = serde_json::from_str(&json).unwrap();
+            assert_eq!(
+                std::mem::discriminant(&read),
+                std::mem::discriminant(&event)
+            );
+        }
+
+        let trade = r#"{"type":"trade","trade_id":1,"buyer":"a","seller":"b","symbol":"AAPL","quantity":"2","price":"100","taker_side":"Buy","timestamp":0,"maker_received_at":0}"#;
+        match serde_json::from_str(trade).unwrap() {
+            EngineEvent::Trade(trade) => assert_eq!(trade.quantity, Qty::shares(2)),
+            other => panic!("expected a trade, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_type_is_not_an_error() {
+        let read = |json: &str| serde_json::from_str::<EngineEvent>(json);
+        assert!(matches!(
+            read(r#"{"type":"order_parked","order_id":3,"until":99}"#),
+            Ok(EngineEvent::Unknown)
+        ));
+        // untagged and mistyped payloads are still refused
+        assert!(read(r#"{"buyer":"a"}"#).is_err());
+        assert!(read(r#"{"type":"rejected","reason":"x"}"#).is_err());
+    }
+}
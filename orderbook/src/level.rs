@@ -0,0 +1,224 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{VecDeque, vec_deque},
+    ops::Index,
+};
+
+use crate::{Order, OrderState, Qty};
+
+// The orders resting at one price in the order they fill, with their total
+// quantity kept alongside so reading it doesn't walk the queue. Everything
+// that changes the orders goes through here, keeping the two in step.
+// Serialized as the bare list of orders; the total is summed again when it
+// is read back.
+#[derive(Debug, Clone, Default)]
+pub struct PriceLevel {
+    total_quantity: Qty,
+    orders: VecDeque<Order>,
+}
+
+impl PriceLevel {
+    pub fn total_quantity(&self) -> Qty {
+        self.total_quantity
+    }
+
+    pub fn orders(&self) -> &VecDeque<Order> {
+        &self.orders
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, Order> {
+        self.orders.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn front(&self) -> Option<&Order> {
+        self.orders.front()
+    }
+
+    pub fn back(&self) -> Option<&Order> {
+        self.orders.back()
+    }
+
+    // How much is displayed here and in how many orders. Hidden orders queue
+    // behind every displayed one, so unless the last order is hidden that is
+    // the whole level.
+    pub fn displayed(&self) -> (Qty, usize) {
+        if !self.back().is_some_and(|o| o.hidden) {
+            return (self.total_quantity, self.len());
+        }
+        self.iter()
+            .take_while(|o| !o.hidden)
+            .fold((Qty::ZERO, 0), |(quantity, orders), o| {
+                (quantity + o.quantity, orders + 1)
+            })
+    }
+
+    pub(crate) fn insert(&mut self, at: usize, order: Order) {
+        self.total_quantity += order.quantity;
+        self.orders.insert(at, order);
+        self.debug_check();
+    }
+
+    pub(crate) fn remove(&mut self, at: usize) -> Option<Order> {
+        let order = self.orders.remove(at)?;
+        self.total_quantity -= order.quantity;
+        self.debug_check();
+        Some(order)
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Order> {
+        self.remove(0)
+    }
+
+    // Takes `quantity` off the order at `at`. A partly filled order keeps its
+    // place; a filled one leaves the level and is returned.
+    pub(crate) fn fill(&mut self, at: usize, quantity: Qty) -> Option<Order> {
+        let order = &mut self.orders[at];
+        order.quantity -= quantity;
+        self.total_quantity -= quantity;
+        if order.quantity.is_zero() {
+            order.state = OrderState::Filled;
+            return self.remove(at);
+        }
+        order.state = OrderState::PartiallyFilled;
+        self.debug_check();
+        None
+    }
+
+    // Sets the quantity of the order at `at` without moving it.
+    pub(crate) fn set_quantity(&mut self, at: usize, quantity: Qty) {
+        let order = &mut self.orders[at];
+        self.total_quantity = self.total_quantity - order.quantity + quantity;
+        order.quantity = quantity;
+        self.debug_check();
+    }
+
+    // Sets the expiry of the order at `at` without moving it.
+    pub(crate) fn set_expiry(&mut self, at: usize, expires_at: Option<i64>) {
+        self.orders[at].expires_at = expires_at;
+    }
+
+    // Keeps the orders `keep` says to, in their order.
+    pub(crate) fn retain(&mut self, keep: impl FnMut(&Order) -> bool) {
+        self.orders.retain(keep);
+        self.total_quantity = self.orders.iter().map(|o| o.quantity).sum();
+    }
+
+    // Whether the kept total still matches the orders; a full walk of the
+    // level, so for checks rather than matching.
+    pub fn check_total(&self) -> Result<(), String> {
+        let sum: Qty = self.orders.iter().map(|o| o.quantity).sum();
+        if sum != self.total_quantity {
+            return Err(format!(
+                "level total {} but its orders add up to {}",
+                self.total_quantity, sum
+            ));
+        }
+        Ok(())
+    }
+
+    fn debug_check(&self) {
+        debug_assert_eq!(self.check_total(), Ok(()));
+    }
+}
+
+impl Index<usize> for PriceLevel {
+    type Output = Order;
+
+    fn index(&self, at: usize) -> &Order {
+        &self.orders[at]
+    }
+}
+
+impl<'a> IntoIterator for &'a PriceLevel {
+    type Item = &'a Order;
+    type IntoIter = vec_deque::Iter<'a, Order>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.orders.iter()
+    }
+}
+
+impl IntoIterator for PriceLevel {
+    type Item = Order;
+    type IntoIter = vec_deque::IntoIter<Order>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.orders.into_iter()
+    }
+}
+
+impl FromIterator<Order> for PriceLevel {
+    fn from_iter<I: IntoIterator<Item = Order>>(orders: I) -> Self {
+        let orders: VecDeque<Order> = orders.into_iter().collect();
+        PriceLevel {
+            total_quantity: orders.iter().map(|o| o.quantity).sum(),
+            orders,
+        }
+    }
+}
+
+impl Serialize for PriceLevel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.orders.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PriceLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        VecDeque::<Order>::deserialize(deserializer).map(PriceLevel::from_iter)
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Side;
+
+    fn order(shares: u64) -> Order {
+        Order::new_limit_order(
+            Qty::shares(shares),
+            Some("100".parse().unwrap()),
+            Side::Sell,
+            "AAPL".to_string(),
+            "a".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_total_follows_every_change() {
+        let mut level: PriceLevel = [order(5), order(3)].into_iter().collect();
+        assert_eq!(level.total_quantity(), Qty::shares(8));
+        level.insert(1, order(2));
+        assert_eq!(level.total_quantity(), Qty::shares(10));
+
+        assert!(level.fill(0, Qty::shares(4)).is_none());
+        assert_eq!(level[0].state, OrderState::PartiallyFilled);
+        let filled = level.fill(0, Qty::shares(1)).unwrap();
+        assert_eq!(filled.state, OrderState::Filled);
+        assert_eq!(level.total_quantity(), Qty::shares(5));
+
+        level.set_quantity(1, Qty::shares(1));
+        assert_eq!(level.total_quantity(), Qty::shares(3));
+        level.retain(|o| o.quantity != Qty::shares(2));
+        assert_eq!(level.total_quantity(), Qty::shares(1));
+        assert_eq!(level.check_total(), Ok(()));
+    }
+
+    #[test]
+    fn test_serialized_as_the_orders() {
+        let level: PriceLevel = [order(5), order(3)].into_iter().collect();
+        let json = serde_json::to_value(&level).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        let read: PriceLevel = serde_json::from_value(json).unwrap();
+        assert_eq!(read.total_quantity(), Qty::shares(8));
+    }
+}
@@ -1,19 +1,38 @@
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, VecDeque};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt,
+    io::{self, Read, Write},
+    ops::Bound::{Excluded, Unbounded},
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+use price::PRICE_SCALE;
+use qty::QTY_SCALE;
+
+pub mod diff;
+pub mod envelope;
+pub mod event;
+pub mod level;
+pub mod price;
+pub mod qty;
+pub mod validation;
+
+pub use event::{CancelAck, EngineEvent, OrderAck, OrderExpired, OrderReject};
+pub use level::PriceLevel;
+pub use price::Price;
+pub use qty::Qty;
+pub use validation::{MAX_ORDER_TAGS, MAX_TAG_LENGTH, ValidationError};
+
+// Written "Buy" or "Sell"; read in lowercase as well, as clients send it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Side {
+    #[serde(alias = "buy")]
     Buy,
+    #[serde(alias = "sell")]
     Sell,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum OrderType {
-    Limit,
-    Market,
-}
-
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderState {
     Filled,
     PartiallyFilled,
@@ -21,285 +40,4916 @@ pub enum OrderState {
     Close, // reserved for cancelling orders, in future use
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Whether incoming orders match on arrival or wait for an uncross.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingPhase {
+    #[default]
+    Continuous,
+    // limit orders rest without matching, crossed or not, until uncross()
+    Auction,
+}
+
+// How long a limit order may stay in the book.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    // good till cancelled: whatever doesn't match rests
+    #[default]
+    Gtc,
+    // immediate or cancel: whatever doesn't match is dropped
+    Ioc,
+    // fill or kill: fills completely or not at all
+    Fok,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TradeEvent {
+    // counts up from 1 per book; 0 for a trade made off the book, such as an
+    // accepted quote
+    #[serde(default)]
+    pub trade_id: u64,
     pub buyer: String,
     pub seller: String,
     pub symbol: String,
-    pub quantity: u64,
-    pub price: i64,
+    pub quantity: Qty,
+    pub price: Price,
+    // side of the incoming order that took liquidity
+    pub taker_side: Side,
+    // unix millis from the book's clock
+    #[serde(default)]
+    pub timestamp: i64,
+    // when the resting side took its place in the queue
+    #[serde(default)]
+    pub maker_received_at: i64,
+    // the resting order that was hit and what it has left after this fill;
+    // 0 means it filled and left the book
+    #[serde(default)]
+    pub maker_order_id: u64,
+    #[serde(default)]
+    pub maker_remaining: Qty,
+    // the incoming order that took liquidity
+    #[serde(default)]
+    pub taker_order_id: u64,
+    // the tags on the buyer's and the seller's orders
+    #[serde(default)]
+    pub buyer_tags: Vec<String>,
+    #[serde(default)]
+    pub seller_tags: Vec<String>,
+}
+
+// What became of an incoming order. The summary fields cover the order's
+// own fills; `events` also carries the trades of any stops those fills set
+// off, after the order's own.
+#[derive(Debug, Default)]
+pub struct MatchResult {
+    pub events: Vec<TradeEvent>,
+    pub filled_quantity: Qty,
+    // for a limit order, what rested or (IOC) was dropped; for a market
+    // order, what the book couldn't fill
+    pub remaining_quantity: Qty,
+    pub average_price: Option<f64>,
+    // the id the book gave the order, resting or not
+    pub order_id: Option<u64>,
+    pub resting_order_id: Option<u64>,
+    // the market collar, not a lack of liquidity, stopped the order
+    pub collared: bool,
+    // what a notional order didn't spend of its budget
+    pub unspent_notional: Option<Price>,
+    // the limit the book gave an order that takes its price from it: for a
+    // market-to-limit order the level it traded against, for a pegged order
+    // where its peg put it
+    pub limit_price: Option<Price>,
+}
+
+// A stop that triggered and neither filled in full nor rested: a market stop
+// the book or the collar ran out on, or an IOC stop-limit. `order` is closed
+// with what was left of it as its quantity.
+#[derive(Debug, Clone)]
+pub struct DroppedStop {
+    pub order: Order,
+    pub collared: bool,
+}
+
+// A price times a quantity that doesn't fit in an i64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowError;
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "notional overflows")
+    }
+}
+
+// What `quantity` shares cost at `price`, in the same units as the price.
+// A fractional quantity can come to a fraction of a unit; that is rounded to
+// the nearest, halves away from zero.
+pub fn notional(price: Price, quantity: Qty) -> Result<Price, OverflowError> {
+    let exact = raw_notional(price, quantity);
+    let (scale, half) = (QTY_SCALE as i128, QTY_SCALE as i128 / 2);
+    let rounded = if exact < 0 {
+        (exact - half) / scale
+    } else {
+        (exact + half) / scale
+    };
+    i64::try_from(rounded)
+        .map(Price::from_units)
+        .map_err(|_| OverflowError)
+}
+
+// Price times quantity in price units times quantity units, exact.
+fn raw_notional(price: Price, quantity: Qty) -> i128 {
+    price.units() as i128 * quantity.units() as i128
+}
+
+// A raw notional over the quantity it covers, as a price in currency units.
+fn average(raw_notional: i128, quantity: Qty) -> f64 {
+    raw_notional as f64 / quantity.units() as f64 / PRICE_SCALE as f64
+}
+
+impl MatchResult {
+    // `quantity` is what the order asked for, 0 for a notional order.
+    fn new(order_id: Option<u64>, quantity: Qty, fills: &[TradeEvent]) -> Self {
+        let filled_quantity: Qty = fills.iter().map(|e| e.quantity).sum();
+        let notional: i128 = fills
+            .iter()
+            .map(|e| raw_notional(e.price, e.quantity))
+            .sum();
+        Self {
+            filled_quantity,
+            remaining_quantity: quantity.saturating_sub(filled_quantity),
+            average_price: (!filled_quantity.is_zero()).then(|| average(notional, filled_quantity)),
+            order_id,
+            ..Self::default()
+        }
+    }
+}
+
+type PriceMap = BTreeMap<Price, PriceLevel>;
+
+// Where each resting order sits: its side and the price of its level.
+type OrderIndex = HashMap<u64, (Side, Price)>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    // assigned by the book when it accepts the order; 0 until then
+    #[serde(default)]
+    pub order_id: u64,
+    pub user: String,
+    pub side: Side,
+    pub price: Option<Price>,
+    // 0 for a notional order, which is sized by its budget alone
+    #[serde(default)]
+    pub quantity: Qty,
+    // unix millis at which the order took its current place in the book's
+    // queues; set by the book, and reset when an amend sends it to the back
+    #[serde(default)]
+    pub received_at: i64,
+    pub symbol: String,
+    #[serde(default = "default_state")]
+    pub state: OrderState,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    // unix millis after which a resting order is swept out of the book
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    // rejected rather than matched if it would take liquidity on arrival
+    #[serde(default)]
+    pub post_only: bool,
+    // makes this a stop order: it waits off the book until a trade prints at
+    // or through this price (at or below for sells, at or above for buys),
+    // then goes in as a limit order if it has a price and a market order if not
+    #[serde(default)]
+    pub stop_price: Option<Price>,
+    // sizes a market order by what it spends rather than by shares: it takes
+    // shares best price first for as long as this budget covers them
+    #[serde(default)]
+    pub notional: Option<Price>,
+    // makes a market order market-to-limit: it takes only the best opposite
+    // level, and whatever that doesn't fill rests as a limit order at its
+    // price instead of walking deeper
+    #[serde(default)]
+    pub market_to_limit: bool,
+    // makes a limit order pegged; the book gives it its price
+    #[serde(default)]
+    pub peg: Option<Peg>,
+    // rests and matches like any other order but is left out of depth, the
+    // best bid and ask quantities and the checksum; at its price it queues
+    // behind every displayed order
+    #[serde(default)]
+    pub hidden: bool,
+    // the least the order will take on arrival: if the book can't fill this
+    // much within its limit it is turned away untouched; otherwise it matches
+    // as usual
+    #[serde(default)]
+    pub min_quantity: Option<Qty>,
+    // once resting, trades only with an incoming order that takes all of it
+    // at once; smaller ones pass over it and it keeps its place. On arrival it
+    // must either fill in full or not trade at all
+    #[serde(default)]
+    pub all_or_none: bool,
+    // the user's own labels, such as the strategy that sent it; copied onto
+    // its trades
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // the submitter's own id for the order, so it can match the book's answer
+    // to what it sent before it knows the order_id; carried, never read
+    #[serde(default)]
+    pub client_order_id: Option<u64>,
+}
+
+// Pegs an order to the best displayed price on its own side, leaving other
+// pegged orders out of it: a buy works at the best bid less `offset`, a sell at the
+// best ask plus it. A negative offset works inside the touch. Past `limit`
+// the order stops following.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peg {
+    #[serde(default)]
+    pub offset: Price,
+    #[serde(default)]
+    pub limit: Option<Price>,
+}
+
+impl Peg {
+    // Where an order on `side` works while its side's best price is
+    // `reference`; None if that doesn't fit in a Price.
+    pub fn price(&self, side: &Side, reference: Price) -> Option<Price> {
+        match side {
+            Side::Buy => {
+                let price = reference.checked_sub(self.offset)?;
+                Some(self.limit.map_or(price, |limit| price.min(limit)))
+            }
+            Side::Sell => {
+                let price = reference.checked_add(self.offset)?;
+                Some(self.limit.map_or(price, |limit| price.max(limit)))
+            }
+        }
+    }
+}
+
+fn default_state() -> OrderState {
+    OrderState::Open
+}
+
+// For `#[serde(default, deserialize_with = "present")]` on an Option<Option<T>>
+// field: tells one sent as null, Some(None), from one left out, None.
+pub fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl Order {
+    pub fn new_limit_order(
+        quantity: Qty,
+        price: Option<Price>,
+        side: Side,
+        symbol: String,
+        user: String,
+    ) -> Self {
+        Self {
+            order_id: 0,
+            user,
+            side,
+            price,
+            quantity,
+            received_at: 0,
+            state: OrderState::Open,
+            symbol,
+            time_in_force: TimeInForce::Gtc,
+            expires_at: None,
+            post_only: false,
+            stop_price: None,
+            notional: None,
+            market_to_limit: false,
+            peg: None,
+            hidden: false,
+            min_quantity: None,
+            all_or_none: false,
+            tags: Vec::new(),
+            client_order_id: None,
+        }
+    }
+
+    pub fn new_market_order(quantity: Qty, side: Side, symbol: String, user: String) -> Self {
+        Self {
+            order_id: 0,
+            user,
+            side,
+            price: None, // as market orders are executed based on the price from the orderbook
+            quantity,
+            received_at: 0,
+            state: OrderState::Open,
+            symbol,
+            time_in_force: TimeInForce::Gtc,
+            expires_at: None,
+            post_only: false,
+            stop_price: None,
+            notional: None,
+            market_to_limit: false,
+            peg: None,
+            hidden: false,
+            min_quantity: None,
+            all_or_none: false,
+            tags: Vec::new(),
+            client_order_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub quantity: Qty,
+    pub orders: usize,
+}
+
+// Aggregated top levels of the book, best price first on both sides.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub symbol: String,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    // depth_checksum of the levels above, for a consumer to check its own
+    // copy of the book against
+    pub checksum: u32,
+}
+
+impl DepthSnapshot {
+    // What `checksum` should be for these levels; a consumer that rebuilt
+    // the book from deltas compares its own depth with this.
+    pub fn compute_checksum(&self) -> u32 {
+        depth_checksum(&self.bids, &self.asks)
+    }
+}
+
+// A change to one level of the displayed book: how much `side` now shows at
+// `price`, zero once nothing does. Applying every delta in order to a copy
+// of the book keeps its depth the same as the book's own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookDelta {
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Qty,
+    // how many displayed orders make up `quantity`
+    #[serde(default)]
+    pub orders: usize,
+}
+
+// CRC32 (IEEE) over the levels in a fixed text form: level by level from
+// the best, the bid then the ask as "price:quantity", with a side that has
+// run out skipped, all joined by ':'. Prices and quantities print as Price
+// and Qty display them, so bids of 101 x 12 and 99.5 x 2 against an ask of
+// 101.25 x 3 give "101:12:101.25:3:99.5:2".
+pub fn depth_checksum(bids: &[DepthLevel], asks: &[DepthLevel]) -> u32 {
+    let mut fields = Vec::new();
+    for i in 0..bids.len().max(asks.len()) {
+        for level in [bids.get(i), asks.get(i)].into_iter().flatten() {
+            fields.push(format!("{}:{}", level.price, level.quantity));
+        }
+    }
+    crc32fast::hash(fields.join(":").as_bytes())
+}
+
+// CRC32 (IEEE) over resting orders in the order given, each as
+// "order_id:price:quantity", all joined by ':'; what a consumer reassembling
+// a streamed snapshot checks its copy against.
+pub fn orders_checksum(orders: &[PlacedOrder]) -> u32 {
+    let fields: Vec<String> = orders
+        .iter()
+        .map(|placed| {
+            format!(
+                "{}:{}:{}",
+                placed.order.order_id, placed.price, placed.order.quantity
+            )
+        })
+        .collect();
+    crc32fast::hash(fields.join(":").as_bytes())
+}
+
+// A resting or waiting order and where it sits: `price` is the limit price
+// for the book, the stop price for the stop book; `position` is its place in
+// that level's queue, 0 being next to fill.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlacedOrder {
+    pub price: Price,
+    pub position: usize,
+    pub order: Order,
+}
+
+// Open, high, low, close and turnover since the session began. Only trades
+// move these; high and low cover this session's trades alone, so they stay
+// unset until the first one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub open: Option<Price>,
+    pub high: Option<Price>,
+    pub low: Option<Price>,
+    pub close: Option<Price>,
+    pub volume: Qty,
+    pub trades: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolStats {
+    pub symbol: String,
+    // survives a session reset, unlike close
+    pub last_price: Option<Price>,
+    #[serde(flatten)]
+    pub session: SessionStats,
+}
+
+// A resting order as it stands right now: what's left of it and its place
+// in its level's queue, 0 being next to fill.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderView {
+    pub order_id: u64,
+    pub user: String,
+    pub symbol: String,
+    pub side: Side,
+    pub price: Price,
+    pub remaining_quantity: Qty,
+    pub state: OrderState,
+    pub time_in_force: TimeInForce,
+    pub received_at: i64,
+    pub expires_at: Option<i64>,
+    pub queue_position: usize,
+    #[serde(default)]
+    pub client_order_id: Option<u64>,
+}
+
+impl OrderView {
+    fn new(price: Price, queue_position: usize, order: &Order) -> Self {
+        Self {
+            order_id: order.order_id,
+            user: order.user.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            price,
+            remaining_quantity: order.quantity,
+            state: order.state.clone(),
+            time_in_force: order.time_in_force,
+            received_at: order.received_at,
+            expires_at: order.expires_at,
+            queue_position,
+            client_order_id: order.client_order_id,
+        }
+    }
+}
+
+// Every order in the book plus the state matching depends on, enough to
+// rebuild an identical book.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    pub last_order_id: u64,
+    pub last_trade_price: Option<Price>,
+    #[serde(default)]
+    pub last_trade_id: u64,
+    #[serde(default)]
+    pub rules: SymbolRules,
+    #[serde(default)]
+    pub halted: bool,
+    #[serde(default)]
+    pub phase: TradingPhase,
+    #[serde(default)]
+    pub session: SessionStats,
+    // the trade history, oldest first
+    #[serde(default)]
+    pub trades: Vec<TradeEvent>,
+    // (timestamp, price) of the trades inside the circuit breaker's window,
+    // so a restored book halts on the same move the original would
+    #[serde(default)]
+    pub breaker_window: Vec<(i64, Price)>,
+    // the fills vwap() is worked out from, oldest first, and how far back
+    // it looks
+    #[serde(default)]
+    pub vwap_fills: Vec<(i64, Price, Qty)>,
+    #[serde(default = "default_vwap_window")]
+    pub vwap_window_millis: i64,
+    pub orders: Vec<PlacedOrder>,
+    pub stops: Vec<PlacedOrder>,
+}
+
+// Written ahead of the snapshot by OrderBook::save; load refuses any other.
+pub const BOOK_FILE_VERSION: u32 = 10;
+#[cfg(not(feature = "json_snapshots"))]
+pub const BOOK_FILE_EXTENSION: &str = "bin";
+#[cfg(feature = "json_snapshots")]
+pub const BOOK_FILE_EXTENSION: &str = "json";
+
+#[cfg(not(feature = "json_snapshots"))]
+fn write_book_file<W: Write>(mut writer: W, snapshot: &BookSnapshot) -> io::Result<()> {
+    bincode::serialize_into(&mut writer, &BOOK_FILE_VERSION).map_err(io::Error::other)?;
+    bincode::serialize_into(&mut writer, snapshot).map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "json_snapshots"))]
+fn read_book_file<R: Read>(mut reader: R) -> io::Result<BookSnapshot> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let version: u32 = bincode::deserialize_from(&mut reader).map_err(invalid)?;
+    check_book_file_version(version)?;
+    bincode::deserialize_from(reader).map_err(invalid)
+}
+
+#[cfg(feature = "json_snapshots")]
+fn write_book_file<W: Write>(writer: W, snapshot: &BookSnapshot) -> io::Result<()> {
+    let file = serde_json::json!({ "version": BOOK_FILE_VERSION, "book": snapshot });
+    serde_json::to_writer_pretty(writer, &file).map_err(io::Error::other)
+}
+
+#[cfg(feature = "json_snapshots")]
+fn read_book_file<R: Read>(reader: R) -> io::Result<BookSnapshot> {
+    let mut file: serde_json::Value = serde_json::from_reader(reader)?;
+    let version = file["version"].as_u64().unwrap_or(0) as u32;
+    check_book_file_version(version)?;
+    Ok(serde_json::from_value(file["book"].take())?)
+}
+
+fn check_book_file_version(version: u32) -> io::Result<()> {
+    if version != BOOK_FILE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "book file is version {}, this build reads version {}",
+                version, BOOK_FILE_VERSION
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// What every order for a symbol is checked against before it reaches the
+// book.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolRules {
+    // every limit and stop price must be a multiple of this
+    pub tick_size: Price,
+    // every quantity must be a multiple of this
+    pub lot_size: Qty,
+    // largest quantity a single order may carry; unlimited when unset
+    pub max_quantity: Option<Qty>,
+    // how far, in basis points, a market order may trade away from the last
+    // trade (or the opposite touch before the first trade); unlimited when
+    // unset
+    pub market_collar_bps: Option<i64>,
+    // halts the book when the price runs too far too fast
+    pub circuit_breaker: Option<CircuitBreaker>,
+    // how long an order must rest before it may be cancelled; any time when
+    // unset
+    pub min_resting: Option<MinRestingTime>,
+}
+
+// Trips when a trade prints more than `move_bps` basis points away from any
+// trade in the `window_millis` before it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreaker {
+    pub move_bps: i64,
+    pub window_millis: i64,
+}
+
+// An order cancelled less than `millis` after the book took it is held back
+// until it has rested that long, or refused, as `early_cancel` says.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MinRestingTime {
+    pub millis: i64,
+    #[serde(default)]
+    pub early_cancel: EarlyCancel,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EarlyCancel {
+    #[default]
+    Defer,
+    Reject,
+}
+
+impl Default for SymbolRules {
+    fn default() -> Self {
+        Self {
+            tick_size: Price::from_units(1),
+            lot_size: Qty::from_units(1),
+            max_quantity: None,
+            market_collar_bps: None,
+            circuit_breaker: None,
+            min_resting: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CancelError {
+    // never issued by this book
+    UnknownOrder(u64),
+    // already filled or cancelled
+    NotResting(u64),
+    // an amend the book would turn away as a new order
+    Rejected(AddOrderError),
+    // a reduction to more than the order has left
+    WouldGrow(u64),
+}
+
+// Why the book turned a limit order away without touching anything.
+#[derive(Debug, PartialEq)]
+pub enum AddOrderError {
+    // the order is wrong on its own or under the book's rules
+    Invalid(ValidationError),
+    // an order for another symbol's book
+    SymbolMismatch,
+    // the circuit breaker has stopped the book until it is resumed
+    Halted,
+    // a market, IOC, FOK, minimum-quantity or all-or-none order while the
+    // book is collecting an auction
+    AuctionOpen,
+    // a FOK order the book can't fill in full
+    Unfillable,
+    // an all-or-none order that would trade on arrival but can't fill in full
+    AllOrNoneUnfilled,
+    // an order whose minimum quantity the book can't fill within its limit
+    BelowMinQuantity,
+    // a post-only order priced at or through the opposite touch
+    WouldCross,
+    // a pegged order its peg prices at zero or below
+    InvalidPegPrice,
+    // a market-to-limit order with nothing on the opposite side to price it,
+    // or a pegged one with nothing on its own side to follow
+    NoLiquidity,
+}
+
+impl fmt::Display for AddOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddOrderError::Invalid(e) => e.fmt(f),
+            AddOrderError::SymbolMismatch => write!(f, "order is for another symbol"),
+            AddOrderError::Halted => write!(f, "book is halted"),
+            AddOrderError::AuctionOpen => {
+                write!(f, "only good-till-cancelled limit orders join an auction")
+            }
+            AddOrderError::Unfillable => write!(f, "not enough liquidity to fill in full"),
+            AddOrderError::AllOrNoneUnfilled => {
+                write!(f, "all-or-none order would fill only in part")
+            }
+            AddOrderError::BelowMinQuantity => {
+                write!(f, "not enough liquidity to fill the minimum quantity")
+            }
+            AddOrderError::WouldCross => write!(f, "post-only order would cross the book"),
+            AddOrderError::InvalidPegPrice => write!(f, "peg prices the order at zero or below"),
+            AddOrderError::NoLiquidity => write!(f, "nothing in the book to price the order from"),
+        }
+    }
+}
+
+impl fmt::Display for CancelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelError::UnknownOrder(id) => write!(f, "unknown order {}", id),
+            CancelError::NotResting(id) => write!(f, "order {} is no longer resting", id),
+            CancelError::Rejected(e) => write!(f, "amend rejected: {}", e),
+            CancelError::WouldGrow(id) => write!(f, "order {} can only be reduced", id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderBook {
+    // private so nothing outside the book can leave an empty level or a
+    // crossed book behind; read them through iter_bids()/iter_asks(), or
+    // bids()/asks() by level
+    bid_map: PriceMap,
+    ask_map: PriceMap,
+    pub symbol: String,
+    #[serde(default)]
+    last_order_id: u64,
+    // no resting order expires before this; may be stale-early, never late
+    #[serde(default)]
+    next_expiry: Option<i64>,
+    // stop orders waiting for their trigger, keyed by stop price
+    #[serde(default)]
+    buy_stops: PriceMap,
+    #[serde(default)]
+    sell_stops: PriceMap,
+    #[serde(default)]
+    last_trade_price: Option<Price>,
+    #[serde(default)]
+    last_trade_id: u64,
+    #[serde(default)]
+    rules: SymbolRules,
+    // set by the circuit breaker; nothing new is accepted until resume()
+    #[serde(default)]
+    halted: bool,
+    // (timestamp, price) of the trades inside the circuit breaker's window
+    #[serde(default)]
+    recent_trades: VecDeque<(i64, Price)>,
+    #[serde(default)]
+    phase: TradingPhase,
+    #[serde(default)]
+    session: SessionStats,
+    #[serde(default)]
+    vwap: VwapWindow,
+    #[serde(default)]
+    trade_history: TradeHistory,
+    #[serde(default)]
+    pegs: Pegs,
+    // every order in bid_map and ask_map, so finding one by id only has to
+    // search its own level; stops aren't in it. Not serialized: from_snapshot
+    // rebuilds it as it places the orders
+    #[serde(skip)]
+    index: OrderIndex,
+    // levels changed since take_deltas last ran
+    #[serde(skip)]
+    changed: ChangedLevels,
+    // triggered stops dropped since take_dropped_stops last ran
+    #[serde(skip)]
+    dropped_stops: Vec<DroppedStop>,
+    #[serde(skip, default = "default_clock")]
+    clock: fn() -> i64,
+}
+
+pub const DEFAULT_VWAP_WINDOW_MILLIS: i64 = 5 * 60 * 1000;
+
+fn default_vwap_window() -> i64 {
+    DEFAULT_VWAP_WINDOW_MILLIS
+}
+
+// The trades of the last `window_millis` with their running notional and
+// volume. Trades that have aged out are dropped when the next one arrives
+// and skipped when reading.
+#[derive(Debug, Serialize, Deserialize)]
+struct VwapWindow {
+    window_millis: i64,
+    // (timestamp, price, quantity), oldest first
+    fills: VecDeque<(i64, Price, Qty)>,
+    notional: i128,
+    volume: Qty,
+}
+
+pub const DEFAULT_TRADE_HISTORY: usize = 1000;
+
+// The last `limit` trades, oldest first; once it is full each new trade
+// pushes out the oldest.
+#[derive(Debug, Serialize, Deserialize)]
+struct TradeHistory {
+    limit: usize,
+    trades: VecDeque<TradeEvent>,
+}
+
+impl Default for TradeHistory {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_TRADE_HISTORY,
+            trades: VecDeque::new(),
+        }
+    }
+}
+
+impl TradeHistory {
+    fn record(&mut self, trade: &TradeEvent) {
+        if self.trades.len() == self.limit {
+            self.trades.pop_front();
+        }
+        self.trades.push_back(trade.clone());
+    }
+
+    fn truncate(&mut self) {
+        let excess = self.trades.len().saturating_sub(self.limit);
+        self.trades.drain(..excess);
+    }
 }
 
-type PriceMap = BTreeMap<i64, VecDeque<Order>>;
+// The resting pegged orders, in the order they were placed, and the best
+// prices they were last worked out from. Ids of orders that have since left
+// the book are dropped at the next re-price.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Pegs {
+    ids: Vec<u64>,
+    bid_reference: Option<Price>,
+    ask_reference: Option<Price>,
+}
+
+impl Default for VwapWindow {
+    fn default() -> Self {
+        Self {
+            window_millis: DEFAULT_VWAP_WINDOW_MILLIS,
+            fills: VecDeque::new(),
+            notional: 0,
+            volume: Qty::ZERO,
+        }
+    }
+}
+
+impl VwapWindow {
+    fn stale(&self, now: i64) -> impl Iterator<Item = &(i64, Price, Qty)> {
+        let start = now - self.window_millis;
+        self.fills.iter().take_while(move |&&(at, _, _)| at < start)
+    }
+
+    fn record(&mut self, at: i64, price: Price, quantity: Qty) {
+        let stale = self.stale(at).count();
+        for (_, price, quantity) in self.fills.drain(..stale) {
+            self.notional -= raw_notional(price, quantity);
+            self.volume -= quantity;
+        }
+        self.fills.push_back((at, price, quantity));
+        self.notional += raw_notional(price, quantity);
+        self.volume += quantity;
+    }
+
+    fn value(&self, now: i64) -> Option<f64> {
+        let (notional, volume) = self
+            .stale(now)
+            .fold((self.notional, self.volume), |(n, v), &(_, price, qty)| {
+                (n - raw_notional(price, qty), v - qty)
+            });
+        (!volume.is_zero()).then(|| average(notional, volume))
+    }
+}
+
+// The prices of the levels each side has had orders added to, filled or
+// taken from. Each path that changes a level marks it, so the deltas come
+// from these alone rather than from comparing whole books.
+#[derive(Debug, Default)]
+struct ChangedLevels {
+    bids: BTreeSet<Price>,
+    asks: BTreeSet<Price>,
+}
+
+impl ChangedLevels {
+    fn mark(&mut self, side: &Side, price: Price) {
+        match side {
+            Side::Buy => self.bids.insert(price),
+            Side::Sell => self.asks.insert(price),
+        };
+    }
+}
+
+fn default_clock() -> fn() -> i64 {
+    system_clock
+}
+
+fn system_clock() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+// The incoming order's side of a fill, plus what it takes to stamp the trades
+// it makes.
+pub struct Taker<'a> {
+    pub order_id: u64,
+    pub user: &'a str,
+    pub tags: &'a [String],
+    pub side: Side,
+    pub now: i64,
+    pub last_trade_id: &'a mut u64,
+    // the book's index, for dropping the makers that fill
+    index: &'a mut OrderIndex,
+    // the book's changed levels, for marking those the taker fills from
+    changed: &'a mut ChangedLevels,
+}
+
+impl Taker<'_> {
+    // Called before `maker` gives up `quantity`.
+    fn trade(&mut self, maker: &Order, quantity: Qty) -> TradeEvent {
+        self.trade_at(maker, quantity, maker.price.unwrap())
+    }
+
+    fn trade_at(&mut self, maker: &Order, quantity: Qty, price: Price) -> TradeEvent {
+        *self.last_trade_id += 1;
+        let ((buyer, buyer_tags), (seller, seller_tags)) = match maker.side {
+            Side::Buy => (
+                (maker.user.clone(), maker.tags.clone()),
+                (self.user.to_string(), self.tags.to_vec()),
+            ),
+            Side::Sell => (
+                (self.user.to_string(), self.tags.to_vec()),
+                (maker.user.clone(), maker.tags.clone()),
+            ),
+        };
+        TradeEvent {
+            trade_id: *self.last_trade_id,
+            buyer,
+            seller,
+            price,
+            quantity,
+            symbol: maker.symbol.clone(),
+            taker_side: self.side.clone(),
+            timestamp: self.now,
+            maker_received_at: maker.received_at,
+            maker_order_id: maker.order_id,
+            maker_remaining: maker.quantity - quantity,
+            taker_order_id: self.order_id,
+            buyer_tags,
+            seller_tags,
+        }
+    }
+}
+
+impl OrderBook {
+    pub fn new(symbol: String) -> Self {
+        Self::with_rules(symbol, SymbolRules::default())
+    }
+
+    pub fn with_rules(symbol: String, rules: SymbolRules) -> Self {
+        assert!(rules.tick_size.is_positive(), "tick size must be positive");
+        assert!(!rules.lot_size.is_zero(), "lot size must be positive");
+        Self {
+            bid_map: BTreeMap::new(),
+            ask_map: BTreeMap::new(),
+            symbol,
+            last_order_id: 0,
+            next_expiry: None,
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            last_trade_price: None,
+            last_trade_id: 0,
+            rules,
+            halted: false,
+            recent_trades: VecDeque::new(),
+            phase: TradingPhase::Continuous,
+            session: SessionStats::default(),
+            vwap: VwapWindow::default(),
+            trade_history: TradeHistory::default(),
+            pegs: Pegs::default(),
+            index: HashMap::new(),
+            changed: ChangedLevels::default(),
+            dropped_stops: Vec::new(),
+            clock: system_clock,
+        }
+    }
+
+    // Where trade and order timestamps come from; the system clock unless
+    // replaced.
+    pub fn set_clock(&mut self, clock: fn() -> i64) {
+        self.clock = clock;
+    }
+
+    // How far back vwap() looks, DEFAULT_VWAP_WINDOW_MILLIS unless changed.
+    pub fn set_vwap_window(&mut self, millis: i64) {
+        assert!(millis > 0, "VWAP window must be positive");
+        self.vwap.window_millis = millis;
+    }
+
+    // How many trades recent_trades() can go back, DEFAULT_TRADE_HISTORY
+    // unless changed. Shrinking it drops the oldest at once.
+    pub fn set_trade_history(&mut self, limit: usize) {
+        assert!(limit > 0, "trade history must hold at least one trade");
+        self.trade_history.limit = limit;
+        self.trade_history.truncate();
+    }
+
+    // The last `limit` trades, or as many as the history holds, oldest first.
+    pub fn recent_trades(&self, limit: usize) -> Vec<TradeEvent> {
+        let trades = &self.trade_history.trades;
+        let skip = trades.len().saturating_sub(limit);
+        trades.range(skip..).cloned().collect()
+    }
+
+    // Volume-weighted average price of the trades inside the window, None if
+    // there were none.
+    pub fn vwap(&self) -> Option<f64> {
+        self.vwap.value((self.clock)())
+    }
+
+    pub fn rules(&self) -> &SymbolRules {
+        &self.rules
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    // Stops matching: limit orders from here on rest where they are priced
+    // until uncross().
+    pub fn start_auction(&mut self) {
+        self.phase = TradingPhase::Auction;
+    }
+
+    // The price an uncross would trade at and how much it would trade: the
+    // price crossing the most volume, then leaving the smallest imbalance,
+    // then nearest the last trade price, then the lowest.
+    pub fn auction_price(&self) -> Option<(Price, Qty)> {
+        // all-or-none orders sit the auction out
+        let open = |(_, queue): (&Price, &PriceLevel)| -> Qty {
+            queue
+                .iter()
+                .filter(|o| !o.all_or_none)
+                .map(|o| o.quantity)
+                .sum()
+        };
+        let volume_at = |price: Price| -> (Qty, Qty) {
+            let demand = self.bid_map.range(price..).map(open).sum();
+            let supply = self.ask_map.range(..=price).map(open).sum();
+            (demand, supply)
+        };
+        let reference = self.last_trade_price;
+        self.bid_map
+            .keys()
+            .chain(self.ask_map.keys())
+            .map(|&price| {
+                let (demand, supply) = volume_at(price);
+                (price, demand.min(supply), demand.abs_diff(supply))
+            })
+            .filter(|&(_, volume, _)| !volume.is_zero())
+            .min_by_key(|&(price, volume, imbalance)| {
+                let distance = reference.map_or(Price::ZERO, |r| (price - r).abs());
+                (std::cmp::Reverse(volume), imbalance, distance, price)
+            })
+            .map(|(price, volume, _)| (price, volume))
+    }
+
+    // Ends the auction: every crossing order trades at the single auction
+    // price, best price first and in time priority within a price, and what
+    // doesn't cross stays resting as the book goes back to continuous
+    // trading. Of each pair the later order counts as the taker. All-or-none
+    // orders take no part and keep their places.
+    pub fn uncross(&mut self) -> Vec<TradeEvent> {
+        self.phase = TradingPhase::Continuous;
+        let Some((price, mut volume)) = self.auction_price() else {
+            return self.run_stops(vec![]);
+        };
+        let now = (self.clock)();
+        let mut events = Vec::new();
+        // the first order at each price that isn't all-or-none
+        let first_open = |(&price, queue): (&Price, &PriceLevel)| {
+            queue
+                .iter()
+                .position(|o| !o.all_or_none)
+                .map(|at| (price, at))
+        };
+        while !volume.is_zero() {
+            let (Some((bid_price, bid_at)), Some((ask_price, ask_at))) = (
+                self.bid_map.iter().rev().find_map(first_open),
+                self.ask_map.iter().find_map(first_open),
+            ) else {
+                break;
+            };
+            let (bid, ask) = (
+                &self.bid_map[&bid_price][bid_at],
+                &self.ask_map[&ask_price][ask_at],
+            );
+            let quantity = volume.min(bid.quantity).min(ask.quantity);
+            let (taker, maker) = if bid.order_id > ask.order_id {
+                (bid, ask)
+            } else {
+                (ask, bid)
+            };
+            let mut taker = Taker {
+                order_id: taker.order_id,
+                user: &taker.user,
+                tags: &taker.tags,
+                side: taker.side.clone(),
+                now,
+                last_trade_id: &mut self.last_trade_id,
+                index: &mut self.index,
+                changed: &mut self.changed,
+            };
+            events.push(taker.trade_at(maker, quantity, price));
+            volume -= quantity;
+
+            self.changed.mark(&Side::Buy, bid_price);
+            self.changed.mark(&Side::Sell, ask_price);
+            for (map, price, at) in [
+                (&mut self.bid_map, bid_price, bid_at),
+                (&mut self.ask_map, ask_price, ask_at),
+            ] {
+                let level = map.get_mut(&price).unwrap();
+                if let Some(filled) = level.fill(at, quantity) {
+                    self.index.remove(&filled.order_id);
+                }
+                if level.is_empty() {
+                    map.remove(&price);
+                }
+            }
+        }
+        self.run_stops(events)
+    }
+
+    // The last trade price and the current session's figures.
+    pub fn stats(&self) -> SymbolStats {
+        SymbolStats {
+            symbol: self.symbol.clone(),
+            last_price: self.last_trade_price,
+            session: self.session,
+        }
+    }
+
+    // Starts a new session opening at the last one's close.
+    pub fn reset_session(&mut self) {
+        self.session = SessionStats {
+            open: self.session.close,
+            ..SessionStats::default()
+        };
+    }
+
+    // Cancels everything in the book, stops included, and halts it until
+    // resume(). Returns the orders closed: bids, asks, then stops.
+    pub fn clear(&mut self) -> Vec<Order> {
+        self.halted = true;
+        self.next_expiry = None;
+        for (side, map) in [(Side::Buy, &self.bid_map), (Side::Sell, &self.ask_map)] {
+            for &price in map.keys() {
+                self.changed.mark(&side, price);
+            }
+        }
+        let mut cancelled: Vec<Order> = [
+            &mut self.bid_map,
+            &mut self.ask_map,
+            &mut self.buy_stops,
+            &mut self.sell_stops,
+        ]
+        .into_iter()
+        .flat_map(|map| std::mem::take(map).into_values().flatten())
+        .collect();
+        self.index.clear();
+        self.pegs = Pegs::default();
+        for order in &mut cancelled {
+            order.state = OrderState::Close;
+        }
+        cancelled
+    }
+
+    // Lifts a circuit breaker halt. The breaker's window starts afresh, so
+    // the trades that tripped it can't trip it again.
+    pub fn resume(&mut self) {
+        self.halted = false;
+        self.recent_trades.clear();
+    }
+
+    // Checks an incoming order against the book's state and then, through
+    // Order::validate, on its own under the book's rules, before it touches
+    // the book. The add_* methods run this first, so nothing invalid ever
+    // rests or trades.
+    pub fn validate(&self, order: &Order) -> Result<(), AddOrderError> {
+        if order.symbol != self.symbol {
+            return Err(AddOrderError::SymbolMismatch);
+        }
+        if self.halted {
+            return Err(AddOrderError::Halted);
+        }
+        let rests = order.price.is_some() || order.stop_price.is_some();
+        if self.phase == TradingPhase::Auction
+            && (!rests
+                || order.time_in_force != TimeInForce::Gtc
+                || order.min_quantity.is_some()
+                || order.all_or_none)
+        {
+            return Err(AddOrderError::AuctionOpen);
+        }
+        order.validate(&self.rules).map_err(AddOrderError::Invalid)
+    }
+
+    pub fn snapshot(&self) -> BookSnapshot {
+        let placed = |maps: [&PriceMap; 2]| -> Vec<PlacedOrder> {
+            maps.into_iter()
+                .flatten()
+                .flat_map(|(&price, queue)| {
+                    queue
+                        .iter()
+                        .enumerate()
+                        .map(move |(position, order)| PlacedOrder {
+                            price,
+                            position,
+                            order: order.clone(),
+                        })
+                })
+                .collect()
+        };
+        BookSnapshot {
+            symbol: self.symbol.clone(),
+            last_order_id: self.last_order_id,
+            last_trade_price: self.last_trade_price,
+            last_trade_id: self.last_trade_id,
+            rules: self.rules,
+            halted: self.halted,
+            phase: self.phase,
+            session: self.session,
+            trades: self.trade_history.trades.iter().cloned().collect(),
+            breaker_window: self.recent_trades.iter().copied().collect(),
+            vwap_fills: self.vwap.fills.iter().copied().collect(),
+            vwap_window_millis: self.vwap.window_millis,
+            orders: placed([&self.bid_map, &self.ask_map]),
+            stops: placed([&self.buy_stops, &self.sell_stops]),
+        }
+    }
+
+    // Rebuilds a book from `snapshot`, queueing each level by position.
+    pub fn from_snapshot(snapshot: BookSnapshot) -> OrderBook {
+        let mut book = OrderBook::with_rules(snapshot.symbol, snapshot.rules);
+        book.halted = snapshot.halted;
+        book.phase = snapshot.phase;
+        book.session = snapshot.session;
+        book.trade_history.trades = snapshot.trades.into();
+        book.trade_history.truncate();
+        book.recent_trades = snapshot.breaker_window.into();
+        book.vwap.window_millis = snapshot.vwap_window_millis;
+        for (at, price, quantity) in snapshot.vwap_fills {
+            book.vwap.record(at, price, quantity);
+        }
+        book.last_order_id = snapshot.last_order_id;
+        book.last_trade_price = snapshot.last_trade_price;
+        book.last_trade_id = snapshot.last_trade_id;
+        for (mut placed, resting) in [(snapshot.orders, true), (snapshot.stops, false)] {
+            placed.sort_by_key(|p| p.position);
+            for PlacedOrder { price, order, .. } in placed {
+                if let Some(at) = order.expires_at {
+                    book.next_expiry = Some(book.next_expiry.map_or(at, |next| next.min(at)));
+                }
+                if resting {
+                    if order.peg.is_some() {
+                        book.pegs.ids.push(order.order_id);
+                    }
+                    book.rest(price, order);
+                    continue;
+                }
+                let stops = match order.side {
+                    Side::Buy => &mut book.buy_stops,
+                    Side::Sell => &mut book.sell_stops,
+                };
+                Self::insert_order(stops, price, order);
+            }
+        }
+        book.pegs.ids.sort_unstable();
+        book
+    }
+
+    // Writes the book's snapshot, bincode by default or JSON with the
+    // json_snapshots feature, for load to rebuild it from.
+    pub fn save<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_book_file(writer, &self.snapshot())
+    }
+
+    pub fn load<R: Read>(reader: R) -> io::Result<OrderBook> {
+        read_book_file(reader).map(OrderBook::from_snapshot)
+    }
+
+    // An IOC order never rests, so whatever its trades don't cover was
+    // dropped. A FOK order the book can't fill in full, or a post-only order
+    // that would cross, is turned away before the book changes at all.
+    pub fn add_limit_order(&mut self, order: Order) -> Result<MatchResult, AddOrderError> {
+        self.validate(&order)?;
+        let Some(price) = order.price else {
+            return Err(AddOrderError::Invalid(ValidationError::MissingPrice));
+        };
+        self.add_priced_order(order, price)
+    }
+
+    // Works a limit order at the price its peg gives it off the best price on
+    // its side, then keeps it following that price: each time it moves, the
+    // order goes to the back of the level its peg now points at, trading
+    // first if that crosses. Pegged orders never count towards the price
+    // they follow, so with nothing else on its side it is turned away.
+    pub fn add_pegged_order(&mut self, mut order: Order) -> Result<MatchResult, AddOrderError> {
+        self.validate(&order)?;
+        let Some(peg) = order.peg else {
+            return Err(AddOrderError::Invalid(ValidationError::MissingPrice));
+        };
+        let reference = self
+            .peg_reference(&order.side)
+            .ok_or(AddOrderError::NoLiquidity)?;
+        let price = peg
+            .price(&order.side, reference)
+            .filter(|price| price.is_positive())
+            .ok_or(AddOrderError::InvalidPegPrice)?;
+        notional(price, order.quantity)
+            .map_err(|_| AddOrderError::Invalid(ValidationError::NotionalOverflow { price }))?;
+        order.price = Some(price);
+        let mut result = self.add_priced_order(order, price)?;
+        if let Some(order_id) = result.resting_order_id {
+            self.pegs.ids.push(order_id);
+        }
+        result.limit_price = Some(price);
+        Ok(result)
+    }
+
+    // The rest of add_limit_order, for an order already checked and priced.
+    fn add_priced_order(
+        &mut self,
+        mut order: Order,
+        price: Price,
+    ) -> Result<MatchResult, AddOrderError> {
+        if order.post_only && self.crosses(&order.side, price) {
+            return Err(AddOrderError::WouldCross);
+        }
+        if order.time_in_force == TimeInForce::Fok
+            && !self.can_fill(&order.side, price, order.quantity)
+        {
+            return Err(AddOrderError::Unfillable);
+        }
+        if order
+            .min_quantity
+            .is_some_and(|min| !self.can_fill(&order.side, price, min))
+        {
+            return Err(AddOrderError::BelowMinQuantity);
+        }
+        if order.all_or_none
+            && self.crosses(&order.side, price)
+            && !self.can_fill(&order.side, price, order.quantity)
+        {
+            return Err(AddOrderError::AllOrNoneUnfilled);
+        }
+        let order_id = self.next_order_id(&mut order);
+        let quantity = order.quantity;
+        // during an auction everything rests, IOC and FOK having been refused
+        let rests = order.time_in_force == TimeInForce::Gtc;
+        let fills = self.place_limit_order(order, price);
+        let mut result = MatchResult::new(Some(order_id), quantity, &fills);
+        if rests && !result.remaining_quantity.is_zero() {
+            result.resting_order_id = Some(order_id);
+        }
+        result.events = self.run_stops(fills);
+        Ok(result)
+    }
+
+    // Parks a stop order until a trade reaches its stop price, then sends it
+    // in as a limit or market order. If the last trade has already reached it,
+    // it goes in straight away. The id it gets is kept once it triggers, so it
+    // cancels the same way waiting or resting.
+    pub fn add_stop_order(
+        &mut self,
+        mut order: Order,
+    ) -> Result<(u64, Vec<TradeEvent>), AddOrderError> {
+        self.validate(&order)?;
+        let Some(stop_price) = order.stop_price else {
+            return Err(AddOrderError::Invalid(ValidationError::MissingPrice));
+        };
+        let order_id = self.next_order_id(&mut order);
+        order.received_at = (self.clock)();
+        if let Some(at) = order.expires_at {
+            self.next_expiry = Some(self.next_expiry.map_or(at, |next| next.min(at)));
+        }
+        let stops = match order.side {
+            Side::Buy => &mut self.buy_stops,
+            Side::Sell => &mut self.sell_stops,
+        };
+        Self::insert_order(stops, stop_price, order);
+        Ok((order_id, self.run_stops(vec![])))
+    }
+
+    // Gives an accepted order the next id in the book's sequence.
+    fn next_order_id(&mut self, order: &mut Order) -> u64 {
+        self.last_order_id += 1;
+        order.order_id = self.last_order_id;
+        order.order_id
+    }
+
+    // Matches an order that already has its id up to `price`, its limit, and
+    // rests whatever is left at the back of its level, stamped with the time
+    // it got there.
+    fn place_limit_order(&mut self, mut order: Order, price: Price) -> Vec<TradeEvent> {
+        if let Some(at) = order.expires_at {
+            self.next_expiry = Some(self.next_expiry.map_or(at, |next| next.min(at)));
+        }
+        order.received_at = (self.clock)();
+        if self.phase == TradingPhase::Auction {
+            self.rest(price, order);
+            return vec![];
+        }
+        let mut to_fill = order.quantity;
+        let mut taker = Taker {
+            order_id: order.order_id,
+            user: &order.user,
+            tags: &order.tags,
+            side: order.side.clone(),
+            now: order.received_at,
+            last_trade_id: &mut self.last_trade_id,
+            index: &mut self.index,
+            changed: &mut self.changed,
+        };
+
+        let mut events = Vec::new();
+
+        // Most orders either rest or are absorbed by the best opposite level,
+        // so that level is filled directly; only a sweep past it falls back to
+        // match_orders for the deeper levels.
+        match taker.side {
+            Side::Buy => {
+                if let Some(mut best) = self.ask_map.first_entry()
+                    && price >= *best.key()
+                {
+                    let best_price = *best.key();
+                    to_fill =
+                        fill_level(best.get_mut(), best_price, to_fill, &mut taker, &mut events);
+                    if best.get().is_empty() {
+                        best.remove();
+                    }
+                    if !to_fill.is_zero() {
+                        let (left, deeper) = Self::match_orders(
+                            to_fill,
+                            Some(price),
+                            &mut self.ask_map,
+                            true,
+                            &mut taker,
+                        );
+                        to_fill = left;
+                        events.extend(deeper);
+                    }
+                }
+                if !to_fill.is_zero() && order.time_in_force == TimeInForce::Gtc {
+                    order.quantity = to_fill;
+                    self.rest(price, order);
+                }
+            }
+            Side::Sell => {
+                if let Some(mut best) = self.bid_map.last_entry()
+                    && price <= *best.key()
+                {
+                    let best_price = *best.key();
+                    to_fill =
+                        fill_level(best.get_mut(), best_price, to_fill, &mut taker, &mut events);
+                    if best.get().is_empty() {
+                        best.remove();
+                    }
+                    if !to_fill.is_zero() {
+                        let (left, deeper) = Self::match_orders(
+                            to_fill,
+                            Some(price),
+                            &mut self.bid_map,
+                            false,
+                            &mut taker,
+                        );
+                        to_fill = left;
+                        events.extend(deeper);
+                    }
+                }
+
+                if !to_fill.is_zero() && order.time_in_force == TimeInForce::Gtc {
+                    order.quantity = to_fill;
+                    self.rest(price, order);
+                }
+            }
+        };
+        events
+    }
+
+    // Removes every resting order whose expiry is at or before `now` and
+    // returns them closed. Cheap when nothing is due.
+    pub fn expire_orders(&mut self, now: i64) -> Vec<Order> {
+        if self.next_expiry.is_none_or(|next| next > now) {
+            return vec![];
+        }
+        let mut expired = Vec::new();
+        let mut next_expiry = None;
+        // stops aren't in the displayed book, so their levels aren't marked
+        for (map, side) in [
+            (&mut self.bid_map, Some(Side::Buy)),
+            (&mut self.ask_map, Some(Side::Sell)),
+            (&mut self.buy_stops, None),
+            (&mut self.sell_stops, None),
+        ] {
+            map.retain(|&price, level| {
+                let before = level.len();
+                level.retain(|order| match order.expires_at {
+                    Some(at) if at <= now => {
+                        expired.push(Order {
+                            state: OrderState::Close,
+                            ..order.clone()
+                        });
+                        false
+                    }
+                    Some(at) => {
+                        next_expiry = Some(next_expiry.map_or(at, |next: i64| next.min(at)));
+                        true
+                    }
+                    None => true,
+                });
+                if let Some(side) = &side
+                    && level.len() < before
+                {
+                    self.changed.mark(side, price);
+                }
+                !level.is_empty()
+            });
+        }
+        self.next_expiry = next_expiry;
+        for order in &expired {
+            self.index.remove(&order.order_id);
+        }
+        self.reprice_pegs_after_removal();
+        expired
+    }
+
+    // Whether a limit order at `price` would trade against the opposite touch.
+    fn crosses(&self, side: &Side, price: Price) -> bool {
+        match side {
+            Side::Buy => self
+                .ask_map
+                .first_key_value()
+                .is_some_and(|(&ask, _)| price >= ask),
+            Side::Sell => self
+                .bid_map
+                .last_key_value()
+                .is_some_and(|(&bid, _)| price <= bid),
+        }
+    }
+
+    // Whether the opposite side holds `quantity` at `price` or better. It
+    // walks the book the way a sweep would, so an all-or-none order counts
+    // only if what is still needed when the sweep reaches it covers all of it.
+    // A level that can't finish the fill is taken whole by its total; every
+    // all-or-none order in it fits, since what's needed covers all of them.
+    fn can_fill(&self, side: &Side, price: Price, quantity: Qty) -> bool {
+        let levels: Box<dyn Iterator<Item = &PriceLevel>> = match side {
+            Side::Buy => Box::new(self.ask_map.range(..=price).map(|l| l.1)),
+            Side::Sell => Box::new(self.bid_map.range(price..).rev().map(|l| l.1)),
+        };
+        let mut needed = quantity;
+        for level in levels {
+            if level.total_quantity() < needed {
+                needed -= level.total_quantity();
+                continue;
+            }
+            for order in level {
+                if order.all_or_none && order.quantity > needed {
+                    continue;
+                }
+                needed -= needed.min(order.quantity);
+                if needed.is_zero() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Pulls a resting order out of the book. Whatever already filled stays
+    // filled; only the remaining quantity is withdrawn.
+    pub fn cancel_order(&mut self, order_id: u64) -> Result<Order, CancelError> {
+        let mut order = self.take_order(order_id).or_else(|e| {
+            [&mut self.buy_stops, &mut self.sell_stops]
+                .into_iter()
+                .find_map(|stops| find_and_remove(stops, order_id))
+                .ok_or(e)
+        })?;
+        order.state = OrderState::Close;
+        self.reprice_pegs_after_removal();
+        Ok(order)
+    }
+
+    // Pulls every order the user has, resting or waiting on a stop, and
+    // returns them closed. Everyone else keeps their place in the queue.
+    pub fn cancel_all_for_user(&mut self, user: &str) -> Vec<Order> {
+        let mut cancelled = Vec::new();
+        for (map, side) in [
+            (&mut self.bid_map, Some(Side::Buy)),
+            (&mut self.ask_map, Some(Side::Sell)),
+            (&mut self.buy_stops, None),
+            (&mut self.sell_stops, None),
+        ] {
+            map.retain(|&price, level| {
+                let (mine, others): (Vec<Order>, Vec<Order>) = std::mem::take(level)
+                    .into_iter()
+                    .partition(|order| order.user == user);
+                if let Some(side) = &side
+                    && !mine.is_empty()
+                {
+                    self.changed.mark(side, price);
+                }
+                cancelled.extend(mine);
+                *level = others.into_iter().collect();
+                !level.is_empty()
+            });
+        }
+        for order in &mut cancelled {
+            self.index.remove(&order.order_id);
+            order.state = OrderState::Close;
+        }
+        self.reprice_pegs_after_removal();
+        cancelled
+    }
+
+    // Changes the price and remaining quantity of a resting order. Shrinking
+    // it in place keeps its spot in the queue; any other change sends it to
+    // the back of its new level, matching first if the new price crosses.
+    // Amending down to zero cancels it. The amended order must pass the same
+    // checks as a new one. Moving a pegged order unpegs it; shrinking it in
+    // place doesn't. An all-or-none order can't be moved to where it would
+    // fill only in part.
+    pub fn amend_order(
+        &mut self,
+        order_id: u64,
+        new_price: Price,
+        new_quantity: Qty,
+    ) -> Result<Vec<TradeEvent>, CancelError> {
+        if new_quantity.is_zero() {
+            return self.cancel_order(order_id).map(|_| vec![]);
+        }
+        let current = self.find_order(order_id)?;
+        let in_place = current.price == Some(new_price) && new_quantity <= current.quantity;
+        let amended = Order {
+            price: Some(new_price),
+            quantity: new_quantity,
+            peg: None,
+            ..current.clone()
+        };
+        self.validate(&amended).map_err(CancelError::Rejected)?;
+        if in_place {
+            self.set_order_quantity(order_id, new_quantity)?;
+            return Ok(vec![]);
+        }
+        // checked before the order leaves the book, so a refusal keeps it resting
+        if amended.post_only && self.crosses(&amended.side, new_price) {
+            return Err(CancelError::Rejected(AddOrderError::WouldCross));
+        }
+        if amended.all_or_none
+            && self.crosses(&amended.side, new_price)
+            && !self.can_fill(&amended.side, new_price, new_quantity)
+        {
+            return Err(CancelError::Rejected(AddOrderError::AllOrNoneUnfilled));
+        }
+        let mut order = self.take_order(order_id)?;
+        order.price = Some(new_price);
+        order.quantity = new_quantity;
+        order.peg = None;
+        let events = self.place_limit_order(order, new_price);
+        Ok(self.run_stops(events))
+    }
+
+    // Shrinks a resting order without moving it in its queue and returns how
+    // much was freed. Reducing to zero cancels it; growing an order goes
+    // through amend_order and costs it its place.
+    pub fn reduce_order(&mut self, order_id: u64, new_quantity: Qty) -> Result<Qty, CancelError> {
+        let quantity = self.find_order(order_id)?.quantity;
+        if new_quantity > quantity {
+            return Err(CancelError::WouldGrow(order_id));
+        }
+        if !new_quantity.is_multiple_of(self.rules.lot_size) {
+            return Err(CancelError::Rejected(AddOrderError::Invalid(
+                ValidationError::OddLot {
+                    quantity: new_quantity,
+                    lot_size: self.rules.lot_size,
+                },
+            )));
+        }
+        let freed = quantity - new_quantity;
+        if new_quantity.is_zero() {
+            self.cancel_order(order_id)?;
+        } else {
+            self.set_order_quantity(order_id, new_quantity)?;
+        }
+        Ok(freed)
+    }
+
+    // None once the order has filled or been cancelled, and for stops that
+    // haven't triggered.
+    pub fn get_order(&self, order_id: u64) -> Option<OrderView> {
+        let (side, price) = self.index.get(&order_id)?;
+        let queue = &self.side_map(side)[price];
+        let position = queue.iter().position(|o| o.order_id == order_id)?;
+        Some(OrderView::new(*price, position, &queue[position]))
+    }
+
+    // Bids then asks, lowest price first. A full scan of the book; an index
+    // by user would only have to replace the filter.
+    pub fn orders_for_user(&self, user: &str) -> Vec<OrderView> {
+        self.resting()
+            .filter(|(_, _, order)| order.user == user)
+            .map(|(price, position, order)| OrderView::new(price, position, order))
+            .collect()
+    }
+
+    // Every resting order with its level's price and its place in the queue.
+    fn resting(&self) -> impl Iterator<Item = (Price, usize, &Order)> {
+        [&self.bid_map, &self.ask_map]
+            .into_iter()
+            .flatten()
+            .flat_map(|(&price, queue)| {
+                queue
+                    .iter()
+                    .enumerate()
+                    .map(move |(position, order)| (price, position, order))
+            })
+    }
+
+    pub fn find_order(&self, order_id: u64) -> Result<&Order, CancelError> {
+        self.check_issued(order_id)?;
+        let (side, price) = self
+            .index
+            .get(&order_id)
+            .ok_or(CancelError::NotResting(order_id))?;
+        self.side_map(side)[price]
+            .iter()
+            .find(|o| o.order_id == order_id)
+            .ok_or(CancelError::NotResting(order_id))
+    }
+
+    // Sets a resting order's quantity where it stands in its queue.
+    fn set_order_quantity(&mut self, order_id: u64, quantity: Qty) -> Result<(), CancelError> {
+        let (side, price, level, at) = self.level_of(order_id)?;
+        level.set_quantity(at, quantity);
+        self.changed.mark(&side, price);
+        Ok(())
+    }
+
+    // Gives a resting order a new expiry, or none, without moving it in its
+    // queue. Taking an expiry away can leave next_expiry early; the sweep
+    // then finds nothing due and works it out again.
+    pub fn set_expiry(
+        &mut self,
+        order_id: u64,
+        expires_at: Option<i64>,
+    ) -> Result<(), CancelError> {
+        let (_, _, level, at) = self.level_of(order_id)?;
+        level.set_expiry(at, expires_at);
+        if let Some(at) = expires_at {
+            self.next_expiry = Some(self.next_expiry.map_or(at, |next| next.min(at)));
+        }
+        Ok(())
+    }
+
+    // The side and price of a resting order's level, the level, and the
+    // order's place in it.
+    fn level_of(
+        &mut self,
+        order_id: u64,
+    ) -> Result<(Side, Price, &mut PriceLevel, usize), CancelError> {
+        self.check_issued(order_id)?;
+        let (side, price) = self
+            .index
+            .get(&order_id)
+            .cloned()
+            .ok_or(CancelError::NotResting(order_id))?;
+        let map = match side {
+            Side::Buy => &mut self.bid_map,
+            Side::Sell => &mut self.ask_map,
+        };
+        let level = map
+            .get_mut(&price)
+            .ok_or(CancelError::NotResting(order_id))?;
+        let at = level
+            .iter()
+            .position(|o| o.order_id == order_id)
+            .ok_or(CancelError::NotResting(order_id))?;
+        Ok((side, price, level, at))
+    }
+
+    fn take_order(&mut self, order_id: u64) -> Result<Order, CancelError> {
+        self.check_issued(order_id)?;
+        let (side, price) = self
+            .index
+            .remove(&order_id)
+            .ok_or(CancelError::NotResting(order_id))?;
+        self.changed.mark(&side, price);
+        let map = match side {
+            Side::Buy => &mut self.bid_map,
+            Side::Sell => &mut self.ask_map,
+        };
+        remove_order(map, price, order_id).ok_or(CancelError::NotResting(order_id))
+    }
+
+    fn side_map(&self, side: &Side) -> &PriceMap {
+        match side {
+            Side::Buy => &self.bid_map,
+            Side::Sell => &self.ask_map,
+        }
+    }
+
+    // Puts an order at the back of its level on its own side of the book.
+    fn rest(&mut self, price: Price, order: Order) {
+        self.index
+            .insert(order.order_id, (order.side.clone(), price));
+        self.changed.mark(&order.side, price);
+        let map = match order.side {
+            Side::Buy => &mut self.bid_map,
+            Side::Sell => &mut self.ask_map,
+        };
+        Self::insert_order(map, price, order);
+    }
+
+    // Checks that the index holds exactly the resting orders, each at its
+    // own side and price. A full scan of the book, so for tests and debugging.
+    pub fn validate_index(&self) -> Result<(), String> {
+        for (price, _, order) in self.resting() {
+            match self.index.get(&order.order_id) {
+                Some((side, at)) if *side == order.side && *at == price => {}
+                Some((side, at)) => {
+                    return Err(format!(
+                        "order {} rests at {:?} {} but is indexed at {:?} {}",
+                        order.order_id, order.side, price, side, at
+                    ));
+                }
+                None => return Err(format!("order {} is not indexed", order.order_id)),
+            }
+        }
+        if self.index.len() != self.len() {
+            return Err(format!(
+                "{} orders indexed but {} resting",
+                self.index.len(),
+                self.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_issued(&self, order_id: u64) -> Result<(), CancelError> {
+        if order_id == 0 || order_id > self.last_order_id {
+            return Err(CancelError::UnknownOrder(order_id));
+        }
+        Ok(())
+    }
+
+    pub fn add_market_order(&mut self, mut order: Order) -> Result<MatchResult, AddOrderError> {
+        self.validate(&order)?;
+        if order.price.is_some() {
+            return Err(AddOrderError::Invalid(ValidationError::UnexpectedPrice));
+        }
+        // a market order's limit is the collar, or none
+        let limit = self.collar_price(&order.side).unwrap_or(match order.side {
+            Side::Buy => Price::MAX,
+            Side::Sell => Price::ZERO,
+        });
+        if order
+            .min_quantity
+            .is_some_and(|min| !self.can_fill(&order.side, limit, min))
+        {
+            return Err(AddOrderError::BelowMinQuantity);
+        }
+        let order_id = self.next_order_id(&mut order);
+        let (fills, collared) = self.execute_market_order(&order);
+        let mut result = MatchResult::new(Some(order_id), order.quantity, &fills);
+        result.collared = !collared.is_zero();
+        result.events = self.run_stops(fills);
+        Ok(result)
+    }
+
+    // Prices a market order at the best opposite level and sends it in as a
+    // limit order there, so it trades at that one price and rests whatever
+    // the level doesn't cover (or, IOC, drops it). The market collar isn't
+    // needed: the order never walks past the touch. With the other side
+    // empty there is no price to give it, and it is turned away.
+    pub fn add_market_to_limit_order(
+        &mut self,
+        mut order: Order,
+    ) -> Result<MatchResult, AddOrderError> {
+        self.validate(&order)?;
+        let touch = match order.side {
+            Side::Buy => self.ask_map.first_key_value(),
+            Side::Sell => self.bid_map.last_key_value(),
+        };
+        let Some((&price, _)) = touch else {
+            return Err(AddOrderError::NoLiquidity);
+        };
+        order.price = Some(price);
+        order.market_to_limit = false;
+        let mut result = self.add_limit_order(order)?;
+        result.limit_price = Some(price);
+        Ok(result)
+    }
+
+    // Like add_market_order for an order with a notional budget; the result
+    // says how much of the budget went unspent. `max_quantity` caps the
+    // shares it buys whatever the budget would cover, for the engine's
+    // position limits.
+    pub fn add_notional_order(
+        &mut self,
+        mut order: Order,
+        max_quantity: Option<Qty>,
+    ) -> Result<MatchResult, AddOrderError> {
+        self.validate(&order)?;
+        let Some(budget) = order.notional else {
+            return Err(AddOrderError::Invalid(ValidationError::ZeroQuantity));
+        };
+        let order_id = self.next_order_id(&mut order);
+        let (fills, unspent) = self.execute_notional_order(&order, budget, max_quantity);
+        let mut result = MatchResult::new(Some(order_id), order.quantity, &fills);
+        result.unspent_notional = Some(unspent);
+        result.events = self.run_stops(fills);
+        Ok(result)
+    }
+
+    // Fires every stop the trades in `events` reach, feeding each one's own
+    // trades back in so stops can set each other off, then moves the pegged
+    // orders after the book; a peg that crosses trades, which can set off more
+    // stops. Returns `events` with all the later trades after it. During an
+    // auction stops wait for uncross(), which runs them.
+    fn run_stops(&mut self, mut events: Vec<TradeEvent>) -> Vec<TradeEvent> {
+        self.record_trades(&events);
+        loop {
+            while !self.halted
+                && self.phase == TradingPhase::Continuous
+                && let Some(stop) = self.next_triggered_stop()
+            {
+                let (fills, collared) = match stop.price {
+                    Some(price) => (self.place_limit_order(stop.clone(), price), Qty::ZERO),
+                    None => self.execute_market_order(&stop),
+                };
+                let filled: Qty = fills.iter().map(|e| e.quantity).sum();
+                if filled < stop.quantity && !self.index.contains_key(&stop.order_id) {
+                    self.dropped_stops.push(DroppedStop {
+                        collared: !collared.is_zero(),
+                        order: Order {
+                            quantity: stop.quantity - filled,
+                            state: OrderState::Close,
+                            ..stop
+                        },
+                    });
+                }
+                self.record_trades(&fills);
+                events.extend(fills);
+            }
+            let fills = self.reprice_pegs();
+            if fills.is_empty() {
+                return events;
+            }
+            self.record_trades(&fills);
+            events.extend(fills);
+        }
+    }
+
+    // The best price on `side` among displayed orders that aren't pegged.
+    fn peg_reference(&self, side: &Side) -> Option<Price> {
+        let unpegged = |(&price, queue): (&Price, &PriceLevel)| {
+            queue
+                .iter()
+                .any(|order| order.peg.is_none() && !order.hidden)
+                .then_some(price)
+        };
+        match side {
+            Side::Buy => self.bid_map.iter().rev().find_map(unpegged),
+            Side::Sell => self.ask_map.iter().find_map(unpegged),
+        }
+    }
+
+    // Sends each pegged order whose reference has moved since it was last
+    // worked out to the back of the level its peg now gives it, matching
+    // first if that crosses. A side with nothing left to follow leaves its
+    // pegs where they are. Nothing moves while the book is halted or in an
+    // auction.
+    fn reprice_pegs(&mut self) -> Vec<TradeEvent> {
+        let mut events = Vec::new();
+        if self.halted || self.phase == TradingPhase::Auction || self.pegs.ids.is_empty() {
+            return events;
+        }
+        for side in [Side::Buy, Side::Sell] {
+            let reference = self.peg_reference(&side);
+            let last = match side {
+                Side::Buy => &mut self.pegs.bid_reference,
+                Side::Sell => &mut self.pegs.ask_reference,
+            };
+            if *last == reference {
+                continue;
+            }
+            *last = reference;
+            let Some(reference) = reference else {
+                continue;
+            };
+            for order_id in self.pegs.ids.clone() {
+                let Ok(order) = self.find_order(order_id) else {
+                    continue;
+                };
+                let (Some(peg), Some(current)) = (order.peg, order.price) else {
+                    continue;
+                };
+                let target = peg
+                    .price(&side, reference)
+                    .filter(|price| price.is_positive());
+                if order.side != side || target.is_none_or(|target| target == current) {
+                    continue;
+                }
+                let target = target.unwrap();
+                let mut order = self.take_order(order_id).unwrap();
+                order.price = Some(target);
+                events.extend(self.place_limit_order(order, target));
+            }
+        }
+        let index = &self.index;
+        self.pegs
+            .ids
+            .retain(|order_id| index.contains_key(order_id));
+        events
+    }
+
+    // Re-prices the pegs after orders have left the book. Taking liquidity
+    // away only moves a side's best price away from the other side, so the
+    // pegs following it can't cross.
+    fn reprice_pegs_after_removal(&mut self) {
+        let events = self.reprice_pegs();
+        debug_assert!(events.is_empty(), "a peg crossed after orders left");
+    }
+
+    // Moves the last trade price and session stats along and halts the book
+    // if the circuit breaker trips. The order that trips it still completes;
+    // stops and anything after it wait for resume().
+    fn record_trades(&mut self, events: &[TradeEvent]) {
+        for event in events {
+            self.last_trade_price = Some(event.price);
+            let session = &mut self.session;
+            session.open.get_or_insert(event.price);
+            session.high = Some(
+                session
+                    .high
+                    .map_or(event.price, |high| high.max(event.price)),
+            );
+            session.low = Some(session.low.map_or(event.price, |low| low.min(event.price)));
+            session.close = Some(event.price);
+            session.volume += event.quantity;
+            session.trades += 1;
+            self.vwap
+                .record(event.timestamp, event.price, event.quantity);
+            self.trade_history.record(event);
+            let Some(breaker) = self.rules.circuit_breaker else {
+                continue;
+            };
+            let window_start = event.timestamp - breaker.window_millis;
+            while self
+                .recent_trades
+                .front()
+                .is_some_and(|&(at, _)| at < window_start)
+            {
+                self.recent_trades.pop_front();
+            }
+            let tripped = self.recent_trades.iter().any(|&(_, price)| {
+                (event.price - price).abs().units() as i128 * 10_000
+                    > price.units() as i128 * breaker.move_bps as i128
+            });
+            self.recent_trades.push_back((event.timestamp, event.price));
+            if tripped {
+                self.halted = true;
+            }
+        }
+    }
+
+    // Takes the next stop the last trade has reached: the one whose stop
+    // price was crossed first, then the oldest at that price.
+    fn next_triggered_stop(&mut self) -> Option<Order> {
+        let last = self.last_trade_price?;
+        let sell = self
+            .sell_stops
+            .last_key_value()
+            .filter(|(stop, _)| **stop >= last)
+            .map(|(&stop, queue)| (stop, queue[0].order_id));
+        let buy = self
+            .buy_stops
+            .first_key_value()
+            .filter(|(stop, _)| **stop <= last)
+            .map(|(&stop, queue)| (stop, queue[0].order_id));
+        let (stops, stop) = match (sell, buy) {
+            (Some((sell, sell_id)), Some((_, buy_id))) if sell_id < buy_id => {
+                (&mut self.sell_stops, sell)
+            }
+            (_, Some((buy, _))) => (&mut self.buy_stops, buy),
+            (Some((sell, _)), None) => (&mut self.sell_stops, sell),
+            (None, None) => return None,
+        };
+        let queue = stops.get_mut(&stop).unwrap();
+        let order = queue.pop_front();
+        if queue.is_empty() {
+            stops.remove(&stop);
+        }
+        order
+    }
+
+    fn execute_market_order(&mut self, order: &Order) -> (Vec<TradeEvent>, Qty) {
+        if let Some(budget) = order.notional {
+            return (
+                self.execute_notional_order(order, budget, None).0,
+                Qty::ZERO,
+            );
+        }
+        let side = &order.side;
+        let remaining_quantity_to_be_filled = order.quantity;
+        // inside the collar a market order sweeps like a limit order at its edge
+        let collar = self.collar_price(side);
+
+        let (price_order_map, ascending) = match side {
+            Side::Buy => (&mut self.ask_map, true),
+            Side::Sell => (&mut self.bid_map, false),
+        };
+
+        let (to_fill, events) = Self::match_orders(
+            remaining_quantity_to_be_filled,
+            collar,
+            price_order_map,
+            ascending,
+            &mut Taker {
+                order_id: order.order_id,
+                user: &order.user,
+                tags: &order.tags,
+                side: side.clone(),
+                now: (self.clock)(),
+                last_trade_id: &mut self.last_trade_id,
+                index: &mut self.index,
+                changed: &mut self.changed,
+            },
+        );
+        // levels left past the collar mean it, not the book, stopped the
+        // order; those left inside it hold only all-or-none orders too big
+        // to take
+        let beyond_collar = collar.is_some_and(|edge| match ascending {
+            true => price_order_map
+                .range((Excluded(edge), Unbounded))
+                .next()
+                .is_some(),
+            false => price_order_map.range(..edge).next_back().is_some(),
+        });
+        let collared = if beyond_collar { to_fill } else { Qty::ZERO };
+        (events, collared)
+    }
+
+    // The furthest price a market order on `side` may trade at, if the book
+    // has a collar and something to measure it from.
+    fn collar_price(&self, side: &Side) -> Option<Price> {
+        let bps = self.rules.market_collar_bps?;
+        let touch = match side {
+            Side::Buy => self.ask_map.first_key_value(),
+            Side::Sell => self.bid_map.last_key_value(),
+        };
+        let reference = self.last_trade_price.or(touch.map(|(&price, _)| price))?;
+        let band = Price::from_units((reference.units() as i128 * bps as i128 / 10_000) as i64);
+        Some(match side {
+            Side::Buy => reference + band,
+            Side::Sell => reference - band,
+        })
+    }
+
+    // Buys (or sells) whole lots level by level while the budget covers
+    // them; at the level where it runs short it takes only as many as it can
+    // still afford. Returns the trades and the unspent budget.
+    fn execute_notional_order(
+        &mut self,
+        order: &Order,
+        mut budget: Price,
+        max_quantity: Option<Qty>,
+    ) -> (Vec<TradeEvent>, Price) {
+        let mut shares_left = max_quantity.unwrap_or(Qty::MAX);
+        let collar = self.collar_price(&order.side);
+        let book = match order.side {
+            Side::Buy => &mut self.ask_map,
+            Side::Sell => &mut self.bid_map,
+        };
+        let mut taker = Taker {
+            order_id: order.order_id,
+            user: &order.user,
+            tags: &order.tags,
+            side: order.side.clone(),
+            now: (self.clock)(),
+            last_trade_id: &mut self.last_trade_id,
+            index: &mut self.index,
+            changed: &mut self.changed,
+        };
+
+        let mut events = Vec::new();
+        loop {
+            let level = match order.side {
+                Side::Buy => book.first_entry(),
+                Side::Sell => book.last_entry(),
+            };
+            let Some(mut level) = level else {
+                break;
+            };
+            let price = *level.key();
+            let beyond_collar = collar.is_some_and(|edge| match order.side {
+                Side::Buy => price > edge,
+                Side::Sell => price < edge,
+            });
+            if beyond_collar {
+                break;
+            }
+            let affordable = (budget.units() as i128 * QTY_SCALE as i128)
+                .checked_div(price.units() as i128)
+                .unwrap_or(0)
+                .clamp(0, u64::MAX as i128);
+            let to_fill = shares_left
+                .min(Qty::from_units(affordable as u64))
+                .round_down_to(self.rules.lot_size);
+            if to_fill.is_zero() {
+                break;
+            }
+            let filled =
+                to_fill - fill_level(level.get_mut(), price, to_fill, &mut taker, &mut events);
+            // no more than the budget, so it can't overflow
+            budget -= notional(price, filled).unwrap_or(budget);
+            shares_left -= filled;
+            // a level left standing means the budget or the cap ran out in it,
+            // or what they still covered was less than an all-or-none order
+            // there
+            if !level.get().is_empty() {
+                break;
+            }
+            level.remove();
+        }
+        (events, budget)
+    }
+
+    // Fills from the best level of `book` inwards, stopping at `limit` if
+    // there is one; with none it sweeps at any price.
+    pub fn match_orders(
+        mut to_fill: Qty,
+        limit: Option<Price>,
+        book: &mut PriceMap,
+        ascending: bool,
+        taker: &mut Taker,
+    ) -> (Qty, Vec<TradeEvent>) {
+        let mut events = Vec::new();
+        // a level left standing holds only all-or-none orders too big to
+        // take, so the next level is looked up past the last one reached;
+        // nothing beyond the levels actually reached is looked at
+        let mut reached: Option<Price> = None;
+        while !to_fill.is_zero() {
+            let next = match (ascending, reached) {
+                (true, None) => book.keys().next(),
+                (true, Some(last)) => book.range((Excluded(last), Unbounded)).next().map(|l| l.0),
+                (false, None) => book.keys().next_back(),
+                (false, Some(last)) => book.range(..last).next_back().map(|l| l.0),
+            };
+            let Some(&price) = next else {
+                break;
+            };
+            if let Some(limit) = limit {
+                let price_cross = if ascending {
+                    limit >= price // Buy vs Ask
+                } else {
+                    limit <= price // Sell vs Bid
+                };
+
+                if !price_cross {
+                    break;
+                }
+            }
+
+            let queue = book.get_mut(&price).unwrap();
+            to_fill = fill_level(queue, price, to_fill, taker, &mut events);
+
+            if queue.is_empty() {
+                book.remove(&price);
+            }
+            reached = Some(price);
+        }
+
+        (to_fill, events)
+    }
+
+    // Displayed orders queue in time order ahead of every hidden one at their
+    // price; hidden orders queue in time order behind them.
+    fn insert_order(price_order_map: &mut PriceMap, price: Price, order: Order) {
+        let level = price_order_map.entry(price).or_default();
+        let at = match order.hidden {
+            true => level.len(),
+            false => level.iter().position(|o| o.hidden).unwrap_or(level.len()),
+        };
+        level.insert(at, order);
+    }
+
+    // Resting bids by price, best (highest) last.
+    pub fn bids(&self) -> &BTreeMap<Price, PriceLevel> {
+        &self.bid_map
+    }
+
+    // Resting asks by price, best (lowest) first.
+    pub fn asks(&self) -> &BTreeMap<Price, PriceLevel> {
+        &self.ask_map
+    }
+
+    // Resting bids in the order they'd fill: best price first, then oldest
+    // first within a level.
+    pub fn iter_bids(&self) -> impl Iterator<Item = &Order> {
+        self.bid_map.values().rev().flatten()
+    }
+
+    // Resting asks in the order they'd fill.
+    pub fn iter_asks(&self) -> impl Iterator<Item = &Order> {
+        self.ask_map.values().flatten()
+    }
+
+    pub fn iter_side(&self, side: Side) -> Box<dyn Iterator<Item = &Order> + '_> {
+        match side {
+            Side::Buy => Box::new(self.iter_bids()),
+            Side::Sell => Box::new(self.iter_asks()),
+        }
+    }
+
+    // Resting orders on both sides; stop orders waiting off the book aren't
+    // counted.
+    pub fn len(&self) -> usize {
+        self.iter_bids().count() + self.iter_asks().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bid_map.is_empty() && self.ask_map.is_empty()
+    }
+
+    pub fn total_quantity(&self, side: Side) -> Qty {
+        self.side_map(&side)
+            .values()
+            .map(PriceLevel::total_quantity)
+            .sum()
+    }
+
+    // Everything resting at `price` on `side`, hidden orders included; zero
+    // if nothing is.
+    pub fn level_quantity(&self, side: Side, price: Price) -> Qty {
+        self.side_map(&side)
+            .get(&price)
+            .map_or(Qty::ZERO, PriceLevel::total_quantity)
+    }
+
+    // Price and displayed quantity of the best level on each side. Hidden
+    // quantity is left out, and a level holding only hidden orders is passed
+    // over.
+    pub fn best_bid(&self) -> Option<(Price, Qty)> {
+        self.bid_map.iter().rev().find_map(displayed_summary)
+    }
+
+    pub fn best_ask(&self) -> Option<(Price, Qty)> {
+        self.ask_map.iter().find_map(displayed_summary)
+    }
+
+    // Both need a two-sided book.
+    pub fn spread(&self) -> Option<Price> {
+        Some(self.best_ask()?.0 - self.best_bid()?.0)
+    }
+
+    pub fn mid_price(&self) -> Option<f64> {
+        let (bid, ask) = (self.best_bid()?.0, self.best_ask()?.0);
+        Some((bid.units() + ask.units()) as f64 / 2.0 / PRICE_SCALE as f64)
+    }
+
+    // Top `levels` price levels per side, without hidden quantity: hidden
+    // orders aren't counted, and a level with nothing else isn't shown.
+    // Levels left empty mid-operation are passed over too, so they can't
+    // shift the depth or its checksum. Only the levels returned are read, and
+    // no order is cloned.
+    pub fn depth(&self, levels: usize) -> DepthSnapshot {
+        let summarize = |(&price, level): (&Price, &PriceLevel)| {
+            let (quantity, orders) = level.displayed();
+            (orders > 0).then_some(DepthLevel {
+                price,
+                quantity,
+                orders,
+            })
+        };
+        let bids: Vec<DepthLevel> = self
+            .bid_map
+            .iter()
+            .rev()
+            .filter_map(summarize)
+            .take(levels)
+            .collect();
+        let asks: Vec<DepthLevel> = self
+            .ask_map
+            .iter()
+            .filter_map(summarize)
+            .take(levels)
+            .collect();
+        DepthSnapshot {
+            symbol: self.symbol.clone(),
+            checksum: depth_checksum(&bids, &asks),
+            bids,
+            asks,
+        }
+    }
+
+    // The stops that triggered since the last call and were dropped without
+    // filling in full, in the order they triggered.
+    pub fn take_dropped_stops(&mut self) -> Vec<DroppedStop> {
+        std::mem::take(&mut self.dropped_stops)
+    }
+
+    // The levels changed since the last call, as they stand now: bids best
+    // first, then asks best first. A level can be reported unchanged, such as
+    // when an order rests at it and is then taken away, or when only hidden
+    // orders come and go.
+    pub fn take_deltas(&mut self) -> Vec<BookDelta> {
+        let ChangedLevels { bids, asks } = std::mem::take(&mut self.changed);
+        let bids = bids.into_iter().rev().map(|price| (Side::Buy, price));
+        let asks = asks.into_iter().map(|price| (Side::Sell, price));
+        bids.chain(asks)
+            .map(|(side, price)| {
+                let (quantity, orders) = self
+                    .side_map(&side)
+                    .get(&price)
+                    .map_or((Qty::ZERO, 0), |level| level.displayed());
+                BookDelta {
+                    side,
+                    price,
+                    quantity,
+                    orders,
+                }
+            })
+            .collect()
+    }
+
+    // The checksum of the top `depth` levels on each side; see depth_checksum.
+    pub fn checksum(&self, depth: usize) -> u32 {
+        self.depth(depth).checksum
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.bid_map.len() + self.ask_map.len()
+    }
+
+    // Cheap enough to run after every operation: only the best level on each
+    // side is inspected.
+    pub fn check_top_of_book(&self) -> Result<(), String> {
+        let best_bid = self.bid_map.last_key_value();
+        let best_ask = self.ask_map.first_key_value();
+
+        for (side, level) in [("bid", best_bid), ("ask", best_ask)] {
+            if let Some((price, queue)) = level {
+                if queue.is_empty() {
+                    return Err(format!("empty {} level at {}", side, price));
+                }
+                if queue.iter().any(|o| o.quantity.is_zero()) {
+                    return Err(format!("zero-quantity {} resting at {}", side, price));
+                }
+                queue
+                    .check_total()
+                    .map_err(|e| format!("{} level at {}: {}", side, price, e))?;
+            }
+        }
+
+        // an auction book is expected to cross until it uncrosses, and an
+        // all-or-none order may rest crossed by orders too small to take it
+        let open = |(&price, queue): (&Price, &PriceLevel)| {
+            queue.iter().any(|o| !o.all_or_none).then_some(price)
+        };
+        if let (Some(bid), Some(ask)) = (
+            self.bid_map.iter().rev().find_map(open),
+            self.ask_map.iter().find_map(open),
+        ) && bid >= ask
+            && self.phase == TradingPhase::Continuous
+        {
+            return Err(format!(
+                "crossed book: best bid {} >= best ask {}",
+                bid, ask
+            ));
+        }
+        Ok(())
+    }
+}
+
+// The level's displayed quantity, which queues ahead of anything hidden;
+// None if it has none.
+fn displayed_summary((&price, level): (&Price, &PriceLevel)) -> Option<(Price, Qty)> {
+    let (quantity, _) = level.displayed();
+    (!quantity.is_zero()).then_some((price, quantity))
+}
+
+// Removes the order with `order_id` from the level at `price`, dropping the
+// level if that empties it.
+fn remove_order(map: &mut PriceMap, price: Price, order_id: u64) -> Option<Order> {
+    let queue = map.get_mut(&price)?;
+    let position = queue.iter().position(|o| o.order_id == order_id)?;
+    let order = queue.remove(position);
+    if queue.is_empty() {
+        map.remove(&price);
+    }
+    order
+}
+
+// Like remove_order when the price isn't known, searching every level.
+fn find_and_remove(map: &mut PriceMap, order_id: u64) -> Option<Order> {
+    let price = map
+        .iter()
+        .find(|(_, queue)| queue.iter().any(|o| o.order_id == order_id))
+        .map(|(&price, _)| price)?;
+    remove_order(map, price, order_id)
+}
+
+// Fills up to `to_fill` from one price level in time priority, returning
+// what's left. An all-or-none order bigger than what's left is passed over
+// where it stands.
+fn fill_level(
+    level: &mut PriceLevel,
+    price: Price,
+    mut to_fill: Qty,
+    taker: &mut Taker,
+    events: &mut Vec<TradeEvent>,
+) -> Qty {
+    let mut at = 0;
+    while !to_fill.is_zero()
+        && let Some(maker) = level.orders().get(at)
+    {
+        if maker.all_or_none && maker.quantity > to_fill {
+            at += 1;
+            continue;
+        }
+        let consumed_quantity = to_fill.min(maker.quantity);
+        events.push(taker.trade(maker, consumed_quantity));
+        taker.changed.mark(&maker.side, price);
+        to_fill -= consumed_quantity;
+
+        // a partly filled order keeps its place; a filled one leaves
+        if let Some(filled) = level.fill(at, consumed_quantity) {
+            taker.index.remove(&filled.order_id);
+        }
+    }
+    to_fill
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_order(_id: u64, dir: Side, qty: u64, price: i64, user_id: String) -> Order {
+        Order {
+            order_id: 0,
+            received_at: 0,
+            side: dir,
+            quantity: Qty::shares(qty),
+            price: Some(Price::cents(price)),
+            state: OrderState::Open,
+            symbol: String::from("AAPL"),
+            user: user_id,
+            time_in_force: TimeInForce::Gtc,
+            expires_at: None,
+            post_only: false,
+            stop_price: None,
+            notional: None,
+            market_to_limit: false,
+            peg: None,
+            hidden: false,
+            min_quantity: None,
+            all_or_none: false,
+            tags: Vec::new(),
+            client_order_id: None,
+        }
+    }
+
+    fn make_market_order(_id: u64, dir: Side, qty: u64, user_id: String) -> Order {
+        Order {
+            order_id: 0,
+            received_at: 0,
+            side: dir,
+            quantity: Qty::shares(qty),
+            price: None, // irrelevant for market
+            state: OrderState::Open,
+            symbol: String::from("AAPL"),
+            user: user_id,
+            time_in_force: TimeInForce::Gtc,
+            expires_at: None,
+            post_only: false,
+            stop_price: None,
+            notional: None,
+            market_to_limit: false,
+            peg: None,
+            hidden: false,
+            min_quantity: None,
+            all_or_none: false,
+            tags: Vec::new(),
+            client_order_id: None,
+        }
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let first = book
+            .add_limit_order(make_order(0, Side::Sell, 10, 101, String::from("a")))
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        let second = book
+            .add_limit_order(make_order(1, Side::Sell, 10, 101, String::from("b")))
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        let filled = book
+            .add_limit_order(make_order(2, Side::Sell, 5, 100, String::from("c")))
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        assert_eq!((first, second, filled), (1, 2, 3));
+
+        // fills all of c and part of a
+        let result = book
+            .add_limit_order(make_order(3, Side::Buy, 9, 101, String::from("d")))
+            .unwrap();
+        assert_eq!(
+            (result.filled_quantity, result.remaining_quantity),
+            (Qty::shares(9), Qty::ZERO)
+        );
+        assert_eq!(result.resting_order_id, None);
+
+        let cancelled = book.cancel_order(first).unwrap();
+        assert_eq!(cancelled.user, "a");
+        assert_eq!(cancelled.quantity, Qty::shares(6));
+        assert!(matches!(cancelled.state, OrderState::Close));
+        let level: Vec<u64> = book.ask_map[&Price::cents(101)]
+            .iter()
+            .map(|o| o.order_id)
+            .collect();
+        assert_eq!(level, vec![second]);
+
+        assert_eq!(
+            book.cancel_order(first).unwrap_err(),
+            CancelError::NotResting(first)
+        );
+        assert_eq!(
+            book.cancel_order(filled).unwrap_err(),
+            CancelError::NotResting(filled)
+        );
+        assert_eq!(
+            book.cancel_order(99).unwrap_err(),
+            CancelError::UnknownOrder(99)
+        );
+
+        // the last order at a level takes the level with it
+        book.cancel_order(second).unwrap();
+        assert!(book.ask_map.is_empty());
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_index_follows_the_book() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for i in 0..1000 {
+            let (side, price) = match i % 2 {
+                0 => (Side::Buy, 100 - i / 2 % 5),
+                _ => (Side::Sell, 101 + i / 2 % 5),
+            };
+            book.add_limit_order(make_order(0, side, 10, price, String::from("m")))
+                .unwrap();
+        }
+        book.validate_index().unwrap();
+
+        // a sweep through three ask levels, the last one partly
+        let result = book
+            .add_limit_order(make_order(0, Side::Buy, 2005, 103, String::from("t")))
+            .unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(2005));
+        book.validate_index().unwrap();
+
+        // cancels from the middle of a level and the last order at a level
+        for id in [1, 501, 16] {
+            book.cancel_order(id).unwrap();
+        }
+        let last_at_96 = book.bid_map[&Price::cents(96)].back().unwrap().order_id;
+        for order in book.bid_map[&Price::cents(96)].clone() {
+            book.cancel_order(order.order_id).unwrap();
+        }
+        assert!(!book.bid_map.contains_key(&Price::cents(96)));
+        assert_eq!(
+            book.cancel_order(last_at_96).unwrap_err(),
+            CancelError::NotResting(last_at_96)
+        );
+        book.validate_index().unwrap();
+
+        // a partially filled maker stays indexed where it was
+        let front = book.ask_map[&Price::cents(103)][0].order_id;
+        assert_eq!(book.get_order(front).unwrap().price, Price::cents(103));
+        book.amend_order(front, Price::cents(104), Qty::shares(1))
+            .unwrap();
+        assert_eq!(book.get_order(front).unwrap().price, Price::cents(104));
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_maker_state_across_sweeps() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let maker = book
+            .add_limit_order(make_order(0, Side::Sell, 10, 101, String::from("m")))
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 10, 101, String::from("n")))
+            .unwrap();
+        assert_eq!(book.get_order(maker).unwrap().state, OrderState::Open);
+
+        let first = book
+            .add_market_order(make_market_order(0, Side::Buy, 4, String::from("t")))
+            .unwrap();
+        assert_eq!(
+            (
+                first.events[0].maker_order_id,
+                first.events[0].maker_remaining
+            ),
+            (maker, Qty::shares(6))
+        );
+        let view = book.get_order(maker).unwrap();
+        assert_eq!(view.state, OrderState::PartiallyFilled);
+        assert_eq!(
+            (view.remaining_quantity, view.queue_position),
+            (Qty::shares(6), 0)
+        );
+
+        let second = book
+            .add_market_order(make_market_order(0, Side::Buy, 8, String::from("t")))
+            .unwrap();
+        let makers: Vec<(u64, Qty)> = second
+            .events
+            .iter()
+            .map(|e| (e.maker_order_id, e.maker_remaining))
+            .collect();
+        assert_eq!(
+            makers,
+            vec![(maker, Qty::ZERO), (maker + 1, Qty::shares(8))]
+        );
+        assert!(book.get_order(maker).is_none());
+        assert_eq!(
+            book.get_order(maker + 1).unwrap().state,
+            OrderState::PartiallyFilled
+        );
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_sweep_names_maker_and_taker() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let makers: Vec<u64> = [101, 101, 102]
+            .into_iter()
+            .map(|price| {
+                book.add_limit_order(make_order(0, Side::Sell, 2, price, String::from("m")))
+                    .unwrap()
+                    .resting_order_id
+                    .unwrap()
+            })
+            .collect();
+
+        let result = book
+            .add_limit_order(make_order(0, Side::Buy, 6, 102, String::from("t")))
+            .unwrap();
+        let taker = result.order_id.unwrap();
+        let ids: Vec<(u64, u64)> = result
+            .events
+            .iter()
+            .map(|e| (e.maker_order_id, e.taker_order_id))
+            .collect();
+        assert_eq!(
+            ids,
+            makers
+                .iter()
+                .map(|&maker| (maker, taker))
+                .collect::<Vec<_>>()
+        );
+        assert!(!makers.contains(&taker));
+        assert_eq!(book.recent_trades(3), result.events);
+
+        // market and notional orders are given an id too, though they never
+        // rest
+        book.add_limit_order(make_order(0, Side::Sell, 3, 103, String::from("m")))
+            .unwrap();
+        let market = book
+            .add_market_order(make_market_order(0, Side::Buy, 1, String::from("t")))
+            .unwrap();
+        let market_id = market.order_id.unwrap();
+        assert!(market_id > taker);
+        assert_eq!(market.events[0].taker_order_id, market_id);
+
+        let mut notional = make_market_order(0, Side::Buy, 0, String::from("t"));
+        notional.notional = Some(Price::cents(103));
+        let notional = book.add_notional_order(notional, None).unwrap();
+        assert_eq!(notional.order_id, Some(market_id + 1));
+        assert_eq!(notional.events[0].taker_order_id, market_id + 1);
+    }
+
+    #[test]
+    fn test_trades_carry_order_tags() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let tagged = |order: Order, tag: &str| Order {
+            tags: vec![String::from(tag)],
+            ..order
+        };
+        book.add_limit_order(tagged(
+            make_order(0, Side::Sell, 5, 101, String::from("m")),
+            "quoting",
+        ))
+        .unwrap();
+        book.add_limit_order(tagged(
+            make_order(0, Side::Buy, 5, 100, String::from("m")),
+            "quoting",
+        ))
+        .unwrap();
+
+        let lifted = book
+            .add_limit_order(tagged(
+                make_order(0, Side::Buy, 2, 101, String::from("t")),
+                "momentum",
+            ))
+            .unwrap();
+        let hit = book
+            .add_market_order(tagged(
+                make_market_order(0, Side::Sell, 2, String::from("t")),
+                "exit",
+            ))
+            .unwrap();
+        let tags = |e: &TradeEvent| (e.buyer_tags.clone(), e.seller_tags.clone());
+        assert_eq!(
+            tags(&lifted.events[0]),
+            (
+                vec![String::from("momentum")],
+                vec![String::from("quoting")]
+            )
+        );
+        assert_eq!(
+            tags(&hit.events[0]),
+            (vec![String::from("quoting")], vec![String::from("exit")])
+        );
+    }
+
+    #[test]
+    fn test_reduce_order_keeps_priority() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let sell = |user: &str| make_order(0, Side::Sell, 10, 101, String::from(user));
+        let early = book
+            .add_limit_order(sell("early"))
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        book.add_limit_order(sell("late")).unwrap();
+
+        assert_eq!(book.reduce_order(early, Qty::shares(4)), Ok(Qty::shares(6)));
+        assert_eq!(
+            book.reduce_order(early, Qty::shares(5)),
+            Err(CancelError::WouldGrow(early))
+        );
+        assert_eq!(book.reduce_order(early, Qty::shares(4)), Ok(Qty::ZERO));
+        assert_eq!(
+            book.reduce_order(99, Qty::shares(1)),
+            Err(CancelError::UnknownOrder(99))
+        );
+
+        // the reduced order still fills first, and only for what's left of it
+        let fills: Vec<(String, Qty)> = book
+            .add_limit_order(make_order(0, Side::Buy, 6, 101, String::from("b")))
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|e| (e.seller, e.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (String::from("early"), Qty::shares(4)),
+                (String::from("late"), Qty::shares(2))
+            ]
+        );
+        assert_eq!(
+            book.reduce_order(early, Qty::ZERO),
+            Err(CancelError::NotResting(early))
+        );
+    }
+
+    #[test]
+    fn test_get_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_clock(|| 7);
+        let sell = |user: &str, qty| make_order(0, Side::Sell, qty, 101, String::from(user));
+        let first = book
+            .add_limit_order(sell("a", 5))
+            .unwrap()
+            .order_id
+            .unwrap();
+        let second = book
+            .add_limit_order(sell("b", 5))
+            .unwrap()
+            .order_id
+            .unwrap();
+        assert_eq!(
+            book.get_order(second),
+            Some(OrderView {
+                order_id: second,
+                user: String::from("b"),
+                symbol: String::from("AAPL"),
+                side: Side::Sell,
+                price: Price::cents(101),
+                remaining_quantity: Qty::shares(5),
+                state: OrderState::Open,
+                time_in_force: TimeInForce::Gtc,
+                received_at: 7,
+                expires_at: None,
+                queue_position: 1,
+                client_order_id: None,
+            })
+        );
+
+        // a fill shows straight away, and b moves up once a is gone
+        book.add_limit_order(make_order(0, Side::Buy, 3, 101, String::from("t")))
+            .unwrap();
+        let view = book.get_order(first).unwrap();
+        assert_eq!(
+            (view.remaining_quantity, view.state, view.queue_position),
+            (Qty::shares(2), OrderState::PartiallyFilled, 0)
+        );
+        book.add_limit_order(make_order(0, Side::Buy, 2, 101, String::from("t")))
+            .unwrap();
+        assert_eq!(book.get_order(first), None);
+        assert_eq!(book.get_order(second).unwrap().queue_position, 0);
+
+        book.cancel_order(second).unwrap();
+        assert_eq!(book.get_order(second), None);
+        assert_eq!(book.get_order(99), None);
+    }
+
+    #[test]
+    fn test_orders_for_user() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for (dir, qty, price, user) in [
+            (Side::Buy, 5, 99, "a"),
+            (Side::Buy, 5, 99, "b"),
+            (Side::Buy, 3, 98, "a"),
+            (Side::Sell, 4, 102, "b"),
+            (Side::Sell, 2, 101, "a"),
+            (Side::Sell, 6, 103, "a"),
+        ] {
+            book.add_limit_order(make_order(0, dir, qty, price, String::from(user)))
+                .unwrap();
+        }
+        let orders: Vec<(Side, Price, Qty, usize)> = book
+            .orders_for_user("a")
+            .into_iter()
+            .map(|o| (o.side, o.price, o.remaining_quantity, o.queue_position))
+            .collect();
+        assert_eq!(
+            orders,
+            vec![
+                (Side::Buy, Price::cents(98), Qty::shares(3), 0),
+                (Side::Buy, Price::cents(99), Qty::shares(5), 0),
+                (Side::Sell, Price::cents(101), Qty::shares(2), 0),
+                (Side::Sell, Price::cents(103), Qty::shares(6), 0),
+            ]
+        );
+        assert_eq!(book.orders_for_user("b").len(), 2);
+        assert!(book.orders_for_user("c").is_empty());
+    }
+
+    #[test]
+    fn test_cancel_all_for_user() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for (dir, price, user) in [
+            (Side::Buy, 99, "a"),
+            (Side::Buy, 99, "x"),
+            (Side::Buy, 99, "a"),
+            (Side::Buy, 99, "y"),
+            (Side::Buy, 98, "a"),
+            (Side::Sell, 101, "x"),
+            (Side::Sell, 101, "a"),
+        ] {
+            book.add_limit_order(make_order(0, dir, 5, price, String::from(user)))
+                .unwrap();
+        }
+        book.add_stop_order(make_stop_order(Side::Buy, 2, 105, "a"))
+            .unwrap();
+
+        let cancelled = book.cancel_all_for_user("a");
+        assert_eq!(cancelled.len(), 5);
+        assert!(
+            cancelled
+                .iter()
+                .all(|o| o.user == "a" && o.state == OrderState::Close)
+        );
+
+        // the emptied level at 98 and the stop are gone; x and y keep their order
+        let users =
+            |queue: &PriceLevel| -> Vec<String> { queue.iter().map(|o| o.user.clone()).collect() };
+        assert_eq!(
+            book.bid_map.keys().collect::<Vec<_>>(),
+            vec![&Price::cents(99)]
+        );
+        assert_eq!(users(&book.bid_map[&Price::cents(99)]), vec!["x", "y"]);
+        assert_eq!(users(&book.ask_map[&Price::cents(101)]), vec!["x"]);
+        assert!(book.buy_stops.is_empty());
+        assert!(book.cancel_all_for_user("a").is_empty());
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for (dir, price) in [(Side::Buy, 99), (Side::Buy, 98), (Side::Sell, 101)] {
+            book.add_limit_order(make_order(0, dir, 5, price, String::from("a")))
+                .unwrap();
+        }
+        book.add_stop_order(make_stop_order(Side::Sell, 5, 97, "b"))
+            .unwrap();
+
+        let cancelled = book.clear();
+        let prices: Vec<Option<Price>> = cancelled.iter().map(|o| o.price).collect();
+        assert_eq!(
+            prices,
+            vec![
+                Some(Price::cents(98)),
+                Some(Price::cents(99)),
+                Some(Price::cents(101)),
+                None
+            ]
+        );
+        assert!(cancelled.iter().all(|o| o.state == OrderState::Close));
+        assert_eq!(book.len(), 0);
+        assert_eq!(book.level_count(), 0);
+        assert!(book.sell_stops.is_empty());
+
+        assert!(book.is_halted());
+        assert_eq!(
+            book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("a")))
+                .unwrap_err(),
+            AddOrderError::Halted
+        );
+        book.resume();
+        book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("a")))
+            .unwrap();
+        assert_eq!(book.best_bid(), Some((Price::cents(99), Qty::shares(5))));
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        // ticks once per reading so each order arrives a millisecond later
+        static NOW: AtomicI64 = AtomicI64::new(1000);
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_clock(|| NOW.fetch_add(1, Ordering::SeqCst));
+
+        let early = book
+            .add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("early")))
+            .unwrap()
+            .order_id
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("late")))
+            .unwrap();
+        let received: Vec<(String, i64)> = book
+            .snapshot()
+            .orders
+            .into_iter()
+            .map(|p| (p.order.user, p.order.received_at))
+            .collect();
+        assert_eq!(
+            received,
+            vec![(String::from("early"), 1000), (String::from("late"), 1001)]
+        );
+
+        // a partial sweep of the level only reaches the earlier order
+        let events = book
+            .add_market_order(make_market_order(0, Side::Buy, 3, String::from("t")))
+            .unwrap()
+            .events;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].seller, "early");
+        assert_eq!(events[0].maker_received_at, 1000);
+        assert_eq!(events[0].timestamp, 1002);
+
+        // growing the earlier order restamps it behind the later one
+        book.amend_order(early, Price::cents(101), Qty::shares(4))
+            .unwrap();
+        let events = book
+            .add_market_order(make_market_order(0, Side::Buy, 3, String::from("t")))
+            .unwrap()
+            .events;
+        assert_eq!(events[0].seller, "late");
+        assert_eq!(events[0].maker_received_at, 1001);
+        assert_eq!(book.find_order(early).unwrap().received_at, 1003);
+    }
+
+    #[test]
+    fn test_invalid_orders_rejected() {
+        let rules = SymbolRules {
+            max_quantity: Some(Qty::shares(1000)),
+            ..SymbolRules::default()
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        book.add_limit_order(make_order(0, Side::Sell, 5, 100, String::from("a")))
+            .unwrap();
+
+        let limit = |qty, price| make_order(0, Side::Buy, qty, price, String::from("b"));
+        let market = |qty| make_market_order(0, Side::Buy, qty, String::from("b"));
+        assert_eq!(
+            book.add_limit_order(limit(0, 100)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::ZeroQuantity)
+        );
+        assert_eq!(
+            book.add_limit_order(limit(1, 0)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::InvalidPrice { price: Price::ZERO })
+        );
+        assert_eq!(
+            book.add_limit_order(limit(1, -100)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::InvalidPrice {
+                price: Price::cents(-100)
+            })
+        );
+        assert_eq!(
+            book.add_market_order(market(0)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::ZeroQuantity)
+        );
+        assert_eq!(
+            book.add_market_order(market(1001)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::TooLarge {
+                quantity: Qty::shares(1001),
+                max_quantity: Qty::shares(1000),
+            })
+        );
+        assert_eq!(
+            book.add_market_order(limit(1, 100)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::UnexpectedPrice)
+        );
+        assert_eq!(
+            book.add_stop_order(make_stop_order(Side::Sell, 1, 0, "b"))
+                .unwrap_err(),
+            AddOrderError::Invalid(ValidationError::InvalidPrice { price: Price::ZERO })
+        );
+
+        // malformed orders come back as errors rather than panics
+        assert_eq!(
+            book.add_limit_order(market(1)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::MissingPrice)
+        );
+        assert_eq!(
+            book.add_stop_order(market(1)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::MissingPrice)
+        );
+        let post_only = Order {
+            post_only: true,
+            ..market(1)
+        };
+        assert_eq!(
+            book.add_market_order(post_only).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::MissingPrice)
+        );
+        assert_eq!(
+            book.add_notional_order(market(1), None).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::ZeroQuantity)
+        );
+        assert_eq!(
+            book.add_limit_order(Order {
+                price: Some(Price::from_units(i64::MAX / 2)),
+                ..limit(4, 100)
+            })
+            .unwrap_err(),
+            AddOrderError::Invalid(ValidationError::NotionalOverflow {
+                price: Price::from_units(i64::MAX / 2)
+            })
+        );
+        let other_symbol = Order {
+            symbol: String::from("MSFT"),
+            ..limit(1, 100)
+        };
+        assert_eq!(
+            book.add_limit_order(other_symbol).unwrap_err(),
+            AddOrderError::SymbolMismatch
+        );
+
+        // none of them touched the book, and the maximum itself is allowed
+        assert_eq!(book.best_ask(), Some((Price::cents(100), Qty::shares(5))));
+        assert_eq!(book.len(), 1);
+        book.add_limit_order(limit(1000, 99)).unwrap();
+    }
+
+    #[test]
+    fn test_notional() {
+        assert_eq!(
+            notional(Price::cents(10150), Qty::shares(4)),
+            Ok(Price::cents(40600))
+        );
+        assert_eq!(
+            notional(Price::from_units(i64::MAX / 2), Qty::shares(4)),
+            Err(OverflowError)
+        );
+        assert_eq!(
+            notional(Price::cents(-5), Qty::shares(3)),
+            Ok(Price::cents(-15))
+        );
+        assert_eq!(notional(Price::MAX, Qty::shares(2)), Err(OverflowError));
+        let half = Qty::from_units(5_000);
+        assert_eq!(
+            notional(Price::cents(101), half),
+            Ok(Price::from_units(5_050))
+        );
+        // half a unit is rounded away from zero
+        let tick = Price::from_units(1);
+        assert_eq!(notional(tick, half), Ok(tick));
+        assert_eq!(notional(-tick, half), Ok(-tick));
+        assert_eq!(notional(Price::from_units(2), half), Ok(tick));
+    }
+
+    #[test]
+    fn test_tick_size() {
+        let rules = SymbolRules {
+            tick_size: Price::cents(5),
+            ..SymbolRules::default()
+        };
+        let off_tick = |cents| {
+            AddOrderError::Invalid(ValidationError::OffTick {
+                price: Price::cents(cents),
+                tick_size: Price::cents(5),
+            })
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        let id = book
+            .add_limit_order(make_order(0, Side::Buy, 5, 100, String::from("a")))
+            .unwrap()
+            .order_id
+            .unwrap();
+        assert_eq!(
+            book.add_limit_order(make_order(0, Side::Buy, 5, 102, String::from("a")))
+                .unwrap_err(),
+            off_tick(102)
+        );
+        let stop = Order {
+            price: Some(Price::cents(105)),
+            ..make_stop_order(Side::Buy, 5, 102, "a")
+        };
+        assert_eq!(book.add_stop_order(stop).unwrap_err(), off_tick(102));
+
+        // amends are held to the grid too, and a rebuilt book keeps it
+        assert_eq!(
+            book.amend_order(id, Price::cents(102), Qty::shares(5))
+                .unwrap_err(),
+            CancelError::Rejected(off_tick(102))
+        );
+        book.amend_order(id, Price::cents(95), Qty::shares(5))
+            .unwrap();
+        let mut book = OrderBook::from_snapshot(book.snapshot());
+        assert_eq!(book.best_bid(), Some((Price::cents(95), Qty::shares(5))));
+        assert_eq!(
+            book.amend_order(id, Price::cents(97), Qty::shares(5))
+                .unwrap_err(),
+            CancelError::Rejected(off_tick(97))
+        );
+    }
+
+    #[test]
+    fn test_lot_size_and_max_quantity() {
+        let rules = SymbolRules {
+            lot_size: Qty::shares(100),
+            max_quantity: Some(Qty::shares(10_000)),
+            ..SymbolRules::default()
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        let buy = |qty| make_order(0, Side::Buy, qty, 100, String::from("a"));
+        let odd_lot = |qty| {
+            AddOrderError::Invalid(ValidationError::OddLot {
+                quantity: Qty::shares(qty),
+                lot_size: Qty::shares(100),
+            })
+        };
+
+        assert_eq!(book.add_limit_order(buy(50)).unwrap_err(), odd_lot(50));
+        assert_eq!(book.add_limit_order(buy(250)).unwrap_err(), odd_lot(250));
+        assert_eq!(
+            book.add_limit_order(buy(10_100)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::TooLarge {
+                quantity: Qty::shares(10_100),
+                max_quantity: Qty::shares(10_000),
+            })
+        );
+        book.add_limit_order(buy(10_000)).unwrap();
+        assert_eq!(
+            book.best_bid(),
+            Some((Price::cents(100), Qty::shares(10_000)))
+        );
+
+        // a notional sell only ever takes whole lots
+        let sell = Order {
+            notional: Some(Price::cents(25_000)),
+            ..make_market_order(0, Side::Sell, 0, String::from("b"))
+        };
+        let result = book.add_notional_order(sell, None).unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(200));
+        assert_eq!(result.unspent_notional, Some(Price::cents(5_000)));
+    }
+
+    #[test]
+    fn test_market_collar() {
+        let rules = SymbolRules {
+            market_collar_bps: Some(1000),
+            ..SymbolRules::default()
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        for (price, user) in [(100, "a"), (105, "b"), (115, "c")] {
+            book.add_limit_order(make_order(0, Side::Sell, 5, price, String::from(user)))
+                .unwrap();
+        }
+
+        // with no trades yet the band is 10% over the best ask: up to 110
+        let result = book
+            .add_market_order(make_market_order(0, Side::Buy, 20, String::from("t")))
+            .unwrap();
+        let prices: Vec<Price> = result.events.iter().map(|e| e.price).collect();
+        assert_eq!(prices, vec![Price::cents(100), Price::cents(105)]);
+        assert!(result.collared);
+        assert_eq!(result.remaining_quantity, Qty::shares(10));
+        assert_eq!(book.best_ask(), Some((Price::cents(115), Qty::shares(5))));
+
+        // measured from the last trade at 105, 115 is now just inside the
+        // band, so a normal order fills as it would without a collar
+        book.add_limit_order(make_order(0, Side::Sell, 5, 115, String::from("d")))
+            .unwrap();
+        let result = book
+            .add_market_order(make_market_order(0, Side::Buy, 8, String::from("t")))
+            .unwrap();
+        let fills: Vec<(Qty, Price)> = result
+            .events
+            .iter()
+            .map(|e| (e.quantity, e.price))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (Qty::shares(5), Price::cents(115)),
+                (Qty::shares(3), Price::cents(115))
+            ]
+        );
+        assert!(!result.collared);
+        assert_eq!(result.remaining_quantity, Qty::ZERO);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let rules = SymbolRules {
+            circuit_breaker: Some(CircuitBreaker {
+                move_bps: 1000,
+                window_millis: 60_000,
+            }),
+            ..SymbolRules::default()
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        book.set_clock(|| 0);
+        for price in [100, 105, 115] {
+            book.add_limit_order(make_order(0, Side::Sell, 5, price, String::from("s")))
+                .unwrap();
+        }
+        let buy = |qty, price| make_order(0, Side::Buy, qty, price, String::from("b"));
+
+        // 100 to 105 is within 10%; 115 is not, and the order that got there
+        // still fills in full
+        book.add_limit_order(buy(5, 100)).unwrap();
+        book.add_limit_order(buy(5, 105)).unwrap();
+        assert!(!book.is_halted());
+        let events = book.add_limit_order(buy(2, 115)).unwrap().events;
+        assert_eq!(events.len(), 1);
+        assert!(book.is_halted());
+
+        // while halted every new order bounces and the book stays as it was
+        assert_eq!(
+            book.add_limit_order(buy(1, 115)).unwrap_err(),
+            AddOrderError::Halted
+        );
+        assert_eq!(
+            book.add_market_order(make_market_order(0, Side::Buy, 1, String::from("b")))
+                .unwrap_err(),
+            AddOrderError::Halted
+        );
+        assert_eq!(book.best_ask(), Some((Price::cents(115), Qty::shares(3))));
+
+        book.resume();
+        let events = book.add_limit_order(buy(1, 115)).unwrap().events;
+        assert_eq!(events.len(), 1);
+        assert!(!book.is_halted());
+    }
+
+    #[test]
+    fn test_restored_book_keeps_breaker_window() {
+        let rules = SymbolRules {
+            circuit_breaker: Some(CircuitBreaker {
+                move_bps: 1000,
+                window_millis: 60_000,
+            }),
+            ..SymbolRules::default()
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        book.set_clock(|| 0);
+        for price in [100, 115] {
+            book.add_limit_order(make_order(0, Side::Sell, 5, price, String::from("s")))
+                .unwrap();
+        }
+        book.add_limit_order(make_order(0, Side::Buy, 5, 100, String::from("b")))
+            .unwrap();
+
+        let json = serde_json::to_string(&book.snapshot()).unwrap();
+        let mut restored = OrderBook::from_snapshot(serde_json::from_str(&json).unwrap());
+        restored.set_clock(|| 0);
+        assert_eq!(restored.vwap(), book.vwap());
+
+        // 100 to 115 trips both, the restored book from its saved window
+        for book in [&mut book, &mut restored] {
+            book.add_limit_order(make_order(0, Side::Buy, 1, 115, String::from("b")))
+                .unwrap();
+            assert!(book.is_halted());
+        }
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 102, String::from("s")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 104, String::from("s")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("b")))
+            .unwrap();
+        assert_eq!(book.stats().session, SessionStats::default());
+
+        book.add_limit_order(make_order(0, Side::Buy, 7, 104, String::from("b")))
+            .unwrap();
+        book.add_market_order(make_market_order(0, Side::Sell, 4, String::from("s")))
+            .unwrap();
+        book.add_market_order(make_market_order(0, Side::Buy, 1, String::from("b")))
+            .unwrap();
+        assert_eq!(
+            book.stats(),
+            SymbolStats {
+                symbol: String::from("AAPL"),
+                last_price: Some(Price::cents(104)),
+                session: SessionStats {
+                    open: Some(Price::cents(102)),
+                    high: Some(Price::cents(104)),
+                    low: Some(Price::cents(99)),
+                    close: Some(Price::cents(104)),
+                    volume: Qty::shares(12),
+                    trades: 4,
+                },
+            }
+        );
+
+        book.reset_session();
+        assert_eq!(
+            book.stats().session,
+            SessionStats {
+                open: Some(Price::cents(104)),
+                ..SessionStats::default()
+            }
+        );
+        assert_eq!(book.stats().last_price, Some(Price::cents(104)));
+        book.add_market_order(make_market_order(0, Side::Buy, 1, String::from("b")))
+            .unwrap();
+        let session = book.stats().session;
+        assert_eq!(
+            (session.open, session.high, session.low),
+            (
+                Some(Price::cents(104)),
+                Some(Price::cents(104)),
+                Some(Price::cents(104))
+            )
+        );
+        assert_eq!(session.volume, Qty::shares(1));
+    }
+
+    #[test]
+    fn test_rolling_vwap() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+
+        static NOW: AtomicI64 = AtomicI64::new(0);
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_clock(|| NOW.load(Ordering::SeqCst));
+        assert_eq!(book.vwap(), None);
+
+        let trade = |book: &mut OrderBook, at, qty, price| {
+            NOW.store(at, Ordering::SeqCst);
+            book.add_limit_order(make_order(0, Side::Sell, qty, price, String::from("s")))
+                .unwrap();
+            book.add_limit_order(make_order(0, Side::Buy, qty, price, String::from("b")))
+                .unwrap();
+        };
+        trade(&mut book, 0, 1, 100);
+        trade(&mut book, 60_000, 3, 104);
+        assert_eq!(book.vwap(), Some(1.03));
+
+        // the first trade is still in on the window's last millisecond
+        NOW.store(DEFAULT_VWAP_WINDOW_MILLIS, Ordering::SeqCst);
+        assert_eq!(book.vwap(), Some(1.03));
+        NOW.store(DEFAULT_VWAP_WINDOW_MILLIS + 1, Ordering::SeqCst);
+        assert_eq!(book.vwap(), Some(1.04));
+
+        // a new trade drops the aged-out one for good
+        trade(&mut book, 330_000, 1, 108);
+        assert_eq!(book.vwap(), Some(1.05));
+        assert_eq!(book.vwap.fills.len(), 2);
+
+        NOW.store(330_000 + DEFAULT_VWAP_WINDOW_MILLIS + 1, Ordering::SeqCst);
+        assert_eq!(book.vwap(), None);
+
+        book.set_vwap_window(DEFAULT_VWAP_WINDOW_MILLIS * 2);
+        assert_eq!(book.vwap(), Some(1.05));
+    }
+
+    #[test]
+    fn test_trade_history_drops_the_oldest() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_trade_history(5);
+        for price in 100..108 {
+            book.add_limit_order(make_order(0, Side::Sell, 1, price, String::from("s")))
+                .unwrap();
+            book.add_limit_order(make_order(0, Side::Buy, 1, price, String::from("b")))
+                .unwrap();
+        }
+        let trades = book.recent_trades(usize::MAX);
+        let ids: Vec<u64> = trades.iter().map(|t| t.trade_id).collect();
+        assert_eq!(ids, [4, 5, 6, 7, 8]);
+        assert_eq!(trades[0].price, Price::cents(103));
+
+        let last: Vec<u64> = book.recent_trades(2).iter().map(|t| t.trade_id).collect();
+        assert_eq!(last, [7, 8]);
+
+        book.set_trade_history(3);
+        let ids: Vec<u64> = book.recent_trades(10).iter().map(|t| t.trade_id).collect();
+        assert_eq!(ids, [6, 7, 8]);
+    }
+
+    #[test]
+    fn test_auction_uncross() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        // a trade at 102 before the auction is the reference price
+        book.add_limit_order(make_order(0, Side::Sell, 1, 102, String::from("x")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 1, 102, String::from("x")))
+            .unwrap();
+
+        book.start_auction();
+        for (dir, qty, price, user) in [
+            (Side::Sell, 5, 100, "s1"),
+            (Side::Buy, 4, 104, "b1"),
+            (Side::Sell, 3, 101, "s2"),
+            (Side::Buy, 6, 102, "b2"),
+            (Side::Sell, 5, 103, "s3"),
+        ] {
+            let events = book
+                .add_limit_order(make_order(0, dir, qty, price, String::from(user)))
+                .unwrap()
+                .events;
+            assert!(events.is_empty());
+        }
+        assert_eq!(
+            book.add_market_order(make_market_order(0, Side::Buy, 1, String::from("m")))
+                .unwrap_err(),
+            AddOrderError::AuctionOpen
+        );
+        assert!(book.check_top_of_book().is_ok());
+
+        // 8 crosses at both 101 and 102 with the same imbalance; the last
+        // trade breaks the tie
+        assert_eq!(
+            book.auction_price(),
+            Some((Price::cents(102), Qty::shares(8)))
+        );
+        let trades: Vec<(String, String, Qty, Price)> = book
+            .uncross()
+            .into_iter()
+            .map(|e| (e.buyer, e.seller, e.quantity, e.price))
+            .collect();
+        let trade = |buyer: &str, seller: &str, qty| {
+            (
+                buyer.to_string(),
+                seller.to_string(),
+                Qty::shares(qty),
+                Price::cents(102),
+            )
+        };
+        assert_eq!(
+            trades,
+            vec![
+                trade("b1", "s1", 4),
+                trade("b2", "s1", 1),
+                trade("b2", "s2", 3)
+            ]
+        );
+
+        // b2's residual rests and continuous matching picks up from there
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert_eq!(book.best_bid(), Some((Price::cents(102), Qty::shares(2))));
+        assert_eq!(book.best_ask(), Some((Price::cents(103), Qty::shares(5))));
+        assert!(book.uncross().is_empty());
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_notional_market_order() {
+        let rules = SymbolRules {
+            lot_size: Qty::shares(1),
+            ..SymbolRules::default()
+        };
+        let mut book = OrderBook::with_rules(String::from("AAPL"), rules);
+        book.add_limit_order(make_order(0, Side::Sell, 5, 100, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 150, String::from("b")))
+            .unwrap();
+        let notional = |budget| Order {
+            notional: Some(budget),
+            ..make_market_order(0, Side::Buy, 0, String::from("t"))
+        };
+        let fills = |events: &[TradeEvent]| -> Vec<(Qty, Price)> {
+            events.iter().map(|e| (e.quantity, e.price)).collect()
+        };
+
+        // 1000 clears the first level and affords 3 of the next, with 50 over
+        let result = book
+            .add_notional_order(notional(Price::cents(1000)), None)
+            .unwrap();
+        assert_eq!(
+            fills(&result.events),
+            vec![
+                (Qty::shares(5), Price::cents(100)),
+                (Qty::shares(3), Price::cents(150))
+            ]
+        );
+        assert_eq!(result.average_price, Some(1.1875));
+        assert_eq!(result.unspent_notional, Some(Price::cents(50)));
+        assert_eq!(book.best_ask(), Some((Price::cents(150), Qty::shares(2))));
+
+        // a cap on shares, as the engine sets for position limits, stops it
+        // before the budget does
+        let result = book
+            .add_notional_order(notional(Price::cents(1000)), Some(Qty::shares(1)))
+            .unwrap();
+        assert_eq!(
+            fills(&result.events),
+            vec![(Qty::shares(1), Price::cents(150))]
+        );
+        assert_eq!(result.unspent_notional, Some(Price::cents(850)));
+
+        // too little to buy a single share leaves the book alone
+        let result = book
+            .add_notional_order(notional(Price::cents(149)), None)
+            .unwrap();
+        assert!(result.events.is_empty());
+        assert_eq!(result.average_price, None);
+        assert_eq!(result.unspent_notional, Some(Price::cents(149)));
+        assert_eq!(book.best_ask(), Some((Price::cents(150), Qty::shares(1))));
+        book.validate_index().unwrap();
+
+        // without a lot size the budget buys down to the last unit
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 150, String::from("a")))
+            .unwrap();
+        let result = book
+            .add_notional_order(notional(Price::cents(500)), None)
+            .unwrap();
+        assert_eq!(
+            fills(&result.events),
+            vec![(Qty::from_units(33_333), Price::cents(150))]
+        );
+        assert_eq!(result.unspent_notional, Some(Price::cents(0)));
+        assert_eq!(
+            book.best_ask(),
+            Some((Price::cents(150), Qty::from_units(16_667)))
+        );
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_amend_order_priority() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let a = book
+            .add_limit_order(make_order(0, Side::Buy, 10, 100, String::from("a")))
+            .unwrap()
+            .order_id
+            .unwrap();
+        let b = book
+            .add_limit_order(make_order(1, Side::Buy, 10, 100, String::from("b")))
+            .unwrap()
+            .order_id
+            .unwrap();
+        let queue = |book: &OrderBook, price| -> Vec<(u64, Qty)> {
+            book.bid_map[&price]
+                .iter()
+                .map(|o| (o.order_id, o.quantity))
+                .collect()
+        };
+
+        // shrinking keeps a at the front
+        assert!(
+            book.amend_order(a, Price::cents(100), Qty::shares(4))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            queue(&book, Price::cents(100)),
+            vec![(a, Qty::shares(4)), (b, Qty::shares(10))]
+        );
+
+        // growing sends it behind b
+        book.amend_order(a, Price::cents(100), Qty::shares(6))
+            .unwrap();
+        assert_eq!(
+            queue(&book, Price::cents(100)),
+            vec![(b, Qty::shares(10)), (a, Qty::shares(6))]
+        );
+
+        // so does moving to another price, which empties the old level
+        book.amend_order(b, Price::cents(99), Qty::shares(10))
+            .unwrap();
+        book.amend_order(a, Price::cents(99), Qty::shares(6))
+            .unwrap();
+        assert!(!book.bid_map.contains_key(&Price::cents(100)));
+        assert_eq!(
+            queue(&book, Price::cents(99)),
+            vec![(b, Qty::shares(10)), (a, Qty::shares(6))]
+        );
+
+        book.amend_order(b, Price::cents(99), Qty::ZERO).unwrap();
+        assert_eq!(queue(&book, Price::cents(99)), vec![(a, Qty::shares(6))]);
+        assert_eq!(
+            book.amend_order(b, Price::cents(99), Qty::shares(1))
+                .unwrap_err(),
+            CancelError::NotResting(b)
+        );
+        assert_eq!(
+            book.amend_order(42, Price::cents(99), Qty::shares(1))
+                .unwrap_err(),
+            CancelError::UnknownOrder(42)
+        );
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_amend_order_across_spread() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("s1")))
+            .unwrap();
+        book.add_limit_order(make_order(1, Side::Sell, 5, 102, String::from("s2")))
+            .unwrap();
+        let bid = book
+            .add_limit_order(make_order(2, Side::Buy, 8, 100, String::from("b")))
+            .unwrap()
+            .order_id
+            .unwrap();
+
+        let events = book
+            .amend_order(bid, Price::cents(101), Qty::shares(8))
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].buyer, "b");
+        assert_eq!(
+            (events[0].quantity, events[0].price),
+            (Qty::shares(5), Price::cents(101))
+        );
+
+        // the remainder rests at the new price under the same id
+        let resting = book.find_order(bid).unwrap();
+        assert_eq!(
+            (resting.price, resting.quantity),
+            (Some(Price::cents(101)), Qty::shares(3))
+        );
+        assert_eq!(
+            *book.ask_map.first_key_value().unwrap().0,
+            Price::cents(102)
+        );
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_amend_post_only_order_cannot_cross() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("s")))
+            .unwrap();
+        let bid = book
+            .add_limit_order(Order {
+                post_only: true,
+                ..make_order(1, Side::Buy, 5, 99, String::from("mm"))
+            })
+            .unwrap()
+            .order_id
+            .unwrap();
+
+        // at the touch and through it, the order stays where it was
+        let before = serde_json::to_string(&book).unwrap();
+        for price in [101, 102] {
+            assert_eq!(
+                book.amend_order(bid, Price::cents(price), Qty::shares(5))
+                    .unwrap_err(),
+                CancelError::Rejected(AddOrderError::WouldCross)
+            );
+        }
+        assert_eq!(serde_json::to_string(&book).unwrap(), before);
+
+        assert!(
+            book.amend_order(bid, Price::cents(100), Qty::shares(5))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(book.find_order(bid).unwrap().price, Some(Price::cents(100)));
+    }
+
+    #[test]
+    fn test_ioc_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("s1")))
+            .unwrap();
+        book.add_limit_order(make_order(1, Side::Sell, 5, 103, String::from("s2")))
+            .unwrap();
+        let ioc = |qty, price| Order {
+            time_in_force: TimeInForce::Ioc,
+            ..make_order(2, Side::Buy, qty, price, String::from("b"))
+        };
+
+        // nothing at or below 100: no fills, and nothing left behind
+        let result = book.add_limit_order(ioc(5, 100)).unwrap();
+        assert!(result.events.is_empty());
+        assert_eq!(result.resting_order_id, None);
+        assert!(book.bid_map.is_empty());
+        assert!(book.find_order(result.order_id.unwrap()).is_err());
+
+        // takes the level at 101, drops the other 3 instead of resting at 102
+        let result = book.add_limit_order(ioc(8, 102)).unwrap();
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(
+            (result.filled_quantity, result.average_price),
+            (Qty::shares(5), Some(1.01))
+        );
+        assert_eq!(result.remaining_quantity, Qty::shares(3));
+        assert_eq!(result.resting_order_id, None);
+        assert!(book.bid_map.is_empty());
+        assert_eq!(
+            book.ask_map.keys().collect::<Vec<_>>(),
+            vec![&Price::cents(103)]
+        );
+    }
+
+    #[test]
+    fn test_fok_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("b1")))
+            .unwrap();
+        book.add_limit_order(make_order(1, Side::Buy, 5, 98, String::from("b2")))
+            .unwrap();
+        book.add_limit_order(make_order(2, Side::Buy, 5, 97, String::from("b3")))
+            .unwrap();
+        let fok = |qty, price| Order {
+            time_in_force: TimeInForce::Fok,
+            ..make_order(3, Side::Sell, qty, price, String::from("s"))
+        };
+
+        // 15 rests across three levels but only 10 of it is at 98 or better
+        let before = serde_json::to_string(&book).unwrap();
+        assert_eq!(
+            book.add_limit_order(fok(11, 98)).unwrap_err(),
+            AddOrderError::Unfillable
+        );
+        assert_eq!(serde_json::to_string(&book).unwrap(), before);
+
+        let result = book.add_limit_order(fok(10, 98)).unwrap();
+        assert_eq!(result.order_id, Some(4));
+        assert_eq!(
+            (result.filled_quantity, result.remaining_quantity),
+            (Qty::shares(10), Qty::ZERO)
+        );
+        assert!(book.ask_map.is_empty());
+        assert_eq!(
+            book.bid_map.keys().collect::<Vec<_>>(),
+            vec![&Price::cents(97)]
+        );
+    }
+
+    #[test]
+    fn test_expire_orders() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let gtd = |user: &str, price, expires_at| Order {
+            expires_at: Some(expires_at),
+            ..make_order(0, Side::Buy, 5, price, String::from(user))
+        };
+        book.add_limit_order(gtd("a", 100, 1_000)).unwrap();
+        book.add_limit_order(make_order(1, Side::Buy, 5, 100, String::from("b")))
+            .unwrap();
+        book.add_limit_order(gtd("c", 99, 1_000)).unwrap();
+        book.add_limit_order(gtd("d", 98, 2_000)).unwrap();
+
+        assert!(book.expire_orders(999).is_empty());
+        let expired: Vec<String> = book
+            .expire_orders(1_000)
+            .into_iter()
+            .map(|o| {
+                assert_eq!(o.state, OrderState::Close);
+                o.user
+            })
+            .collect();
+        assert_eq!(expired, vec!["c", "a"]);
+        assert_eq!(
+            book.bid_map.keys().collect::<Vec<_>>(),
+            vec![&Price::cents(98), &Price::cents(100)]
+        );
+        assert_eq!(book.bid_map[&Price::cents(100)][0].user, "b");
+        assert_eq!(book.next_expiry, Some(2_000));
+
+        assert_eq!(book.expire_orders(5_000).len(), 1);
+        assert_eq!(book.next_expiry, None);
+        book.validate_index().unwrap();
+    }
+
+    fn make_stop_order(dir: Side, qty: u64, stop: i64, user_id: &str) -> Order {
+        Order {
+            stop_price: Some(Price::cents(stop)),
+            ..make_market_order(0, dir, qty, String::from(user_id))
+        }
+    }
+
+    #[test]
+    fn test_stop_cascade() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for (price, user) in [(100, "b1"), (99, "b2"), (98, "b3"), (97, "b4")] {
+            book.add_limit_order(make_order(0, Side::Buy, 5, price, String::from(user)))
+                .unwrap();
+        }
+        // placed before any trade: nothing to trigger off yet
+        let (_, events) = book
+            .add_stop_order(make_stop_order(Side::Sell, 5, 99, "stop99"))
+            .unwrap();
+        assert!(events.is_empty());
+        book.add_stop_order(make_stop_order(Side::Sell, 5, 98, "stop98"))
+            .unwrap();
+        let (far, _) = book
+            .add_stop_order(make_stop_order(Side::Sell, 5, 90, "stop90"))
+            .unwrap();
+
+        // a trade at 100 leaves the stops alone
+        let events = book
+            .add_market_order(make_market_order(0, Side::Sell, 5, String::from("s")))
+            .unwrap()
+            .events;
+        assert_eq!(events.len(), 1);
+
+        // one at 99 fires stop99, whose fill at 98 fires stop98
+        let events = book
+            .add_market_order(make_market_order(0, Side::Sell, 1, String::from("s")))
+            .unwrap()
+            .events;
+        let fills: Vec<(&str, Price, Qty)> = events
+            .iter()
+            .map(|e| (e.seller.as_str(), e.price, e.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                ("s", Price::cents(99), Qty::shares(1)),
+                ("stop99", Price::cents(99), Qty::shares(4)),
+                ("stop99", Price::cents(98), Qty::shares(1)),
+                ("stop98", Price::cents(98), Qty::shares(4)),
+                ("stop98", Price::cents(97), Qty::shares(1)),
+            ]
+        );
+        assert_eq!(book.last_trade_price, Some(Price::cents(97)));
+        assert!(book.find_order(far).is_err());
+        assert_eq!(book.cancel_order(far).unwrap().user, "stop90");
+        assert!(book.sell_stops.is_empty());
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_stop_trigger_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 100, 105, String::from("maker")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 1, 105, String::from("t")))
+            .unwrap();
+
+        // already through the last trade at 105, so it fires on placement
+        let (_, events) = book
+            .add_stop_order(make_stop_order(Side::Buy, 2, 104, "now"))
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].buyer, "now");
+
+        // the lower stop price was crossed first, then time breaks the tie
+        for (stop, user) in [(108, "late"), (107, "a"), (107, "b")] {
+            book.add_stop_order(make_stop_order(Side::Buy, 1, stop, user))
+                .unwrap();
+        }
+        book.add_limit_order(make_order(0, Side::Sell, 10, 103, String::from("low")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 10, 110, String::from("high")))
+            .unwrap();
+        let events = book
+            .add_limit_order(make_order(0, Side::Buy, 108, 110, String::from("t")))
+            .unwrap()
+            .events;
+        let buyers: Vec<&str> = events.iter().map(|e| e.buyer.as_str()).collect();
+        assert_eq!(buyers, vec!["t", "t", "t", "a", "b", "late"]);
+    }
+
+    #[test]
+    fn test_stop_waits_for_the_uncross() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 100, 105, String::from("maker")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 1, 105, String::from("t")))
+            .unwrap();
+
+        // through the last trade, but nothing trades until the uncross
+        book.start_auction();
+        let (_, events) = book
+            .add_stop_order(make_stop_order(Side::Buy, 2, 104, "stop"))
+            .unwrap();
+        assert!(events.is_empty());
+        assert_eq!(book.buy_stops.len(), 1);
+
+        let events = book.uncross();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].buyer.as_str(), events[0].quantity),
+            ("stop", Qty::shares(2))
+        );
+        assert!(book.buy_stops.is_empty());
+    }
+
+    #[test]
+    fn test_stop_limit_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("s1")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 103, String::from("s2")))
+            .unwrap();
+        let stop_limit = |qty, stop, price, user| Order {
+            stop_price: Some(Price::cents(stop)),
+            price: Some(Price::cents(price)),
+            ..make_stop_order(Side::Buy, qty, stop, user)
+        };
+        let (waiting, _) = book.add_stop_order(stop_limit(5, 101, 101, "w")).unwrap();
+        let (triggered, _) = book.add_stop_order(stop_limit(8, 101, 102, "t")).unwrap();
+
+        // cancelling a stop that hasn't fired only touches the stop book
+        assert_eq!(book.cancel_order(waiting).unwrap().user, "w");
+        assert_eq!(book.ask_map[&Price::cents(101)].len(), 1);
+
+        // a trade at 101 fires the stop, which can't reach 103 and rests at 102
+        let events = book
+            .add_limit_order(make_order(0, Side::Buy, 1, 101, String::from("b")))
+            .unwrap()
+            .events;
+        let fills: Vec<(&str, Price, Qty)> = events
+            .iter()
+            .map(|e| (e.buyer.as_str(), e.price, e.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                ("b", Price::cents(101), Qty::shares(1)),
+                ("t", Price::cents(101), Qty::shares(4))
+            ]
+        );
+        let resting = book.find_order(triggered).unwrap();
+        assert_eq!(
+            (resting.price, resting.quantity),
+            (Some(Price::cents(102)), Qty::shares(4))
+        );
+
+        assert_eq!(
+            book.cancel_order(triggered).unwrap().quantity,
+            Qty::shares(4)
+        );
+        assert!(book.bid_map.is_empty());
+        assert!(book.buy_stops.is_empty());
+    }
+
+    #[test]
+    fn test_post_only_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("b")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("s")))
+            .unwrap();
+        let post_only = |dir, price| Order {
+            post_only: true,
+            ..make_order(0, dir, 5, price, String::from("mm"))
+        };
+
+        // exactly at the touch counts as crossing
+        let before = serde_json::to_string(&book).unwrap();
+        for (dir, price) in [(Side::Buy, 101), (Side::Buy, 102), (Side::Sell, 99)] {
+            assert_eq!(
+                book.add_limit_order(post_only(dir, price)).unwrap_err(),
+                AddOrderError::WouldCross
+            );
+        }
+        assert_eq!(serde_json::to_string(&book).unwrap(), before);
+
+        let events = book
+            .add_limit_order(post_only(Side::Buy, 100))
+            .unwrap()
+            .events;
+        assert!(events.is_empty());
+        assert_eq!(book.bid_map[&Price::cents(100)][0].user, "mm");
+    }
+
+    #[test]
+    fn test_market_to_limit_order() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 3, 101, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 4, 102, String::from("b")))
+            .unwrap();
+        let mtl = |qty, user: &str| Order {
+            market_to_limit: true,
+            ..make_market_order(0, Side::Buy, qty, String::from(user))
+        };
+
+        // takes the 101 level only and rests the rest there, not at 102
+        let result = book.add_market_to_limit_order(mtl(5, "m")).unwrap();
+        let fills: Vec<(&str, Price, Qty)> = result
+            .events
+            .iter()
+            .map(|e| (e.seller.as_str(), e.price, e.quantity))
+            .collect();
+        assert_eq!(fills, vec![("a", Price::cents(101), Qty::shares(3))]);
+        assert_eq!(result.limit_price, Some(Price::cents(101)));
+        assert_eq!(result.remaining_quantity, Qty::shares(2));
+        let resting = book.find_order(result.resting_order_id.unwrap()).unwrap();
+        assert_eq!(
+            (resting.price, resting.quantity, resting.market_to_limit),
+            (Some(Price::cents(101)), Qty::shares(2), false)
+        );
+        assert_eq!(book.best_bid(), Some((Price::cents(101), Qty::shares(2))));
+        assert_eq!(book.best_ask(), Some((Price::cents(102), Qty::shares(4))));
+
+        // with nothing to sell against, the whole order is turned away
+        let mut empty = OrderBook::new(String::from("AAPL"));
+        assert_eq!(
+            empty.add_market_to_limit_order(mtl(5, "m")).unwrap_err(),
+            AddOrderError::NoLiquidity
+        );
+        assert!(empty.is_empty());
+
+        let priced = Order {
+            price: Some(Price::cents(101)),
+            ..mtl(1, "m")
+        };
+        assert_eq!(
+            book.add_market_to_limit_order(priced).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::UnexpectedPrice)
+        );
+    }
+
+    fn make_pegged_order(dir: Side, qty: u64, offset: i64, user: &str) -> Order {
+        Order {
+            price: None,
+            peg: Some(Peg {
+                offset: Price::cents(offset),
+                limit: None,
+            }),
+            ..make_order(0, dir, qty, 0, String::from(user))
+        }
+    }
+
+    #[test]
+    fn test_pegged_order_follows_best_bid() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        assert_eq!(
+            book.add_pegged_order(make_pegged_order(Side::Buy, 5, 1, "p"))
+                .unwrap_err(),
+            AddOrderError::NoLiquidity
+        );
+        book.add_limit_order(make_order(0, Side::Buy, 5, 100, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 105, String::from("s")))
+            .unwrap();
+        let result = book
+            .add_pegged_order(make_pegged_order(Side::Buy, 3, 1, "p"))
+            .unwrap();
+        assert_eq!(result.limit_price, Some(Price::cents(99)));
+        let pegged = result.resting_order_id.unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 1, 99, String::from("b")))
+            .unwrap();
+        let at = |book: &OrderBook| {
+            let view = book.get_order(pegged).unwrap();
+            (view.price, view.queue_position)
+        };
+        assert_eq!(at(&book), (Price::cents(99), 0));
+
+        // a better bid moves it up; the pegged order itself never counts
+        book.add_limit_order(make_order(0, Side::Buy, 1, 102, String::from("c")))
+            .unwrap();
+        assert_eq!(at(&book), (Price::cents(101), 0));
+        assert_eq!(book.best_bid(), Some((Price::cents(102), Qty::shares(1))));
+
+        // and losing it moves it back down, behind b at 99
+        book.cancel_order(5).unwrap();
+        assert_eq!(at(&book), (Price::cents(99), 1));
+        book.validate_index().unwrap();
+
+        // a restored book keeps following; at 100 it queues behind a
+        let mut book = OrderBook::from_snapshot(book.snapshot());
+        book.add_limit_order(make_order(0, Side::Buy, 1, 101, String::from("d")))
+            .unwrap();
+        assert_eq!(at(&book), (Price::cents(100), 1));
+
+        // moving it by hand unpegs it
+        book.amend_order(pegged, Price::cents(97), Qty::shares(3))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 1, 103, String::from("e")))
+            .unwrap();
+        assert_eq!(at(&book), (Price::cents(97), 0));
+    }
+
+    #[test]
+    fn test_pegged_order_stops_at_its_limit() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 5, 105, String::from("a")))
+            .unwrap();
+        let capped = Order {
+            peg: Some(Peg {
+                offset: Price::cents(2),
+                limit: Some(Price::cents(108)),
+            }),
+            ..make_pegged_order(Side::Sell, 2, 0, "p")
+        };
+        let pegged = book
+            .add_pegged_order(capped)
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        assert_eq!(book.get_order(pegged).unwrap().price, Price::cents(108));
+        book.add_limit_order(make_order(0, Side::Sell, 1, 110, String::from("b")))
+            .unwrap();
+        book.cancel_order(1).unwrap();
+        // a sell's limit is a floor: it follows the best ask up freely
+        assert_eq!(book.get_order(pegged).unwrap().price, Price::cents(112));
+
+        // but not down past it
+        book.add_limit_order(make_order(0, Side::Sell, 1, 101, String::from("c")))
+            .unwrap();
+        assert_eq!(book.get_order(pegged).unwrap().price, Price::cents(108));
+    }
+
+    #[test]
+    fn test_pegged_order_crosses_after_repricing() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Buy, 5, 100, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 2, 102, String::from("s")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 2, 103, String::from("t")))
+            .unwrap();
+        // works a cent inside the best bid
+        let pegged = book
+            .add_pegged_order(make_pegged_order(Side::Buy, 3, -1, "p"))
+            .unwrap()
+            .resting_order_id
+            .unwrap();
+        assert_eq!(book.get_order(pegged).unwrap().price, Price::cents(101));
+
+        // a new best bid of 101 pulls the peg up to 102, through the ask
+        let result = book
+            .add_limit_order(make_order(0, Side::Buy, 1, 101, String::from("b")))
+            .unwrap();
+        assert_eq!(result.filled_quantity, Qty::ZERO);
+        let fills: Vec<(&str, &str, Price, Qty)> = result
+            .events
+            .iter()
+            .map(|e| (e.buyer.as_str(), e.seller.as_str(), e.price, e.quantity))
+            .collect();
+        assert_eq!(fills, vec![("p", "s", Price::cents(102), Qty::shares(2))]);
+        assert_eq!(result.events[0].taker_side, Side::Buy);
+
+        // the rest of it waits at 102 and doesn't reach the 103 ask
+        let view = book.get_order(pegged).unwrap();
+        assert_eq!(
+            (view.price, view.remaining_quantity),
+            (Price::cents(102), Qty::shares(1))
+        );
+        assert_eq!(book.best_bid(), Some((Price::cents(102), Qty::shares(1))));
+        assert_eq!(book.best_ask(), Some((Price::cents(103), Qty::shares(2))));
+        book.check_top_of_book().unwrap();
+        book.validate_index().unwrap();
+    }
+
+    #[test]
+    fn test_hidden_order_queues_behind_displayed() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        let hidden = |qty, user: &str| Order {
+            hidden: true,
+            ..make_order(0, Side::Sell, qty, 101, String::from(user))
+        };
+        book.add_limit_order(hidden(4, "h")).unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 3, 101, String::from("d")))
+            .unwrap();
+        book.add_limit_order(hidden(2, "i")).unwrap();
+        let sellers: Vec<&str> = book.iter_asks().map(|o| o.user.as_str()).collect();
+        assert_eq!(sellers, ["d", "h", "i"]);
+
+        // only the displayed order shows, in depth, the BBO and the checksum
+        let depth = book.depth(5);
+        assert_eq!(
+            depth.asks,
+            vec![DepthLevel {
+                price: Price::cents(101),
+                quantity: Qty::shares(3),
+                orders: 1,
+            }]
+        );
+        assert_eq!(book.best_ask(), Some((Price::cents(101), Qty::shares(3))));
+        let mut displayed_only = OrderBook::new(String::from("AAPL"));
+        displayed_only
+            .add_limit_order(make_order(0, Side::Sell, 3, 101, String::from("d")))
+            .unwrap();
+        assert_eq!(book.checksum(5), displayed_only.checksum(5));
+
+        // the displayed order fills first though it came later
+        let events = book
+            .add_limit_order(make_order(0, Side::Buy, 5, 101, String::from("b")))
+            .unwrap()
+            .events;
+        let fills: Vec<(&str, Qty)> = events
+            .iter()
+            .map(|e| (e.seller.as_str(), e.quantity))
+            .collect();
+        assert_eq!(fills, vec![("d", Qty::shares(3)), ("h", Qty::shares(2))]);
+
+        // with only hidden orders left the level doesn't show at all
+        assert_eq!(book.best_ask(), None);
+        assert!(book.depth(5).asks.is_empty());
+        assert_eq!(book.total_quantity(Side::Sell), Qty::shares(4));
+    }
+
+    #[test]
+    fn test_min_quantity() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 2, 100, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 2, 101, String::from("b")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 5, 103, String::from("c")))
+            .unwrap();
+        let at_least = |qty, min, price| Order {
+            min_quantity: Some(Qty::shares(min)),
+            ..make_order(0, Side::Buy, qty, price, String::from("m"))
+        };
+        let before = serde_json::to_string(&book.snapshot()).unwrap();
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Order {
-    // pub order_id: u64,
-    pub user: String,
-    pub side: Side,
-    pub price: Option<i64>,
-    pub quantity: u64,
-    // pub timestamp: i64,
-    pub symbol: String,
-    #[serde(default = "default_state")]
-    pub state: OrderState,
-}
+        // 4 shares sit at 101 or better, one short
+        assert_eq!(
+            book.add_limit_order(at_least(8, 5, 101)).unwrap_err(),
+            AddOrderError::BelowMinQuantity
+        );
+        assert_eq!(serde_json::to_string(&book.snapshot()).unwrap(), before);
+        assert_eq!(
+            book.add_limit_order(at_least(3, 5, 101)).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::InvalidMinQuantity)
+        );
 
-fn default_state() -> OrderState {
-    OrderState::Open
-}
+        // exactly the minimum is enough, and the rest rests as usual
+        let result = book.add_limit_order(at_least(8, 4, 101)).unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(4));
+        assert_eq!(book.best_bid(), Some((Price::cents(101), Qty::shares(4))));
 
-impl Order {
-    pub fn new_limit_order(
-        // order_id: u64,
-        quantity: u64,
-        // timestamp: i64,
-        price: Option<i64>,
-        side: Side,
-        symbol: String,
-        user: String,
-    ) -> Self {
-        Self {
-            // order_id,
-            user,
-            side,
-            price,
-            quantity,
-            // timestamp,
-            state: OrderState::Open,
-            symbol,
-        }
+        // a market order measures against the whole side
+        let market = |min| Order {
+            min_quantity: Some(Qty::shares(min)),
+            ..make_market_order(0, Side::Buy, 6, String::from("n"))
+        };
+        assert_eq!(
+            book.add_market_order(market(6)).unwrap_err(),
+            AddOrderError::BelowMinQuantity
+        );
+        let result = book.add_market_order(market(5)).unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(5));
     }
 
-    pub fn new_market_order(
-        // order_id: u64,
-        quantity: u64,
-        // timestamp: i64,
-        side: Side,
-        symbol: String,
-        user: String,
-    ) -> Self {
-        Self {
-            // order_id,
-            user,
-            side,
-            price: None, // as market orders are executed based on the price from the orderbook
-            quantity,
-            // timestamp,
-            state: OrderState::Open,
-            symbol,
+    fn make_all_or_none_order(dir: Side, qty: u64, price: i64, user: &str) -> Order {
+        Order {
+            all_or_none: true,
+            ..make_order(0, dir, qty, price, String::from(user))
         }
     }
-}
 
-#[derive(Debug)]
-pub struct OrderBook {
-    pub bid_map: PriceMap,
-    pub ask_map: PriceMap,
-    pub symbol: String,
-}
+    #[test]
+    fn test_all_or_none_skipped_by_small_taker() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_all_or_none_order(Side::Sell, 5, 100, "a"))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 3, 100, String::from("b")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 2, 101, String::from("c")))
+            .unwrap();
 
-impl OrderBook {
-    pub fn new(symbol: String) -> Self {
-        Self {
-            bid_map: BTreeMap::new(),
-            ask_map: BTreeMap::new(),
-            symbol,
-        }
+        // too small for "a", so it passes over it and carries on to 101
+        let events = book
+            .add_limit_order(make_order(0, Side::Buy, 4, 101, String::from("t")))
+            .unwrap()
+            .events;
+        let fills: Vec<(&str, Qty)> = events
+            .iter()
+            .map(|e| (e.seller.as_str(), e.quantity))
+            .collect();
+        assert_eq!(fills, vec![("b", Qty::shares(3)), ("c", Qty::shares(1))]);
+        let asks: Vec<(&str, Qty)> = book
+            .iter_asks()
+            .map(|o| (o.user.as_str(), o.quantity))
+            .collect();
+        assert_eq!(asks, vec![("a", Qty::shares(5)), ("c", Qty::shares(1))]);
+
+        // a bid too small for it rests crossing it, which the book allows
+        let result = book
+            .add_limit_order(make_order(0, Side::Buy, 2, 100, String::from("u")))
+            .unwrap();
+        assert!(result.events.is_empty());
+        assert_eq!(book.best_bid(), Some((Price::cents(100), Qty::shares(2))));
+        book.check_top_of_book().unwrap();
+        book.validate_index().unwrap();
+
+        // a FOK order can't count on it either
+        let fok = Order {
+            time_in_force: TimeInForce::Fok,
+            ..make_order(0, Side::Buy, 4, 100, String::from("v"))
+        };
+        assert_eq!(
+            book.add_limit_order(fok).unwrap_err(),
+            AddOrderError::Unfillable
+        );
     }
 
-    pub fn add_limit_order(&mut self, mut order: Order) -> Vec<TradeEvent> {
-        let side = &order.side;
-        let price = order.price.unwrap();
-        let mut to_fill = order.quantity;
+    #[test]
+    fn test_all_or_none_filled_whole() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Sell, 1, 100, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_all_or_none_order(Side::Sell, 5, 100, "b"))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 4, 100, String::from("c")))
+            .unwrap();
 
-        let mut events = Vec::new();
+        // after "a" there are 6 left to fill, enough to take all of "b"
+        let result = book
+            .add_limit_order(make_order(0, Side::Buy, 7, 100, String::from("t")))
+            .unwrap();
+        let fills: Vec<(&str, Qty)> = result
+            .events
+            .iter()
+            .map(|e| (e.seller.as_str(), e.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                ("a", Qty::shares(1)),
+                ("b", Qty::shares(5)),
+                ("c", Qty::shares(1)),
+            ]
+        );
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.best_ask(), Some((Price::cents(100), Qty::shares(3))));
 
-        match side {
-            Side::Buy => {
-                if let Some((&lowest_ask_price, _)) = self.ask_map.first_key_value() {
-                    if price >= lowest_ask_price {
-                        (to_fill, events) = Self::match_orders(
-                            to_fill,
-                            Some(price),
-                            &mut self.ask_map,
-                            true,
-                            OrderType::Limit,
-                            order.user.as_str(),
+        // an incoming one trades only if it fills in full
+        book.add_limit_order(make_order(0, Side::Sell, 3, 101, String::from("d")))
+            .unwrap();
+        assert_eq!(
+            book.add_limit_order(make_all_or_none_order(Side::Buy, 7, 101, "u"))
+                .unwrap_err(),
+            AddOrderError::AllOrNoneUnfilled
+        );
+        let result = book
+            .add_limit_order(make_all_or_none_order(Side::Buy, 6, 101, "u"))
+            .unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(6));
+        assert!(book.is_empty());
+
+        let market = Order {
+            all_or_none: true,
+            ..make_market_order(0, Side::Buy, 1, String::from("w"))
+        };
+        assert_eq!(
+            book.add_market_order(market).unwrap_err(),
+            AddOrderError::Invalid(ValidationError::InvalidAllOrNone)
+        );
+    }
+
+    // Every level's kept total must match its orders whatever is done to it.
+    #[test]
+    fn test_level_totals_under_random_operations() {
+        for seed in 1..=50u64 {
+            let mut rng = seed;
+            let mut next = || {
+                rng = rng
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                rng >> 33
+            };
+            let mut book = OrderBook::new(String::from("AAPL"));
+            for _ in 0..300 {
+                let side = if next() % 2 == 0 {
+                    Side::Buy
+                } else {
+                    Side::Sell
+                };
+                let qty = next() % 10 + 1;
+                let price = 95 + (next() % 11) as i64;
+                let last_id = book.last_order_id.max(1);
+                match next() % 6 {
+                    0 | 1 => {
+                        let order = Order {
+                            hidden: next() % 5 == 0,
+                            all_or_none: next() % 5 == 0,
+                            ..make_order(0, side, qty, price, String::from("u"))
+                        };
+                        let _ = book.add_limit_order(order);
+                    }
+                    2 => {
+                        let _ = book.add_market_order(make_market_order(
+                            0,
+                            side,
+                            qty,
+                            String::from("m"),
+                        ));
+                    }
+                    3 => {
+                        let _ = book.cancel_order(next() % last_id + 1);
+                    }
+                    4 => {
+                        let _ = book.reduce_order(next() % last_id + 1, Qty::shares(qty));
+                    }
+                    _ => {
+                        let _ = book.amend_order(
+                            next() % last_id + 1,
+                            Price::cents(price),
+                            Qty::shares(qty),
                         );
                     }
                 }
-                if to_fill > 0 {
-                    order.quantity = to_fill;
-                    Self::insert_order(&mut self.bid_map, price, order);
+
+                for side in [Side::Buy, Side::Sell] {
+                    for (&price, level) in book.side_map(&side) {
+                        assert_eq!(level.check_total(), Ok(()), "seed {seed}");
+                        let resting: Qty = book
+                            .resting()
+                            .filter(|(at, _, o)| *at == price && o.side == side)
+                            .map(|(_, _, o)| o.quantity)
+                            .sum();
+                        assert_eq!(book.level_quantity(side.clone(), price), resting);
+                    }
                 }
+                book.validate_index().unwrap();
+                book.check_top_of_book().unwrap();
             }
-            Side::Sell => {
-                if let Some((&highest_bid_price, _)) = self.bid_map.last_key_value() {
-                    if price <= highest_bid_price {
-                        (to_fill, events) = Self::match_orders(
-                            to_fill,
-                            Some(price),
-                            &mut self.bid_map,
-                            false,
-                            OrderType::Limit,
-                            order.user.as_str(),
+        }
+    }
+
+    // A copy of the depth kept only from deltas matches the book's own
+    // after every operation.
+    #[test]
+    fn test_deltas_rebuild_the_depth() {
+        for seed in 1..=30u64 {
+            let mut rng = seed;
+            let mut next = || {
+                rng = rng
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                rng >> 33
+            };
+            let mut book = OrderBook::new(String::from("AAPL"));
+            book.set_clock(|| 0);
+            let mut bids: BTreeMap<Price, (Qty, usize)> = BTreeMap::new();
+            let mut asks: BTreeMap<Price, (Qty, usize)> = BTreeMap::new();
+            for step in 0..200i64 {
+                let side = if next() % 2 == 0 {
+                    Side::Buy
+                } else {
+                    Side::Sell
+                };
+                let qty = next() % 10 + 1;
+                let price = 95 + (next() % 11) as i64;
+                let last_id = book.last_order_id.max(1);
+                let user = format!("u{}", next() % 4);
+                match next() % 8 {
+                    0..=2 => {
+                        let order = Order {
+                            hidden: next() % 5 == 0,
+                            all_or_none: next() % 8 == 0,
+                            expires_at: (next() % 4 == 0).then_some(step + 5),
+                            ..make_order(0, side, qty, price, user)
+                        };
+                        let _ = book.add_limit_order(order);
+                    }
+                    3 => {
+                        let _ = book.add_market_order(make_market_order(0, side, qty, user));
+                    }
+                    4 => {
+                        let _ = book.cancel_order(next() % last_id + 1);
+                    }
+                    5 => {
+                        let _ = book.reduce_order(next() % last_id + 1, Qty::shares(qty));
+                    }
+                    6 => {
+                        let _ = book.amend_order(
+                            next() % last_id + 1,
+                            Price::cents(price),
+                            Qty::shares(qty),
                         );
                     }
+                    _ => {
+                        book.cancel_all_for_user(&user);
+                    }
                 }
+                book.expire_orders(step);
 
-                if to_fill > 0 {
-                    order.quantity = to_fill;
-                    Self::insert_order(&mut self.ask_map, price, order);
+                for delta in book.take_deltas() {
+                    let local = match delta.side {
+                        Side::Buy => &mut bids,
+                        Side::Sell => &mut asks,
+                    };
+                    if delta.quantity.is_zero() {
+                        local.remove(&delta.price);
+                    } else {
+                        local.insert(delta.price, (delta.quantity, delta.orders));
+                    }
                 }
+                let depth = book.depth(usize::MAX);
+                let levels = |levels: &[DepthLevel]| -> Vec<(Price, Qty, usize)> {
+                    levels
+                        .iter()
+                        .map(|l| (l.price, l.quantity, l.orders))
+                        .collect()
+                };
+                let local_bids: Vec<(Price, Qty, usize)> =
+                    bids.iter().rev().map(|(&p, &(q, n))| (p, q, n)).collect();
+                let local_asks: Vec<(Price, Qty, usize)> =
+                    asks.iter().map(|(&p, &(q, n))| (p, q, n)).collect();
+                assert_eq!(local_bids, levels(&depth.bids), "seed {seed} step {step}");
+                assert_eq!(local_asks, levels(&depth.asks), "seed {seed} step {step}");
+
+                // and the checksum published with the deltas agrees with the copy's
+                let top = |levels: Vec<(Price, Qty, usize)>| -> Vec<DepthLevel> {
+                    levels
+                        .into_iter()
+                        .take(10)
+                        .map(|(price, quantity, orders)| DepthLevel {
+                            price,
+                            quantity,
+                            orders,
+                        })
+                        .collect()
+                };
+                let local = DepthSnapshot {
+                    symbol: book.symbol.clone(),
+                    bids: top(local_bids),
+                    asks: top(local_asks),
+                    checksum: 0,
+                };
+                assert_eq!(local.compute_checksum(), book.checksum(10));
             }
-        };
-        events
+            assert!(book.take_deltas().is_empty());
+        }
     }
 
-    pub fn add_market_order(&mut self, order: Order) -> Vec<TradeEvent> {
-        let side = &order.side;
-        let remaining_quantity_to_be_filled = order.quantity;
+    #[test]
+    fn test_deltas_cover_the_auction_and_clear() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.start_auction();
+        book.add_limit_order(make_order(0, Side::Buy, 5, 101, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 3, 100, String::from("b")))
+            .unwrap();
+        book.take_deltas();
 
-        let (price_order_map, ascending) = match side {
-            Side::Buy => (&mut self.ask_map, true),
-            Side::Sell => (&mut self.bid_map, false),
-        };
+        book.uncross();
+        assert_eq!(
+            book.take_deltas(),
+            vec![
+                BookDelta {
+                    side: Side::Buy,
+                    price: Price::cents(101),
+                    quantity: Qty::shares(2),
+                    orders: 1,
+                },
+                BookDelta {
+                    side: Side::Sell,
+                    price: Price::cents(100),
+                    quantity: Qty::ZERO,
+                    orders: 0,
+                },
+            ]
+        );
 
-        let (_to_fill, events) = Self::match_orders(
-            remaining_quantity_to_be_filled,
-            None,
-            price_order_map,
-            ascending,
-            OrderType::Market,
-            order.user.as_str(),
+        book.clear();
+        assert_eq!(
+            book.take_deltas(),
+            vec![BookDelta {
+                side: Side::Buy,
+                price: Price::cents(101),
+                quantity: Qty::ZERO,
+                orders: 0,
+            }]
         );
-        events
     }
 
-    pub fn match_orders(
-        mut to_fill: u64,
-        price: Option<i64>,
-        book: &mut PriceMap,
-        ascending: bool,
-        ordertype: OrderType,
-        user_id: &str,
-    ) -> (u64, Vec<TradeEvent>) {
-        let mut events = Vec::new();
-        let keys: Vec<i64> = if ascending {
-            book.keys().cloned().collect()
-        } else {
-            book.keys().rev().cloned().collect()
-        };
+    #[test]
+    fn test_top_of_book_accessors() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        assert_eq!((book.best_bid(), book.best_ask()), (None, None));
+        assert_eq!((book.spread(), book.mid_price()), (None, None));
 
-        for current_price in keys {
-            if let OrderType::Limit = ordertype {
-                let price_cross = if ascending {
-                    price.unwrap() >= current_price // Buy vs Ask
-                } else {
-                    price.unwrap() <= current_price // Sell vs Bid
-                };
+        book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 7, 99, String::from("b")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 1, 98, String::from("c")))
+            .unwrap();
+        // one-sided
+        assert_eq!(book.best_bid(), Some((Price::cents(99), Qty::shares(12))));
+        assert_eq!(book.best_ask(), None);
+        assert_eq!((book.spread(), book.mid_price()), (None, None));
 
-                if !price_cross {
-                    break;
-                }
-            }
+        book.add_limit_order(make_order(0, Side::Sell, 3, 102, String::from("d")))
+            .unwrap();
+        assert_eq!(book.best_ask(), Some((Price::cents(102), Qty::shares(3))));
+        assert_eq!(book.spread(), Some(Price::cents(3)));
+        assert_eq!(book.mid_price(), Some(1.005));
+    }
 
-            let current_queue = book.get_mut(&current_price).unwrap();
+    #[test]
+    fn test_depth() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        for (price, qty) in [(100, 5), (100, 7), (98, 1), (99, 2)] {
+            book.add_limit_order(make_order(0, Side::Buy, qty, price, String::from("b")))
+                .unwrap();
+        }
+        let level = |price, quantity, orders| DepthLevel {
+            price,
+            quantity,
+            orders,
+        };
 
-            while to_fill > 0 {
-                if let Some(mut front_order) = current_queue.pop_front() {
-                    let consumed_quantity = to_fill.min(front_order.quantity);
+        let depth = book.depth(2);
+        assert_eq!(
+            depth.bids,
+            vec![
+                level(Price::cents(100), Qty::shares(12), 2),
+                level(Price::cents(99), Qty::shares(2), 1)
+            ]
+        );
+        assert!(depth.asks.is_empty());
 
-                    // Update resting order state
-                    front_order.quantity -= consumed_quantity;
-                    front_order.state = if front_order.quantity == 0 {
-                        OrderState::Filled
-                    } else {
-                        OrderState::PartiallyFilled
-                    };
+        book.add_limit_order(make_order(0, Side::Sell, 4, 103, String::from("s")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Sell, 3, 101, String::from("s")))
+            .unwrap();
+        // asking for more levels than exist returns what there is
+        let depth = book.depth(10);
+        assert_eq!(depth.bids.len(), 3);
+        assert_eq!(
+            depth.asks,
+            vec![
+                level(Price::cents(101), Qty::shares(3), 1),
+                level(Price::cents(103), Qty::shares(4), 1)
+            ]
+        );
+        assert_eq!(book.depth(0).bids, vec![]);
+    }
 
-                    // Emit event
-                    events.push(make_event(&front_order, &user_id, consumed_quantity));
+    #[test]
+    fn test_iterators_follow_priority() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        assert!(book.is_empty());
+        for (side, qty, price, user) in [
+            (Side::Buy, 1, 99, "b1"),
+            (Side::Buy, 2, 100, "b2"),
+            (Side::Buy, 3, 99, "b3"),
+            (Side::Sell, 4, 102, "s1"),
+            (Side::Sell, 5, 101, "s2"),
+            (Side::Sell, 6, 102, "s3"),
+        ] {
+            book.add_limit_order(make_order(0, side, qty, price, String::from(user)))
+                .unwrap();
+        }
+        book.add_stop_order(make_stop_order(Side::Buy, 1, 105, "stop"))
+            .unwrap();
 
-                    // Put back if partially filled
-                    if front_order.quantity > 0 {
-                        current_queue.push_front(front_order);
-                    }
+        let users = |orders: &mut dyn Iterator<Item = &Order>| -> Vec<String> {
+            orders.map(|o| o.user.clone()).collect()
+        };
+        assert_eq!(users(&mut book.iter_bids()), ["b2", "b1", "b3"]);
+        assert_eq!(users(&mut book.iter_asks()), ["s2", "s1", "s3"]);
+        assert_eq!(users(&mut book.iter_side(Side::Buy)), ["b2", "b1", "b3"]);
+        assert_eq!(users(&mut book.iter_side(Side::Sell)), ["s2", "s1", "s3"]);
 
-                    to_fill -= consumed_quantity;
-                } else {
-                    break;
-                }
-            }
+        // the waiting stop isn't on the book
+        assert_eq!(book.len(), 6);
+        assert!(!book.is_empty());
+        assert_eq!(book.total_quantity(Side::Buy), Qty::shares(6));
+        assert_eq!(book.total_quantity(Side::Sell), Qty::shares(15));
 
-            if current_queue.is_empty() {
-                book.remove(&current_price);
-            }
+        // a partial fill shows in what's left, not in how many
+        book.add_limit_order(make_order(0, Side::Buy, 7, 102, String::from("t")))
+            .unwrap();
+        assert_eq!(users(&mut book.iter_asks()), ["s1", "s3"]);
+        assert_eq!(book.total_quantity(Side::Sell), Qty::shares(8));
+        assert_eq!(book.len(), 5);
+    }
 
-            if to_fill == 0 {
-                break;
-            }
+    #[test]
+    fn test_checksum() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_clock(|| 0);
+        for (side, qty, price) in [
+            (Side::Buy, 5, 100),
+            (Side::Buy, 7, 100),
+            (Side::Buy, 2, 99),
+            (Side::Sell, 3, 101),
+            (Side::Sell, 4, 103),
+        ] {
+            book.add_limit_order(make_order(0, side, qty, price, String::from("u")))
+                .unwrap();
         }
+        assert_eq!(
+            book.checksum(10),
+            crc32fast::hash(b"1:12:1.01:3:0.99:2:1.03:4")
+        );
+        assert_eq!(book.checksum(1), crc32fast::hash(b"1:12:1.01:3"));
+        assert_eq!(book.checksum(0), crc32fast::hash(b""));
 
-        (to_fill, events)
-    }
+        // a consumer gets the same from the published depth
+        let json = serde_json::to_string(&book.depth(10)).unwrap();
+        let depth: DepthSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(depth.compute_checksum(), depth.checksum);
+        assert_eq!(depth.checksum, book.checksum(10));
 
-    fn insert_order(price_order_map: &mut PriceMap, price: i64, order: Order) {
-        price_order_map
-            .entry(price)
-            .or_insert_with(VecDeque::new)
-            .push_back(order);
-    }
-}
+        // and the same from a restored book
+        let restored = OrderBook::from_snapshot(book.snapshot());
+        assert_eq!(restored.checksum(10), book.checksum(10));
 
-fn trade_parties(maker: &Order, taker_id: &str) -> (String, String) {
-    match maker.side {
-        Side::Buy => (maker.user.clone(), taker_id.to_string()),
-        Side::Sell => (taker_id.to_string(), maker.user.clone()),
+        // an empty level, as a sweep leaves one before cleaning it up,
+        // doesn't count against the depth
+        let mut with_gap = serde_json::to_value(&book).unwrap();
+        with_gap["ask_map"]["1.02"] = serde_json::json!([]);
+        let with_gap: OrderBook = serde_json::from_value(with_gap).unwrap();
+        assert_eq!(with_gap.checksum(2), book.checksum(2));
+
+        book.add_limit_order(make_order(0, Side::Sell, 1, 101, String::from("v")))
+            .unwrap();
+        assert_ne!(book.checksum(10), restored.checksum(10));
     }
-}
 
-fn make_event(maker: &Order, taker_id: &str, qty: u64) -> TradeEvent {
-    let (buyer, seller) = trade_parties(maker, taker_id);
-    TradeEvent {
-        buyer,
-        seller,
-        price: maker.price.unwrap(),
-        quantity: qty,
-        symbol: maker.symbol.clone(),
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_clock(|| 0);
+        for (user, qty, price) in [("a", 5, 101), ("b", 3, 101), ("c", 4, 102), ("d", 2, 101)] {
+            book.add_limit_order(make_order(0, Side::Sell, qty, price, String::from(user)))
+                .unwrap();
+        }
+        book.add_limit_order(make_order(0, Side::Buy, 6, 99, String::from("e")))
+            .unwrap();
+        // a partial fill leaves a at the front with 4
+        book.add_limit_order(make_order(0, Side::Buy, 1, 101, String::from("f")))
+            .unwrap();
+        book.add_stop_order(make_stop_order(Side::Buy, 2, 102, "g"))
+            .unwrap();
+
+        let json = serde_json::to_string(&book.snapshot()).unwrap();
+        let mut restored = OrderBook::from_snapshot(serde_json::from_str(&json).unwrap());
+        restored.set_clock(|| 0);
+        assert_eq!(serde_json::to_string(&restored.snapshot()).unwrap(), json);
+        restored.validate_index().unwrap();
+
+        let sweep = || make_order(0, Side::Buy, 12, 102, String::from("t"));
+        let original = book.add_limit_order(sweep()).unwrap();
+        let replayed = restored.add_limit_order(sweep()).unwrap();
+        assert_eq!(original.order_id, replayed.order_id);
+        let sellers: Vec<&str> = original.events.iter().map(|e| e.seller.as_str()).collect();
+        assert_eq!(sellers, vec!["a", "b", "d", "c", "c"]);
+        assert_eq!(
+            serde_json::to_string(&original.events).unwrap(),
+            serde_json::to_string(&replayed.events).unwrap()
+        );
     }
-}
 
-// ---------------------------------------------TESTS---------------------------------------------------------
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_trade_ids_and_taker_side() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.set_clock(|| 42);
+        book.add_limit_order(make_order(0, Side::Sell, 5, 101, String::from("s")))
+            .unwrap();
+        book.add_limit_order(make_order(0, Side::Buy, 5, 99, String::from("b")))
+            .unwrap();
 
-    fn make_order(id: u64, dir: Side, qty: u64, price: i64, user_id: String) -> Order {
-        Order {
-            // order_id: id,
-            // timestamp: id as i64,
-            side: dir,
-            quantity: qty,
-            price: Some(price),
-            state: OrderState::Open,
-            symbol: String::from("AAPL"),
-            user: user_id,
-        }
+        let lifted = book
+            .add_limit_order(make_order(0, Side::Buy, 2, 101, String::from("t")))
+            .unwrap()
+            .events;
+        let hit = book
+            .add_market_order(make_market_order(0, Side::Sell, 8, String::from("t")))
+            .unwrap()
+            .events;
+        let stamps: Vec<(u64, &str, &str, i64)> = lifted
+            .iter()
+            .chain(&hit)
+            .map(|e| {
+                let side = match e.taker_side {
+                    Side::Buy => "buy",
+                    Side::Sell => "sell",
+                };
+                (e.trade_id, side, e.buyer.as_str(), e.timestamp)
+            })
+            .collect();
+        assert_eq!(stamps, vec![(1, "buy", "t", 42), (2, "sell", "b", 42)]);
     }
 
-    fn make_market_order(id: u64, dir: Side, qty: u64, user_id: String) -> Order {
-        Order {
-            // order_id: id,
-            // timestamp: id as i64,
-            side: dir,
-            quantity: qty,
-            price: None, // irrelevant for market
-            state: OrderState::Open,
-            symbol: String::from("AAPL"),
-            user: user_id,
-        }
+    #[test]
+    fn test_check_top_of_book() {
+        let mut book = OrderBook::new(String::from("AAPL"));
+        book.add_limit_order(make_order(0, Side::Buy, 10, 100, String::from("a")))
+            .unwrap();
+        book.add_limit_order(make_order(1, Side::Sell, 10, 101, String::from("b")))
+            .unwrap();
+        assert_eq!(book.check_top_of_book(), Ok(()));
+
+        // bypass matching to cross the book
+        OrderBook::insert_order(
+            &mut book.bid_map,
+            Price::cents(102),
+            make_order(2, Side::Buy, 5, 102, String::from("c")),
+        );
+        assert_eq!(
+            book.check_top_of_book(),
+            Err(String::from("crossed book: best bid 1.02 >= best ask 1.01"))
+        );
+
+        *book.bid_map.get_mut(&Price::cents(102)).unwrap() = PriceLevel::default();
+        assert_eq!(
+            book.check_top_of_book(),
+            Err(String::from("empty bid level at 1.02"))
+        );
     }
 
     #[test]
@@ -308,32 +4958,42 @@ mod tests {
 
         // Insert 10 limit orders (5 buys, 5 sells)
         for i in 0..5 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Buy,
-                10,
-                100 - i as i64,
-                String::from("shyamnatesan21@gmail.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Buy,
+                    10,
+                    100 - i as i64,
+                    String::from("shyamnatesan21@gmail.com"),
+                ))
+                .unwrap()
+                .events;
             // no matches should occur, so no events
             assert!(events.is_empty());
         }
         for i in 5..10 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Sell,
-                10,
-                101 + (i - 5) as i64,
-                String::from("shyamnatesan21@gmail.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Sell,
+                    10,
+                    101 + (i - 5) as i64,
+                    String::from("shyamnatesan21@gmail.com"),
+                ))
+                .unwrap()
+                .events;
             assert!(events.is_empty());
         }
 
         // Assertions: best bid = 100, best ask = 101
-        assert_eq!(*book.bid_map.last_key_value().unwrap().0, 100);
-        assert_eq!(*book.ask_map.first_key_value().unwrap().0, 101);
-        assert_eq!(book.bid_map.values().map(|q| q.len()).sum::<usize>(), 5);
-        assert_eq!(book.ask_map.values().map(|q| q.len()).sum::<usize>(), 5);
+        assert_eq!(*book.bid_map.last_key_value().unwrap().0, Price::cents(100));
+        assert_eq!(
+            *book.ask_map.first_key_value().unwrap().0,
+            Price::cents(101)
+        );
+        assert_eq!(book.iter_bids().count(), 5);
+        assert_eq!(book.iter_asks().count(), 5);
+        assert_eq!(book.len(), 10);
     }
 
     #[test]
@@ -342,40 +5002,38 @@ mod tests {
 
         // Seed asks (10 sell orders at prices 100..109, qty 5 each)
         for i in 0..10 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Sell,
-                5,
-                100 + i as i64,
-                String::from("shyamnatesan21@gmail.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Sell,
+                    5,
+                    100 + i as i64,
+                    String::from("shyamnatesan21@gmail.com"),
+                ))
+                .unwrap()
+                .events;
             assert!(events.is_empty()); // no trades yet
         }
 
         // // Incoming buy order at 110 for qty 50(should sweep lowest asks fully)
-        let events = book.add_limit_order(make_order(
-            99,
-            Side::Buy,
-            50,
-            110,
-            String::from("monishnatesan17@gmail.com"),
-        ));
+        let result = book
+            .add_limit_order(make_order(
+                99,
+                Side::Buy,
+                50,
+                110,
+                String::from("monishnatesan17@gmail.com"),
+            ))
+            .unwrap();
 
         // It should generate trades for all 10 asks (5 qty each) = 50 qty total
-        assert_eq!(events.len(), 10);
-
-        // Verify quantities sum up correctly
-        let total_qty: i64 = events.iter().map(|e| e.quantity as i64).sum();
-        assert_eq!(total_qty, 50);
-
-        // Compute weighted average trade price
-        let total_notional: i64 = events.iter().map(|e| e.price * e.quantity as i64).sum();
-        let average_price = total_notional as f64 / total_qty as f64;
-
-        assert_eq!((average_price - 104.5).abs(), 0.0);
+        assert_eq!(result.events.len(), 10);
+        assert_eq!(result.filled_quantity, Qty::shares(50));
+        assert_eq!(result.remaining_quantity, Qty::ZERO);
+        assert_eq!(result.average_price, Some(1.045));
 
         // After execution, 0 asks remain up to 109
-        assert!(book.ask_map.range(..=109).all(|(_, q)| q.is_empty()));
+        assert!(book.iter_asks().all(|o| o.price > Some(Price::cents(109))));
     }
 
     #[test]
@@ -384,32 +5042,40 @@ mod tests {
 
         // Seed 10 asks with 10 qty each
         for i in 0..10 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Sell,
-                10,
-                100 + i as i64,
-                String::from("shyamnatesan21@gmail.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Sell,
+                    10,
+                    100 + i as i64,
+                    String::from("shyamnatesan21@gmail.com"),
+                ))
+                .unwrap()
+                .events;
             assert!(events.is_empty()); // seeding should not trigger trades
         }
 
         // Incoming large buy of 150 at 110
-        let events = book.add_limit_order(make_order(
-            200,
-            Side::Buy,
-            150,
-            110,
-            String::from("monishnatesan17@gmail.com"),
-        ));
+        let result = book
+            .add_limit_order(make_order(
+                200,
+                Side::Buy,
+                150,
+                110,
+                String::from("monishnatesan17@gmail.com"),
+            ))
+            .unwrap();
 
         // It should consume all 100 shares from asks [100..109], but leave 50 unfilled
-        let total_filled: i64 = events.iter().map(|e| e.quantity as i64).sum();
-        assert_eq!(total_filled, 100);
+        assert_eq!(result.filled_quantity, Qty::shares(100));
+        assert_eq!(result.remaining_quantity, Qty::shares(50));
 
         // That leftover 50 should sit in bid book at price 110
-        let bid_q = book.bid_map.get(&110).unwrap();
-        assert_eq!(bid_q.front().unwrap().quantity, 50);
+        let bid = book.iter_bids().next().unwrap();
+        assert_eq!(
+            (bid.price, bid.quantity),
+            (Some(Price::cents(110)), Qty::shares(50))
+        );
     }
 
     #[test]
@@ -418,40 +5084,37 @@ mod tests {
 
         // Seed 10 asks of 10 qty each (prices 100..109)
         for i in 0..10 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Sell,
-                10,
-                100 + i as i64,
-                String::from("shyamnatesan21@gmail.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Sell,
+                    10,
+                    100 + i as i64,
+                    String::from("shyamnatesan21@gmail.com"),
+                ))
+                .unwrap()
+                .events;
             assert!(events.is_empty()); // limit orders don't immediately match
         }
 
         // Incoming market buy of 60
-        let events = book.add_market_order(make_market_order(
-            500,
-            Side::Buy,
-            60,
-            String::from("monishnatesan17@gmail.com"),
-        ));
-
-        // Check total filled = 60
-        let total_filled: u64 = events.iter().map(|e| e.quantity).sum();
-        assert_eq!(total_filled, 60);
+        let result = book
+            .add_market_order(make_market_order(
+                500,
+                Side::Buy,
+                60,
+                String::from("monishnatesan17@gmail.com"),
+            ))
+            .unwrap();
 
-        // Compute average trade price
-        let total_notional: i64 = events.iter().map(|e| e.price * e.quantity as i64).sum();
-        let average_price = total_notional as f64 / total_filled as f64;
-        assert_eq!((average_price - 102.5).abs(), 0.0);
+        // Check total filled = 60 at an average of 102.5
+        assert_eq!(result.filled_quantity, Qty::shares(60));
+        assert_eq!(result.remaining_quantity, Qty::ZERO);
+        assert_eq!(result.average_price, Some(1.025));
 
         // Remaining asks should reflect 40 left
-        let total_remaining: u64 = book
-            .ask_map
-            .values()
-            .map(|q| q.iter().map(|o| o.quantity).sum::<u64>())
-            .sum();
-        assert_eq!(total_remaining, 40);
+        assert_eq!(book.total_quantity(Side::Sell), Qty::shares(40));
+        book.validate_index().unwrap();
     }
 
     #[test]
@@ -460,80 +5123,91 @@ mod tests {
 
         // Step 1: add 5 buys
         for i in 0..5 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Buy,
-                10,
-                100 - i as i64,
-                format!("buyer{i}@test.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Buy,
+                    10,
+                    100 - i as i64,
+                    format!("buyer{i}@test.com"),
+                ))
+                .unwrap()
+                .events;
             assert!(events.is_empty());
         }
         // Step 2: add 5 sells
         for i in 5..10 {
-            let events = book.add_limit_order(make_order(
-                i,
-                Side::Sell,
-                10,
-                101 + (i - 5) as i64,
-                format!("seller{i}@test.com"),
-            ));
+            let events = book
+                .add_limit_order(make_order(
+                    i,
+                    Side::Sell,
+                    10,
+                    101 + (i - 5) as i64,
+                    format!("seller{i}@test.com"),
+                ))
+                .unwrap()
+                .events;
             assert!(events.is_empty());
         }
 
         // Step 3: Add crossing buy at 105 (should eat ask at 101,102,...)
-        let events = book.add_limit_order(make_order(
-            20,
-            Side::Buy,
-            25,
-            105,
-            "crossbuyer@test.com".to_string(),
-        ));
-        let total_qty: u64 = events.iter().map(|e| e.quantity).sum();
-        assert_eq!(total_qty, 25);
-        let total_notional: i64 = events.iter().map(|e| e.price * e.quantity as i64).sum();
-        let avg_price = total_notional as f64 / total_qty as f64;
-        assert_eq!((avg_price - 101.8).abs(), 0.0);
+        let result = book
+            .add_limit_order(make_order(
+                20,
+                Side::Buy,
+                25,
+                105,
+                "crossbuyer@test.com".to_string(),
+            ))
+            .unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(25));
+        assert_eq!(result.average_price, Some(1.018));
+        assert_eq!(result.resting_order_id, None);
 
         // Step 4: Market sell of 30, consuming from bid side (100..96)
-        let events = book.add_market_order(make_market_order(
-            21,
-            Side::Sell,
-            30,
-            "marketseller@test.com".to_string(),
-        ));
-        let total_qty: u64 = events.iter().map(|e| e.quantity).sum();
-        assert_eq!(total_qty, 30);
-        let total_notional: i64 = events.iter().map(|e| e.price * e.quantity as i64).sum();
-        let avg_price = total_notional as f64 / total_qty as f64;
-        assert_eq!((avg_price - 99.0).abs(), 0.0);
+        let result = book
+            .add_market_order(make_market_order(
+                21,
+                Side::Sell,
+                30,
+                "marketseller@test.com".to_string(),
+            ))
+            .unwrap();
+        assert_eq!(result.filled_quantity, Qty::shares(30));
+        assert_eq!(result.average_price, Some(0.99));
 
         // Best ask should now be 103
-        assert_eq!(*book.ask_map.first_key_value().unwrap().0, 103);
+        assert_eq!(
+            *book.ask_map.first_key_value().unwrap().0,
+            Price::cents(103)
+        );
 
         // Step 5: Big buy sweep (1000 qty) — only 25 ask qty left
-        let events = book.add_market_order(make_market_order(
-            22,
-            Side::Buy,
-            1000,
-            "bigbuyer@test.com".to_string(),
-        ));
-
-        let total_qty: u64 = events.iter().map(|e| e.quantity).sum();
-        assert_eq!(total_qty, 25); // only 25 left to take
+        let result = book
+            .add_market_order(make_market_order(
+                22,
+                Side::Buy,
+                1000,
+                "bigbuyer@test.com".to_string(),
+            ))
+            .unwrap();
 
-        let total_notional: i64 = events.iter().map(|e| e.price * e.quantity as i64).sum();
-        let avg_price = total_notional as f64 / total_qty as f64;
-        assert_eq!((avg_price - 104.2).abs(), 0.0);
+        // only 25 left to take, and no collar to blame for the rest
+        assert_eq!(result.filled_quantity, Qty::shares(25));
+        assert_eq!(result.remaining_quantity, Qty::shares(975));
+        assert!(!result.collared);
+        assert_eq!(result.average_price, Some(1.042));
 
         // Assertions: no asks left
-        assert!(book.ask_map.values().all(|q| q.is_empty()));
+        assert!(book.iter_asks().next().is_none());
+        assert_eq!(book.total_quantity(Side::Sell), Qty::ZERO);
 
         // Bid side should still have resting bids
-        assert_eq!(book.bid_map.values().map(|q| q.len()).sum::<usize>(), 2);
+        assert_eq!(book.iter_bids().count(), 2);
 
         // best bid = 97
-        assert_eq!(*book.bid_map.last_key_value().unwrap().0, 97);
+        assert_eq!(*book.bid_map.last_key_value().unwrap().0, Price::cents(97));
+        book.validate_index().unwrap();
     }
 
     #[test]
@@ -566,7 +5240,8 @@ mod tests {
                 qty,
                 price,
                 format!("user{i}@test.com"),
-            ));
+            ))
+            .unwrap();
         }
 
         // Add 10 market orders interleaved
@@ -589,26 +5264,17 @@ mod tests {
                 dir,
                 qty,
                 format!("mktuser{i}@test.com"),
-            ));
+            ))
+            .unwrap();
         }
 
         // Assertions: order book should remain consistent
-        assert_eq!(*book.bid_map.last_key_value().unwrap().0, 90);
-        assert_eq!(book.ask_map.is_empty(), true);
+        assert_eq!(*book.bid_map.last_key_value().unwrap().0, Price::cents(90));
+        assert!(book.ask_map.is_empty());
 
         // Ensure at least some quantities remain on both sides
-        let total_bids: u64 = book
-            .bid_map
-            .values()
-            .map(|q| q.iter().map(|o| o.quantity).sum::<u64>())
-            .sum();
-        let total_asks: u64 = book
-            .ask_map
-            .values()
-            .map(|q| q.iter().map(|o| o.quantity).sum::<u64>())
-            .sum();
-
-        assert_eq!(total_bids, 115);
-        assert_eq!(total_asks, 0);
+        assert_eq!(book.total_quantity(Side::Buy), Qty::shares(115));
+        assert_eq!(book.total_quantity(Side::Sell), Qty::ZERO);
+        book.validate_index().unwrap();
     }
 }
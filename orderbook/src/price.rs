@@ -0,0 +1,347 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+
+// Prices are held in ten-thousandths of the quote currency.
+pub const PRICE_DECIMALS: u32 = 4;
+pub const PRICE_SCALE: i64 = 10i64.pow(PRICE_DECIMALS);
+// units in one cent, the scale integer prices were given in before Price
+const CENT: i64 = PRICE_SCALE / 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParsePriceError {
+    Invalid(String),
+    TooPrecise(String),
+    Overflow,
+}
+
+impl fmt::Display for ParsePriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsePriceError::Invalid(s) => write!(f, "invalid price: {s:?}"),
+            ParsePriceError::TooPrecise(s) => {
+                write!(
+                    f,
+                    "price {s:?} has more than {PRICE_DECIMALS} decimal places"
+                )
+            }
+            ParsePriceError::Overflow => f.write_str("price is too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParsePriceError {}
+
+// A price, or an amount of money at the same precision such as a notional,
+// kept as an integer count of 1 / PRICE_SCALE so ordering and sums are exact.
+// In JSON it is a decimal string ("101.25"). Decimal numbers (101.25) are
+// read as they are written; plain integers are read as cents (10125), the
+// form prices took before they had decimals.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(i64);
+
+impl Price {
+    pub const ZERO: Price = Price(0);
+    pub const MAX: Price = Price(i64::MAX);
+
+    pub const fn from_units(units: i64) -> Self {
+        Price(units)
+    }
+
+    // Panics if `cents` is more than a Price can hold; checked_cents doesn't.
+    pub const fn cents(cents: i64) -> Self {
+        match Self::checked_cents(cents) {
+            Some(price) => price,
+            None => panic!("price is too large"),
+        }
+    }
+
+    pub const fn checked_cents(cents: i64) -> Option<Self> {
+        match cents.checked_mul(CENT) {
+            Some(units) => Some(Price(units)),
+            None => None,
+        }
+    }
+
+    // Panics if `whole` is more than a Price can hold.
+    pub const fn whole(whole: i64) -> Self {
+        match whole.checked_mul(PRICE_SCALE) {
+            Some(units) => Price(units),
+            None => panic!("price is too large"),
+        }
+    }
+
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub const fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn abs(self) -> Price {
+        Price(self.0.abs())
+    }
+
+    pub fn checked_add(self, other: Price) -> Option<Price> {
+        self.0.checked_add(other.0).map(Price)
+    }
+
+    pub fn checked_sub(self, other: Price) -> Option<Price> {
+        self.0.checked_sub(other.0).map(Price)
+    }
+
+    // Whether this sits on a grid of `tick`s; a tick of zero has no grid.
+    pub fn is_multiple_of(self, tick: Price) -> bool {
+        tick.0 != 0 && self.0 % tick.0 == 0
+    }
+
+    // Only for display and averages; every comparison stays on units.
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / PRICE_SCALE as f64
+    }
+}
+
+impl Add for Price {
+    type Output = Price;
+
+    fn add(self, other: Price) -> Price {
+        Price(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Price {
+    fn add_assign(&mut self, other: Price) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Price {
+    type Output = Price;
+
+    fn sub(self, other: Price) -> Price {
+        Price(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Price {
+    fn sub_assign(&mut self, other: Price) {
+        self.0 -= other.0;
+    }
+}
+
+impl Neg for Price {
+    type Output = Price;
+
+    fn neg(self) -> Price {
+        Price(-self.0)
+    }
+}
+
+impl Sum for Price {
+    fn sum<I: Iterator<Item = Price>>(iter: I) -> Price {
+        iter.fold(Price::ZERO, Add::add)
+    }
+}
+
+// Shows the decimal, so log lines and test failures read as prices.
+impl fmt::Debug for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Price({self})")
+    }
+}
+
+// Whole prices print without a decimal point; fractions without trailing
+// zeros.
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let magnitude = self.0.unsigned_abs();
+        let scale = PRICE_SCALE as u64;
+        let (whole, fraction) = (magnitude / scale, magnitude % scale);
+        if fraction == 0 {
+            return write!(f, "{sign}{whole}");
+        }
+        let digits = format!("{:0width$}", fraction, width = PRICE_DECIMALS as usize);
+        write!(f, "{}{}.{}", sign, whole, digits.trim_end_matches('0'))
+    }
+}
+
+// Accepts "101", "101.25" and "-0.0001"; anything finer than a unit is
+// refused rather than rounded.
+impl FromStr for Price {
+    type Err = ParsePriceError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParsePriceError::Invalid(input.to_string());
+        let (negative, unsigned) = match input.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(fraction) || unsigned.ends_with('.') {
+            return Err(invalid());
+        }
+        if fraction.len() > PRICE_DECIMALS as usize {
+            return Err(ParsePriceError::TooPrecise(input.to_string()));
+        }
+
+        let whole: i64 = whole.parse().map_err(|_| ParsePriceError::Overflow)?;
+        let fraction: i64 = if fraction.is_empty() {
+            0
+        } else {
+            let width = PRICE_DECIMALS as usize;
+            format!("{fraction:0<width$}")
+                .parse()
+                .map_err(|_| invalid())?
+        };
+        let magnitude = whole
+            .checked_mul(PRICE_SCALE)
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or(ParsePriceError::Overflow)?;
+        Ok(Price(if negative { -magnitude } else { magnitude }))
+    }
+}
+
+// Binary formats such as bincode get the bare units.
+impl Serialize for Price {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(PriceVisitor)
+        } else {
+            i64::deserialize(deserializer).map(Price)
+        }
+    }
+}
+
+struct PriceVisitor;
+
+impl de::Visitor<'_> for PriceVisitor {
+    type Value = Price;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a price as a decimal or a whole number of cents")
+    }
+
+    fn visit_i64<E: de::Error>(self, cents: i64) -> Result<Price, E> {
+        Price::checked_cents(cents).ok_or_else(|| E::custom(ParsePriceError::Overflow))
+    }
+
+    fn visit_u64<E: de::Error>(self, cents: u64) -> Result<Price, E> {
+        let cents = i64::try_from(cents).map_err(|_| E::custom(ParsePriceError::Overflow))?;
+        self.visit_i64(cents)
+    }
+
+    // a JSON number such as 101.25, read through its shortest decimal form
+    fn visit_f64<E: de::Error>(self, price: f64) -> Result<Price, E> {
+        self.visit_str(&price.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Price, E> {
+        s.parse().map_err(E::custom)
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        for (input, units, shown) in [
+            ("101", 1_010_000, "101"),
+            ("101.25", 1_012_500, "101.25"),
+            ("0.0001", 1, "0.0001"),
+            ("-3.50", -35_000, "-3.5"),
+            ("0", 0, "0"),
+        ] {
+            let price: Price = input.parse().unwrap();
+            assert_eq!(price, Price::from_units(units));
+            assert_eq!(price.to_string(), shown);
+        }
+        assert_eq!(
+            "1.00001".parse::<Price>(),
+            Err(ParsePriceError::TooPrecise("1.00001".to_string()))
+        );
+        for bad in ["", "-", ".5", "5.", "+1", "1e3", "1.2.3", " 1", "--1"] {
+            assert_eq!(
+                bad.parse::<Price>(),
+                Err(ParsePriceError::Invalid(bad.to_string()))
+            );
+        }
+        assert_eq!(
+            "922337203685478".parse::<Price>(),
+            Err(ParsePriceError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_serde_accepts_decimals_and_cents() {
+        let read = |json: &str| serde_json::from_str::<Price>(json);
+        assert_eq!(read("10125").unwrap(), Price::cents(10125));
+        assert_eq!(read("101.25").unwrap(), Price::cents(10125));
+        assert_eq!(read("\"101.25\"").unwrap(), Price::cents(10125));
+        assert_eq!(read("\"101.2501\"").unwrap(), Price::from_units(1_012_501));
+        assert!(read("101.25001").is_err());
+        assert!(read("9223372036854775807").is_err());
+        assert_eq!(
+            serde_json::to_string(&Price::cents(10125)).unwrap(),
+            "\"101.25\""
+        );
+        // binary formats carry the units as they are
+        let binary = bincode::serialize(&Price::from_units(-1_012_501)).unwrap();
+        assert_eq!(binary, (-1_012_501i64).to_le_bytes());
+        assert_eq!(
+            bincode::deserialize::<Price>(&binary).unwrap(),
+            Price::from_units(-1_012_501)
+        );
+    }
+
+    #[test]
+    fn test_ordering_and_ticks() {
+        let mut prices: Vec<Price> = ["101.25", "99.9999", "101.2", "-1", "100"]
+            .iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+        prices.sort();
+        let shown: Vec<String> = prices.iter().map(Price::to_string).collect();
+        assert_eq!(shown, ["-1", "99.9999", "100", "101.2", "101.25"]);
+
+        let nickel = Price::cents(5);
+        assert!(Price::cents(10125).is_multiple_of(nickel));
+        assert!(!Price::from_units(1_012_501).is_multiple_of(nickel));
+        assert!(!Price::cents(100).is_multiple_of(Price::ZERO));
+    }
+
+    #[test]
+    fn test_no_drift_over_many_trades() {
+        // 0.1 can't be held exactly in binary floating point, so summing it
+        // in f64 drifts; in units it doesn't
+        let tenth: Price = "0.1".parse().unwrap();
+        let mut float = 0.0f64;
+        let mut total = Price::ZERO;
+        for _ in 0..100_000 {
+            float += 0.1;
+            total += tenth;
+        }
+        assert_ne!(float, 10_000.0);
+        assert_eq!(total, Price::whole(10_000));
+        assert_eq!(total.to_string(), "10000");
+    }
+}
@@ -0,0 +1,303 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    str::FromStr,
+};
+
+// Quantities are held in ten-thousandths of a share.
+pub const QTY_DECIMALS: u32 = 4;
+pub const QTY_SCALE: u64 = 10u64.pow(QTY_DECIMALS);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseQtyError {
+    Invalid(String),
+    TooPrecise(String),
+    Overflow,
+}
+
+impl fmt::Display for ParseQtyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseQtyError::Invalid(s) => write!(f, "invalid quantity: {s:?}"),
+            ParseQtyError::TooPrecise(s) => write!(
+                f,
+                "quantity {s:?} has more than {QTY_DECIMALS} decimal places"
+            ),
+            ParseQtyError::Overflow => f.write_str("quantity is too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParseQtyError {}
+
+// A number of shares, fractional down to 1 / QTY_SCALE, kept as an integer
+// count of those units so matching never rounds. In JSON it is a decimal
+// string ("0.5"); plain integers are read as whole shares.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Qty(u64);
+
+impl Qty {
+    pub const ZERO: Qty = Qty(0);
+    pub const MAX: Qty = Qty(u64::MAX);
+
+    pub const fn from_units(units: u64) -> Self {
+        Qty(units)
+    }
+
+    // Panics if `shares` is more than a Qty can hold; checked_shares doesn't.
+    pub const fn shares(shares: u64) -> Self {
+        match Self::checked_shares(shares) {
+            Some(qty) => qty,
+            None => panic!("quantity is too large"),
+        }
+    }
+
+    pub const fn checked_shares(shares: u64) -> Option<Self> {
+        match shares.checked_mul(QTY_SCALE) {
+            Some(units) => Some(Qty(units)),
+            None => None,
+        }
+    }
+
+    pub const fn units(self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn saturating_sub(self, other: Qty) -> Qty {
+        Qty(self.0.saturating_sub(other.0))
+    }
+
+    pub fn abs_diff(self, other: Qty) -> Qty {
+        Qty(self.0.abs_diff(other.0))
+    }
+
+    pub fn checked_add(self, other: Qty) -> Option<Qty> {
+        self.0.checked_add(other.0).map(Qty)
+    }
+
+    // Whether this is a whole number of `lot`s; a zero lot divides nothing.
+    pub fn is_multiple_of(self, lot: Qty) -> bool {
+        self.0.is_multiple_of(lot.0)
+    }
+
+    // The largest whole number of `lot`s that fits in this.
+    pub fn round_down_to(self, lot: Qty) -> Qty {
+        Qty(self.0 - self.0 % lot.0)
+    }
+}
+
+impl Add for Qty {
+    type Output = Qty;
+
+    fn add(self, other: Qty) -> Qty {
+        Qty(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Qty {
+    fn add_assign(&mut self, other: Qty) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Qty {
+    type Output = Qty;
+
+    fn sub(self, other: Qty) -> Qty {
+        Qty(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Qty {
+    fn sub_assign(&mut self, other: Qty) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Qty {
+    fn sum<I: Iterator<Item = Qty>>(iter: I) -> Qty {
+        iter.fold(Qty::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Qty> for Qty {
+    fn sum<I: Iterator<Item = &'a Qty>>(iter: I) -> Qty {
+        iter.copied().sum()
+    }
+}
+
+// Whole shares print without a decimal point; fractions without trailing
+// zeros.
+impl fmt::Display for Qty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (whole, fraction) = (self.0 / QTY_SCALE, self.0 % QTY_SCALE);
+        if fraction == 0 {
+            return write!(f, "{whole}");
+        }
+        let digits = format!("{:0width$}", fraction, width = QTY_DECIMALS as usize);
+        write!(f, "{}.{}", whole, digits.trim_end_matches('0'))
+    }
+}
+
+// Accepts "5", "0.5" and "12.0001"; anything finer than a unit is refused
+// rather than rounded.
+impl FromStr for Qty {
+    type Err = ParseQtyError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseQtyError::Invalid(input.to_string());
+        let (whole, fraction) = input.split_once('.').unwrap_or((input, ""));
+        let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(fraction) {
+            return Err(invalid());
+        }
+        if input.ends_with('.') {
+            return Err(invalid());
+        }
+        if fraction.len() > QTY_DECIMALS as usize {
+            return Err(ParseQtyError::TooPrecise(input.to_string()));
+        }
+
+        let whole: u64 = whole.parse().map_err(|_| ParseQtyError::Overflow)?;
+        let fraction: u64 = if fraction.is_empty() {
+            0
+        } else {
+            let width = QTY_DECIMALS as usize;
+            format!("{fraction:0<width$}")
+                .parse()
+                .map_err(|_| invalid())?
+        };
+        Qty::checked_shares(whole)
+            .and_then(|qty| qty.checked_add(Qty(fraction)))
+            .ok_or(ParseQtyError::Overflow)
+    }
+}
+
+// Binary formats such as bincode get the bare units.
+impl Serialize for Qty {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Qty {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(QtyVisitor)
+        } else {
+            u64::deserialize(deserializer).map(Qty)
+        }
+    }
+}
+
+struct QtyVisitor;
+
+impl de::Visitor<'_> for QtyVisitor {
+    type Value = Qty;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a quantity as a decimal string or a whole number of shares")
+    }
+
+    fn visit_u64<E: de::Error>(self, shares: u64) -> Result<Qty, E> {
+        Qty::checked_shares(shares).ok_or_else(|| E::custom(ParseQtyError::Overflow))
+    }
+
+    fn visit_i64<E: de::Error>(self, shares: i64) -> Result<Qty, E> {
+        let shares = u64::try_from(shares)
+            .map_err(|_| E::custom(ParseQtyError::Invalid(shares.to_string())))?;
+        self.visit_u64(shares)
+    }
+
+    // a JSON number such as 0.5, read through its shortest decimal form
+    fn visit_f64<E: de::Error>(self, shares: f64) -> Result<Qty, E> {
+        self.visit_str(&shares.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Qty, E> {
+        s.parse().map_err(E::custom)
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        for (input, units, shown) in [
+            ("5", 50_000, "5"),
+            ("0.5", 5_000, "0.5"),
+            ("12.0001", 120_001, "12.0001"),
+            ("3.1000", 31_000, "3.1"),
+            ("0", 0, "0"),
+        ] {
+            let qty: Qty = input.parse().unwrap();
+            assert_eq!(qty, Qty::from_units(units));
+            assert_eq!(qty.to_string(), shown);
+        }
+        assert_eq!(
+            "0.00001".parse::<Qty>(),
+            Err(ParseQtyError::TooPrecise("0.00001".to_string()))
+        );
+        for bad in ["", ".5", "5.", "-1", "1e3", "1.2.3", " 1"] {
+            assert_eq!(
+                bad.parse::<Qty>(),
+                Err(ParseQtyError::Invalid(bad.to_string()))
+            );
+        }
+        assert_eq!(
+            "18446744073709551615".parse::<Qty>(),
+            Err(ParseQtyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_serde_accepts_integers_and_strings() {
+        let read = |json: &str| serde_json::from_str::<Qty>(json);
+        assert_eq!(read("5").unwrap(), Qty::shares(5));
+        assert_eq!(read("\"0.5\"").unwrap(), Qty::from_units(5_000));
+        assert_eq!(read("0.5").unwrap(), Qty::from_units(5_000));
+        assert!(read("-5").is_err());
+        assert!(read("\"0.12345\"").is_err());
+        assert!(read("18446744073709551615").is_err());
+        assert_eq!(
+            serde_json::to_string(&Qty::from_units(5_000)).unwrap(),
+            "\"0.5\""
+        );
+        let binary = bincode::serialize(&Qty::from_units(5_000)).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Qty>(&binary).unwrap(),
+            Qty::from_units(5_000)
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_is_exact() {
+        let half = Qty::from_units(5_000);
+        let mut total = Qty::ZERO;
+        for _ in 0..10_000 {
+            total += half;
+        }
+        assert_eq!(total, Qty::shares(5_000));
+        assert_eq!(total - half, Qty::from_units(49_995_000));
+        assert_eq!(
+            [half, half, half].iter().sum::<Qty>(),
+            Qty::from_units(15_000)
+        );
+        assert!(Qty::shares(3).is_multiple_of(half));
+        assert!(!Qty::from_units(7_500).is_multiple_of(half));
+        assert_eq!(Qty::from_units(7_500).round_down_to(half), half);
+    }
+}
@@ -0,0 +1,491 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::{Order, Price, Qty, SymbolRules, TimeInForce, notional};
+
+// How many tags an order may carry, and how long each may be in bytes.
+pub const MAX_ORDER_TAGS: usize = 8;
+pub const MAX_TAG_LENGTH: usize = 32;
+
+// What is wrong with an order on its own or under its symbol's rules,
+// whatever the book it goes to holds. Published in a rejection as
+// {"code": "off_tick", ...} with the details of the rule it broke; the code
+// is the rejection's reason.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum ValidationError {
+    EmptyUser,
+    EmptySymbol,
+    // nothing to fill: no shares, or a notional budget that isn't positive
+    ZeroQuantity,
+    // sized both in shares and by a notional budget; it takes one or the other
+    QuantityAndNotional,
+    // a post-only order with no limit price; the book also gives this for
+    // an order sent in as a limit order without one, a stop without a stop
+    // price or a pegged order without a peg
+    MissingPrice,
+    // a limit, stop or peg limit price of zero or below
+    InvalidPrice {
+        price: Price,
+    },
+    // a market-to-limit, pegged or notional order with a price of its own,
+    // or one both market-to-limit and pegged; the book gives it its price.
+    // Also a market order sent in with a limit price
+    UnexpectedPrice,
+    // a limit, stop or peg limit price, or a peg offset, off the tick grid
+    OffTick {
+        price: Price,
+        tick_size: Price,
+    },
+    // a quantity that isn't a whole number of lots
+    OddLot {
+        quantity: Qty,
+        lot_size: Qty,
+    },
+    #[serde(rename = "quantity_too_large")]
+    TooLarge {
+        quantity: Qty,
+        max_quantity: Qty,
+    },
+    // a minimum quantity of zero, off the lot size, above the order's own
+    // quantity, or on a stop or notional order
+    InvalidMinQuantity,
+    // an all-or-none order that isn't a plain good-till-cancelled limit
+    // order: one without a price, pegged, a stop, IOC or FOK
+    InvalidAllOrNone,
+    // more than MAX_ORDER_TAGS tags, or an empty one or one longer than
+    // MAX_TAG_LENGTH
+    InvalidTags,
+    // a price times quantity too large to hold; every trade the order could
+    // make costs no more than this, so accepting it keeps them all in range
+    NotionalOverflow {
+        price: Price,
+    },
+}
+
+impl ValidationError {
+    // The code it is published under.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ValidationError::EmptyUser => "empty_user",
+            ValidationError::EmptySymbol => "empty_symbol",
+            ValidationError::ZeroQuantity => "zero_quantity",
+            ValidationError::QuantityAndNotional => "quantity_and_notional",
+            ValidationError::MissingPrice => "missing_price",
+            ValidationError::InvalidPrice { .. } => "invalid_price",
+            ValidationError::UnexpectedPrice => "unexpected_price",
+            ValidationError::OffTick { .. } => "off_tick",
+            ValidationError::OddLot { .. } => "odd_lot",
+            ValidationError::TooLarge { .. } => "quantity_too_large",
+            ValidationError::InvalidMinQuantity => "invalid_min_quantity",
+            ValidationError::InvalidAllOrNone => "invalid_all_or_none",
+            ValidationError::InvalidTags => "invalid_tags",
+            ValidationError::NotionalOverflow { .. } => "notional_overflow",
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyUser => write!(f, "order has no user"),
+            ValidationError::EmptySymbol => write!(f, "order has no symbol"),
+            ValidationError::ZeroQuantity => write!(f, "order has nothing to fill"),
+            ValidationError::QuantityAndNotional => {
+                write!(f, "order has both a quantity and a notional")
+            }
+            ValidationError::MissingPrice => write!(f, "order has no price"),
+            ValidationError::InvalidPrice { price } => {
+                write!(f, "price {} must be positive", price)
+            }
+            ValidationError::UnexpectedPrice => write!(f, "order takes its price from the book"),
+            ValidationError::OffTick { price, tick_size } => {
+                write!(
+                    f,
+                    "price {} is not a multiple of the tick {}",
+                    price, tick_size
+                )
+            }
+            ValidationError::OddLot { quantity, lot_size } => write!(
+                f,
+                "quantity {} is not a multiple of the lot size {}",
+                quantity, lot_size
+            ),
+            ValidationError::TooLarge {
+                quantity,
+                max_quantity,
+            } => write!(
+                f,
+                "quantity {} is above the book's maximum of {}",
+                quantity, max_quantity
+            ),
+            ValidationError::InvalidMinQuantity => write!(f, "invalid minimum quantity"),
+            ValidationError::InvalidAllOrNone => {
+                write!(
+                    f,
+                    "only good-till-cancelled limit orders can be all-or-none"
+                )
+            }
+            ValidationError::InvalidTags => write!(
+                f,
+                "order may carry up to {} tags of 1 to {} bytes",
+                MAX_ORDER_TAGS, MAX_TAG_LENGTH
+            ),
+            ValidationError::NotionalOverflow { price } => {
+                write!(f, "price {} times quantity overflows", price)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Order {
+    // Checks everything about the order that doesn't depend on what the
+    // book holds: its own fields, and `rules`. An order without a price is a
+    // market order; one with a price is a limit order at it.
+    pub fn validate(&self, rules: &SymbolRules) -> Result<(), ValidationError> {
+        if self.user.is_empty() {
+            return Err(ValidationError::EmptyUser);
+        }
+        if self.symbol.is_empty() {
+            return Err(ValidationError::EmptySymbol);
+        }
+        let nothing_to_fill = match self.notional {
+            Some(budget) => !budget.is_positive(),
+            None => self.quantity.is_zero(),
+        };
+        if nothing_to_fill {
+            return Err(ValidationError::ZeroQuantity);
+        }
+        if self.notional.is_some() && !self.quantity.is_zero() {
+            return Err(ValidationError::QuantityAndNotional);
+        }
+        // a post-only order without a price would take liquidity as a market
+        // order
+        if self.post_only && self.price.is_none() {
+            return Err(ValidationError::MissingPrice);
+        }
+        let priced_by_book = self.market_to_limit || self.peg.is_some();
+        if (self.market_to_limit && self.peg.is_some())
+            || (self.notional.is_some() && self.price.is_some())
+            || (priced_by_book
+                && (self.price.is_some() || self.stop_price.is_some() || self.notional.is_some()))
+        {
+            return Err(ValidationError::UnexpectedPrice);
+        }
+        let peg_limit = self.peg.and_then(|peg| peg.limit);
+        let prices = [self.price, self.stop_price, peg_limit];
+        if let Some(price) = prices.into_iter().flatten().find(|p| !p.is_positive()) {
+            return Err(ValidationError::InvalidPrice { price });
+        }
+        // an offset of zero is on every grid
+        if let Some(price) = prices
+            .into_iter()
+            .flatten()
+            .chain(self.peg.map(|peg| peg.offset.abs()))
+            .find(|&p| p != Price::ZERO && !p.is_multiple_of(rules.tick_size))
+        {
+            return Err(ValidationError::OffTick {
+                price,
+                tick_size: rules.tick_size,
+            });
+        }
+        if !self.quantity.is_multiple_of(rules.lot_size) {
+            return Err(ValidationError::OddLot {
+                quantity: self.quantity,
+                lot_size: rules.lot_size,
+            });
+        }
+        if let Some(max_quantity) = rules.max_quantity
+            && self.quantity > max_quantity
+        {
+            return Err(ValidationError::TooLarge {
+                quantity: self.quantity,
+                max_quantity,
+            });
+        }
+        if let Some(min) = self.min_quantity
+            && (min.is_zero()
+                || !min.is_multiple_of(rules.lot_size)
+                || min > self.quantity
+                || self.stop_price.is_some()
+                || self.notional.is_some())
+        {
+            return Err(ValidationError::InvalidMinQuantity);
+        }
+        if self.all_or_none
+            && (self.price.is_none()
+                || self.peg.is_some()
+                || self.stop_price.is_some()
+                || self.time_in_force != TimeInForce::Gtc)
+        {
+            return Err(ValidationError::InvalidAllOrNone);
+        }
+        if self.tags.len() > MAX_ORDER_TAGS
+            || self
+                .tags
+                .iter()
+                .any(|tag| tag.is_empty() || tag.len() > MAX_TAG_LENGTH)
+        {
+            return Err(ValidationError::InvalidTags);
+        }
+        for price in prices.into_iter().flatten() {
+            notional(price, self.quantity)
+                .map_err(|_| ValidationError::NotionalOverflow { price })?;
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Peg, Side};
+
+    fn limit(quantity: &str, price: &str) -> Order {
+        Order::new_limit_order(
+            quantity.parse().unwrap(),
+            Some(price.parse().unwrap()),
+            Side::Buy,
+            "AAPL".to_string(),
+            "a".to_string(),
+        )
+    }
+
+    fn market(quantity: &str) -> Order {
+        Order::new_market_order(
+            quantity.parse().unwrap(),
+            Side::Sell,
+            "AAPL".to_string(),
+            "a".to_string(),
+        )
+    }
+
+    fn price(p: &str) -> Price {
+        p.parse().unwrap()
+    }
+
+    #[test]
+    fn test_every_variant() {
+        let rules = SymbolRules {
+            tick_size: price("0.05"),
+            lot_size: Qty::shares(10),
+            max_quantity: Some(Qty::shares(1000)),
+            ..SymbolRules::default()
+        };
+        let cases: Vec<(&str, Order, Result<(), ValidationError>)> = vec![
+            ("limit", limit("100", "101.05"), Ok(())),
+            ("market", market("100"), Ok(())),
+            (
+                "no user",
+                Order {
+                    user: String::new(),
+                    ..limit("100", "101")
+                },
+                Err(ValidationError::EmptyUser),
+            ),
+            (
+                "no symbol",
+                Order {
+                    symbol: String::new(),
+                    ..market("100")
+                },
+                Err(ValidationError::EmptySymbol),
+            ),
+            (
+                "no shares",
+                limit("0", "101"),
+                Err(ValidationError::ZeroQuantity),
+            ),
+            (
+                "no budget",
+                Order {
+                    notional: Some(Price::ZERO),
+                    ..market("0")
+                },
+                Err(ValidationError::ZeroQuantity),
+            ),
+            (
+                "shares and a budget",
+                Order {
+                    notional: Some(Price::whole(1000)),
+                    ..market("5")
+                },
+                Err(ValidationError::QuantityAndNotional),
+            ),
+            (
+                "post-only market",
+                Order {
+                    post_only: true,
+                    ..market("100")
+                },
+                Err(ValidationError::MissingPrice),
+            ),
+            (
+                "zero price",
+                limit("100", "0"),
+                Err(ValidationError::InvalidPrice { price: Price::ZERO }),
+            ),
+            (
+                "negative stop",
+                Order {
+                    stop_price: Some(price("-1")),
+                    ..market("100")
+                },
+                Err(ValidationError::InvalidPrice { price: price("-1") }),
+            ),
+            (
+                "priced market-to-limit",
+                Order {
+                    market_to_limit: true,
+                    ..limit("100", "101")
+                },
+                Err(ValidationError::UnexpectedPrice),
+            ),
+            (
+                "priced notional",
+                Order {
+                    notional: Some(Price::whole(1000)),
+                    ..limit("0", "101")
+                },
+                Err(ValidationError::UnexpectedPrice),
+            ),
+            (
+                "off tick",
+                limit("100", "101.01"),
+                Err(ValidationError::OffTick {
+                    price: price("101.01"),
+                    tick_size: price("0.05"),
+                }),
+            ),
+            (
+                "peg offset off tick",
+                Order {
+                    peg: Some(Peg {
+                        offset: price("-0.02"),
+                        limit: None,
+                    }),
+                    ..market("100")
+                },
+                Err(ValidationError::OffTick {
+                    price: price("0.02"),
+                    tick_size: price("0.05"),
+                }),
+            ),
+            (
+                "odd lot",
+                limit("105", "101"),
+                Err(ValidationError::OddLot {
+                    quantity: Qty::shares(105),
+                    lot_size: Qty::shares(10),
+                }),
+            ),
+            (
+                "too large",
+                market("1010"),
+                Err(ValidationError::TooLarge {
+                    quantity: Qty::shares(1010),
+                    max_quantity: Qty::shares(1000),
+                }),
+            ),
+            (
+                "minimum above the quantity",
+                Order {
+                    min_quantity: Some(Qty::shares(200)),
+                    ..limit("100", "101")
+                },
+                Err(ValidationError::InvalidMinQuantity),
+            ),
+            (
+                "all-or-none market",
+                Order {
+                    all_or_none: true,
+                    ..market("100")
+                },
+                Err(ValidationError::InvalidAllOrNone),
+            ),
+            (
+                "all-or-none IOC",
+                Order {
+                    all_or_none: true,
+                    time_in_force: TimeInForce::Ioc,
+                    ..limit("100", "101")
+                },
+                Err(ValidationError::InvalidAllOrNone),
+            ),
+            (
+                "tagged",
+                Order {
+                    tags: vec![String::from("momentum"), String::from("desk-2")],
+                    ..limit("100", "101")
+                },
+                Ok(()),
+            ),
+            (
+                "too many tags",
+                Order {
+                    tags: vec![String::from("t"); MAX_ORDER_TAGS + 1],
+                    ..limit("100", "101")
+                },
+                Err(ValidationError::InvalidTags),
+            ),
+            (
+                "tag too long",
+                Order {
+                    tags: vec!["x".repeat(MAX_TAG_LENGTH + 1)],
+                    ..limit("100", "101")
+                },
+                Err(ValidationError::InvalidTags),
+            ),
+        ];
+        for (name, order, expected) in cases {
+            assert_eq!(order.validate(&rules), expected, "{name}");
+        }
+
+        // under the default rules, so the size isn't capped first
+        let huge = limit("1000000", "900000000000000");
+        assert_eq!(
+            huge.validate(&SymbolRules::default()),
+            Err(ValidationError::NotionalOverflow {
+                price: price("900000000000000")
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialized_with_its_code() {
+        for (error, json) in [
+            (
+                ValidationError::EmptyUser,
+                serde_json::json!({ "code": "empty_user" }),
+            ),
+            (
+                ValidationError::OffTick {
+                    price: price("101.01"),
+                    tick_size: price("0.05"),
+                },
+                serde_json::json!({ "code": "off_tick", "price": "101.01", "tick_size": "0.05" }),
+            ),
+            (
+                ValidationError::TooLarge {
+                    quantity: Qty::shares(1010),
+                    max_quantity: Qty::shares(1000),
+                },
+                serde_json::json!({
+                    "code": "quantity_too_large",
+                    "quantity": "1010",
+                    "max_quantity": "1000",
+                }),
+            ),
+        ] {
+            assert_eq!(serde_json::to_value(error).unwrap(), json);
+            assert_eq!(json["code"], error.reason());
+            assert_eq!(
+                serde_json::from_value::<ValidationError>(json).unwrap(),
+                error
+            );
+        }
+    }
+}
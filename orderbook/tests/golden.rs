@@ -0,0 +1,165 @@
+// Golden-file tests: each script in tests/scripts is replayed against a fresh
+// book and the resulting event stream is compared with tests/golden. Run with
+// UPDATE_GOLDEN=1 to rewrite the golden files after an intentional change.
+//
+// Script lines look like
+//   limit buy 10 @ 100 alice@test.com
+//   market sell 30 bob@test.com
+// and blank lines or lines starting with '#' are ignored.
+
+use orderbook::{Order, OrderBook, Side};
+use std::{fs, path::PathBuf};
+
+const SYMBOL: &str = "AAPL";
+
+fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn parse_side(word: &str) -> Side {
+    match word {
+        "buy" => Side::Buy,
+        "sell" => Side::Sell,
+        other => panic!("unknown side {:?}", other),
+    }
+}
+
+fn parse_command(line: &str) -> Order {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["limit", side, qty, "@", price, user] => Order::new_limit_order(
+            qty.parse().unwrap(),
+            Some(price.parse().unwrap()),
+            parse_side(side),
+            SYMBOL.to_string(),
+            user.to_string(),
+        ),
+        ["market", side, qty, user] => Order::new_market_order(
+            qty.parse().unwrap(),
+            parse_side(side),
+            SYMBOL.to_string(),
+            user.to_string(),
+        ),
+        _ => panic!("cannot parse script line {:?}", line),
+    }
+}
+
+// Echoes every command as "> ..." followed by its events, one JSON object per
+// line, and finishes with the resting book.
+fn run_script(script: &str) -> String {
+    let mut book = OrderBook::new(SYMBOL.to_string());
+    // fixed so trade timestamps don't churn the golden files
+    book.set_clock(|| 0);
+    let mut out = String::new();
+
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let order = parse_command(line);
+        let events = match order.price {
+            Some(_) => book.add_limit_order(order).unwrap().events,
+            None => book.add_market_order(order).unwrap().events,
+        };
+        out.push_str(&format!("> {}\n", line));
+        for event in events {
+            out.push_str(&serde_json::to_string(&event).unwrap());
+            out.push('\n');
+        }
+    }
+
+    out.push_str("= book\n");
+    for (price, queue) in book.asks().iter().rev() {
+        let quantities: Vec<String> = queue.iter().map(|o| o.quantity.to_string()).collect();
+        out.push_str(&format!("ask {} {}\n", price, quantities.join(" ")));
+    }
+    for (price, queue) in book.bids().iter().rev() {
+        let quantities: Vec<String> = queue.iter().map(|o| o.quantity.to_string()).collect();
+        out.push_str(&format!("bid {} {}\n", price, quantities.join(" ")));
+    }
+    out
+}
+
+// Line-by-line diff, good enough to spot which event changed.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => {}
+            (e, a) => {
+                out.push_str(&format!("line {}:\n", i + 1));
+                if let Some(e) = e {
+                    out.push_str(&format!("  - {}\n", e));
+                }
+                if let Some(a) = a {
+                    out.push_str(&format!("  + {}\n", a));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn check(name: &str) {
+    let script = fs::read_to_string(tests_dir().join("scripts").join(format!("{name}.txt")))
+        .unwrap_or_else(|e| panic!("missing script {}: {}", name, e));
+    let actual = run_script(&script);
+    let golden_path = tests_dir().join("golden").join(format!("{name}.golden"));
+
+    if std::env::var("UPDATE_GOLDEN").is_ok_and(|v| v == "1") {
+        fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden_path).unwrap_or_else(|_| {
+        panic!(
+            "no golden file for {}, run with UPDATE_GOLDEN=1 to create it",
+            name
+        )
+    });
+    if expected != actual {
+        panic!(
+            "event stream for {} differs from {}:\n{}",
+            name,
+            golden_path.display(),
+            diff(&expected, &actual)
+        );
+    }
+}
+
+#[test]
+fn golden_limit_orders_sit_in_book() {
+    check("limit_orders_sit_in_book");
+}
+
+#[test]
+fn golden_full_fill_limit_vs_limit() {
+    check("full_fill_limit_vs_limit");
+}
+
+#[test]
+fn golden_partial_fill_large_buy() {
+    check("partial_fill_large_buy");
+}
+
+#[test]
+fn golden_market_orders_sweep() {
+    check("market_orders_sweep");
+}
+
+#[test]
+fn golden_mixed_complex_flow() {
+    check("mixed_complex_flow");
+}
+
+#[test]
+fn golden_complex_order_flow_one() {
+    check("complex_order_flow_one");
+}
+
+#[test]
+fn golden_best_level_then_sweep() {
+    check("best_level_then_sweep");
+}
@@ -0,0 +1,84 @@
+// Saves a book through OrderBook::save, loads it back and checks the copy
+// matches the same incoming orders exactly as the original does.
+
+use orderbook::{Order, OrderBook, Price, Qty, Side};
+
+const SYMBOL: &str = "AAPL";
+
+fn limit(side: Side, shares: u64, price: &str, user: &str) -> Order {
+    Order::new_limit_order(
+        Qty::shares(shares),
+        Some(price.parse().unwrap()),
+        side,
+        SYMBOL.to_string(),
+        user.to_string(),
+    )
+}
+
+fn build_book() -> OrderBook {
+    let mut book = OrderBook::new(SYMBOL.to_string());
+    book.set_clock(|| 0);
+    for (side, shares, price, user) in [
+        (Side::Sell, 5, "101.25", "a"),
+        (Side::Sell, 3, "101.25", "b"),
+        (Side::Sell, 4, "102", "c"),
+        (Side::Buy, 6, "99.5", "d"),
+        (Side::Buy, 2, "100", "e"),
+    ] {
+        book.add_limit_order(limit(side, shares, price, user))
+            .unwrap();
+    }
+    // trades a partly, so the front of its level has 4 left
+    book.add_limit_order(limit(Side::Buy, 1, "101.25", "f"))
+        .unwrap();
+    let stop = Order {
+        stop_price: Some(Price::whole(102)),
+        ..limit(Side::Buy, 2, "103", "g")
+    };
+    book.add_stop_order(stop).unwrap();
+    book
+}
+
+#[test]
+fn saved_book_matches_like_the_original() {
+    let mut book = build_book();
+    let mut saved = Vec::new();
+    book.save(&mut saved).unwrap();
+    let mut restored = OrderBook::load(saved.as_slice()).unwrap();
+    restored.set_clock(|| 0);
+    restored.validate_index().unwrap();
+
+    let state = |book: &OrderBook| serde_json::to_string(&book.snapshot()).unwrap();
+    assert_eq!(state(&restored), state(&book));
+    assert_eq!(restored.stats(), book.stats());
+    assert_eq!(restored.recent_trades(10), book.recent_trades(10));
+    assert_eq!(restored.recent_trades(10).len(), 1);
+
+    // sweeps both ask levels and sets off the stop
+    let sweep = || limit(Side::Buy, 12, "102", "t");
+    let original = book.add_limit_order(sweep()).unwrap();
+    let replayed = restored.add_limit_order(sweep()).unwrap();
+    assert_eq!(replayed.order_id, original.order_id);
+    assert_eq!(
+        serde_json::to_string(&replayed.events).unwrap(),
+        serde_json::to_string(&original.events).unwrap()
+    );
+    let sellers: Vec<&str> = original.events.iter().map(|e| e.seller.as_str()).collect();
+    assert_eq!(sellers, ["a", "b", "c"]);
+
+    // the next order and trade ids carry on rather than starting over
+    let next = || limit(Side::Sell, 1, "99.5", "u");
+    let original = book.add_limit_order(next()).unwrap();
+    let replayed = restored.add_limit_order(next()).unwrap();
+    assert_eq!(replayed.order_id, original.order_id);
+    assert_eq!(replayed.events[0].trade_id, original.events[0].trade_id);
+    assert_eq!(state(&restored), state(&book));
+}
+
+#[test]
+fn truncated_file_is_refused() {
+    let mut saved = Vec::new();
+    build_book().save(&mut saved).unwrap();
+    assert!(OrderBook::load(&saved[..saved.len() / 2]).is_err());
+    assert!(OrderBook::load(&[][..]).is_err());
+}
@@ -0,0 +1,358 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::clock::{self, Clock};
+
+pub const AUDIT_KEY_ENV: &str = "AUDIT_SIGNING_KEY";
+// Only good for local runs; exports signed with it prove nothing.
+pub const DEV_AUDIT_KEY: &str = "dev-audit-key";
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+pub fn signing_key_from_env() -> Vec<u8> {
+    match std::env::var(AUDIT_KEY_ENV) {
+        Ok(key) if !key.is_empty() => key.into_bytes(),
+        _ => {
+            println!(
+                "⚠️ {} not set, signing audit exports with the development key",
+                AUDIT_KEY_ENV
+            );
+            DEV_AUDIT_KEY.as_bytes().to_vec()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    Order,
+    Trade,
+    OrderRejected,
+    OrderLost,
+    TradeBust,
+    AdminAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub at: i64,
+    pub kind: AuditKind,
+    pub users: Vec<String>,
+    pub symbol: Option<String>,
+    pub data: serde_json::Value,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditRecord {
+    // Covers every field but the hash itself, chained onto the previous one.
+    fn compute_hash(&self) -> String {
+        let body = serde_json::json!({
+            "seq": self.seq,
+            "at": self.at,
+            "kind": self.kind,
+            "users": self.users,
+            "symbol": self.symbol,
+            "data": self.data,
+        });
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(body.to_string().as_bytes());
+        hex(&hasher.finalize())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuditError {
+    // the record's contents no longer match its hash
+    BadHash(u64),
+    // the record doesn't chain onto the one before it
+    BrokenLink(u64),
+    BadSignature,
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::BadHash(seq) => write!(f, "record {} does not match its hash", seq),
+            AuditError::BrokenLink(seq) => {
+                write!(f, "record {} does not chain onto its predecessor", seq)
+            }
+            AuditError::BadSignature => f.write_str("signature does not match"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct AuditFilter {
+    pub user: Option<String>,
+    pub symbol: Option<String>,
+    // inclusive YYYY-MM-DD bounds (UTC)
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        let date = clock::format_day(clock::day_number(record.at));
+        self.user.as_ref().is_none_or(|u| record.users.contains(u))
+            && self
+                .symbol
+                .as_ref()
+                .is_none_or(|s| record.symbol.as_ref() == Some(s))
+            && self.from.as_ref().is_none_or(|from| &date >= from)
+            && self.to.as_ref().is_none_or(|to| &date <= to)
+    }
+}
+
+// A filtered slice of the log. Records keep their chain hashes, so each one
+// can be checked on its own and consecutive ones against each other; the
+// signature covers the filter, the chain head at export time and every
+// record hash.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditExport {
+    pub filter: AuditFilter,
+    pub exported_at: i64,
+    pub chain_head: String,
+    pub records: Vec<AuditRecord>,
+    pub signature: String,
+}
+
+impl AuditExport {
+    fn signature_for(&self, key: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes any key length");
+        mac.update(serde_json::to_string(&self.filter).unwrap().as_bytes());
+        mac.update(&self.exported_at.to_be_bytes());
+        mac.update(self.chain_head.as_bytes());
+        for record in &self.records {
+            mac.update(record.hash.as_bytes());
+        }
+        hex(&mac.finalize().into_bytes())
+    }
+
+    pub fn verify(&self, key: &[u8]) -> Result<(), AuditError> {
+        let mut previous: Option<&AuditRecord> = None;
+        for record in &self.records {
+            if record.compute_hash() != record.hash {
+                return Err(AuditError::BadHash(record.seq));
+            }
+            if let Some(previous) = previous
+                && previous.seq + 1 == record.seq
+                && previous.hash != record.prev_hash
+            {
+                return Err(AuditError::BrokenLink(record.seq));
+            }
+            previous = Some(record);
+        }
+        if self.signature_for(key) != self.signature {
+            return Err(AuditError::BadSignature);
+        }
+        Ok(())
+    }
+}
+
+// Backs the `verify <file>` subcommand: checks an export offline, returning
+// how many records it holds.
+pub fn verify_file(path: &Path, key: &[u8]) -> Result<usize, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let export: AuditExport = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    export.verify(key).map_err(|e| e.to_string())?;
+    Ok(export.records.len())
+}
+
+// Append-only audit trail. Each record is hashed onto its predecessor as it
+// is written, so editing or dropping anything already stored breaks the
+// chain from that point on.
+pub struct AuditLog {
+    clock: Arc<dyn Clock>,
+    records: Mutex<Vec<AuditRecord>>,
+}
+
+impl AuditLog {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            records: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn append(
+        &self,
+        kind: AuditKind,
+        users: &[&str],
+        symbol: Option<&str>,
+        data: serde_json::Value,
+    ) -> u64 {
+        let at = self.clock.now_millis();
+        let mut records = self.records.lock().unwrap();
+        let prev_hash = records
+            .last()
+            .map_or(GENESIS_HASH.to_string(), |r| r.hash.clone());
+        let mut record = AuditRecord {
+            seq: records.len() as u64 + 1,
+            at,
+            kind,
+            users: users.iter().map(|u| u.to_string()).collect(),
+            symbol: symbol.map(str::to_string),
+            data,
+            prev_hash,
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash();
+        records.push(record);
+        records.len() as u64
+    }
+
+    // Walks the whole stored chain from the genesis hash.
+    pub fn verify_chain(&self) -> Result<(), AuditError> {
+        let records = self.records.lock().unwrap();
+        let mut prev_hash = GENESIS_HASH;
+        for record in records.iter() {
+            if record.prev_hash != prev_hash {
+                return Err(AuditError::BrokenLink(record.seq));
+            }
+            if record.compute_hash() != record.hash {
+                return Err(AuditError::BadHash(record.seq));
+            }
+            prev_hash = &record.hash;
+        }
+        Ok(())
+    }
+
+    pub fn export(&self, filter: AuditFilter, key: &[u8]) -> AuditExport {
+        let exported_at = self.clock.now_millis();
+        let records = self.records.lock().unwrap();
+        let mut export = AuditExport {
+            chain_head: records
+                .last()
+                .map_or(GENESIS_HASH.to_string(), |r| r.hash.clone()),
+            records: records
+                .iter()
+                .filter(|r| filter.matches(r))
+                .cloned()
+                .collect(),
+            filter,
+            exported_at,
+            signature: String::new(),
+        };
+        export.signature = export.signature_for(key);
+        export
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MILLIS_PER_DAY, test_support::FakeClock};
+    use serde_json::json;
+
+    const NOW: i64 = 1_760_486_400_000;
+    const KEY: &[u8] = b"test-key";
+
+    fn sample_log() -> AuditLog {
+        let clock = Arc::new(FakeClock::new(NOW));
+        let log = AuditLog::new(clock.clone());
+        log.append(
+            AuditKind::Order,
+            &["a"],
+            Some("AAPL"),
+            json!({ "quantity": 5 }),
+        );
+        log.append(
+            AuditKind::Order,
+            &["b"],
+            Some("MSFT"),
+            json!({ "quantity": 1 }),
+        );
+        clock.set(NOW + MILLIS_PER_DAY);
+        log.append(
+            AuditKind::Trade,
+            &["a", "c"],
+            Some("AAPL"),
+            json!({ "price": 100 }),
+        );
+        log.append(
+            AuditKind::AdminAction,
+            &["a"],
+            None,
+            json!({ "action": "x" }),
+        );
+        log
+    }
+
+    #[test]
+    fn test_filtered_export_verifies() {
+        let log = sample_log();
+        assert_eq!(log.verify_chain(), Ok(()));
+
+        let export = log.export(
+            AuditFilter {
+                user: Some("a".to_string()),
+                ..AuditFilter::default()
+            },
+            KEY,
+        );
+        let seqs: Vec<u64> = export.records.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, vec![1, 3, 4]);
+        assert_eq!(export.chain_head, export.records[2].hash);
+        assert_eq!(export.verify(KEY), Ok(()));
+        assert_eq!(export.verify(b"other-key"), Err(AuditError::BadSignature));
+
+        // the file round-trips through JSON
+        let file = serde_json::to_string(&export).unwrap();
+        let parsed: AuditExport = serde_json::from_str(&file).unwrap();
+        assert_eq!(parsed.verify(KEY), Ok(()));
+
+        let export = log.export(
+            AuditFilter {
+                symbol: Some("AAPL".to_string()),
+                from: Some("2025-10-16".to_string()),
+                ..AuditFilter::default()
+            },
+            KEY,
+        );
+        assert_eq!(export.records.len(), 1);
+        assert_eq!(export.records[0].kind, AuditKind::Trade);
+    }
+
+    #[test]
+    fn test_tampered_export_detected() {
+        let log = sample_log();
+        let export = log.export(AuditFilter::default(), KEY);
+
+        let mut edited = export.clone();
+        edited.records[2].data = json!({ "price": 1 });
+        assert_eq!(edited.verify(KEY), Err(AuditError::BadHash(3)));
+
+        // rehashing the edit doesn't help: the next record no longer links
+        edited.records[2].hash = edited.records[2].compute_hash();
+        assert_eq!(edited.verify(KEY), Err(AuditError::BrokenLink(4)));
+
+        // and dropping a record breaks the signature
+        let mut dropped = export.clone();
+        dropped.records.remove(1);
+        assert_eq!(dropped.verify(KEY), Err(AuditError::BadSignature));
+    }
+
+    #[test]
+    fn test_tampered_store_detected() {
+        let log = sample_log();
+        log.records.lock().unwrap()[1].users = vec!["mallory".to_string()];
+        assert_eq!(log.verify_chain(), Err(AuditError::BadHash(2)));
+
+        let log = sample_log();
+        log.records.lock().unwrap().remove(0);
+        assert_eq!(log.verify_chain(), Err(AuditError::BrokenLink(2)));
+    }
+}
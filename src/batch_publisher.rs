@@ -0,0 +1,236 @@
+use futures::future::BoxFuture;
+use redis::{Client, aio::MultiplexedConnection};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::publisher::{PublishError, Publisher, PublisherMetrics};
+
+pub const PUBLISH_QUEUE_ENV: &str = "PUBLISH_QUEUE_CAPACITY";
+pub const DEFAULT_PUBLISH_QUEUE: usize = 1024;
+// Upper bound on how many queued messages go out in one round trip.
+pub const MAX_BATCH: usize = 64;
+
+pub fn queue_capacity_from_env() -> Result<usize, String> {
+    match std::env::var(PUBLISH_QUEUE_ENV) {
+        Ok(value) => match value.parse::<usize>() {
+            Ok(capacity) if capacity > 0 => Ok(capacity),
+            _ => Err(format!("{} must be a positive integer", PUBLISH_QUEUE_ENV)),
+        },
+        Err(_) => Ok(DEFAULT_PUBLISH_QUEUE),
+    }
+}
+
+pub type Message = (&'static str, String);
+
+// Whatever the batches are finally written to.
+pub trait BatchSink: Send + 'static {
+    fn send_batch<'a>(
+        &'a mut self,
+        batch: &'a [Message],
+    ) -> BoxFuture<'a, Result<(), PublishError>>;
+}
+
+// Sends each batch as one pipelined round trip over a single connection,
+// reconnecting on the next batch after a failure.
+pub struct RedisSink {
+    client: Client,
+    conn: Option<MultiplexedConnection>,
+}
+
+impl RedisSink {
+    pub fn new(client: Client) -> Self {
+        Self { client, conn: None }
+    }
+}
+
+impl BatchSink for RedisSink {
+    fn send_batch<'a>(
+        &'a mut self,
+        batch: &'a [Message],
+    ) -> BoxFuture<'a, Result<(), PublishError>> {
+        Box::pin(async move {
+            let conn = match &mut self.conn {
+                Some(conn) => conn,
+                None => self
+                    .conn
+                    .insert(self.client.get_multiplexed_async_connection().await?),
+            };
+            let mut pipe = redis::pipe();
+            for (channel, payload) in batch {
+                pipe.publish(*channel, payload).ignore();
+            }
+            let result: redis::RedisResult<()> = pipe.query_async(conn).await;
+            if result.is_err() {
+                self.conn = None;
+            }
+            Ok(result?)
+        })
+    }
+}
+
+type Queued = (Message, oneshot::Sender<Result<(), PublishError>>);
+
+// Handlers queue messages on a bounded channel and wait for their batch to
+// go out; a single task owns the sink and drains the queue in batches. A full
+// queue fails the publish straight away, which handlers surface as 503.
+pub struct BatchingPublisher {
+    queue: mpsc::Sender<Queued>,
+    metrics: Arc<Mutex<PublisherMetrics>>,
+}
+
+impl BatchingPublisher {
+    pub fn spawn(sink: impl BatchSink, capacity: usize) -> Self {
+        let (queue, receiver) = mpsc::channel(capacity);
+        let metrics = Arc::new(Mutex::new(PublisherMetrics {
+            queue_capacity: capacity,
+            ..PublisherMetrics::default()
+        }));
+        tokio::spawn(run(sink, receiver, metrics.clone()));
+        Self { queue, metrics }
+    }
+}
+
+impl Publisher for BatchingPublisher {
+    fn publish(
+        &self,
+        channel: &'static str,
+        payload: String,
+    ) -> BoxFuture<'_, Result<(), PublishError>> {
+        let (done, result) = oneshot::channel();
+        let queued = match self.queue.try_send(((channel, payload), done)) {
+            Ok(()) => Ok(result),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.metrics.lock().unwrap().rejected += 1;
+                Err(PublishError("publish queue is full".to_string()))
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                Err(PublishError("publisher has stopped".to_string()))
+            }
+        };
+        Box::pin(async move {
+            queued?
+                .await
+                .map_err(|_| PublishError("publisher has stopped".to_string()))?
+        })
+    }
+
+    fn metrics(&self) -> Option<PublisherMetrics> {
+        let mut metrics = self.metrics.lock().unwrap().clone();
+        metrics.queue_depth = self.queue.max_capacity() - self.queue.capacity();
+        Some(metrics)
+    }
+}
+
+async fn run(
+    mut sink: impl BatchSink,
+    mut receiver: mpsc::Receiver<Queued>,
+    metrics: Arc<Mutex<PublisherMetrics>>,
+) {
+    let mut queued = Vec::with_capacity(MAX_BATCH);
+    while receiver.recv_many(&mut queued, MAX_BATCH).await > 0 {
+        let (batch, waiters): (Vec<Message>, Vec<_>) = queued.drain(..).unzip();
+        let result = sink.send_batch(&batch).await;
+        if let Err(e) = &result {
+            eprintln!("Failed to publish a batch of {}: {}", batch.len(), e);
+        }
+        {
+            let mut metrics = metrics.lock().unwrap();
+            metrics.batches += 1;
+            metrics.messages += batch.len() as u64;
+            metrics.largest_batch = metrics.largest_batch.max(batch.len());
+        }
+        for waiter in waiters {
+            let _ = waiter.send(match &result {
+                Ok(()) => Ok(()),
+                Err(e) => Err(PublishError(e.0.clone())),
+            });
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::Semaphore;
+
+    // Records batch sizes; each batch waits for a permit so tests can hold
+    // the publisher task mid-send.
+    struct Gate {
+        permits: Semaphore,
+        entered: AtomicUsize,
+        batches: Mutex<Vec<usize>>,
+    }
+
+    struct GatedSink(Arc<Gate>);
+
+    impl BatchSink for GatedSink {
+        fn send_batch<'a>(
+            &'a mut self,
+            batch: &'a [Message],
+        ) -> BoxFuture<'a, Result<(), PublishError>> {
+            Box::pin(async move {
+                self.0.entered.fetch_add(1, Ordering::SeqCst);
+                self.0.permits.acquire().await.unwrap().forget();
+                self.0.batches.lock().unwrap().push(batch.len());
+                Ok(())
+            })
+        }
+    }
+
+    fn spawn_publish(
+        publisher: &Arc<BatchingPublisher>,
+        payload: &str,
+    ) -> tokio::task::JoinHandle<Result<(), PublishError>> {
+        let publisher = publisher.clone();
+        let payload = payload.to_string();
+        tokio::spawn(async move { publisher.publish("c", payload).await })
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_pushes_back() {
+        let gate = Arc::new(Gate {
+            permits: Semaphore::new(0),
+            entered: AtomicUsize::new(0),
+            batches: Mutex::new(vec![]),
+        });
+        let publisher = Arc::new(BatchingPublisher::spawn(GatedSink(gate.clone()), 2));
+
+        // the task takes the first message and is held in the sink
+        let first = spawn_publish(&publisher, "1");
+        while gate.entered.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let queued = [
+            spawn_publish(&publisher, "2"),
+            spawn_publish(&publisher, "3"),
+        ];
+        while publisher.metrics().unwrap().queue_depth < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let rejected = publisher.publish("c", "4".to_string()).await.unwrap_err();
+        assert_eq!(rejected.0, "publish queue is full");
+        assert_eq!(publisher.metrics().unwrap().rejected, 1);
+
+        // once the sink frees up, the two queued messages go out together
+        gate.permits.add_permits(2);
+        first.await.unwrap().unwrap();
+        for publish in queued {
+            publish.await.unwrap().unwrap();
+        }
+        assert_eq!(*gate.batches.lock().unwrap(), vec![1, 2]);
+        assert_eq!(
+            publisher.metrics().unwrap(),
+            PublisherMetrics {
+                queue_depth: 0,
+                queue_capacity: 2,
+                batches: 2,
+                messages: 3,
+                largest_batch: 2,
+                rejected: 1,
+            }
+        );
+    }
+}
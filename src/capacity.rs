@@ -0,0 +1,213 @@
+use orderbook::Qty;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Mutex};
+
+// A week of daily reports is what capacity planning looks at.
+pub const RETAINED_DAYS: usize = 7;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SymbolCapacity {
+    pub orders: u64,
+    pub trades: u64,
+    pub volume: Qty,
+    pub peak_resting_orders: u64,
+    pub max_levels: u64,
+    // None when unknown: no matches, or slower than the engine's largest
+    // latency bucket
+    pub p99_match_micros: Option<u64>,
+}
+
+impl SymbolCapacity {
+    // Counts add up across days; peaks and latency keep the worst day.
+    fn merge(&mut self, other: &SymbolCapacity) {
+        self.orders += other.orders;
+        self.trades += other.trades;
+        self.volume += other.volume;
+        self.peak_resting_orders = self.peak_resting_orders.max(other.peak_resting_orders);
+        self.max_levels = self.max_levels.max(other.max_levels);
+        self.p99_match_micros = self.p99_match_micros.max(other.p99_match_micros);
+    }
+
+    fn sort_key(&self, sort: CapacitySort) -> u64 {
+        match sort {
+            CapacitySort::Orders => self.orders,
+            CapacitySort::Trades => self.trades,
+            CapacitySort::Volume => self.volume.units(),
+            CapacitySort::PeakRestingOrders => self.peak_resting_orders,
+            CapacitySort::MaxLevels => self.max_levels,
+            CapacitySort::P99MatchMicros => self.p99_match_micros.unwrap_or(0),
+        }
+    }
+}
+
+// Sent by the engine on the outbound channel.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CapacityReport {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub date: String,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    pub symbols: BTreeMap<String, SymbolCapacity>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CapacitySort {
+    Orders,
+    Trades,
+    #[default]
+    Volume,
+    PeakRestingOrders,
+    MaxLevels,
+    P99MatchMicros,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SymbolRow {
+    pub symbol: String,
+    #[serde(flatten)]
+    pub capacity: SymbolCapacity,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CapacityView {
+    pub dates: Vec<String>,
+    pub symbols: Vec<SymbolRow>,
+}
+
+// Latest report per date. Intra-day reports replace each other until the
+// final one arrives; a late non-final report never overwrites a final one.
+#[derive(Default)]
+pub struct CapacityStore {
+    days: Mutex<BTreeMap<String, CapacityReport>>,
+}
+
+impl CapacityStore {
+    pub fn record(&self, report: CapacityReport) {
+        let mut days = self.days.lock().unwrap();
+        if days
+            .get(&report.date)
+            .is_some_and(|existing| existing.is_final && !report.is_final)
+        {
+            return;
+        }
+        days.insert(report.date.clone(), report);
+        while days.len() > RETAINED_DAYS {
+            days.pop_first();
+        }
+    }
+
+    // Aggregates the most recent `days` reports and ranks symbols by `sort`,
+    // busiest first.
+    pub fn top(&self, days: usize, sort: CapacitySort, limit: usize) -> CapacityView {
+        let reports = self.days.lock().unwrap();
+        let recent: Vec<&CapacityReport> = reports.values().rev().take(days).collect();
+
+        let mut totals: BTreeMap<&str, SymbolCapacity> = BTreeMap::new();
+        for report in &recent {
+            for (symbol, capacity) in &report.symbols {
+                totals.entry(symbol).or_default().merge(capacity);
+            }
+        }
+        let mut symbols: Vec<SymbolRow> = totals
+            .into_iter()
+            .map(|(symbol, capacity)| SymbolRow {
+                symbol: symbol.to_string(),
+                capacity,
+            })
+            .collect();
+        // stable sort keeps ties in symbol order
+        symbols.sort_by_key(|row| std::cmp::Reverse(row.capacity.sort_key(sort)));
+        symbols.truncate(limit);
+
+        CapacityView {
+            dates: recent.iter().rev().map(|r| r.date.clone()).collect(),
+            symbols,
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(date: &str, is_final: bool, symbols: &[(&str, u64, Option<u64>)]) -> CapacityReport {
+        CapacityReport {
+            kind: "capacity_report".to_string(),
+            date: date.to_string(),
+            is_final,
+            symbols: symbols
+                .iter()
+                .map(|(symbol, volume, p99)| {
+                    let capacity = SymbolCapacity {
+                        orders: 1,
+                        volume: Qty::shares(*volume),
+                        p99_match_micros: *p99,
+                        ..SymbolCapacity::default()
+                    };
+                    (symbol.to_string(), capacity)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_final_report_wins_and_old_days_drop() {
+        let store = CapacityStore::default();
+        store.record(report("2025-10-15", false, &[("AAPL", 10, None)]));
+        store.record(report("2025-10-15", true, &[("AAPL", 20, None)]));
+        store.record(report("2025-10-15", false, &[("AAPL", 5, None)]));
+        assert_eq!(
+            store.top(1, CapacitySort::Volume, 10).symbols[0]
+                .capacity
+                .volume,
+            Qty::shares(20)
+        );
+
+        for day in 16..=25 {
+            store.record(report(&format!("2025-10-{}", day), true, &[]));
+        }
+        let view = store.top(30, CapacitySort::Volume, 10);
+        assert_eq!(view.dates.len(), RETAINED_DAYS);
+        assert_eq!(view.dates[0], "2025-10-19");
+    }
+
+    #[test]
+    fn test_top_ranks_across_days() {
+        let store = CapacityStore::default();
+        store.record(report(
+            "2025-10-15",
+            true,
+            &[("AAPL", 10, Some(50)), ("MSFT", 30, Some(10))],
+        ));
+        store.record(report(
+            "2025-10-16",
+            false,
+            &[("AAPL", 25, Some(100)), ("TSLA", 1, None)],
+        ));
+
+        let view = store.top(7, CapacitySort::Volume, 2);
+        let ranked: Vec<(&str, Qty)> = view
+            .symbols
+            .iter()
+            .map(|r| (r.symbol.as_str(), r.capacity.volume))
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![("AAPL", Qty::shares(35)), ("MSFT", Qty::shares(30))]
+        );
+        assert_eq!(view.symbols[0].capacity.orders, 2);
+        assert_eq!(view.symbols[0].capacity.p99_match_micros, Some(100));
+
+        let slowest = store.top(7, CapacitySort::P99MatchMicros, 3);
+        assert_eq!(slowest.symbols[0].symbol, "AAPL");
+        assert_eq!(slowest.symbols[2].symbol, "TSLA");
+
+        // only the latest day
+        let today = store.top(1, CapacitySort::Volume, 10);
+        assert_eq!(today.dates, vec!["2025-10-16"]);
+        assert_eq!(today.symbols[0].capacity.volume, Qty::shares(25));
+    }
+}
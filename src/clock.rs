@@ -0,0 +1,76 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MILLIS_PER_DAY: i64 = 86_400_000;
+
+// Wall clock abstraction so scheduled jobs can be driven by tests.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0)
+    }
+}
+
+// Days since the unix epoch for a millisecond timestamp (UTC).
+pub fn day_number(millis: i64) -> i64 {
+    millis.div_euclid(MILLIS_PER_DAY)
+}
+
+// Formats a day number as an ISO date (YYYY-MM-DD), proleptic Gregorian calendar.
+pub fn format_day(day: i64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = day + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::Clock;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    pub struct FakeClock(AtomicI64);
+
+    impl FakeClock {
+        pub fn new(now_millis: i64) -> Self {
+            Self(AtomicI64::new(now_millis))
+        }
+
+        pub fn set(&self, now_millis: i64) {
+            self.0.store(now_millis, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now_millis(&self) -> i64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_day() {
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(-1), "1969-12-31");
+        assert_eq!(format_day(11_016), "2000-02-29");
+        assert_eq!(format_day(day_number(1_760_486_400_000)), "2025-10-15");
+    }
+}
@@ -0,0 +1,156 @@
+use futures::future::BoxFuture;
+use matching_engine::{
+    ENGINE_ADMIN_CHANNEL, EXPIRY_SWEEP_INTERVAL, MatchingEngine, ORDER_INBOUND_CHANNEL,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    handle_market_data, handle_outbound,
+    publisher::{PublishError, Publisher},
+    state::AppState,
+};
+
+type Envelope = (&'static str, String);
+
+// Hands messages straight to an in-process engine instead of Redis.
+pub struct EmbeddedPublisher {
+    sender: UnboundedSender<Envelope>,
+}
+
+impl EmbeddedPublisher {
+    pub fn channel() -> (Self, UnboundedReceiver<Envelope>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
+}
+
+impl Publisher for EmbeddedPublisher {
+    fn publish(
+        &self,
+        channel: &'static str,
+        payload: String,
+    ) -> BoxFuture<'_, Result<(), PublishError>> {
+        let sent = self
+            .sender
+            .send((channel, payload))
+            .map_err(|_| PublishError("embedded engine has stopped".to_string()));
+        Box::pin(async move { sent })
+    }
+}
+
+// Owns the engine for the life of the server. Everything goes through the
+// same process_message/handle_outbound paths as the Redis deployment, with
+// the sealed outbound messages standing in for the outbound channel.
+pub async fn run_engine(mut inbound: UnboundedReceiver<Envelope>, state: AppState) {
+    let mut engine = MatchingEngine::new(matching_engine::default_symbols());
+    println!("⚙️ Running embedded matching engine");
+
+    let mut sweep = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+    loop {
+        let messages = tokio::select! {
+            received = inbound.recv() => {
+                let Some((channel, payload)) = received else {
+                    break;
+                };
+                // the engine only listens on these; other channels have no consumer
+                if channel != ORDER_INBOUND_CHANNEL && channel != ENGINE_ADMIN_CHANNEL {
+                    continue;
+                }
+                engine.process_message(channel, &payload)
+            }
+            _ = sweep.tick() => {
+                let mut messages = engine.expire_orders();
+                messages.extend(engine.apply_deferred_cancels());
+                messages
+            }
+        };
+        for message in messages {
+            handle_outbound(&engine.seal(&message), &state);
+        }
+        for update in engine.book_updates() {
+            let sealed = orderbook::envelope::seal(update.kind, state.clock.now_millis(), &update);
+            handle_market_data(&sealed, &state);
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        User,
+        money::Money,
+        repository::{InMemoryUserRepository, UserRepository},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use orderbook::{Price, Qty};
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+    use tower::ServiceExt;
+
+    async fn place(state: &AppState, user: &str, side: &str) -> StatusCode {
+        let order = serde_json::json!({
+            "symbol": "AAPL",
+            "side": side,
+            "quantity": 10,
+            "price": "100",
+            "user": user,
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/place_order")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(order.to_string()))
+            .unwrap();
+        crate::router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_order_fill_and_settlement_in_process() {
+        let users = Arc::new(InMemoryUserRepository::default());
+        for (email, shares) in [("buyer", 0), ("seller", 10)] {
+            users.insert(User {
+                email: email.to_string(),
+                current_balance: Money::usd(100_000),
+                stocks: HashMap::from([("AAPL".to_string(), Qty::shares(shares))]),
+                faucet_claims_remaining: 0,
+                market_maker: false,
+            });
+        }
+        let (publisher, inbound) = EmbeddedPublisher::channel();
+        let state = AppState::builder()
+            .with_repository(users.clone())
+            .with_publisher(Arc::new(publisher))
+            .build();
+        tokio::spawn(run_engine(inbound, state.clone()));
+
+        // sides as the client writes them
+        assert_eq!(place(&state, "seller", "sell").await, StatusCode::OK);
+        assert_eq!(place(&state, "buyer", "buy").await, StatusCode::OK);
+
+        let mut settled = false;
+        for _ in 0..100 {
+            if users.get("buyer").unwrap().stocks["AAPL"] == Qty::shares(10) {
+                settled = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(settled, "trade was never settled");
+        assert_eq!(users.get("buyer").unwrap().current_balance, Money::usd(0));
+        assert_eq!(
+            users.get("seller").unwrap().current_balance,
+            Money::usd(200_000)
+        );
+        assert_eq!(users.get("seller").unwrap().stocks["AAPL"], Qty::ZERO);
+        assert_eq!(state.market_data.last_prices()["AAPL"], Price::whole(100));
+        assert_eq!(state.dead_letters.depth(), 0);
+    }
+}
@@ -0,0 +1,313 @@
+use orderbook::{Order, OrderView, Qty, Side};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+use crate::money::{Currency, Money, MoneyError};
+
+// How long ?verify=true waits for the engine's open orders.
+pub const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What a user's resting orders in one symbol could still do.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SymbolExposure {
+    pub buy_quantity: Qty,
+    pub buy_notional: Money,
+    pub sell_quantity: Qty,
+    pub sell_notional: Money,
+    // shares held now
+    pub position: Qty,
+    pub position_if_buys_fill: Qty,
+    // shares the sells would deliver beyond those held count as none left
+    pub position_if_sells_fill: Qty,
+}
+
+// A user's resting orders summed up per symbol, with the cash the buys
+// would spend and the shares the sells would deliver if every one filled.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Exposure {
+    pub symbols: BTreeMap<String, SymbolExposure>,
+    pub reserved_cash: Money,
+    pub reserved_shares: BTreeMap<String, Qty>,
+    pub balance: Money,
+    pub cash_if_buys_fill: Money,
+    // only when checked against the engine
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discrepancy: Option<Discrepancy>,
+}
+
+// Works out the exposure of `orders`, a user's resting orders with what
+// each has left, against their balance and holdings.
+pub fn exposure(
+    balance: Money,
+    stocks: &HashMap<String, Qty>,
+    orders: &[Order],
+) -> Result<Exposure, MoneyError> {
+    let mut symbols: BTreeMap<String, SymbolExposure> = BTreeMap::new();
+    for order in orders {
+        let Some(price) = order.price else {
+            continue;
+        };
+        let symbol = symbols
+            .entry(order.symbol.clone())
+            .or_insert_with(|| SymbolExposure {
+                buy_quantity: Qty::ZERO,
+                buy_notional: Money::usd(0),
+                sell_quantity: Qty::ZERO,
+                sell_notional: Money::usd(0),
+                position: Qty::ZERO,
+                position_if_buys_fill: Qty::ZERO,
+                position_if_sells_fill: Qty::ZERO,
+            });
+        let notional = Money::notional(price, order.quantity, Currency::Usd)?;
+        match order.side {
+            Side::Buy => {
+                symbol.buy_quantity += order.quantity;
+                symbol.buy_notional = symbol.buy_notional.checked_add(notional)?;
+            }
+            Side::Sell => {
+                symbol.sell_quantity += order.quantity;
+                symbol.sell_notional = symbol.sell_notional.checked_add(notional)?;
+            }
+        }
+    }
+
+    let mut reserved_cash = Money::usd(0);
+    let mut reserved_shares = BTreeMap::new();
+    for (name, symbol) in &mut symbols {
+        symbol.position = stocks.get(name).copied().unwrap_or(Qty::ZERO);
+        symbol.position_if_buys_fill = symbol.position + symbol.buy_quantity;
+        symbol.position_if_sells_fill = symbol.position.saturating_sub(symbol.sell_quantity);
+        reserved_cash = reserved_cash.checked_add(symbol.buy_notional)?;
+        if !symbol.sell_quantity.is_zero() {
+            reserved_shares.insert(name.clone(), symbol.sell_quantity);
+        }
+    }
+    Ok(Exposure {
+        symbols,
+        reserved_cash,
+        reserved_shares,
+        balance,
+        cash_if_buys_fill: balance.checked_sub(reserved_cash)?,
+        discrepancy: None,
+    })
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OrderRef {
+    pub symbol: String,
+    pub order_id: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QuantityMismatch {
+    pub symbol: String,
+    pub order_id: u64,
+    pub gateway: Qty,
+    pub engine: Qty,
+}
+
+// Where the gateway's idea of a user's resting orders and the engine's
+// differ; all empty when they agree.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Discrepancy {
+    // open here but not in the engine's books
+    pub missing_from_engine: Vec<OrderRef>,
+    // resting in the engine but not open here
+    pub unknown_to_gateway: Vec<OrderRef>,
+    pub quantity_mismatches: Vec<QuantityMismatch>,
+}
+
+// Order ids are per book, so orders are matched on symbol and id.
+pub fn discrepancy(gateway: &[Order], engine: &[OrderView]) -> Discrepancy {
+    let engine: BTreeMap<(&str, u64), Qty> = engine
+        .iter()
+        .map(|view| {
+            (
+                (view.symbol.as_str(), view.order_id),
+                view.remaining_quantity,
+            )
+        })
+        .collect();
+    let gateway: BTreeMap<(&str, u64), Qty> = gateway
+        .iter()
+        .map(|order| ((order.symbol.as_str(), order.order_id), order.quantity))
+        .collect();
+    let order_ref = |&(symbol, order_id): &(&str, u64)| OrderRef {
+        symbol: symbol.to_string(),
+        order_id,
+    };
+
+    let mut discrepancy = Discrepancy::default();
+    for (key, &quantity) in &gateway {
+        match engine.get(key) {
+            None => discrepancy.missing_from_engine.push(order_ref(key)),
+            Some(&engine) if engine != quantity => {
+                discrepancy.quantity_mismatches.push(QuantityMismatch {
+                    symbol: key.0.to_string(),
+                    order_id: key.1,
+                    gateway: quantity,
+                    engine,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    discrepancy.unknown_to_gateway = engine
+        .keys()
+        .filter(|key| !gateway.contains_key(*key))
+        .map(order_ref)
+        .collect();
+    discrepancy
+}
+
+// Requests waiting on the engine's open orders for a user. Any open_orders
+// reply for the user answers all of them, whoever asked.
+#[derive(Default)]
+pub struct OpenOrderQueries {
+    waiting: Mutex<HashMap<String, Vec<oneshot::Sender<Vec<OrderView>>>>>,
+}
+
+impl OpenOrderQueries {
+    // True for the first to wait on `user`, who then sends the query.
+    pub fn subscribe(&self, user: &str) -> (bool, oneshot::Receiver<Vec<OrderView>>) {
+        let (sender, reply) = oneshot::channel();
+        let mut waiting = self.waiting.lock().unwrap();
+        let waiters = waiting.entry(user.to_string()).or_default();
+        waiters.push(sender);
+        (waiters.len() == 1, reply)
+    }
+
+    // Drops everyone waiting on `user`, for when the query didn't go out.
+    pub fn abandon(&self, user: &str) {
+        self.waiting.lock().unwrap().remove(user);
+    }
+
+    pub fn answer(&self, user: &str, orders: &[OrderView]) {
+        let waiters = self.waiting.lock().unwrap().remove(user);
+        for waiter in waiters.into_iter().flatten() {
+            let _ = waiter.send(orders.to_vec());
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orderbook::{OrderState, Price, TimeInForce};
+
+    fn order(order_id: u64, side: Side, price: i64, quantity: u64) -> Order {
+        Order {
+            order_id,
+            ..Order::new_limit_order(
+                Qty::shares(quantity),
+                Some(Price::cents(price)),
+                side,
+                String::from("AAPL"),
+                String::from("a"),
+            )
+        }
+    }
+
+    fn view(order: &Order) -> OrderView {
+        OrderView {
+            order_id: order.order_id,
+            user: order.user.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            price: order.price.unwrap(),
+            remaining_quantity: order.quantity,
+            state: OrderState::Open,
+            time_in_force: TimeInForce::Gtc,
+            received_at: 0,
+            expires_at: None,
+            queue_position: 0,
+            client_order_id: None,
+        }
+    }
+
+    #[test]
+    fn test_worst_case_from_resting_orders() {
+        let stocks = HashMap::from([(String::from("AAPL"), Qty::shares(5))]);
+        let orders = [
+            order(1, Side::Buy, 10000, 10),
+            // a 10-share buy that has filled 4
+            order(2, Side::Buy, 9950, 6),
+            // selling more than is held
+            order(3, Side::Sell, 10100, 8),
+        ];
+        let exposure = exposure(Money::usd(200000), &stocks, &orders).unwrap();
+        assert_eq!(
+            exposure.symbols["AAPL"],
+            SymbolExposure {
+                buy_quantity: Qty::shares(16),
+                buy_notional: Money::usd(159700),
+                sell_quantity: Qty::shares(8),
+                sell_notional: Money::usd(80800),
+                position: Qty::shares(5),
+                position_if_buys_fill: Qty::shares(21),
+                position_if_sells_fill: Qty::ZERO,
+            }
+        );
+        assert_eq!(exposure.reserved_cash, Money::usd(159700));
+        assert_eq!(exposure.reserved_shares["AAPL"], Qty::shares(8));
+        assert_eq!(exposure.cash_if_buys_fill, Money::usd(40300));
+    }
+
+    #[test]
+    fn test_discrepancy_against_engine() {
+        let gateway = [
+            order(1, Side::Buy, 10000, 10),
+            order(2, Side::Buy, 10000, 6),
+            order(3, Side::Sell, 10100, 8),
+        ];
+        let mut partly_filled = view(&gateway[1]);
+        partly_filled.remaining_quantity = Qty::shares(2);
+        let engine = [
+            view(&gateway[0]),
+            partly_filled,
+            view(&order(4, Side::Sell, 10200, 1)),
+        ];
+
+        assert_eq!(
+            discrepancy(&gateway, &engine),
+            Discrepancy {
+                missing_from_engine: vec![OrderRef {
+                    symbol: String::from("AAPL"),
+                    order_id: 3,
+                }],
+                unknown_to_gateway: vec![OrderRef {
+                    symbol: String::from("AAPL"),
+                    order_id: 4,
+                }],
+                quantity_mismatches: vec![QuantityMismatch {
+                    symbol: String::from("AAPL"),
+                    order_id: 2,
+                    gateway: Qty::shares(6),
+                    engine: Qty::shares(2),
+                }],
+            }
+        );
+        let agreed: Vec<OrderView> = gateway.iter().map(view).collect();
+        assert_eq!(discrepancy(&gateway, &agreed), Discrepancy::default());
+    }
+
+    #[test]
+    fn test_one_query_answers_every_waiter() {
+        let queries = OpenOrderQueries::default();
+        let (first, mut one) = queries.subscribe("a");
+        let (second, mut two) = queries.subscribe("a");
+        assert!(first && !second);
+
+        let open = [view(&order(1, Side::Buy, 10000, 1))];
+        queries.answer("a", &open);
+        assert_eq!(one.try_recv().unwrap(), open.to_vec());
+        assert_eq!(two.try_recv().unwrap(), open.to_vec());
+        assert!(queries.subscribe("a").0);
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    clock::{Clock, MILLIS_PER_DAY},
+    money::Money,
+    rate_limit::RollingWindowLimiter,
+};
+
+const EXCHANGE_MODE_ENV: &str = "EXCHANGE_MODE";
+const FAUCET_AMOUNT_ENV: &str = "FAUCET_AMOUNT";
+const FAUCET_DAILY_CLAIMS_ENV: &str = "FAUCET_DAILY_CLAIMS";
+
+const DEFAULT_FAUCET_AMOUNT: i64 = 100000;
+const DEFAULT_FAUCET_DAILY_CLAIMS: usize = 3;
+
+pub struct FaucetConfig {
+    pub enabled: bool,
+    pub amount: Money,
+    pub claims_per_day: usize,
+}
+
+impl FaucetConfig {
+    // The faucet only exists for paper trading, so it is switched off whenever
+    // EXCHANGE_MODE=production.
+    pub fn from_env() -> Result<Self, String> {
+        let enabled = std::env::var(EXCHANGE_MODE_ENV).map_or(true, |mode| mode != "production");
+        let amount = match std::env::var(FAUCET_AMOUNT_ENV) {
+            Ok(value) => value
+                .parse()
+                .map_err(|e| format!("invalid {FAUCET_AMOUNT_ENV}: {e}"))?,
+            Err(_) => Money::usd(DEFAULT_FAUCET_AMOUNT),
+        };
+        let claims_per_day = match std::env::var(FAUCET_DAILY_CLAIMS_ENV) {
+            Ok(value) => value
+                .parse()
+                .map_err(|e| format!("invalid {FAUCET_DAILY_CLAIMS_ENV}: {e}"))?,
+            Err(_) => DEFAULT_FAUCET_DAILY_CLAIMS,
+        };
+        Ok(Self {
+            enabled,
+            amount,
+            claims_per_day,
+        })
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            amount: Money::usd(0),
+            claims_per_day: 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FaucetError {
+    Disabled,
+    Exhausted,
+}
+
+// Hands out demo funds, at most `claims_per_day` per user over a rolling 24h.
+pub struct Faucet {
+    config: FaucetConfig,
+    clock: Arc<dyn Clock>,
+    limiter: Mutex<RollingWindowLimiter>,
+}
+
+impl Faucet {
+    pub fn new(config: FaucetConfig, clock: Arc<dyn Clock>) -> Self {
+        let limiter = RollingWindowLimiter::new(config.claims_per_day, MILLIS_PER_DAY);
+        Self {
+            config,
+            clock,
+            limiter: Mutex::new(limiter),
+        }
+    }
+
+    pub fn amount(&self) -> Money {
+        self.config.amount
+    }
+
+    // Consumes one claim for `email`, returning the claims left in the window.
+    pub fn claim(&self, email: &str) -> Result<usize, FaucetError> {
+        if !self.config.enabled {
+            return Err(FaucetError::Disabled);
+        }
+        self.limiter
+            .lock()
+            .unwrap()
+            .try_acquire(email, self.clock.now_millis())
+            .ok_or(FaucetError::Exhausted)
+    }
+
+    pub fn remaining(&self, email: &str) -> usize {
+        if !self.config.enabled {
+            return 0;
+        }
+        self.limiter
+            .lock()
+            .unwrap()
+            .remaining(email, self.clock.now_millis())
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::test_support::FakeClock;
+
+    const OCT_15_2025: i64 = 1_760_486_400_000;
+
+    fn config(enabled: bool) -> FaucetConfig {
+        FaucetConfig {
+            enabled,
+            amount: Money::usd(100000),
+            claims_per_day: 2,
+        }
+    }
+
+    #[test]
+    fn test_claims_reset_after_a_rolling_day() {
+        let clock = Arc::new(FakeClock::new(OCT_15_2025 + 20 * 3_600_000));
+        let faucet = Faucet::new(config(true), clock.clone());
+
+        assert_eq!(faucet.claim("a@test.com"), Ok(1));
+        clock.set(OCT_15_2025 + 23 * 3_600_000);
+        assert_eq!(faucet.claim("a@test.com"), Ok(0));
+        assert_eq!(faucet.remaining("a@test.com"), 0);
+
+        // past midnight is not enough, the window is a rolling 24h
+        clock.set(OCT_15_2025 + MILLIS_PER_DAY + 3_600_000);
+        assert_eq!(faucet.claim("a@test.com"), Err(FaucetError::Exhausted));
+
+        // 24h after the first claim one slot frees up
+        clock.set(OCT_15_2025 + MILLIS_PER_DAY + 20 * 3_600_000);
+        assert_eq!(faucet.remaining("a@test.com"), 1);
+        assert_eq!(faucet.claim("a@test.com"), Ok(0));
+        assert_eq!(faucet.claim("a@test.com"), Err(FaucetError::Exhausted));
+    }
+
+    #[test]
+    fn test_disabled_faucet() {
+        let faucet = Faucet::new(config(false), Arc::new(FakeClock::new(OCT_15_2025)));
+        assert_eq!(faucet.claim("a@test.com"), Err(FaucetError::Disabled));
+        assert_eq!(faucet.remaining("a@test.com"), 0);
+    }
+}
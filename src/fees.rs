@@ -0,0 +1,248 @@
+use orderbook::{Side, TradeEvent};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use crate::money::{Money, MoneyError};
+
+pub const FEE_SCHEDULE_ENV: &str = "FEE_SCHEDULE";
+// The fee account keeps this many ledger entries, oldest dropped first.
+pub const RETAINED_LEDGER_ENTRIES: usize = 10_000;
+const BPS: i64 = 10_000;
+
+// What a trade costs each side, in basis points of its notional: the taker
+// pays `taker_bps` and the maker `maker_bps`. A negative maker rate is a
+// rebate, paid out of the fee account.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub maker_bps: i64,
+    pub taker_bps: i64,
+}
+
+impl FeeSchedule {
+    // A rebate may be at most what the taker pays, so under one schedule
+    // every trade leaves the fee account no worse off.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0..=BPS).contains(&self.taker_bps) {
+            return Err(format!(
+                "taker_bps must be between 0 and {}, got {}",
+                BPS, self.taker_bps
+            ));
+        }
+        if !(-self.taker_bps..=BPS).contains(&self.maker_bps) {
+            return Err(format!(
+                "maker_bps must be between -taker_bps ({}) and {}, got {}",
+                -self.taker_bps, BPS, self.maker_bps
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    // charged to the user
+    Fee,
+    // paid to the user
+    Rebate,
+    // a busted trade's fee handed back to the user, or, when negative, its
+    // rebate taken back
+    Reversal,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub trade_id: u64,
+    pub user: String,
+    pub kind: EntryKind,
+    pub amount: Money,
+}
+
+// What each side of one trade owes the fee account; negative is owed to
+// them.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct TradeFees {
+    pub buyer: Money,
+    pub seller: Money,
+}
+
+struct Account {
+    balance: Money,
+    // oldest first
+    entries: VecDeque<LedgerEntry>,
+}
+
+// The fee schedule, the tiers admins give some users instead, and the
+// account fees are paid into and rebates out of.
+pub struct Fees {
+    schedule: FeeSchedule,
+    tiers: Mutex<HashMap<String, FeeSchedule>>,
+    account: Mutex<Account>,
+}
+
+impl Default for Fees {
+    fn default() -> Self {
+        Self::new(FeeSchedule::default())
+    }
+}
+
+impl Fees {
+    pub fn new(schedule: FeeSchedule) -> Self {
+        Self {
+            schedule,
+            tiers: Mutex::new(HashMap::new()),
+            account: Mutex::new(Account {
+                balance: Money::usd(0),
+                entries: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn schedule(&self) -> FeeSchedule {
+        self.schedule
+    }
+
+    // The rates `user` trades at, and whether they are a tier of their own.
+    pub fn effective(&self, user: &str) -> (FeeSchedule, bool) {
+        match self.tiers.lock().unwrap().get(user) {
+            Some(&tier) => (tier, true),
+            None => (self.schedule, false),
+        }
+    }
+
+    // None puts the user back on the schedule.
+    pub fn set_tier(&self, user: &str, tier: Option<FeeSchedule>) {
+        let mut tiers = self.tiers.lock().unwrap();
+        match tier {
+            Some(tier) => tiers.insert(user.to_string(), tier),
+            None => tiers.remove(user),
+        };
+    }
+
+    // Each side's fee on a trade worth `notional`, at its own rates.
+    pub fn for_trade(&self, event: &TradeEvent, notional: Money) -> Result<TradeFees, MoneyError> {
+        let (buyer, _) = self.effective(&event.buyer);
+        let (seller, _) = self.effective(&event.seller);
+        let (buyer_bps, seller_bps) = match event.taker_side {
+            Side::Buy => (buyer.taker_bps, seller.maker_bps),
+            Side::Sell => (buyer.maker_bps, seller.taker_bps),
+        };
+        Ok(TradeFees {
+            buyer: notional.bps(buyer_bps)?,
+            seller: notional.bps(seller_bps)?,
+        })
+    }
+
+    // Books a settled trade's fees: the account takes exactly what the two
+    // sides paid less what it paid out to them.
+    pub fn collect(&self, event: &TradeEvent, fees: TradeFees) -> Result<(), MoneyError> {
+        let mut account = self.account.lock().unwrap();
+        account.balance = account
+            .balance
+            .checked_add(fees.buyer)?
+            .checked_add(fees.seller)?;
+        for (user, amount) in [(&event.buyer, fees.buyer), (&event.seller, fees.seller)] {
+            let entry = if amount.is_negative() {
+                LedgerEntry {
+                    trade_id: event.trade_id,
+                    user: user.clone(),
+                    kind: EntryKind::Rebate,
+                    amount: Money::usd(0).checked_sub(amount)?,
+                }
+            } else if amount != Money::usd(0) {
+                LedgerEntry {
+                    trade_id: event.trade_id,
+                    user: user.clone(),
+                    kind: EntryKind::Fee,
+                    amount,
+                }
+            } else {
+                continue;
+            };
+            account.entries.push_back(entry);
+            if account.entries.len() > RETAINED_LEDGER_ENTRIES {
+                account.entries.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    // Hands a busted trade's fees back: the account gives up exactly what it
+    // took when the trade settled.
+    pub fn refund(&self, event: &TradeEvent, fees: TradeFees) -> Result<(), MoneyError> {
+        let mut account = self.account.lock().unwrap();
+        account.balance = account
+            .balance
+            .checked_sub(fees.buyer)?
+            .checked_sub(fees.seller)?;
+        for (user, amount) in [(&event.buyer, fees.buyer), (&event.seller, fees.seller)] {
+            if amount == Money::usd(0) {
+                continue;
+            }
+            account.entries.push_back(LedgerEntry {
+                trade_id: event.trade_id,
+                user: user.clone(),
+                kind: EntryKind::Reversal,
+                amount,
+            });
+            if account.entries.len() > RETAINED_LEDGER_ENTRIES {
+                account.entries.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn balance(&self) -> Money {
+        self.account.lock().unwrap().balance
+    }
+
+    pub fn ledger(&self) -> Vec<LedgerEntry> {
+        self.account
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_validated() {
+        let schedule = |maker_bps, taker_bps| FeeSchedule {
+            maker_bps,
+            taker_bps,
+        };
+        assert!(schedule(-2, 10).validate().is_ok());
+        assert!(schedule(10, 10).validate().is_ok());
+        // a rebate bigger than the taker fee would pay out more than it took
+        assert!(schedule(-11, 10).validate().is_err());
+        assert!(schedule(0, -1).validate().is_err());
+        assert!(schedule(0, BPS + 1).validate().is_err());
+    }
+
+    #[test]
+    fn test_tiers_override_the_schedule() {
+        let fees = Fees::new(FeeSchedule {
+            maker_bps: -2,
+            taker_bps: 10,
+        });
+        let tier = FeeSchedule {
+            maker_bps: -5,
+            taker_bps: 5,
+        };
+        fees.set_tier("mm", Some(tier));
+        assert_eq!(fees.effective("mm"), (tier, true));
+        assert_eq!(fees.effective("a"), (fees.schedule(), false));
+        fees.set_tier("mm", None);
+        assert_eq!(fees.effective("mm"), (fees.schedule(), false));
+    }
+}
@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const MAX_MESSAGE_BYTES_ENV: &str = "MAX_MESSAGE_BYTES";
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+// Nothing we exchange nests more than a few levels.
+pub const MAX_NESTING_DEPTH: usize = 32;
+// HTTP request bodies, enforced by the router's body-limit layer.
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadRejection {
+    Oversized,
+    TooDeep,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PayloadLimits {
+    pub max_bytes: usize,
+    pub max_depth: usize,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            max_depth: MAX_NESTING_DEPTH,
+        }
+    }
+}
+
+impl PayloadLimits {
+    pub fn from_env() -> Result<Self, String> {
+        let max_bytes = match std::env::var(MAX_MESSAGE_BYTES_ENV) {
+            Ok(value) => match value.parse::<usize>() {
+                Ok(bytes) if bytes > 0 => bytes,
+                _ => return Err(format!("invalid {}: {:?}", MAX_MESSAGE_BYTES_ENV, value)),
+            },
+            Err(_) => DEFAULT_MAX_MESSAGE_BYTES,
+        };
+        Ok(Self {
+            max_bytes,
+            ..Self::default()
+        })
+    }
+
+    // Runs before any deserialization, so a hostile payload is turned away
+    // without serde allocating anything for it.
+    pub fn check(&self, payload: &str) -> Result<(), PayloadRejection> {
+        if payload.len() > self.max_bytes {
+            return Err(PayloadRejection::Oversized);
+        }
+        if exceeds_depth(payload, self.max_depth) {
+            return Err(PayloadRejection::TooDeep);
+        }
+        Ok(())
+    }
+}
+
+// Byte scan for array/object nesting, skipping over string contents.
+fn exceeds_depth(payload: &str, max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for byte in payload.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct RejectedCounts {
+    pub oversized: u64,
+    pub too_deep: u64,
+    pub invalid: u64,
+}
+
+// Applies the limits to the engine's outbound messages and counts what is
+// turned away, since none of it can reach the dead-letter queue.
+#[derive(Default)]
+pub struct PayloadGuard {
+    pub limits: PayloadLimits,
+    oversized: AtomicU64,
+    too_deep: AtomicU64,
+    invalid: AtomicU64,
+}
+
+impl PayloadGuard {
+    pub fn new(limits: PayloadLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    pub fn admit(&self, payload: &str) -> Result<(), PayloadRejection> {
+        let checked = self.limits.check(payload);
+        match checked {
+            Err(PayloadRejection::Oversized) => self.oversized.fetch_add(1, Ordering::Relaxed),
+            Err(PayloadRejection::TooDeep) => self.too_deep.fetch_add(1, Ordering::Relaxed),
+            Ok(()) => 0,
+        };
+        checked
+    }
+
+    // Within limits but not any message we understand.
+    pub fn record_invalid(&self) {
+        self.invalid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> RejectedCounts {
+        RejectedCounts {
+            oversized: self.oversized.load(Ordering::Relaxed),
+            too_deep: self.too_deep.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let limits = PayloadLimits {
+            max_bytes: 64,
+            max_depth: 3,
+        };
+        assert_eq!(limits.check(r#"{"a":[{"b":1}]}"#), Ok(()));
+        assert_eq!(
+            limits.check(r#"{"a":[{"b":[1]}]}"#),
+            Err(PayloadRejection::TooDeep)
+        );
+        // brackets inside strings, including after escaped quotes, don't count
+        assert_eq!(limits.check(r#"{"a":"[[[[\"[[[["}"#), Ok(()));
+        assert_eq!(
+            limits.check(&"x".repeat(65)),
+            Err(PayloadRejection::Oversized)
+        );
+    }
+
+    #[test]
+    fn test_deep_payload_rejected_without_parsing() {
+        let limits = PayloadLimits {
+            max_bytes: usize::MAX,
+            max_depth: MAX_NESTING_DEPTH,
+        };
+        let payload = "[".repeat(1_000_000);
+        assert_eq!(limits.check(&payload), Err(PayloadRejection::TooDeep));
+    }
+}
@@ -0,0 +1,228 @@
+use orderbook::{Price, Qty};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    User,
+    clock::{self, Clock, MILLIS_PER_DAY},
+    market_data::MarketData,
+    money::{Currency, Money, MoneyError},
+    publisher::Publisher,
+    repository::UserRepository,
+};
+
+pub const ADMIN_EVENTS_CHANNEL: &str = "admin_events";
+pub const DEFAULT_HISTORY_DAYS: usize = 30;
+
+pub type History = Arc<Mutex<HashMap<String, Vec<EndOfDay>>>>;
+
+// A user's balances and positions frozen at a daily rollover.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct EndOfDay {
+    pub date: String,
+    pub balance: Money,
+    pub stocks: HashMap<String, Qty>,
+    pub equity: Money,
+}
+
+// Cash plus every position marked at its price in `prices`. Symbols without
+// one contribute nothing.
+pub fn equity(user: &User, prices: &HashMap<String, Price>) -> Result<Money, MoneyError> {
+    let mut equity = user.current_balance;
+    for (symbol, quantity) in &user.stocks {
+        if let Some(&price) = prices.get(symbol) {
+            equity = equity.checked_add(Money::notional(price, *quantity, Currency::Usd)?)?;
+        }
+    }
+    Ok(equity)
+}
+
+// Snapshots every user into the history under `date`, returning how many
+// users were recorded.
+pub fn roll_day(
+    date: &str,
+    users: &dyn UserRepository,
+    market_data: &dyn MarketData,
+    history: &History,
+) -> usize {
+    let prices = market_data.valuation_prices();
+    let mut history = history.lock().unwrap();
+
+    let mut recorded = 0;
+    for user in users.all() {
+        let equity = match equity(&user, &prices) {
+            Ok(equity) => equity,
+            Err(e) => {
+                eprintln!("Skipping end-of-day record for {}: {}", user.email, e);
+                continue;
+            }
+        };
+        history
+            .entry(user.email.clone())
+            .or_default()
+            .push(EndOfDay {
+                date: date.to_string(),
+                balance: user.current_balance,
+                stocks: user.stocks.clone(),
+                equity,
+            });
+        recorded += 1;
+    }
+    recorded
+}
+
+// The first rollover instant strictly after `now`, where `at_millis` is the
+// offset into the UTC day at which sessions roll.
+pub fn next_rollover(now: i64, at_millis: i64) -> i64 {
+    let today = clock::day_number(now) * MILLIS_PER_DAY + at_millis;
+    if today > now {
+        today
+    } else {
+        today + MILLIS_PER_DAY
+    }
+}
+
+// The session date closed by a rollover happening at `rollover`.
+pub fn session_date(rollover: i64) -> String {
+    clock::format_day(clock::day_number(rollover - 1))
+}
+
+// Parses "HH:MM" into an offset in milliseconds from UTC midnight.
+pub fn parse_time_of_day(value: &str) -> Option<i64> {
+    let (hours, minutes) = value.split_once(':')?;
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+    Some((hours * 60 + minutes) * 60_000)
+}
+
+pub async fn run_daily_rollover(
+    clock: Arc<dyn Clock>,
+    at_millis: i64,
+    users: Arc<dyn UserRepository>,
+    market_data: Arc<dyn MarketData>,
+    history: History,
+    publisher: Arc<dyn Publisher>,
+) {
+    loop {
+        let now = clock.now_millis();
+        let rollover = next_rollover(now, at_millis);
+        tokio::time::sleep(Duration::from_millis((rollover - now) as u64)).await;
+
+        let date = session_date(rollover);
+        let recorded = roll_day(&date, users.as_ref(), market_data.as_ref(), &history);
+        println!("📅 Rolled session {} ({} users)", date, recorded);
+
+        let event = serde_json::json!({
+            "type": "day_rolled",
+            "date": date,
+            "users": recorded,
+        });
+        let event = orderbook::envelope::seal("day_rolled", clock.now_millis(), &event);
+        if let Err(e) = publisher.publish(ADMIN_EVENTS_CHANNEL, event).await {
+            eprintln!("Failed to publish day_rolled event: {}", e);
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::test_support::FakeClock, market_data::InMemoryMarketData,
+        repository::InMemoryUserRepository,
+    };
+
+    const OCT_15_2025: i64 = 1_760_486_400_000;
+
+    fn user(email: &str, balance: i64, stocks: &[(&str, u64)]) -> User {
+        User {
+            email: email.to_string(),
+            current_balance: Money::usd(balance),
+            stocks: stocks
+                .iter()
+                .map(|(s, q)| (s.to_string(), Qty::shares(*q)))
+                .collect(),
+            faucet_claims_remaining: 0,
+            market_maker: false,
+        }
+    }
+
+    #[test]
+    fn test_next_rollover() {
+        let close = parse_time_of_day("21:00").unwrap();
+        assert_eq!(next_rollover(OCT_15_2025, close), OCT_15_2025 + close);
+        assert_eq!(
+            next_rollover(OCT_15_2025 + close, close),
+            OCT_15_2025 + MILLIS_PER_DAY + close
+        );
+        assert_eq!(next_rollover(OCT_15_2025, 0), OCT_15_2025 + MILLIS_PER_DAY);
+        assert_eq!(session_date(OCT_15_2025 + MILLIS_PER_DAY), "2025-10-15");
+        assert_eq!(session_date(OCT_15_2025 + close), "2025-10-15");
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(parse_time_of_day("00:00"), Some(0));
+        assert_eq!(parse_time_of_day("21:30"), Some(77_400_000));
+        assert_eq!(parse_time_of_day("24:00"), None);
+        assert_eq!(parse_time_of_day("12:60"), None);
+        assert_eq!(parse_time_of_day("noon"), None);
+    }
+
+    #[test]
+    fn test_two_simulated_days() {
+        let clock = FakeClock::new(OCT_15_2025 + 3_600_000);
+        let users = InMemoryUserRepository::default();
+        let market_data = InMemoryMarketData::default();
+        let history: History = Arc::new(Mutex::new(HashMap::new()));
+
+        users.insert(user("alice@test.com", 500000, &[("AAPL", 10)]));
+        users.insert(user("bob@test.com", 500000, &[]));
+        market_data.record_trade("AAPL", Price::cents(10000));
+
+        // day one closes
+        let rollover = next_rollover(clock.now_millis(), 0);
+        clock.set(rollover);
+        assert_eq!(
+            roll_day(&session_date(rollover), &users, &market_data, &history),
+            2
+        );
+
+        // during day two alice sells 5 AAPL to bob at 110.00 and the price moves
+        users.update("alice@test.com", &mut |alice| {
+            alice.current_balance = Money::usd(555000);
+            alice.stocks.insert("AAPL".to_string(), Qty::shares(5));
+        });
+        users.update("bob@test.com", &mut |bob| {
+            bob.current_balance = Money::usd(445000);
+            bob.stocks.insert("AAPL".to_string(), Qty::shares(5));
+        });
+        market_data.record_trade("AAPL", Price::cents(12000));
+
+        // day two closes
+        let rollover = next_rollover(clock.now_millis(), 0);
+        clock.set(rollover);
+        roll_day(&session_date(rollover), &users, &market_data, &history);
+
+        let history = history.lock().unwrap();
+        let alice = &history["alice@test.com"];
+        assert_eq!(alice.len(), 2);
+        assert_eq!(alice[0].date, "2025-10-15");
+        assert_eq!(alice[0].equity, Money::usd(600000));
+        assert_eq!(alice[1].date, "2025-10-16");
+        assert_eq!(alice[1].equity, Money::usd(615000));
+
+        let bob = &history["bob@test.com"];
+        assert_eq!(bob[0].equity, Money::usd(500000));
+        assert_eq!(bob[1].balance, Money::usd(445000));
+        assert_eq!(bob[1].equity, Money::usd(505000));
+    }
+}
@@ -1,27 +1,95 @@
 use axum::{
     Json, Router,
-    extract::{Path, State},
+    extract::{DefaultBodyLimit, Path, Query, State},
     http::StatusCode,
     response::Result,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
 };
+#[cfg(not(feature = "embedded_engine"))]
 use futures::StreamExt;
-use redis::{AsyncCommands, Client};
+use orderbook::{Price, Qty, Side, TimeInForce, TradeEvent, envelope};
+#[cfg(not(feature = "embedded_engine"))]
+use redis::Client;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
 };
 use tokio::net::TcpListener;
 
+mod audit;
+#[cfg(not(feature = "embedded_engine"))]
+mod batch_publisher;
+mod capacity;
+mod clock;
+#[cfg(feature = "embedded_engine")]
+mod embedded;
+mod exposure;
+mod faucet;
+mod fees;
+mod guard;
+mod history;
+mod ids;
+mod market_data;
+mod money;
+mod notifications;
+mod orders;
+mod pagination;
+mod publisher;
+mod quotes;
+mod rate_limit;
+mod repository;
+mod rfq;
+mod settlement;
+mod snapshots;
+mod state;
+mod watchdog;
+
+use audit::{AuditExport, AuditFilter, AuditKind};
+#[cfg(not(feature = "embedded_engine"))]
+use batch_publisher::{BatchingPublisher, RedisSink};
+use capacity::{CapacityReport, CapacitySort, CapacityView};
+use clock::{Clock, SystemClock};
+use exposure::Exposure;
+use faucet::{Faucet, FaucetConfig, FaucetError};
+use fees::FeeSchedule;
+use guard::{PayloadLimits, RejectedCounts};
+use history::EndOfDay;
+use market_data::{BookUpdate, InMemoryMarketData, LastPrice, ValuationSource};
+use money::Money;
+use notifications::{Notification, NotificationKind, NotificationPrefs};
+use orders::OrderStatus;
+use pagination::{Page, PageQuery};
+use publisher::PublisherMetrics;
+use quotes::Step;
+use repository::InMemoryUserRepository;
+use rfq::{Rfq, RfqError, RfqSide};
+use settlement::{BustError, DeadLetterError, SettledTrade};
+use snapshots::{L3Snapshot, SnapshotChunk, SnapshotEnd, SnapshotError};
+use state::AppState;
+use watchdog::Resolution;
+
 const ORDER_INBOUND_CHANNEL: &str = "order_inbound";
+#[cfg(not(feature = "embedded_engine"))]
 const ORDER_OUTBOUND_CHANNEL: &str = "order_outbound";
+#[cfg(not(feature = "embedded_engine"))]
+const MARKET_DATA_CHANNEL: &str = "market_data";
+// levels a side GET /orderbook/{symbol} shows unless asked for more
+const DEFAULT_DEPTH_LEVELS: usize = 10;
+const ENGINE_ADMIN_CHANNEL: &str = "engine_admin";
+const DAILY_ROLLOVER_ENV: &str = "DAILY_ROLLOVER_UTC";
 
 #[derive(Serialize, Deserialize, Clone)]
 struct User {
     email: String,
-    current_balance: i64,
-    stocks: HashMap<String, u64>,
+    current_balance: Money,
+    stocks: HashMap<String, Qty>,
+    // refreshed whenever the user is served
+    #[serde(default)]
+    faucet_claims_remaining: usize,
+    // allowed to answer requests for quotes
+    #[serde(default)]
+    market_maker: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -32,32 +100,312 @@ struct UserRequest {
 #[derive(Serialize, Deserialize, Debug)]
 struct Order {
     symbol: String,
-    side: String,
-    quantity: u32,
-    price: Option<i64>,
+    side: Side,
+    // shares; left out of an order sized by notional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quantity: Option<Qty>,
+    price: Option<Price>,
+    user: String,
+    // left to the engine's default when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time_in_force: Option<TimeInForce>,
+    // unix millis; the engine drops the order from the book after this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    // refused by the engine instead of matched if it would take liquidity
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    post_only: bool,
+    // holds the order back until a trade reaches this price; with a price as
+    // well it then enters as a limit order (stop-limit)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop_price: Option<Price>,
+    // for market orders: spend up to this much instead of buying a set
+    // number of shares
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notional: Option<Price>,
+    // for market orders: trade only at the best price on the other side and
+    // rest the remainder there as a limit order
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    market_to_limit: bool,
+    // works the order at an offset from the best price on its own side and
+    // keeps it there as that price moves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peg: Option<orderbook::Peg>,
+    // rests without showing in market data, behind the displayed orders at
+    // its price
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hidden: bool,
+    // the least the order will take on arrival; refused by the engine if
+    // the book can't fill that much
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_quantity: Option<Qty>,
+    // once resting, fills only against an order that takes all of it at once
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    all_or_none: bool,
+    // the user's own labels, such as the strategy that sent the order; they
+    // come back on its trades
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    // given by place_order, for matching the engine's answer to the order
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    client_order_id: Option<u64>,
+    // lets a limit order through the price collar; never sent to the engine
+    #[serde(default, skip_serializing)]
+    allow_far_price: bool,
+}
+
+impl From<&Order> for orderbook::Order {
+    fn from(order: &Order) -> Self {
+        orderbook::Order {
+            time_in_force: order.time_in_force.unwrap_or_default(),
+            expires_at: order.expires_at,
+            post_only: order.post_only,
+            stop_price: order.stop_price,
+            notional: order.notional,
+            market_to_limit: order.market_to_limit,
+            peg: order.peg,
+            hidden: order.hidden,
+            min_quantity: order.min_quantity,
+            all_or_none: order.all_or_none,
+            tags: order.tags.clone(),
+            client_order_id: order.client_order_id,
+            ..orderbook::Order::new_limit_order(
+                order.quantity.unwrap_or(Qty::ZERO),
+                order.price,
+                order.side.clone(),
+                order.symbol.clone(),
+                order.user.clone(),
+            )
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PositionLimitRequest {
+    user: String,
+    symbol: String,
+    limit: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct OrderRejected {
+    reason: String,
+    order: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ResumeSymbolRequest {
+    symbol: String,
+    #[serde(rename = "override", default)]
+    override_halt: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum AuctionAction {
+    Start,
+    Uncross,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuctionRequest {
+    symbol: String,
+    action: AuctionAction,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClearSymbolRequest {
+    symbol: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IntegrityHalt {
+    symbol: String,
+    seq: u64,
+    reason: String,
+    snapshot_hash: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SymbolHalted {
+    #[serde(rename = "type")]
+    kind: String,
+    symbol: String,
+    seq: u64,
+    price: Price,
+}
+
+// The engine's answer to an open_orders request, which the ack watchdog
+// makes.
+#[derive(Deserialize, Debug)]
+struct OpenOrders {
+    #[serde(rename = "type")]
+    kind: String,
+    user: String,
+    orders: Vec<orderbook::OrderView>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OrderAmended {
+    #[serde(rename = "type")]
+    kind: String,
+    symbol: String,
+    order_id: u64,
+    price: Price,
+    quantity: Qty,
+    #[serde(default)]
+    expires_at: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReduceRequest {
+    // taken off what the order has left
+    quantity: Qty,
+}
+
+// New terms for a resting order; whatever is left out it keeps.
+#[derive(Serialize, Deserialize, Debug)]
+struct AmendRequest {
+    symbol: String,
+    user: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    price: Option<Price>,
+    // the remaining quantity; 0 cancels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quantity: Option<Qty>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time_in_force: Option<TimeInForce>,
+    // unix millis; null makes a good-till-date order good till cancelled
+    #[serde(
+        default,
+        deserialize_with = "orderbook::present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    expires_at: Option<Option<i64>>,
+}
+
+// A cancel or amend the engine turned down.
+#[derive(Deserialize, Debug)]
+struct RequestRejected {
+    #[serde(rename = "type")]
+    kind: String,
+    symbol: String,
+    order_id: u64,
+    reason: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeadLetterFilter {
+    status: Option<settlement::DeadLetterStatus>,
+    // matches either side of the trade
+    user: Option<String>,
+    symbol: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MarketMakerRequest {
+    email: String,
+    enabled: bool,
+}
+
+// Puts `user` on a fee tier of their own, or back on the schedule with none.
+#[derive(Deserialize, Debug)]
+struct FeeTierRequest {
     user: String,
+    schedule: Option<FeeSchedule>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RfqRequest {
+    requester: String,
+    symbol: String,
+    side: RfqSide,
+    quantity: Qty,
+}
+
+#[derive(Deserialize, Debug)]
+struct QuoteRequest {
+    maker: String,
+    price: Price,
+}
+
+#[derive(Deserialize, Debug)]
+struct AcceptQuoteRequest {
+    requester: String,
+    maker: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DiscardRequest {
+    reason: String,
 }
 
-#[derive(Clone)]
-struct AppState {
-    db: Db,
-    redis_client: Client,
+#[derive(Deserialize, Debug)]
+struct BustRequest {
+    reason: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TradeEvent {
-    pub buyer: String,
-    pub seller: String,
-    pub symbol: String,
-    pub quantity: u64,
-    pub price: i64,
+#[derive(Deserialize, Debug)]
+struct CapacityQuery {
+    days: Option<usize>,
+    top: Option<usize>,
+    #[serde(default)]
+    sort: CapacitySort,
 }
 
-type Db = Arc<Mutex<HashMap<String, User>>>;
+#[derive(Serialize, Debug)]
+struct NotificationsPage {
+    unread: usize,
+    #[serde(flatten)]
+    page: Page<Notification>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryQuery {
+    days: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LadderRequest {
+    user: String,
+    rungs: Vec<quotes::Rung>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExposureQuery {
+    #[serde(default)]
+    verify: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct DepthQuery {
+    levels: Option<usize>,
+    // width of the price bands to merge levels into; a multiple of the tick
+    aggregation: Option<Price>,
+}
 
 #[tokio::main]
 async fn main() {
-    let db: Db = Arc::new(Mutex::new(HashMap::new()));
+    // `verify <file>` checks an audit export offline instead of serving
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, path] = args.as_slice()
+        && command == "verify"
+    {
+        let key = audit::signing_key_from_env();
+        match audit::verify_file(std::path::Path::new(path), &key) {
+            Ok(count) => println!("✅ {}: {} records verified", path, count),
+            Err(e) => {
+                println!("❌ {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // with embedded_engine the engine runs in-process and Redis is not needed
+    #[cfg(feature = "embedded_engine")]
+    let (publisher, engine_inbound) = embedded::EmbeddedPublisher::channel();
+    #[cfg(not(feature = "embedded_engine"))]
     let redis_client = match redis::Client::open("redis://127.0.0.1/") {
         Ok(client) => client,
         Err(e) => {
@@ -65,21 +413,174 @@ async fn main() {
             return;
         }
     };
+    #[cfg(not(feature = "embedded_engine"))]
+    let publisher = match batch_publisher::queue_capacity_from_env() {
+        Ok(capacity) => BatchingPublisher::spawn(RedisSink::new(redis_client.clone()), capacity),
+        Err(e) => {
+            println!("Invalid publisher configuration: {}", e);
+            return;
+        }
+    };
+
+    // sessions roll at midnight UTC unless configured otherwise ("HH:MM")
+    let rollover_at = match std::env::var(DAILY_ROLLOVER_ENV) {
+        Ok(value) => match history::parse_time_of_day(&value) {
+            Some(at) => at,
+            None => {
+                println!(
+                    "Invalid {}: {:?}, expected HH:MM",
+                    DAILY_ROLLOVER_ENV, value
+                );
+                return;
+            }
+        },
+        Err(_) => 0,
+    };
+
+    let faucet_config = match FaucetConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Invalid faucet configuration: {}", e);
+            return;
+        }
+    };
+    // optional cold-start prices so valuation works before the first trade
+    let reference_prices = match std::env::var(market_data::REFERENCE_PRICES_ENV) {
+        Ok(path) => match market_data::load_reference_prices(std::path::Path::new(&path)) {
+            Ok(prices) => prices,
+            Err(e) => {
+                println!("Invalid reference prices: {}", e);
+                return;
+            }
+        },
+        Err(_) => HashMap::new(),
+    };
+
+    // what end-of-day equity marks positions at; the last price by default
+    let valuation = match std::env::var(market_data::VALUATION_SOURCE_ENV) {
+        Ok(value) => match value.parse::<ValuationSource>() {
+            Ok(source) => source,
+            Err(e) => {
+                println!("Invalid {}: {}", market_data::VALUATION_SOURCE_ENV, e);
+                return;
+            }
+        },
+        Err(_) => ValuationSource::default(),
+    };
+
+    let price_collar_percent = match std::env::var(market_data::PRICE_COLLAR_ENV) {
+        Ok(value) => match value.parse::<u32>() {
+            Ok(percent) if percent < 100 => percent,
+            _ => {
+                println!("Invalid {}: {:?}", market_data::PRICE_COLLAR_ENV, value);
+                return;
+            }
+        },
+        Err(_) => market_data::DEFAULT_PRICE_COLLAR_PERCENT,
+    };
+
+    // what trades cost, as {"maker_bps":..,"taker_bps":..}; free by default
+    let fee_schedule = match std::env::var(fees::FEE_SCHEDULE_ENV) {
+        Ok(value) => match serde_json::from_str::<FeeSchedule>(&value)
+            .map_err(|e| e.to_string())
+            .and_then(|schedule| schedule.validate().map(|_| schedule))
+        {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                println!("Invalid {}: {}", fees::FEE_SCHEDULE_ENV, e);
+                return;
+            }
+        },
+        Err(_) => FeeSchedule::default(),
+    };
+
+    let ack_timeout_millis = match std::env::var(watchdog::ACK_TIMEOUT_ENV) {
+        Ok(value) => match value.parse::<i64>() {
+            Ok(secs) if secs > 0 => secs * 1000,
+            _ => {
+                println!("Invalid {}: {:?}", watchdog::ACK_TIMEOUT_ENV, value);
+                return;
+            }
+        },
+        Err(_) => watchdog::DEFAULT_ACK_TIMEOUT_MILLIS,
+    };
+
+    let rfq_window_millis = match std::env::var(rfq::RFQ_WINDOW_ENV) {
+        Ok(value) => match value.parse::<i64>() {
+            Ok(secs) if secs > 0 => secs * 1000,
+            _ => {
+                println!("Invalid {}: {:?}", rfq::RFQ_WINDOW_ENV, value);
+                return;
+            }
+        },
+        Err(_) => rfq::DEFAULT_RFQ_WINDOW_MILLIS,
+    };
+
+    let payload_limits = match PayloadLimits::from_env() {
+        Ok(limits) => limits,
+        Err(e) => {
+            println!("Invalid payload limits: {}", e);
+            return;
+        }
+    };
 
-    let state = AppState {
-        db: db.clone(),
-        redis_client: redis_client.clone(),
+    let instance_id = match ids::instance_id_from_env() {
+        Ok(id) => id,
+        Err(e) => {
+            println!("Invalid instance id: {}", e);
+            return;
+        }
     };
 
+    // in-process, orders are checked under the engine's own rules before
+    // they reach it; a gateway in front of Redis doesn't know them and
+    // checks under the defaults
+    #[cfg(feature = "embedded_engine")]
+    let symbol_rules = matching_engine::default_symbols();
+    #[cfg(not(feature = "embedded_engine"))]
+    let symbol_rules = Vec::new();
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+    let state = AppState::builder()
+        .with_repository(Arc::new(InMemoryUserRepository::default()))
+        .with_market_data(Arc::new(InMemoryMarketData::with_valuation(valuation)))
+        .with_publisher(Arc::new(publisher))
+        .with_faucet(Faucet::new(faucet_config, clock.clone()))
+        .with_clock(clock.clone())
+        .with_rfq_window(rfq_window_millis)
+        .with_price_collar(price_collar_percent)
+        .with_fee_schedule(fee_schedule)
+        .with_ack_timeout(ack_timeout_millis)
+        .with_payload_limits(payload_limits)
+        .with_audit_key(audit::signing_key_from_env())
+        .with_instance_id(instance_id)
+        .with_symbol_rules(symbol_rules)
+        .build();
+    for (symbol, price) in &reference_prices {
+        state.market_data.seed_reference(symbol, *price);
+    }
+
+    #[cfg(feature = "embedded_engine")]
+    tokio::spawn(embedded::run_engine(engine_inbound, state.clone()));
     // spawn background task to handle outbound events
-    tokio::spawn(listen_outbound(redis_client.clone(), db.clone()));
+    #[cfg(not(feature = "embedded_engine"))]
+    tokio::spawn(listen_outbound(redis_client, state.clone()));
 
-    let app = Router::new()
-        .route("/user", post(create_user))
-        .route("/user/{email}", get(get_user))
-        .route("/users", get(get_all_users))
-        .route("/place_order", post(place_order))
-        .with_state(state);
+    // spawn the job that chases orders the engine never answered
+    tokio::spawn(run_ack_watchdog(state.clone()));
+
+    // spawn the end-of-day rollover job
+    tokio::spawn(history::run_daily_rollover(
+        clock,
+        rollover_at,
+        state.users.clone(),
+        state.market_data.clone(),
+        state.history.clone(),
+        state.publisher.clone(),
+    ));
+
+    let app = router(state);
 
     let listener = TcpListener::bind("localhost:8080").await.unwrap();
     println!("🚀 Server running on http://localhost:8080");
@@ -87,32 +588,96 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/user", post(create_user))
+        .route("/user/{email}", get(get_user))
+        .route("/user/{email}/history", get(get_user_history))
+        .route("/user/{email}/exposure", get(get_exposure))
+        .route("/user/{email}/fees", get(get_user_fees))
+        .route("/user/{email}/faucet", post(claim_faucet))
+        .route("/user/{email}/notifications", get(list_notifications))
+        .route(
+            "/user/{email}/notifications/{id}/read",
+            post(mark_notification_read),
+        )
+        .route(
+            "/user/{email}/notification_prefs",
+            get(get_notification_prefs).put(set_notification_prefs),
+        )
+        .route("/users", get(get_all_users))
+        .route("/prices", get(get_prices))
+        .route("/fees", get(get_fee_schedule))
+        .route("/orderbook/{symbol}", get(get_depth))
+        .route("/place_order", post(place_order))
+        .route("/order/{id}", patch(amend_order))
+        .route("/order/{id}/reduce", post(reduce_order))
+        .route("/quotes/{symbol}", post(update_quotes))
+        .route("/admin/position_limits", post(set_position_limit))
+        .route("/admin/resume_symbol", post(resume_symbol))
+        .route("/admin/auction", post(run_auction))
+        .route("/admin/clear_symbol", post(clear_symbol))
+        .route("/admin/reference_prices", post(seed_reference_prices))
+        .route("/admin/market_makers", post(set_market_maker))
+        .route("/admin/fees/tiers", post(set_fee_tier))
+        .route("/admin/fees/ledger", get(get_fee_ledger))
+        .route("/rfq", post(request_quotes).get(list_open_rfqs))
+        .route("/rfq/{id}", get(get_rfq))
+        .route("/rfq/{id}/quotes", post(submit_quote))
+        .route("/rfq/{id}/quotes/{maker}", delete(withdraw_quote))
+        .route("/rfq/{id}/accept", post(accept_quote))
+        .route("/admin/book/{symbol}/snapshot", get(get_book_snapshot))
+        .route("/admin/capacity", get(get_capacity))
+        .route("/admin/orders/lost", get(lost_orders))
+        .route("/admin/audit/export", get(export_audit))
+        .route("/admin/audit/verify", get(verify_audit))
+        .route("/admin/ids/{id}", get(decode_id))
+        .route("/admin/settlement/dlq", get(list_dead_letters))
+        .route("/admin/settlement/rejected", get(rejected_payloads))
+        .route("/admin/publisher", get(publisher_metrics))
+        .route("/admin/settlement/dlq/{id}/retry", post(retry_dead_letter))
+        .route("/admin/trades/{trade_id}/bust", post(bust_trade))
+        .route(
+            "/admin/settlement/dlq/{id}/discard",
+            post(discard_dead_letter),
+        )
+        .layer(DefaultBodyLimit::max(guard::MAX_BODY_BYTES))
+        .with_state(state)
+}
+
+// Liveness probe used by the client's `profiles check`
+async fn health() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "ok"
+    }))
+}
+
 // Create new user
 async fn create_user(
     State(state): State<AppState>,
     Json(payload): Json<UserRequest>,
 ) -> Json<String> {
-    let mut db = state.db.lock().unwrap();
-
     let user = User {
         email: payload.email.clone(),
-        current_balance: 500000,
+        current_balance: Money::usd(500000),
         stocks: HashMap::new(),
+        faucet_claims_remaining: state.faucet.remaining(&payload.email),
+        market_maker: false,
     };
 
-    db.insert(payload.email.clone(), user.clone());
+    state.users.insert(user.clone());
     Json(user.email)
 }
 
 // Fetch individual user
 async fn get_user(state: State<AppState>, Path(email): Path<String>) -> Result<Json<User>> {
-    let db = state.db.lock().unwrap();
-
     // Attempt to get the user from the database
-    let user = db.get(&email).cloned();
+    let user = state.users.get(&email);
 
     // Check if the user was found
-    if let Some(user) = user {
+    if let Some(mut user) = user {
+        user.faucet_claims_remaining = state.faucet.remaining(&email);
         Ok(Json(user))
     } else {
         // If no user is found, return a 404 Not Found error
@@ -120,92 +685,3054 @@ async fn get_user(state: State<AppState>, Path(email): Path<String>) -> Result<J
     }
 }
 
+// Fetch a user's end-of-day equity curve, most recent `days` sessions
+async fn get_user_history(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<EndOfDay>>> {
+    if state.users.get(&email).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let days = query.days.unwrap_or(history::DEFAULT_HISTORY_DAYS);
+    let history = state.history.lock().unwrap();
+    let records = history.get(&email).map(Vec::as_slice).unwrap_or_default();
+    let start = records.len().saturating_sub(days);
+    Ok(Json(records[start..].to_vec()))
+}
+
+// What a user's resting orders could still do if they all filled, from the
+// orders this gateway tracks. With ?verify=true those are checked against
+// the engine's own open orders too.
+async fn get_exposure(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+    Query(query): Query<ExposureQuery>,
+) -> Result<Json<Exposure>> {
+    let Some(user) = state.users.get(&email) else {
+        return Err(StatusCode::NOT_FOUND.into());
+    };
+    let resting = state.orders.resting(&email);
+    let mut exposure = exposure::exposure(user.current_balance, &user.stocks, &resting)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !query.verify {
+        return Ok(Json(exposure));
+    }
+
+    let (first, reply) = state.open_order_queries.subscribe(&email);
+    if first {
+        let payload = serde_json::json!({ "type": "open_orders", "user": email });
+        if let Err(e) = state
+            .publisher
+            .publish(
+                ORDER_INBOUND_CHANNEL,
+                sealed(&state, "open_orders", &payload),
+            )
+            .await
+        {
+            eprintln!("Failed to ask after {}'s open orders: {}", email, e);
+            state.open_order_queries.abandon(&email);
+            return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+        }
+    }
+    match tokio::time::timeout(exposure::VERIFY_TIMEOUT, reply).await {
+        Ok(Ok(engine)) => {
+            exposure.discrepancy = Some(exposure::discrepancy(&resting, &engine));
+            Ok(Json(exposure))
+        }
+        Ok(Err(_)) => Err(StatusCode::SERVICE_UNAVAILABLE.into()),
+        Err(_) => {
+            if first {
+                state.open_order_queries.abandon(&email);
+            }
+            Err(StatusCode::GATEWAY_TIMEOUT.into())
+        }
+    }
+}
+
+async fn list_notifications(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<NotificationsPage>> {
+    if state.users.get(&email).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    let page = pagination::paginate(state.notifier.list(&email), |n| n.id, &page)
+        .map_err(|_| invalid_cursor())?;
+    Ok(Json(NotificationsPage {
+        unread: state.notifier.unread(&email),
+        page,
+    }))
+}
+
+async fn mark_notification_read(
+    State(state): State<AppState>,
+    Path((email, id)): Path<(String, u64)>,
+) -> Result<Json<serde_json::Value>> {
+    state
+        .notifier
+        .mark_read(&email, id)
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok(Json(serde_json::json!({
+        "unread": state.notifier.unread(&email)
+    })))
+}
+
+async fn get_notification_prefs(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+) -> Result<Json<NotificationPrefs>> {
+    if state.users.get(&email).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    Ok(Json(state.notifier.prefs(&email)))
+}
+
+async fn set_notification_prefs(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+    Json(prefs): Json<NotificationPrefs>,
+) -> Result<Json<NotificationPrefs>> {
+    if state.users.get(&email).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    state.notifier.set_prefs(&email, prefs.clone());
+    Ok(Json(prefs))
+}
+
+// Tells both sides of a settled trade about it.
+fn notify_trade(state: &AppState, event: &TradeEvent) {
+    for (user, side) in [(&event.buyer, "buy"), (&event.seller, "sell")] {
+        state.notifier.dispatch(
+            user,
+            NotificationKind::Trade,
+            serde_json::json!({ "side": side, "trade": event }),
+        );
+    }
+}
+
+fn audit_trade(state: &AppState, event: &TradeEvent) {
+    state.audit.append(
+        AuditKind::Trade,
+        &[&event.buyer, &event.seller],
+        Some(&event.symbol),
+        serde_json::to_value(event).unwrap(),
+    );
+}
+
 // Fetch all users
-async fn get_all_users(State(state): State<AppState>) -> Json<Vec<User>> {
-    let db = state.db.lock().unwrap();
-    let users = db.values().cloned().collect();
-    Json(users)
+async fn get_all_users(
+    State(state): State<AppState>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<User>>> {
+    let mut users = pagination::paginate(state.users.all(), |u| u.email.clone(), &page)
+        .map_err(|_| invalid_cursor())?;
+    for user in &mut users.items {
+        user.faucet_claims_remaining = state.faucet.remaining(&user.email);
+    }
+    Ok(Json(users))
 }
 
-async fn place_order(
+fn invalid_cursor() -> (StatusCode, &'static str) {
+    (StatusCode::BAD_REQUEST, "invalid cursor")
+}
+
+// Last price per symbol, flagged `reference` until the symbol first trades
+async fn get_prices(State(state): State<AppState>) -> Json<BTreeMap<String, LastPrice>> {
+    Json(state.market_data.prices().into_iter().collect())
+}
+
+// The book's displayed levels as the engine's updates left them, optionally
+// merged into coarser price bands; see market_data::aggregate for where band
+// boundaries fall.
+async fn get_depth(
     State(state): State<AppState>,
-    Json(order): Json<Order>,
-) -> Json<serde_json::Value> {
-    // get a multiplexed async connection
-    let mut conn = state
-        .redis_client
-        .get_multiplexed_async_connection()
-        .await
-        .expect("failed to get Redis connection");
+    Path(symbol): Path<String>,
+    Query(query): Query<DepthQuery>,
+) -> Result<Json<orderbook::DepthSnapshot>> {
+    if let Some(band) = query.aggregation {
+        let tick = state.rules(&symbol).tick_size;
+        if !band.is_positive() || !band.is_multiple_of(tick) {
+            let message = format!(
+                "aggregation must be a positive multiple of the tick {}",
+                tick
+            );
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, message).into());
+        }
+    }
+    let levels = query.levels.unwrap_or(DEFAULT_DEPTH_LEVELS);
+    state
+        .market_data
+        .depth(&symbol, levels, query.aggregation)
+        .map(Json)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into())
+}
 
-    // serialize order
-    let payload = serde_json::to_string(&order).unwrap();
+// Seed reference prices; symbols that have already traded are left alone
+async fn seed_reference_prices(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<Json<serde_json::Value>> {
+    let prices = market_data::parse_reference_prices(&body)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
 
-    // publish to redis channel
-    let _: () = conn.publish(ORDER_INBOUND_CHANNEL, payload).await.unwrap();
+    let (mut applied, mut skipped) = (vec![], vec![]);
+    for (symbol, price) in prices {
+        if state.market_data.seed_reference(&symbol, price) {
+            applied.push(symbol);
+        } else {
+            skipped.push(symbol);
+        }
+    }
+    applied.sort();
+    skipped.sort();
+    Ok(Json(serde_json::json!({
+        "applied": applied,
+        "skipped": skipped,
+    })))
+}
 
-    Json(serde_json::json!({
-        "status": "submitted"
-    }))
+// Credit demo funds to a paper-trading account
+async fn claim_faucet(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+) -> Result<Json<User>> {
+    let mut credited = Err(StatusCode::NOT_FOUND);
+    state.users.update(&email, &mut |user| {
+        credited = faucet_credit(&state.faucet, &email, user);
+    });
+    Ok(Json(credited?))
 }
 
-async fn listen_outbound(client: Client, db: Db) {
-    // Get PubSub connection
-    let mut pubsub = client
-        .get_async_pubsub()
-        .await
-        .expect("failed to open PubSub connection");
+fn faucet_credit(faucet: &Faucet, email: &str, user: &mut User) -> Result<User, StatusCode> {
+    let balance = user
+        .current_balance
+        .checked_add(faucet.amount())
+        .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
 
-    // Subscribe to outbound channel
-    pubsub
-        .subscribe(ORDER_OUTBOUND_CHANNEL)
-        .await
-        .expect("failed to subscribe");
+    let remaining = faucet.claim(email).map_err(|e| match e {
+        FaucetError::Disabled => StatusCode::FORBIDDEN,
+        FaucetError::Exhausted => StatusCode::TOO_MANY_REQUESTS,
+    })?;
 
-    let mut stream = pubsub.on_message();
+    user.current_balance = balance;
+    user.faucet_claims_remaining = remaining;
+    Ok(user.clone())
+}
 
-    println!(
-        "📡 Listening for trade events on {}",
-        ORDER_OUTBOUND_CHANNEL
+async fn place_order(
+    State(state): State<AppState>,
+    Json(mut order): Json<Order>,
+) -> Result<Json<serde_json::Value>> {
+    // checked as the engine will check it, so what it would turn away isn't
+    // published
+    let rules = state.rules(&order.symbol);
+    if let Err(e) = orderbook::Order::from(&order).validate(&rules) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(e)).into());
+    }
+    // a limit price far through the book is more likely a slip of the finger
+    // than meant; skipped while there's no book to measure it against
+    if let (Some(price), None, false) = (order.price, order.stop_price, order.allow_far_price)
+        && let Some(book) = state.market_data.depth(&order.symbol, 1, None)
+        && let Some(touch) =
+            market_data::outside_collar(&order.side, price, &book, state.price_collar_percent)
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "code": "outside_price_collar",
+                "price": price,
+                "touch": touch,
+                "collar_percent": state.price_collar_percent,
+            })),
+        )
+            .into());
+    }
+
+    let client_order_id = state.ids.next();
+    order.client_order_id = Some(client_order_id);
+    let payload = sealed(&state, "order", &order);
+
+    // watched from before it goes out, so an ack can't come back first
+    state.watchdog.submitted(orderbook::Order::from(&order));
+    // publish to the engine's inbound channel
+    if let Err(e) = state
+        .publisher
+        .publish(ORDER_INBOUND_CHANNEL, payload)
+        .await
+    {
+        eprintln!("Failed to submit order {:?}: {}", order, e);
+        state.watchdog.answered(client_order_id);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    state.audit.append(
+        AuditKind::Order,
+        &[&order.user],
+        Some(&order.symbol),
+        serde_json::to_value(&order).unwrap(),
     );
 
-    while let Some(msg) = stream.next().await {
-        let payload: String = match msg.get_payload() {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("Failed to parse message: {:?}", e);
-                continue;
-            }
-        };
+    Ok(Json(serde_json::json!({
+        "status": "submitted",
+        "client_order_id": client_order_id,
+    })))
+}
 
-        match serde_json::from_str::<TradeEvent>(&payload) {
-            Ok(event) => {
-                println!("Received trade event: {:?}", event);
-
-                // Update user DB
-                let mut db = db.lock().unwrap();
-                if let Some(buyer) = db.get_mut(&event.buyer) {
-                    // Buyer spends money
-                    buyer.current_balance -= event.price * event.quantity as i64;
-                    // Buyer gains stock
-                    *buyer.stocks.entry(event.symbol.clone()).or_insert(0) += event.quantity;
-                }
-                if let Some(seller) = db.get_mut(&event.seller) {
-                    // Seller receives money
-                    seller.current_balance += event.price * event.quantity as i64;
-
-                    // Seller loses stock, so subtract the quantity
-                    if let Some(current_quantity) = seller.stocks.get_mut(&event.symbol) {
-                        *current_quantity = current_quantity.saturating_sub(event.quantity as u64);
-                    }
-                }
-            }
-            Err(e) => {
+// Asks the engine for the open orders of every user with an order it hasn't
+// answered in time; the replies are settled by resolve_unanswered.
+async fn query_unanswered_orders(state: &AppState) {
+    for user in state.watchdog.overdue() {
+        let payload = serde_json::json!({ "type": "open_orders", "user": user });
+        if let Err(e) = state
+            .publisher
+            .publish(
+                ORDER_INBOUND_CHANNEL,
+                sealed(state, "open_orders", &payload),
+            )
+            .await
+        {
+            eprintln!("Failed to ask after {}'s unanswered orders: {}", user, e);
+            state.watchdog.requery(&user);
+        }
+    }
+}
+
+async fn run_ack_watchdog(state: AppState) {
+    let every =
+        std::time::Duration::from_millis((state.watchdog.timeout_millis() / 2).max(1) as u64);
+    loop {
+        tokio::time::sleep(every).await;
+        query_unanswered_orders(&state).await;
+    }
+}
+
+// Settles `user`'s unanswered orders against their open orders in the
+// engine: one found resting is tracked as the engine has it, and one that
+// isn't there is given up as lost.
+fn resolve_unanswered(state: &AppState, user: &str, open: &[orderbook::OrderView]) {
+    for resolution in state.watchdog.resolve(user, open) {
+        match resolution {
+            Resolution::Found(order) => {
                 println!(
-                    "Failed to deserialize TradeEvent: {:?}, raw: {}",
-                    e, payload
+                    "Order {} found resting in {} without an ack; tracking it",
+                    order.order_id, order.symbol
                 );
+                state.orders.accepted(&order);
+            }
+            Resolution::Lost(order) => {
+                eprintln!(
+                    "🚨 Order {:?} for {} never reached the engine ({} lost so far)",
+                    order.client_order_id,
+                    order.user,
+                    state.watchdog.lost()
+                );
+                let details = serde_json::json!({ "order": order });
+                state.audit.append(
+                    AuditKind::OrderLost,
+                    &[user],
+                    Some(&order.symbol),
+                    details.clone(),
+                );
+                state
+                    .notifier
+                    .dispatch(user, NotificationKind::OrderLost, details);
             }
         }
     }
 }
+
+// Sends new terms for a resting order to the engine, which answers with an
+// order_amended or amend_rejected
+async fn amend_order(
+    State(state): State<AppState>,
+    Path(order_id): Path<u64>,
+    Json(amend): Json<AmendRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let unchanged = amend.price.is_none()
+        && amend.quantity.is_none()
+        && amend.time_in_force.is_none()
+        && amend.expires_at.is_none();
+    if unchanged {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "nothing to amend").into());
+    }
+    // refused here as the engine would refuse them
+    if matches!(
+        amend.time_in_force,
+        Some(TimeInForce::Ioc | TimeInForce::Fok)
+    ) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "a resting order can't be made IOC or FOK",
+        )
+            .into());
+    }
+    if let Some(Some(at)) = amend.expires_at
+        && at <= state.clock.now_millis()
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "expires_at must be in the future",
+        )
+            .into());
+    }
+
+    let mut payload = serde_json::to_value(&amend).unwrap();
+    payload["type"] = serde_json::json!("amend");
+    payload["order_id"] = serde_json::json!(order_id);
+    if let Err(e) = state
+        .publisher
+        .publish(ORDER_INBOUND_CHANNEL, sealed(&state, "amend", &payload))
+        .await
+    {
+        eprintln!("Failed to submit amend {:?}: {}", amend, e);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    state.audit.append(
+        AuditKind::Order,
+        &[&amend.user],
+        Some(&amend.symbol),
+        payload,
+    );
+
+    Ok(Json(serde_json::json!({
+        "status": "submitted"
+    })))
+}
+
+// Takes some of a resting order's remaining quantity away without costing
+// it its place; taking all it has left cancels it. The engine answers with
+// an order_amended or a cancel, so the reply is only what should be left.
+async fn reduce_order(
+    State(state): State<AppState>,
+    Path(order_id): Path<u64>,
+    Json(reduce): Json<ReduceRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>)> {
+    if reduce.quantity.is_zero() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "nothing to reduce by").into());
+    }
+    let Some(tracked) = state.orders.get(order_id) else {
+        return Err(StatusCode::NOT_FOUND.into());
+    };
+    if !tracked.can_rest() {
+        return Err((StatusCode::CONFLICT, "market and IOC orders never rest").into());
+    }
+    if tracked.status == OrderStatus::Closed {
+        return Err((StatusCode::CONFLICT, "order is no longer resting").into());
+    }
+
+    let order = &tracked.order;
+    let payload = serde_json::json!({
+        "type": "reduce",
+        "symbol": order.symbol,
+        "order_id": order_id,
+        "quantity": reduce.quantity,
+    });
+    if let Err(e) = state
+        .publisher
+        .publish(ORDER_INBOUND_CHANNEL, sealed(&state, "reduce", &payload))
+        .await
+    {
+        eprintln!("Failed to submit reduce {:?}: {}", reduce, e);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    state.audit.append(
+        AuditKind::Order,
+        &[&order.user],
+        Some(&order.symbol),
+        payload,
+    );
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "status": "pending",
+            "order_id": order_id,
+            "remaining": order.quantity.saturating_sub(reduce.quantity),
+        })),
+    ))
+}
+
+// Turns a user's resting orders in a symbol into the ladder asked for,
+// sending the engine only the cancels, resizes and new orders that differ.
+// The reply is the plan and, for each rung, the order_id it rests under or
+// the client_order_id of the order placed for it.
+async fn update_quotes(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    Json(request): Json<LadderRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let rules = state.rules(&symbol);
+    for rung in &request.rungs {
+        let order = orderbook::Order::new_limit_order(
+            rung.quantity,
+            Some(rung.price),
+            rung.side.clone(),
+            symbol.clone(),
+            request.user.clone(),
+        );
+        if let Err(e) = order.validate(&rules) {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(e)).into());
+        }
+    }
+    let current: Vec<orderbook::Order> = state
+        .orders
+        .resting(&request.user)
+        .into_iter()
+        .filter(|order| order.symbol == symbol)
+        .collect();
+    let plan = quotes::plan(&current, &request.rungs).map_err(|duplicate| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "more than one {:?} rung at {}",
+                duplicate.side, duplicate.price
+            ),
+        )
+    })?;
+
+    let mut orders = Vec::new();
+    for step in &plan {
+        let (kind, payload, client_order_id) = match step {
+            Step::Keep { order_id } => {
+                orders.push(serde_json::json!({ "order_id": order_id }));
+                continue;
+            }
+            Step::Cancel { order_id } => {
+                let payload = serde_json::json!({
+                    "type": "cancel",
+                    "symbol": symbol,
+                    "order_id": order_id,
+                });
+                ("cancel", payload, None)
+            }
+            Step::Reduce { order_id, by, .. } => {
+                orders.push(serde_json::json!({ "order_id": order_id }));
+                let payload = serde_json::json!({
+                    "type": "reduce",
+                    "symbol": symbol,
+                    "order_id": order_id,
+                    "quantity": by,
+                });
+                ("reduce", payload, None)
+            }
+            Step::Amend { order_id, quantity } => {
+                orders.push(serde_json::json!({ "order_id": order_id }));
+                let payload = serde_json::json!({
+                    "type": "amend",
+                    "symbol": symbol,
+                    "user": request.user,
+                    "order_id": order_id,
+                    "quantity": quantity,
+                });
+                ("amend", payload, None)
+            }
+            Step::Place { rung } => {
+                let client_order_id = state.ids.next();
+                orders.push(serde_json::json!({ "client_order_id": client_order_id }));
+                state.watchdog.submitted(orderbook::Order {
+                    client_order_id: Some(client_order_id),
+                    ..orderbook::Order::new_limit_order(
+                        rung.quantity,
+                        Some(rung.price),
+                        rung.side.clone(),
+                        symbol.clone(),
+                        request.user.clone(),
+                    )
+                });
+                let payload = serde_json::json!({
+                    "symbol": symbol,
+                    "side": rung.side,
+                    "quantity": rung.quantity,
+                    "price": rung.price,
+                    "user": request.user,
+                    "client_order_id": client_order_id,
+                });
+                ("order", payload, Some(client_order_id))
+            }
+        };
+        if let Err(e) = state
+            .publisher
+            .publish(ORDER_INBOUND_CHANNEL, sealed(&state, kind, &payload))
+            .await
+        {
+            eprintln!("Failed to submit quote {} {}: {}", kind, payload, e);
+            if let Some(client_order_id) = client_order_id {
+                state.watchdog.answered(client_order_id);
+            }
+            return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+        }
+        state
+            .audit
+            .append(AuditKind::Order, &[&request.user], Some(&symbol), payload);
+    }
+
+    Ok(Json(serde_json::json!({
+        "plan": plan,
+        "orders": orders,
+    })))
+}
+
+// Every resting order in a book. The engine streams it in chunks, which are
+// checked against its checksum before anything is served; requests for a
+// symbol whose snapshot is already coming share it.
+async fn get_book_snapshot(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+) -> Result<Json<L3Snapshot>> {
+    let (request_id, reply) = state.snapshots.subscribe(&symbol);
+    if let Some(request_id) = request_id {
+        let payload = serde_json::json!({
+            "type": "book_snapshot",
+            "symbol": symbol,
+            "request_id": request_id,
+        });
+        if let Err(e) = state
+            .publisher
+            .publish(
+                ORDER_INBOUND_CHANNEL,
+                sealed(&state, "book_snapshot", &payload),
+            )
+            .await
+        {
+            eprintln!("Failed to request a {} snapshot: {}", symbol, e);
+            state.snapshots.abandon(&symbol, request_id);
+            return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+        }
+    }
+
+    match tokio::time::timeout(snapshots::SNAPSHOT_TIMEOUT, reply).await {
+        Ok(Ok(Ok(snapshot))) => Ok(Json(snapshot)),
+        Ok(Ok(Err(SnapshotError::UnknownSymbol))) => Err(StatusCode::NOT_FOUND.into()),
+        Ok(Ok(Err(e))) => {
+            eprintln!("Refused a {} snapshot: {}", symbol, e);
+            Err((StatusCode::BAD_GATEWAY, e.to_string()).into())
+        }
+        // the request it was waiting on was given up
+        Ok(Err(_)) => Err(StatusCode::SERVICE_UNAVAILABLE.into()),
+        Err(_) => {
+            if let Some(request_id) = request_id {
+                state.snapshots.abandon(&symbol, request_id);
+            }
+            Err(StatusCode::GATEWAY_TIMEOUT.into())
+        }
+    }
+}
+
+// Push a per-user per-symbol position cap to the matching engine
+async fn set_position_limit(
+    State(state): State<AppState>,
+    Json(limit): Json<PositionLimitRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let mut payload = serde_json::to_value(&limit).unwrap();
+    payload["type"] = serde_json::json!("position_limit");
+
+    if let Err(e) = state
+        .publisher
+        .publish(
+            ENGINE_ADMIN_CHANNEL,
+            sealed(&state, "position_limit", &payload),
+        )
+        .await
+    {
+        eprintln!("Failed to submit position limit {:?}: {}", limit, e);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    let action = serde_json::json!({
+        "action": "position_limit",
+        "symbol": limit.symbol,
+        "limit": limit.limit,
+    });
+    state.audit.append(
+        AuditKind::AdminAction,
+        &[&limit.user],
+        Some(&limit.symbol),
+        action.clone(),
+    );
+    state
+        .notifier
+        .dispatch(&limit.user, NotificationKind::AdminAction, action);
+
+    Ok(Json(serde_json::json!({
+        "status": "submitted"
+    })))
+}
+
+// Let a symbol halted on an integrity failure or by its circuit breaker match
+// again
+async fn resume_symbol(
+    State(state): State<AppState>,
+    Json(resume): Json<ResumeSymbolRequest>,
+) -> Result<Json<serde_json::Value>> {
+    if !resume.override_halt {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "resuming a halted symbol requires \"override\": true",
+        )
+            .into());
+    }
+
+    let mut payload = serde_json::to_value(&resume).unwrap();
+    payload["type"] = serde_json::json!("resume_symbol");
+    if let Err(e) = state
+        .publisher
+        .publish(
+            ENGINE_ADMIN_CHANNEL,
+            sealed(&state, "resume_symbol", &payload),
+        )
+        .await
+    {
+        eprintln!("Failed to submit resume for {}: {}", resume.symbol, e);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    state.audit.append(
+        AuditKind::AdminAction,
+        &[],
+        Some(&resume.symbol),
+        serde_json::json!({ "action": "resume_symbol" }),
+    );
+
+    Ok(Json(serde_json::json!({
+        "status": "submitted"
+    })))
+}
+
+// Open a symbol's call auction, or uncross it and return to continuous trading
+async fn run_auction(
+    State(state): State<AppState>,
+    Json(request): Json<AuctionRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let kind = match request.action {
+        AuctionAction::Start => "start_auction",
+        AuctionAction::Uncross => "uncross",
+    };
+    let payload = serde_json::json!({ "type": kind, "symbol": request.symbol });
+    if let Err(e) = state
+        .publisher
+        .publish(ENGINE_ADMIN_CHANNEL, sealed(&state, kind, &payload))
+        .await
+    {
+        eprintln!("Failed to submit {} for {}: {}", kind, request.symbol, e);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    state.audit.append(
+        AuditKind::AdminAction,
+        &[],
+        Some(&request.symbol),
+        serde_json::json!({ "action": kind }),
+    );
+
+    Ok(Json(serde_json::json!({
+        "status": "submitted"
+    })))
+}
+
+// Cancel everything resting in a symbol and halt it until it is resumed
+async fn clear_symbol(
+    State(state): State<AppState>,
+    Json(request): Json<ClearSymbolRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let payload = serde_json::json!({ "type": "clear_symbol", "symbol": request.symbol });
+    if let Err(e) = state
+        .publisher
+        .publish(
+            ENGINE_ADMIN_CHANNEL,
+            sealed(&state, "clear_symbol", &payload),
+        )
+        .await
+    {
+        eprintln!("Failed to submit clear for {}: {}", request.symbol, e);
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+    }
+    state.audit.append(
+        AuditKind::AdminAction,
+        &[],
+        Some(&request.symbol),
+        serde_json::json!({ "action": "clear_symbol" }),
+    );
+
+    Ok(Json(serde_json::json!({
+        "status": "submitted"
+    })))
+}
+
+async fn set_market_maker(
+    State(state): State<AppState>,
+    Json(request): Json<MarketMakerRequest>,
+) -> Result<Json<User>> {
+    let mut updated = None;
+    state.users.update(&request.email, &mut |user| {
+        user.market_maker = request.enabled;
+        updated = Some(user.clone());
+    });
+    let user = updated.ok_or(StatusCode::NOT_FOUND)?;
+    let action = serde_json::json!({ "action": "market_maker", "enabled": request.enabled });
+    state
+        .audit
+        .append(AuditKind::AdminAction, &[&user.email], None, action.clone());
+    state
+        .notifier
+        .dispatch(&user.email, NotificationKind::AdminAction, action);
+    Ok(Json(user))
+}
+
+// The rates everyone without a tier of their own trades at
+async fn get_fee_schedule(State(state): State<AppState>) -> Json<FeeSchedule> {
+    Json(state.fees.schedule())
+}
+
+// The rates `email` trades at, and whether they come from a tier
+async fn get_user_fees(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    if state.users.get(&email).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    Ok(Json(user_fees(&state, &email)))
+}
+
+fn user_fees(state: &AppState, email: &str) -> serde_json::Value {
+    let (schedule, tier) = state.fees.effective(email);
+    serde_json::json!({
+        "maker_bps": schedule.maker_bps,
+        "taker_bps": schedule.taker_bps,
+        "tier": tier,
+    })
+}
+
+async fn set_fee_tier(
+    State(state): State<AppState>,
+    Json(request): Json<FeeTierRequest>,
+) -> Result<Json<serde_json::Value>> {
+    if state.users.get(&request.user).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    if let Some(schedule) = &request.schedule {
+        schedule
+            .validate()
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    }
+    state.fees.set_tier(&request.user, request.schedule);
+    let action = serde_json::json!({ "action": "fee_tier", "schedule": request.schedule });
+    state.audit.append(
+        AuditKind::AdminAction,
+        &[&request.user],
+        None,
+        action.clone(),
+    );
+    state
+        .notifier
+        .dispatch(&request.user, NotificationKind::AdminAction, action);
+    Ok(Json(user_fees(&state, &request.user)))
+}
+
+// What the fee account holds, and the fees and rebates that moved it
+async fn get_fee_ledger(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "balance": state.fees.balance(),
+        "entries": state.fees.ledger(),
+    }))
+}
+
+fn rfq_status(e: RfqError) -> (StatusCode, String) {
+    match e {
+        RfqError::NotFound => (StatusCode::NOT_FOUND, "no such rfq".to_string()),
+        RfqError::NoSuchQuote => (StatusCode::NOT_FOUND, "no such quote".to_string()),
+        RfqError::Closed(status) => (StatusCode::CONFLICT, format!("rfq is {:?}", status)),
+        RfqError::NotRequester => (
+            StatusCode::FORBIDDEN,
+            "only the requester can accept".to_string(),
+        ),
+        RfqError::SelfQuote => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "cannot quote your own rfq".to_string(),
+        ),
+        RfqError::Settlement(e) => (StatusCode::CONFLICT, e.to_string()),
+    }
+}
+
+// Ask market makers to quote a block trade off the book
+async fn request_quotes(
+    State(state): State<AppState>,
+    Json(request): Json<RfqRequest>,
+) -> Result<Json<Rfq>> {
+    if request.quantity.is_zero() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "quantity must be positive",
+        )
+            .into());
+    }
+    if state.users.get(&request.requester).is_none() {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    Ok(Json(state.rfqs.request(
+        &request.requester,
+        &request.symbol,
+        request.side,
+        request.quantity,
+    )))
+}
+
+// Requests still open for quotes; market makers poll this
+async fn list_open_rfqs(
+    State(state): State<AppState>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<Rfq>>> {
+    let rfqs = pagination::paginate(state.rfqs.open(), |rfq| rfq.id, &page)
+        .map_err(|_| invalid_cursor())?;
+    Ok(Json(rfqs))
+}
+
+async fn get_rfq(State(state): State<AppState>, Path(id): Path<u64>) -> Result<Json<Rfq>> {
+    state
+        .rfqs
+        .get(id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND.into())
+}
+
+async fn submit_quote(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(quote): Json<QuoteRequest>,
+) -> Result<Json<Rfq>> {
+    if !quote.price.is_positive() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "price must be positive").into());
+    }
+    if !state
+        .users
+        .get(&quote.maker)
+        .is_some_and(|user| user.market_maker)
+    {
+        return Err((StatusCode::FORBIDDEN, "only market makers can quote").into());
+    }
+    let rfq = state
+        .rfqs
+        .quote(id, &quote.maker, quote.price)
+        .map_err(rfq_status)?;
+    Ok(Json(rfq))
+}
+
+async fn withdraw_quote(
+    State(state): State<AppState>,
+    Path((id, maker)): Path<(u64, String)>,
+) -> Result<Json<Rfq>> {
+    let rfq = state.rfqs.withdraw(id, &maker).map_err(rfq_status)?;
+    Ok(Json(rfq))
+}
+
+// Settle the block against one maker's quote; it never touches the book
+async fn accept_quote(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(accept): Json<AcceptQuoteRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let (rfq, trade) = state
+        .rfqs
+        .accept(
+            id,
+            &accept.requester,
+            &accept.maker,
+            state.users.as_ref(),
+            &state.fees,
+        )
+        .map_err(rfq_status)?;
+    audit_trade(&state, &trade);
+    notify_trade(&state, &trade);
+    Ok(Json(serde_json::json!({
+        "rfq": rfq,
+        "trade": trade,
+        "off_book": true,
+    })))
+}
+
+// Orders the engine never answered and didn't have when asked
+async fn lost_orders(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "lost": state.watchdog.lost() }))
+}
+
+// Busiest symbols over the last few days, from the engine's capacity reports
+async fn get_capacity(
+    State(state): State<AppState>,
+    Query(query): Query<CapacityQuery>,
+) -> Json<CapacityView> {
+    let days = query.days.unwrap_or(capacity::RETAINED_DAYS);
+    let top = query.top.unwrap_or(10);
+    Json(state.capacity.top(days, query.sort, top))
+}
+
+// Signed slice of the audit trail for a user and/or symbol over a date range
+async fn export_audit(
+    State(state): State<AppState>,
+    Query(filter): Query<AuditFilter>,
+) -> Json<AuditExport> {
+    Json(state.audit.export(filter, &state.audit_key))
+}
+
+// Re-walks the whole stored chain
+async fn verify_audit(State(state): State<AppState>) -> Json<serde_json::Value> {
+    match state.audit.verify_chain() {
+        Ok(()) => Json(serde_json::json!({ "status": "ok" })),
+        Err(e) => Json(serde_json::json!({ "status": "broken", "error": e.to_string() })),
+    }
+}
+
+// Which replica minted an id, and when
+async fn decode_id(Path(id): Path<u64>) -> Json<ids::IdParts> {
+    Json(ids::parse(id))
+}
+
+// Queue depth and batching of the engine-bound publisher
+async fn publisher_metrics(State(state): State<AppState>) -> Result<Json<PublisherMetrics>> {
+    let metrics = state.publisher.metrics().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(metrics))
+}
+
+// Engine messages dropped before reaching settlement
+async fn rejected_payloads(State(state): State<AppState>) -> Json<RejectedCounts> {
+    Json(state.payload_guard.counts())
+}
+
+// Settlement failures parked for an admin to look at
+async fn list_dead_letters(
+    State(state): State<AppState>,
+    Query(filter): Query<DeadLetterFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<Json<Page<settlement::DeadLetter>>> {
+    let letters = state.dead_letters.list().into_iter().filter(|letter| {
+        filter.status.is_none_or(|status| letter.status == status)
+            && filter
+                .user
+                .as_ref()
+                .is_none_or(|user| &letter.event.buyer == user || &letter.event.seller == user)
+            && filter
+                .symbol
+                .as_ref()
+                .is_none_or(|symbol| &letter.event.symbol == symbol)
+    });
+    let letters =
+        pagination::paginate(letters, |letter| letter.id, &page).map_err(|_| invalid_cursor())?;
+    Ok(Json(letters))
+}
+
+fn dead_letter_status(e: DeadLetterError) -> (StatusCode, String) {
+    match e {
+        DeadLetterError::NotFound => (StatusCode::NOT_FOUND, "no such dead letter".to_string()),
+        DeadLetterError::NotPending => (
+            StatusCode::CONFLICT,
+            "dead letter was already discarded".to_string(),
+        ),
+        DeadLetterError::Settlement(e) => (StatusCode::CONFLICT, e.to_string()),
+    }
+}
+
+// Run a dead letter back through settlement
+async fn retry_dead_letter(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<serde_json::Value>> {
+    let (event, fees) = state
+        .dead_letters
+        .retry(id, state.users.as_ref(), &state.fees)
+        .map_err(dead_letter_status)?;
+    audit_trade(&state, &event);
+    notify_trade(&state, &event);
+    state.trades.record(event, fees);
+    Ok(Json(serde_json::json!({
+        "status": "settled"
+    })))
+}
+
+fn bust_status(e: BustError) -> (StatusCode, String) {
+    match e {
+        BustError::NotFound => (StatusCode::NOT_FOUND, "no such trade".to_string()),
+        BustError::AlreadyBusted => (StatusCode::CONFLICT, "trade was already busted".to_string()),
+        BustError::Settlement(e) => (StatusCode::CONFLICT, e.to_string()),
+    }
+}
+
+// Reverse a settled trade that shouldn't stand. The orders it filled stay
+// filled; only the cash, shares and fees move back.
+async fn bust_trade(
+    State(state): State<AppState>,
+    Path(trade_id): Path<u64>,
+    Json(request): Json<BustRequest>,
+) -> Result<Json<SettledTrade>> {
+    if request.reason.trim().is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "a bust reason is required",
+        )
+            .into());
+    }
+    let trade = state
+        .trades
+        .bust(trade_id, state.users.as_ref(), &state.fees)
+        .map_err(bust_status)?;
+    let event = &trade.event;
+    state.audit.append(
+        AuditKind::TradeBust,
+        &[&event.buyer, &event.seller],
+        Some(&event.symbol),
+        serde_json::json!({
+            "trade": event,
+            "fees": trade.fees,
+            "reason": request.reason,
+        }),
+    );
+    for (user, side) in [(&event.buyer, "buy"), (&event.seller, "sell")] {
+        state.notifier.dispatch(
+            user,
+            NotificationKind::TradeBust,
+            serde_json::json!({ "side": side, "trade": event, "reason": request.reason }),
+        );
+    }
+    Ok(Json(trade))
+}
+
+async fn discard_dead_letter(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(request): Json<DiscardRequest>,
+) -> Result<Json<settlement::DeadLetter>> {
+    if request.reason.trim().is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "a discard reason is required",
+        )
+            .into());
+    }
+    let letter = state
+        .dead_letters
+        .discard(id, request.reason)
+        .map_err(dead_letter_status)?;
+    let action = serde_json::json!({
+        "action": "trade_discarded",
+        "reason": letter.discard_reason,
+        "trade": letter.event,
+    });
+    state.audit.append(
+        AuditKind::AdminAction,
+        &[&letter.event.buyer, &letter.event.seller],
+        Some(&letter.event.symbol),
+        action.clone(),
+    );
+    for user in [&letter.event.buyer, &letter.event.seller] {
+        state
+            .notifier
+            .dispatch(user, NotificationKind::AdminAction, action.clone());
+    }
+    Ok(Json(letter))
+}
+
+#[cfg(not(feature = "embedded_engine"))]
+async fn listen_outbound(client: Client, state: AppState) {
+    // Get PubSub connection
+    let mut pubsub = client
+        .get_async_pubsub()
+        .await
+        .expect("failed to open PubSub connection");
+
+    // Subscribe to outbound channel and the book updates
+    pubsub
+        .subscribe(&[ORDER_OUTBOUND_CHANNEL, MARKET_DATA_CHANNEL])
+        .await
+        .expect("failed to subscribe");
+
+    let mut stream = pubsub.on_message();
+
+    println!(
+        "📡 Listening for trade events on {}",
+        ORDER_OUTBOUND_CHANNEL
+    );
+
+    while let Some(msg) = stream.next().await {
+        let payload: String = match msg.get_payload() {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Failed to parse message: {:?}", e);
+                continue;
+            }
+        };
+
+        if msg.get_channel_name() == MARKET_DATA_CHANNEL {
+            handle_market_data(&payload, &state);
+        } else {
+            handle_outbound(&payload, &state);
+        }
+    }
+}
+
+// A message for the engine, in the envelope it reads them in.
+fn sealed<T: Serialize + ?Sized>(state: &AppState, kind: &str, payload: &T) -> String {
+    envelope::seal(kind, state.clock.now_millis(), payload)
+}
+
+// Applies one message from the engine's outbound channel, `raw` being the
+// message in its envelope.
+fn handle_outbound(raw: &str, state: &AppState) {
+    let guard = &state.payload_guard;
+    if let Err(rejection) = guard.admit(raw) {
+        eprintln!(
+            "Dropped outbound message ({:?}, {} bytes)",
+            rejection,
+            raw.len()
+        );
+        return;
+    }
+    let envelope = match envelope::open(raw) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            guard.record_invalid();
+            eprintln!("Dropped outbound message ({}): {}", e, raw);
+            return;
+        }
+    };
+    let payload = envelope.payload.get();
+
+    match serde_json::from_str::<orderbook::EngineEvent>(payload) {
+        Ok(orderbook::EngineEvent::Trade(event)) => apply_trade(state, event),
+        Ok(orderbook::EngineEvent::Accepted(accepted)) => {
+            let order = &accepted.order;
+            if let Some(client_order_id) = order.client_order_id {
+                state.watchdog.answered(client_order_id);
+            }
+            state.orders.accepted(order);
+            println!(
+                "Order {} accepted by engine: {} {:?} {} @ {:?} for {}",
+                order.order_id, order.symbol, order.side, order.quantity, order.price, order.user
+            );
+        }
+        Ok(orderbook::EngineEvent::Rejected(rejected)) => {
+            let order = serde_json::to_value(&rejected.order).unwrap_or_default();
+            apply_rejection(state, &rejected.reason, rejected.error, order);
+        }
+        Ok(orderbook::EngineEvent::Cancelled(cancelled)) => {
+            let order = &cancelled.order;
+            state.orders.closed(order.order_id);
+            println!(
+                "Order {} cancelled by engine ({}): {} {} left for {}",
+                order.order_id, cancelled.reason, order.symbol, order.quantity, order.user
+            );
+        }
+        Ok(orderbook::EngineEvent::Expired(expired)) => {
+            let order = &expired.order;
+            state.orders.closed(order.order_id);
+            println!(
+                "Order {} expired: {} {} left for {}",
+                order.order_id, order.symbol, order.quantity, order.user
+            );
+        }
+        // a type this build doesn't know, or one of the engine's own reports
+        Ok(orderbook::EngineEvent::Unknown) => handle_engine_report(payload, state, true),
+        Err(_) => handle_engine_report(payload, state, false),
+    }
+}
+
+// Applies one book update from the market_data channel, `raw` being the
+// update in its envelope.
+fn handle_market_data(raw: &str, state: &AppState) {
+    let guard = &state.payload_guard;
+    if let Err(rejection) = guard.admit(raw) {
+        eprintln!("Dropped book update ({:?}, {} bytes)", rejection, raw.len());
+        return;
+    }
+    let update = envelope::open(raw)
+        .map_err(|e| e.to_string())
+        .and_then(|envelope| {
+            serde_json::from_str::<BookUpdate>(envelope.payload.get()).map_err(|e| e.to_string())
+        });
+    match update {
+        // a kind of update this gateway doesn't know
+        Ok(update) if update.kind != "book_delta" => {}
+        Ok(update) if !state.market_data.apply_book_update(&update) => {
+            eprintln!(
+                "⚠️ Copy of the {} book out of step with the engine at update {}",
+                update.symbol, update.seq
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            guard.record_invalid();
+            eprintln!("Dropped book update ({}): {}", e, raw);
+        }
+    }
+}
+
+fn apply_trade(state: &AppState, event: TradeEvent) {
+    println!("Received trade event: {:?}", event);
+    state.orders.traded(&event);
+
+    state.market_data.record_trade(&event.symbol, event.price);
+    match settlement::settle_trade(state.users.as_ref(), &state.fees, &event) {
+        Ok(fees) => {
+            audit_trade(state, &event);
+            notify_trade(state, &event);
+            state.trades.record(event, fees);
+        }
+        Err(e) => {
+            eprintln!("Failed to settle trade event {:?}: {}", event, e);
+            state.dead_letters.push(event, &e);
+            let depth = state.dead_letters.depth();
+            if depth >= settlement::DLQ_WARN_DEPTH {
+                eprintln!("⚠️ Settlement dead-letter queue holds {} events", depth);
+            }
+        }
+    }
+}
+
+fn apply_rejection(
+    state: &AppState,
+    reason: &str,
+    error: Option<orderbook::ValidationError>,
+    order: serde_json::Value,
+) {
+    println!("Order rejected by engine ({}): {}", reason, order);
+    if let Some(client_order_id) = order["client_order_id"].as_u64() {
+        state.watchdog.answered(client_order_id);
+    }
+    if let Some(user) = order["user"].as_str() {
+        let mut details = serde_json::json!({
+            "reason": reason,
+            "order": order,
+        });
+        // what exactly was wrong, for an order that failed validation
+        if let Some(error) = error {
+            details["error"] = serde_json::to_value(error).unwrap_or_default();
+        }
+        state.audit.append(
+            AuditKind::OrderRejected,
+            &[user],
+            order["symbol"].as_str(),
+            details.clone(),
+        );
+        state
+            .notifier
+            .dispatch(user, NotificationKind::OrderRejected, details);
+    }
+}
+
+// Everything that isn't an EngineEvent: untagged trades from engines that
+// predate it, rejections of orders that never parsed, and the engine's
+// reports on itself and its books.
+fn handle_engine_report(payload: &str, state: &AppState, tagged: bool) {
+    let guard = &state.payload_guard;
+    match serde_json::from_str::<TradeEvent>(payload) {
+        Ok(event) => apply_trade(state, event),
+        Err(e) => match serde_json::from_str::<OrderRejected>(payload) {
+            Ok(rejected) => apply_rejection(state, &rejected.reason, None, rejected.order),
+            Err(_) if let Ok(halt) = serde_json::from_str::<IntegrityHalt>(payload) => {
+                eprintln!(
+                    "🚨 Engine halted {} at seq {}: {} (snapshot {})",
+                    halt.symbol, halt.seq, halt.reason, halt.snapshot_hash
+                );
+            }
+            Err(_)
+                if let Ok(report) = serde_json::from_str::<CapacityReport>(payload)
+                    && report.kind == "capacity_report" =>
+            {
+                state.capacity.record(report);
+            }
+            Err(_)
+                if let Ok(halted) = serde_json::from_str::<SymbolHalted>(payload)
+                    && halted.kind == "symbol_halted" =>
+            {
+                eprintln!(
+                    "⏸️ Circuit breaker halted {} at seq {} after a trade at {}",
+                    halted.symbol, halted.seq, halted.price
+                );
+            }
+            Err(_)
+                if let Ok(amended) = serde_json::from_str::<OrderAmended>(payload)
+                    && amended.kind == "order_amended" =>
+            {
+                state.orders.amended(amended.order_id, amended.quantity);
+                println!(
+                    "Order {} in {} amended by engine to {} @ {}, expiring {:?}",
+                    amended.order_id,
+                    amended.symbol,
+                    amended.quantity,
+                    amended.price,
+                    amended.expires_at
+                );
+            }
+            Err(_)
+                if let Ok(chunk) = serde_json::from_str::<SnapshotChunk>(payload)
+                    && chunk.kind == "snapshot_chunk" =>
+            {
+                state.snapshots.chunk(chunk);
+            }
+            Err(_)
+                if let Ok(end) = serde_json::from_str::<SnapshotEnd>(payload)
+                    && end.kind == "snapshot_end" =>
+            {
+                state.snapshots.end(end);
+            }
+            Err(_)
+                if let Ok(open) = serde_json::from_str::<OpenOrders>(payload)
+                    && open.kind == "open_orders" =>
+            {
+                resolve_unanswered(state, &open.user, &open.orders);
+                state.open_order_queries.answer(&open.user, &open.orders);
+            }
+            Err(_)
+                if let Ok(rejected) = serde_json::from_str::<RequestRejected>(payload)
+                    && matches!(rejected.kind.as_str(), "cancel_rejected" | "amend_rejected") =>
+            {
+                println!(
+                    "Engine refused {} for {} order {}: {}",
+                    rejected.kind, rejected.symbol, rejected.order_id, rejected.reason
+                );
+            }
+            // newer engines may publish events this gateway can't read yet;
+            // they are skipped, not counted against the stream
+            Err(_) if tagged => {
+                println!("Skipped engine event of an unknown type: {}", payload);
+            }
+            Err(_) => {
+                guard.record_invalid();
+                println!(
+                    "Failed to deserialize TradeEvent: {:?}, raw: {}",
+                    e, payload
+                );
+            }
+        },
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, to_bytes},
+        http::{Request, header},
+    };
+    use market_data::MarketData;
+    use repository::UserRepository;
+    use state::test_support::TestAppState;
+    use tower::ServiceExt;
+
+    async fn send(
+        app: &TestAppState,
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = app.router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        (status, json)
+    }
+
+    async fn signup(app: &TestAppState, email: &str) {
+        let (status, _) = send(
+            app,
+            "POST",
+            "/user",
+            Some(serde_json::json!({ "email": email })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // a message as the engine publishes it
+    fn from_engine(payload: &str) -> String {
+        let payload = serde_json::value::RawValue::from_string(payload.to_string()).unwrap();
+        envelope::seal("test", 0, &payload)
+    }
+
+    fn order_json(user: &str) -> serde_json::Value {
+        serde_json::json!({
+            "symbol": "AAPL",
+            "side": "Buy",
+            "quantity": "5",
+            "price": "101.5",
+            "user": user,
+        })
+    }
+
+    // What was sent to the engine, less the client_order_id place_order gives
+    // each order.
+    fn sent_to_engine(app: &TestAppState) -> Vec<serde_json::Value> {
+        let mut messages = app.publisher.messages(ORDER_INBOUND_CHANNEL);
+        for message in &mut messages {
+            if let Some(fields) = message.as_object_mut() {
+                fields.remove("client_order_id");
+            }
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let app = TestAppState::new();
+        let (status, body) = send(&app, "GET", "/health", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_create_and_get_user() {
+        let app = TestAppState::new();
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/user",
+            Some(serde_json::json!({ "email": "a@test.com" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "a@test.com");
+
+        let (status, body) = send(&app, "GET", "/user/a@test.com", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["current_balance"]["amount"], "5000.00");
+        assert_eq!(body["faucet_claims_remaining"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_rejects_malformed_body() {
+        let app = TestAppState::new();
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/user",
+            Some(serde_json::json!({ "name": "a" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(app.users.all().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_unknown_user() {
+        let app = TestAppState::new();
+        let (status, _) = send(&app, "GET", "/user/nobody@test.com", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_all_users() {
+        let app = TestAppState::new();
+        let (_, body) = send(&app, "GET", "/users", None).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "items": [], "next_cursor": null })
+        );
+
+        signup(&app, "b@test.com").await;
+        signup(&app, "a@test.com").await;
+        signup(&app, "c@test.com").await;
+        let (status, body) = send(&app, "GET", "/users?limit=2", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["items"][0]["email"], "a@test.com");
+        assert_eq!(body["items"][1]["email"], "b@test.com");
+        assert_eq!(body["next_cursor"], "b@test.com");
+
+        let (_, body) = send(&app, "GET", "/users?limit=2&cursor=b@test.com", None).await;
+        assert_eq!(body["items"].as_array().unwrap().len(), 1);
+        assert_eq!(body["next_cursor"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_publishes_to_engine() {
+        let app = TestAppState::new();
+        let (status, body) = send(&app, "POST", "/place_order", Some(order_json("a"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "submitted");
+        assert_eq!(sent_to_engine(&app), vec![order_json("a")]);
+        assert!(body["client_order_id"].is_u64());
+        assert_eq!(
+            app.publisher.messages(ORDER_INBOUND_CHANNEL)[0]["client_order_id"],
+            body["client_order_id"]
+        );
+        let envelope = &app.publisher.envelopes(ORDER_INBOUND_CHANNEL)[0];
+        assert_eq!(envelope["type"], "order");
+        assert_eq!(envelope["ts"], state::test_support::TEST_NOW);
+    }
+
+    #[tokio::test]
+    async fn test_placed_order_reads_as_an_engine_order() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["side"] = serde_json::json!("sell");
+        order["time_in_force"] = serde_json::json!("IOC");
+        let (status, _) = send(&app, "POST", "/place_order", Some(order)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let published = sent_to_engine(&app).remove(0);
+        let order: orderbook::Order = serde_json::from_value(published).unwrap();
+        assert_eq!(order.side, Side::Sell);
+        assert_eq!(order.time_in_force, TimeInForce::Ioc);
+        assert_eq!(order.quantity, Qty::shares(5));
+        assert_eq!(order.price, Some(Price::cents(10150)));
+
+        // a side neither crate knows is refused before it is published
+        let mut hold = order_json("a");
+        hold["side"] = serde_json::json!("Hold");
+        let (status, _) = send(&app, "POST", "/place_order", Some(hold)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sent_to_engine(&app).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_price_formats() {
+        let app = TestAppState::new();
+        // a decimal number, or an integer in cents as before prices had
+        // decimals, both go out as the same decimal string
+        for price in [serde_json::json!(101.5), serde_json::json!(10150)] {
+            let mut order = order_json("a");
+            order["price"] = price;
+            let (status, _) = send(&app, "POST", "/place_order", Some(order)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert_eq!(sent_to_engine(&app), vec![order_json("a"), order_json("a")]);
+
+        let mut too_fine = order_json("a");
+        too_fine["price"] = serde_json::json!("101.00001");
+        let (status, _) = send(&app, "POST", "/place_order", Some(too_fine)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_passes_stop_limit_through() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["price"] = serde_json::json!("101");
+        order["stop_price"] = serde_json::json!("100");
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sent_to_engine(&app), vec![order]);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_fractional_quantity() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["quantity"] = serde_json::json!("0.5");
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // integers still count whole shares and go out as decimal strings
+        let mut whole = order_json("a");
+        whole["quantity"] = serde_json::json!(5);
+        let (status, _) = send(&app, "POST", "/place_order", Some(whole)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sent_to_engine(&app), vec![order, order_json("a")]);
+
+        let mut too_fine = order_json("a");
+        too_fine["quantity"] = serde_json::json!("0.00001");
+        let (status, _) = send(&app, "POST", "/place_order", Some(too_fine)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn test_engine_trade_fields_survive_the_gateway() {
+        let payload = serde_json::json!({
+            "trade_id": 7,
+            "buyer": "buyer@test.com",
+            "seller": "seller@test.com",
+            "symbol": "AAPL",
+            "quantity": "1",
+            "price": "100",
+            "taker_side": "Sell",
+            "timestamp": 1_760_486_400_000i64,
+            "maker_received_at": 1_760_486_399_250i64,
+            "maker_order_id": 3,
+            "maker_remaining": "4",
+            "taker_order_id": 9,
+            "buyer_tags": ["momentum"],
+            "seller_tags": [],
+        });
+        let event: TradeEvent = serde_json::from_value(payload.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&event).unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_depth_from_book_updates() {
+        let app = TestAppState::new();
+        let delta = |side: &str, price: &str, quantity: &str| serde_json::json!({ "side": side, "price": price, "quantity": quantity, "orders": 1 });
+        let update = serde_json::json!({
+            "type": "book_delta",
+            "symbol": "AAPL",
+            "seq": 1,
+            "deltas": [
+                delta("Buy", "100.10", "1"),
+                delta("Buy", "99.90", "2"),
+                delta("Buy", "99.80", "3"),
+                delta("Sell", "100.20", "4"),
+                delta("Sell", "100.60", "5"),
+            ],
+            "checksum": 0,
+        });
+        handle_market_data(&from_engine(&update.to_string()), &app.state);
+        let bands = |levels: &serde_json::Value| -> Vec<(String, String, u64)> {
+            levels
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|l| {
+                    (
+                        l["price"].as_str().unwrap().to_string(),
+                        l["quantity"].as_str().unwrap().to_string(),
+                        l["orders"].as_u64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+        let band =
+            |price: &str, quantity: &str, orders| (price.to_string(), quantity.to_string(), orders);
+
+        let (status, body) = send(&app, "GET", "/orderbook/AAPL?levels=1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bands(&body["bids"]), vec![band("100.1", "1", 1)]);
+        assert_eq!(bands(&body["asks"]), vec![band("100.2", "4", 1)]);
+
+        let (status, body) = send(&app, "GET", "/orderbook/AAPL?aggregation=0.25", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            bands(&body["bids"]),
+            vec![band("100", "1", 1), band("99.75", "5", 2)]
+        );
+        // nothing between 100.25 and 100.50
+        assert_eq!(
+            bands(&body["asks"]),
+            vec![band("100.25", "4", 1), band("100.75", "5", 1)]
+        );
+
+        for bad in ["0", "-0.25"] {
+            let uri = format!("/orderbook/AAPL?aggregation={}", bad);
+            let (status, _) = send(&app, "GET", &uri, None).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{bad}");
+        }
+        let (status, _) = send(&app, "GET", "/orderbook/MSFT", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_book_snapshot_reassembled_from_engine_chunks() {
+        let app = TestAppState::new();
+        let orders: Vec<serde_json::Value> = (1..=250)
+            .map(|i| {
+                let mut order = order_json("a");
+                order["order_id"] = serde_json::json!(i);
+                order["quantity"] = serde_json::json!("1");
+                serde_json::json!({ "price": "1", "position": i - 1, "order": order })
+            })
+            .collect();
+        let placed: Vec<orderbook::PlacedOrder> =
+            serde_json::from_value(serde_json::json!(orders)).unwrap();
+        let checksum = orderbook::orders_checksum(&placed);
+
+        // answers the `nth` snapshot request the way the engine would, with
+        // its checksum off by `skew`
+        let (app, orders) = (&app, &orders);
+        let engine = |nth: usize, skew: u32| async move {
+            let request = loop {
+                match sent_to_engine(app).get(nth) {
+                    Some(request) => break request.clone(),
+                    None => tokio::task::yield_now().await,
+                }
+            };
+            let request_id = request["request_id"].as_u64().unwrap();
+            for (index, chunk) in orders.chunks(100).enumerate() {
+                let chunk = serde_json::json!({
+                    "type": "snapshot_chunk",
+                    "symbol": "AAPL",
+                    "request_id": request_id,
+                    "index": index,
+                    "orders": chunk,
+                });
+                handle_outbound(&from_engine(&chunk.to_string()), &app.state);
+            }
+            let end = serde_json::json!({
+                "type": "snapshot_end",
+                "symbol": "AAPL",
+                "request_id": request_id,
+                "found": true,
+                "chunks": 3,
+                "checksum": checksum ^ skew,
+                "book_update_seq": 9,
+                "last_order_id": 250,
+            });
+            handle_outbound(&from_engine(&end.to_string()), &app.state);
+        };
+
+        let uri = "/admin/book/AAPL/snapshot";
+        let ((status, body), ()) = tokio::join!(send(app, "GET", uri, None), engine(0, 0));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["orders"].as_array().unwrap().len(), 250);
+        assert_eq!(body["orders"][249]["order"]["order_id"], 250);
+        assert_eq!(body["book_update_seq"], 9);
+
+        let ((status, _), ()) = tokio::join!(send(app, "GET", uri, None), engine(1, 1));
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_when_publisher_is_down() {
+        let app = TestAppState::new();
+        app.publisher.fail();
+        let (status, _) = send(&app, "POST", "/place_order", Some(order_json("a"))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_rejects_malformed_order() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["quantity"] = serde_json::json!(-5);
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // a notional that doesn't fit in an i64
+        order["quantity"] = serde_json::json!(1_000_000);
+        order["price"] = serde_json::json!("900000000000000");
+        let (status, _) = send(&app, "POST", "/place_order", Some(order)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(sent_to_engine(&app).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_place_order_checked_under_symbol_rules() {
+        let mut app = TestAppState::new();
+        let rules = orderbook::SymbolRules {
+            tick_size: Price::cents(5),
+            ..orderbook::SymbolRules::default()
+        };
+        app.state.symbol_rules = Arc::new(HashMap::from([("AAPL".to_string(), rules)]));
+
+        let mut order = order_json("a");
+        order["price"] = serde_json::json!("101.52");
+        let (status, body) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body,
+            serde_json::json!({ "code": "off_tick", "price": "101.52", "tick_size": "0.05" })
+        );
+
+        // other symbols keep the defaults
+        order["symbol"] = serde_json::json!("MSFT");
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sent_to_engine(&app), vec![order]);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_by_notional() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order.as_object_mut().unwrap().remove("quantity");
+        order["price"] = serde_json::Value::Null;
+        order["notional"] = serde_json::json!("1000");
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // exactly one of quantity and notional, and a notional order has no
+        // price
+        let mut both = order.clone();
+        both["quantity"] = serde_json::json!("5");
+        let mut neither = order.clone();
+        neither.as_object_mut().unwrap().remove("notional");
+        let mut limit = order.clone();
+        limit["price"] = serde_json::json!("101");
+        for (invalid, code) in [
+            (both, "quantity_and_notional"),
+            (neither, "zero_quantity"),
+            (limit, "unexpected_price"),
+        ] {
+            let (status, body) = send(&app, "POST", "/place_order", Some(invalid)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["code"], code);
+        }
+        assert_eq!(sent_to_engine(&app), vec![order]);
+    }
+
+    #[tokio::test]
+    async fn test_place_order_with_tags() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["tags"] = serde_json::json!(["momentum"]);
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sent_to_engine(&app), vec![order.clone()]);
+
+        order["tags"] = serde_json::json!(vec!["t"; orderbook::MAX_ORDER_TAGS + 1]);
+        let (status, body) = send(&app, "POST", "/place_order", Some(order)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "invalid_tags");
+    }
+
+    #[tokio::test]
+    async fn test_price_collar() {
+        let app = TestAppState::new();
+        let order = |side: &str, price: &str| {
+            let mut order = order_json("a");
+            order["side"] = serde_json::json!(side);
+            order["price"] = serde_json::json!(price);
+            order
+        };
+
+        // no book yet, so nothing to measure against
+        let (status, _) = send(&app, "POST", "/place_order", Some(order("Buy", "500"))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let update = serde_json::json!({
+            "type": "book_delta",
+            "symbol": "AAPL",
+            "seq": 1,
+            "deltas": [
+                { "side": "Buy", "price": "100", "quantity": "1", "orders": 1 },
+                { "side": "Sell", "price": "101", "quantity": "1", "orders": 1 },
+            ],
+            "checksum": 0,
+        });
+        handle_market_data(&from_engine(&update.to_string()), &app.state);
+
+        for (side, within, beyond, touch) in [
+            ("Buy", "111.1", "111.11", "101"),
+            ("Sell", "90", "89.99", "100"),
+        ] {
+            let (status, _) = send(&app, "POST", "/place_order", Some(order(side, within))).await;
+            assert_eq!(status, StatusCode::OK);
+            let (status, body) =
+                send(&app, "POST", "/place_order", Some(order(side, beyond))).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(
+                body,
+                serde_json::json!({
+                    "code": "outside_price_collar",
+                    "price": beyond,
+                    "touch": touch,
+                    "collar_percent": 10,
+                })
+            );
+        }
+        assert_eq!(sent_to_engine(&app).len(), 3);
+
+        let mut overridden = order("Sell", "1");
+        overridden["allow_far_price"] = serde_json::json!(true);
+        let (status, _) = send(&app, "POST", "/place_order", Some(overridden)).await;
+        assert_eq!(status, StatusCode::OK);
+        let published = sent_to_engine(&app);
+        assert_eq!(published[3], order("Sell", "1"));
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_orders_chased_through_open_orders() {
+        let app = TestAppState::new();
+        let mut client_ids = Vec::new();
+        for user in ["a", "a", "b"] {
+            let (_, body) = send(&app, "POST", "/place_order", Some(order_json(user))).await;
+            client_ids.push(body["client_order_id"].as_u64().unwrap());
+        }
+        // b's order is acked in time
+        let ack = serde_json::json!({
+            "type": "order_accepted",
+            "order": {
+                "order_id": 1,
+                "user": "b",
+                "side": "Buy",
+                "symbol": "AAPL",
+                "price": "101.5",
+                "quantity": "5",
+                "client_order_id": client_ids[2],
+            },
+        });
+        handle_outbound(&from_engine(&ack.to_string()), &app.state);
+
+        query_unanswered_orders(&app.state).await;
+        assert_eq!(sent_to_engine(&app).len(), 3);
+        app.clock
+            .set(state::test_support::TEST_NOW + watchdog::DEFAULT_ACK_TIMEOUT_MILLIS);
+        query_unanswered_orders(&app.state).await;
+        assert_eq!(
+            sent_to_engine(&app)[3],
+            serde_json::json!({ "type": "open_orders", "user": "a" })
+        );
+
+        // the engine has the second of a's orders, partly filled, and never
+        // saw the first
+        let open = serde_json::json!({
+            "type": "open_orders",
+            "user": "a",
+            "orders": [{
+                "order_id": 2,
+                "user": "a",
+                "symbol": "AAPL",
+                "side": "Buy",
+                "price": "101.5",
+                "remaining_quantity": "3",
+                "state": "Open",
+                "time_in_force": "GTC",
+                "received_at": state::test_support::TEST_NOW,
+                "expires_at": null,
+                "queue_position": 0,
+                "client_order_id": client_ids[1],
+            }],
+        });
+        handle_outbound(&from_engine(&open.to_string()), &app.state);
+
+        let repaired = app.state.orders.get(2).unwrap();
+        assert_eq!(
+            (repaired.order.quantity, repaired.status),
+            (Qty::shares(3), OrderStatus::Open)
+        );
+        let lost = app.state.notifier.list("a");
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].kind, NotificationKind::OrderLost);
+        assert_eq!(lost[0].payload["order"]["client_order_id"], client_ids[0]);
+        assert!(app.state.notifier.list("b").is_empty());
+
+        let (_, body) = send(&app, "GET", "/admin/orders/lost", None).await;
+        assert_eq!(body["lost"], 1);
+        // nothing is left to chase
+        query_unanswered_orders(&app.state).await;
+        assert_eq!(sent_to_engine(&app).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_amend_order() {
+        let app = TestAppState::new();
+        let amend = |terms: serde_json::Value| {
+            let mut body = serde_json::json!({ "symbol": "AAPL", "user": "a" });
+            body.as_object_mut()
+                .unwrap()
+                .extend(terms.as_object().unwrap().clone());
+            body
+        };
+        let later = state::test_support::TEST_NOW + 60_000;
+        for terms in [
+            serde_json::json!({ "price": "101", "quantity": "3" }),
+            serde_json::json!({ "expires_at": later }),
+            serde_json::json!({ "expires_at": null }),
+            serde_json::json!({ "time_in_force": "GTC" }),
+        ] {
+            let (status, _) = send(&app, "PATCH", "/order/7", Some(amend(terms))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let published = sent_to_engine(&app);
+        assert_eq!(published.len(), 4);
+        assert_eq!(
+            published[0],
+            serde_json::json!({
+                "type": "amend",
+                "order_id": 7,
+                "symbol": "AAPL",
+                "user": "a",
+                "price": "101",
+                "quantity": "3",
+            })
+        );
+        // an explicit null goes through, so the engine takes the expiry away
+        assert_eq!(published[2]["expires_at"], serde_json::Value::Null);
+        assert!(published[2].as_object().unwrap().contains_key("expires_at"));
+        assert!(!published[0].as_object().unwrap().contains_key("expires_at"));
+
+        for terms in [
+            serde_json::json!({}),
+            serde_json::json!({ "time_in_force": "IOC" }),
+            serde_json::json!({ "time_in_force": "FOK" }),
+            serde_json::json!({ "expires_at": state::test_support::TEST_NOW }),
+        ] {
+            let (status, _) = send(&app, "PATCH", "/order/7", Some(amend(terms))).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert_eq!(sent_to_engine(&app).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_reduce_order() {
+        let app = TestAppState::new();
+        let accepted = |order_id, price: serde_json::Value, time_in_force| {
+            let ack = serde_json::json!({
+                "type": "order_accepted",
+                "order": {
+                    "order_id": order_id,
+                    "user": "a",
+                    "side": "Buy",
+                    "symbol": "AAPL",
+                    "price": price,
+                    "quantity": "10",
+                    "time_in_force": time_in_force,
+                },
+            });
+            handle_outbound(&from_engine(&ack.to_string()), &app.state);
+        };
+        accepted(1, serde_json::json!("100"), "GTC");
+        accepted(2, serde_json::Value::Null, "GTC");
+        accepted(3, serde_json::json!("100"), "IOC");
+        let reduce = |quantity: &str| Some(serde_json::json!({ "quantity": quantity }));
+
+        let (status, body) = send(&app, "POST", "/order/1/reduce", reduce("4")).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["remaining"], "6");
+        assert_eq!(
+            sent_to_engine(&app),
+            vec![serde_json::json!({
+                "type": "reduce",
+                "symbol": "AAPL",
+                "order_id": 1,
+                "quantity": "4",
+            })]
+        );
+
+        // by more than is left, the engine cancels it
+        let (status, body) = send(&app, "POST", "/order/1/reduce", reduce("25")).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["remaining"], "0");
+
+        // market and IOC orders never rest; nor does one that has since filled
+        let trade = r#"{"type":"trade","buyer":"a","seller":"b","symbol":"AAPL","quantity":10,"price":100,"taker_side":"sell","maker_order_id":1,"taker_order_id":9}"#;
+        handle_outbound(&from_engine(trade), &app.state);
+        for id in [1, 2, 3] {
+            let uri = format!("/order/{id}/reduce");
+            let (status, _) = send(&app, "POST", &uri, reduce("1")).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+        }
+        let (status, _) = send(&app, "POST", "/order/4/reduce", reduce("1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(sent_to_engine(&app).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exposure_from_resting_orders() {
+        let app = TestAppState::new();
+        signup(&app, "a@test.com").await;
+        signup(&app, "b@test.com").await;
+        for (email, shares) in [("a@test.com", 5), ("b@test.com", 10)] {
+            app.users.update(email, &mut |user| {
+                user.stocks.insert("AAPL".to_string(), Qty::shares(shares));
+            });
+        }
+        let accepted = |order_id, side, price, quantity| {
+            let ack = serde_json::json!({
+                "type": "order_accepted",
+                "order": {
+                    "order_id": order_id,
+                    "user": "a@test.com",
+                    "side": side,
+                    "symbol": "AAPL",
+                    "price": price,
+                    "quantity": quantity,
+                },
+            });
+            handle_outbound(&from_engine(&ack.to_string()), &app.state);
+        };
+        accepted(1, "Buy", "100", "10");
+        accepted(2, "Sell", "101", "3");
+        // four of the buy's ten fill
+        let trade = r#"{"type":"trade","buyer":"a@test.com","seller":"b@test.com","symbol":"AAPL","quantity":"4","price":"100","taker_side":"sell","maker_order_id":1,"taker_order_id":9}"#;
+        handle_outbound(&from_engine(trade), &app.state);
+
+        let (status, body) = send(&app, "GET", "/user/a@test.com/exposure", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["symbols"]["AAPL"],
+            serde_json::json!({
+                "buy_quantity": "6",
+                "buy_notional": { "amount": "600.00", "currency": "USD" },
+                "sell_quantity": "3",
+                "sell_notional": { "amount": "303.00", "currency": "USD" },
+                "position": "9",
+                "position_if_buys_fill": "15",
+                "position_if_sells_fill": "6",
+            })
+        );
+        assert_eq!(body["reserved_cash"]["amount"], "600.00");
+        assert_eq!(body["reserved_shares"]["AAPL"], "3");
+        assert_eq!(body["balance"]["amount"], "4600.00");
+        assert_eq!(body["cash_if_buys_fill"]["amount"], "4000.00");
+        assert!(body.get("discrepancy").is_none());
+
+        // the engine has the sell partly filled and an order this gateway
+        // never heard of
+        let app = &app;
+        let engine = async {
+            let query = loop {
+                match sent_to_engine(app).first() {
+                    Some(query) => break query.clone(),
+                    None => tokio::task::yield_now().await,
+                }
+            };
+            assert_eq!(
+                query,
+                serde_json::json!({ "type": "open_orders", "user": "a@test.com" })
+            );
+            let view = |order_id, side, price, remaining| {
+                serde_json::json!({
+                    "order_id": order_id,
+                    "user": "a@test.com",
+                    "symbol": "AAPL",
+                    "side": side,
+                    "price": price,
+                    "remaining_quantity": remaining,
+                    "state": "Open",
+                    "time_in_force": "GTC",
+                    "received_at": 0,
+                    "expires_at": null,
+                    "queue_position": 0,
+                })
+            };
+            let open = serde_json::json!({
+                "type": "open_orders",
+                "user": "a@test.com",
+                "orders": [
+                    view(1, "Buy", "100", "6"),
+                    view(2, "Sell", "101", "1"),
+                    view(5, "Buy", "99", "1"),
+                ],
+            });
+            handle_outbound(&from_engine(&open.to_string()), &app.state);
+        };
+        let uri = "/user/a@test.com/exposure?verify=true";
+        let ((status, body), ()) = tokio::join!(send(app, "GET", uri, None), engine);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["discrepancy"],
+            serde_json::json!({
+                "missing_from_engine": [],
+                "unknown_to_gateway": [{ "symbol": "AAPL", "order_id": 5 }],
+                "quantity_mismatches": [
+                    { "symbol": "AAPL", "order_id": 2, "gateway": "3", "engine": "1" },
+                ],
+            })
+        );
+
+        let (status, _) = send(app, "GET", "/user/nobody@test.com/exposure", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_quote_ladder_replaced_with_the_difference() {
+        let app = TestAppState::new();
+        for (order_id, side, price) in [(1, "Buy", "99"), (2, "Buy", "98"), (3, "Sell", "101")] {
+            let ack = serde_json::json!({
+                "type": "order_accepted",
+                "order": {
+                    "order_id": order_id,
+                    "user": "mm",
+                    "side": side,
+                    "symbol": "AAPL",
+                    "price": price,
+                    "quantity": "10",
+                },
+            });
+            handle_outbound(&from_engine(&ack.to_string()), &app.state);
+        }
+        let rung = |side: &str, price: &str, quantity: &str| serde_json::json!({ "side": side, "price": price, "quantity": quantity });
+        let quotes = |rungs: Vec<serde_json::Value>| {
+            Some(serde_json::json!({ "user": "mm", "rungs": rungs }))
+        };
+
+        let ladder = vec![
+            rung("Buy", "99", "6"),
+            rung("Sell", "101", "10"),
+            rung("Sell", "102", "5"),
+        ];
+        let (status, body) = send(&app, "POST", "/quotes/AAPL", quotes(ladder)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["plan"],
+            serde_json::json!([
+                { "action": "cancel", "order_id": 2 },
+                { "action": "reduce", "order_id": 1, "by": "4", "quantity": "6" },
+                { "action": "keep", "order_id": 3 },
+                { "action": "place", "rung": rung("Sell", "102", "5") },
+            ])
+        );
+        assert_eq!(body["orders"][0], serde_json::json!({ "order_id": 1 }));
+        assert!(body["orders"][2]["client_order_id"].is_u64());
+        assert_eq!(
+            sent_to_engine(&app),
+            vec![
+                serde_json::json!({ "type": "cancel", "symbol": "AAPL", "order_id": 2 }),
+                serde_json::json!({ "type": "reduce", "symbol": "AAPL", "order_id": 1, "quantity": "4" }),
+                serde_json::json!({ "symbol": "AAPL", "side": "Sell", "quantity": "5", "price": "102", "user": "mm" }),
+            ]
+        );
+
+        // a rung at the same price twice, or an empty one, sends nothing
+        let doubled = vec![rung("Buy", "99", "6"), rung("Buy", "99", "1")];
+        let (status, _) = send(&app, "POST", "/quotes/AAPL", quotes(doubled)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let empty = vec![rung("Buy", "99", "0")];
+        let (status, body) = send(&app, "POST", "/quotes/AAPL", quotes(empty)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["code"].is_string());
+        assert_eq!(sent_to_engine(&app).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_place_market_to_limit_order() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["price"] = serde_json::Value::Null;
+        order["market_to_limit"] = serde_json::json!(true);
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the price comes from the book, so it can't be given one
+        let mut priced = order.clone();
+        priced["price"] = serde_json::json!("101");
+        let (status, _) = send(&app, "POST", "/place_order", Some(priced)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sent_to_engine(&app), vec![order]);
+    }
+
+    #[tokio::test]
+    async fn test_place_pegged_order() {
+        let app = TestAppState::new();
+        let mut order = order_json("a");
+        order["price"] = serde_json::Value::Null;
+        order["peg"] = serde_json::json!({ "offset": "0.01", "limit": "101" });
+        let (status, _) = send(&app, "POST", "/place_order", Some(order.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut priced = order.clone();
+        priced["price"] = serde_json::json!("101");
+        let (status, _) = send(&app, "POST", "/place_order", Some(priced)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sent_to_engine(&app), vec![order]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_symbol_requires_override() {
+        let app = TestAppState::new();
+        let resume = serde_json::json!({ "symbol": "AAPL" });
+        let (status, _) = send(&app, "POST", "/admin/resume_symbol", Some(resume)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(app.publisher.messages(ENGINE_ADMIN_CHANNEL).is_empty());
+
+        let resume = serde_json::json!({ "symbol": "AAPL", "override": true });
+        let (status, _) = send(&app, "POST", "/admin/resume_symbol", Some(resume)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            app.publisher.messages(ENGINE_ADMIN_CHANNEL),
+            vec![
+                serde_json::json!({ "type": "resume_symbol", "symbol": "AAPL", "override": true })
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auction_admin_messages() {
+        let app = TestAppState::new();
+        for action in ["start", "uncross"] {
+            let request = serde_json::json!({ "symbol": "AAPL", "action": action });
+            let (status, _) = send(&app, "POST", "/admin/auction", Some(request)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let request = serde_json::json!({ "symbol": "AAPL", "action": "close" });
+        let (status, _) = send(&app, "POST", "/admin/auction", Some(request)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            app.publisher.messages(ENGINE_ADMIN_CHANNEL),
+            vec![
+                serde_json::json!({ "type": "start_auction", "symbol": "AAPL" }),
+                serde_json::json!({ "type": "uncross", "symbol": "AAPL" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clear_symbol_admin_message() {
+        let app = TestAppState::new();
+        let request = serde_json::json!({ "symbol": "AAPL" });
+        let (status, _) = send(&app, "POST", "/admin/clear_symbol", Some(request)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            app.publisher.messages(ENGINE_ADMIN_CHANNEL),
+            vec![serde_json::json!({ "type": "clear_symbol", "symbol": "AAPL" })]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outbound_payload_guard() {
+        let app = TestAppState::new();
+        signup(&app, "buyer@test.com").await;
+        signup(&app, "seller@test.com").await;
+        let state = &app.state;
+        let apply = |payload: &str| handle_outbound(payload, state);
+
+        // a trade padded past the size limit is dropped unparsed
+        let padding = "x".repeat(guard::DEFAULT_MAX_MESSAGE_BYTES);
+        apply(&format!(
+            r#"{{"buyer":"buyer@test.com","seller":"seller@test.com","symbol":"AAPL","quantity":1,"price":100,"pad":"{}"}}"#,
+            padding
+        ));
+        apply(&"[".repeat(1_000));
+        apply(&from_engine(r#"{"unexpected":true}"#));
+        // a message from before envelopes
+        apply(
+            r#"{"buyer":"buyer@test.com","seller":"seller@test.com","symbol":"AAPL","quantity":1,"price":100}"#,
+        );
+        // event types this gateway doesn't know yet are skipped, not invalid,
+        // and so are fields a newer envelope adds
+        apply(
+            r#"{"v":2,"type":"order_parked","ts":0,"route":"x","payload":{"type":"order_parked","order_id":7}}"#,
+        );
+
+        assert_eq!(
+            state.payload_guard.counts(),
+            RejectedCounts {
+                oversized: 1,
+                too_deep: 1,
+                invalid: 2,
+            }
+        );
+        assert!(app.market_data.last_prices().is_empty());
+        assert_eq!(state.dead_letters.depth(), 0);
+
+        let (status, body) = send(&app, "GET", "/admin/settlement/rejected", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["too_deep"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_notification_inbox() {
+        let app = TestAppState::new();
+        signup(&app, "buyer@test.com").await;
+        signup(&app, "seller@test.com").await;
+        app.users.update("seller@test.com", &mut |seller| {
+            seller.stocks.insert("AAPL".to_string(), Qty::shares(10));
+        });
+
+        // the seller only wants trades in their inbox
+        let (status, prefs) = send(
+            &app,
+            "PUT",
+            "/user/seller@test.com/notification_prefs",
+            Some(serde_json::json!({ "inbox": ["trade"] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(prefs["inbox"], serde_json::json!(["trade"]));
+
+        let trade = r#"{"buyer":"buyer@test.com","seller":"seller@test.com","symbol":"AAPL","quantity":2,"price":100,"taker_side":"buy"}"#;
+        handle_outbound(&from_engine(trade), &app.state);
+        for user in ["buyer@test.com", "seller@test.com"] {
+            let rejected = serde_json::json!({
+                "type": "rejected",
+                "reason": "position_limit",
+                "order": { "user": user, "symbol": "AAPL" },
+            });
+            handle_outbound(&from_engine(&rejected.to_string()), &app.state);
+        }
+        send(
+            &app,
+            "POST",
+            "/admin/market_makers",
+            Some(serde_json::json!({ "email": "seller@test.com", "enabled": true })),
+        )
+        .await;
+
+        let (status, body) = send(&app, "GET", "/user/buyer@test.com/notifications", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["unread"], 2);
+        assert_eq!(body["items"][0]["kind"], "trade");
+        assert_eq!(body["items"][0]["payload"]["side"], "buy");
+        assert_eq!(body["items"][1]["kind"], "order_rejected");
+
+        let (_, body) = send(&app, "GET", "/user/seller@test.com/notifications", None).await;
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["payload"]["side"], "sell");
+
+        let read = format!(
+            "/user/buyer@test.com/notifications/{}/read",
+            body["items"][0]["id"]
+        );
+        // ids are per user, the seller's notification isn't the buyer's to read
+        let (status, _) = send(&app, "POST", &read, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = send(&app, "GET", "/user/buyer@test.com/notifications", None).await;
+        let read = format!(
+            "/user/buyer@test.com/notifications/{}/read",
+            body["items"][0]["id"]
+        );
+        let (status, body) = send(&app, "POST", &read, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["unread"], 1);
+
+        let (status, _) = send(&app, "GET", "/user/nobody@test.com/notifications", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_fee_tiers_and_ledger() {
+        let app = TestAppState::new();
+        signup(&app, "buyer@test.com").await;
+        signup(&app, "seller@test.com").await;
+        app.users.update("seller@test.com", &mut |seller| {
+            seller.stocks.insert("AAPL".to_string(), Qty::shares(10));
+        });
+
+        let (status, body) = send(&app, "GET", "/fees", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "maker_bps": 0, "taker_bps": 0 }));
+
+        let tier = |user: &str, maker_bps: i64, taker_bps: i64| {
+            serde_json::json!({
+                "user": user,
+                "schedule": { "maker_bps": maker_bps, "taker_bps": taker_bps },
+            })
+        };
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/admin/fees/tiers",
+            Some(tier("buyer@test.com", -11, 10)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/admin/fees/tiers",
+            Some(tier("nobody@test.com", 0, 10)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/admin/fees/tiers",
+            Some(tier("buyer@test.com", 0, 10)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({ "maker_bps": 0, "taker_bps": 10, "tier": true })
+        );
+        send(
+            &app,
+            "POST",
+            "/admin/fees/tiers",
+            Some(tier("seller@test.com", -5, 10)),
+        )
+        .await;
+        let (_, body) = send(&app, "GET", "/user/seller@test.com/fees", None).await;
+        assert_eq!(body["maker_bps"], -5);
+        let (status, _) = send(&app, "GET", "/user/nobody@test.com/fees", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // the buyer lifts the seller's offer: $1,000 of stock
+        let trade = r#"{"buyer":"buyer@test.com","seller":"seller@test.com","symbol":"AAPL","quantity":"10","price":"100","taker_side":"buy"}"#;
+        handle_outbound(&from_engine(trade), &app.state);
+        let (status, body) = send(&app, "GET", "/admin/fees/ledger", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["balance"]["amount"], "0.50");
+        assert_eq!(body["entries"][0]["kind"], "fee");
+        assert_eq!(body["entries"][0]["amount"]["amount"], "1.00");
+        assert_eq!(body["entries"][1]["kind"], "rebate");
+        assert_eq!(body["entries"][1]["user"], "seller@test.com");
+        assert_eq!(body["entries"][1]["amount"]["amount"], "0.50");
+        let (_, body) = send(&app, "GET", "/user/buyer@test.com", None).await;
+        assert_eq!(body["current_balance"]["amount"], "3999.00");
+        let (_, body) = send(&app, "GET", "/user/seller@test.com", None).await;
+        assert_eq!(body["current_balance"]["amount"], "6000.50");
+
+        // and the seller goes back on the schedule
+        send(
+            &app,
+            "POST",
+            "/admin/fees/tiers",
+            Some(serde_json::json!({ "user": "seller@test.com", "schedule": null })),
+        )
+        .await;
+        let (_, body) = send(&app, "GET", "/user/seller@test.com/fees", None).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "maker_bps": 0, "taker_bps": 0, "tier": false })
+        );
+        let (_, body) = send(&app, "GET", "/user/seller@test.com/notifications", None).await;
+        assert_eq!(body["items"][0]["kind"], "admin_action");
+    }
+
+    #[tokio::test]
+    async fn test_trade_bust() {
+        let app = TestAppState::new();
+        signup(&app, "buyer@test.com").await;
+        signup(&app, "seller@test.com").await;
+        app.users.update("seller@test.com", &mut |seller| {
+            seller.stocks.insert("AAPL".to_string(), Qty::shares(10));
+        });
+        for trade_id in [7, 8] {
+            let trade = serde_json::json!({
+                "trade_id": trade_id,
+                "buyer": "buyer@test.com",
+                "seller": "seller@test.com",
+                "symbol": "AAPL",
+                "quantity": "5",
+                "price": "100",
+                "taker_side": "buy",
+            });
+            handle_outbound(&from_engine(&trade.to_string()), &app.state);
+        }
+        let bust = |reason: &str| Some(serde_json::json!({ "reason": reason }));
+
+        let (status, _) = send(&app, "POST", "/admin/trades/7/bust", bust(" ")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = send(&app, "POST", "/admin/trades/9/bust", bust("fat finger")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(&app, "POST", "/admin/trades/7/bust", bust("fat finger")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["busted"], true);
+        let (_, buyer) = send(&app, "GET", "/user/buyer@test.com", None).await;
+        assert_eq!(buyer["current_balance"]["amount"], "4500.00");
+        let (_, seller) = send(&app, "GET", "/user/seller@test.com", None).await;
+        assert_eq!(seller["current_balance"]["amount"], "5500.00");
+        assert_eq!(seller["stocks"]["AAPL"], "5");
+
+        let (status, _) = send(&app, "POST", "/admin/trades/7/bust", bust("again")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // the buyer sold the other five on before the second bust
+        app.users.update("buyer@test.com", &mut |buyer| {
+            buyer.stocks.insert("AAPL".to_string(), Qty::ZERO);
+        });
+        let (status, _) = send(&app, "POST", "/admin/trades/8/bust", bust("fat finger")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, seller) = send(&app, "GET", "/user/seller@test.com", None).await;
+        assert_eq!(seller["current_balance"]["amount"], "5500.00");
+
+        let busts: Vec<_> = app
+            .state
+            .audit
+            .export(AuditFilter::default(), &app.state.audit_key)
+            .records
+            .into_iter()
+            .filter(|record| record.kind == AuditKind::TradeBust)
+            .collect();
+        assert_eq!(busts.len(), 1);
+        assert_eq!(busts[0].users, vec!["buyer@test.com", "seller@test.com"]);
+        assert_eq!(busts[0].data["reason"], "fat finger");
+        assert_eq!(busts[0].data["trade"]["trade_id"], 7);
+
+        for user in ["buyer@test.com", "seller@test.com"] {
+            let (_, body) = send(&app, "GET", &format!("/user/{}/notifications", user), None).await;
+            // after the two trades
+            let bust = &body["items"][2];
+            assert_eq!(bust["kind"], "trade_bust");
+            assert_eq!(bust["payload"]["trade"]["trade_id"], 7);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_export_round_trip() {
+        let app = TestAppState::new();
+        signup(&app, "buyer@test.com").await;
+        signup(&app, "seller@test.com").await;
+        app.users.update("seller@test.com", &mut |seller| {
+            seller.stocks.insert("AAPL".to_string(), Qty::shares(10));
+        });
+
+        send(
+            &app,
+            "POST",
+            "/place_order",
+            Some(order_json("buyer@test.com")),
+        )
+        .await;
+        let trade = r#"{"buyer":"buyer@test.com","seller":"seller@test.com","symbol":"AAPL","quantity":2,"price":100,"taker_side":"buy"}"#;
+        handle_outbound(&from_engine(trade), &app.state);
+        send(
+            &app,
+            "POST",
+            "/admin/market_makers",
+            Some(serde_json::json!({ "email": "seller@test.com", "enabled": true })),
+        )
+        .await;
+
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/admin/audit/export?user=seller@test.com&from=2025-10-15&to=2025-10-15",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let export: AuditExport = serde_json::from_value(body).unwrap();
+        let kinds: Vec<AuditKind> = export.records.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, vec![AuditKind::Trade, AuditKind::AdminAction]);
+        assert_eq!(export.verify(&app.state.audit_key), Ok(()));
+
+        // what the verify subcommand does with a saved export
+        let path = std::env::temp_dir().join(format!("audit-{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::to_string(&export).unwrap()).unwrap();
+        assert_eq!(audit::verify_file(&path, &app.state.audit_key), Ok(2));
+        let mut tampered = export.clone();
+        tampered.records[0].data["quantity"] = serde_json::json!(1);
+        std::fs::write(&path, serde_json::to_string(&tampered).unwrap()).unwrap();
+        assert!(audit::verify_file(&path, &app.state.audit_key).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        let (_, body) = send(&app, "GET", "/admin/audit/export?from=2025-10-16", None).await;
+        assert_eq!(body["records"], serde_json::json!([]));
+
+        let (_, body) = send(&app, "GET", "/admin/audit/verify", None).await;
+        assert_eq!(body["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_capacity_top_n() {
+        let app = TestAppState::new();
+        let report = |date: &str, is_final: bool, aapl: u64, msft: u64| {
+            let symbol = |trades: u64| {
+                serde_json::json!({
+                    "orders": trades * 2,
+                    "trades": trades,
+                    "volume": trades * 10,
+                    "peak_resting_orders": 4,
+                    "max_levels": 2,
+                    "p99_match_micros": 50,
+                })
+            };
+            serde_json::json!({
+                "type": "capacity_report",
+                "date": date,
+                "final": is_final,
+                "symbols": { "AAPL": symbol(aapl), "MSFT": symbol(msft) },
+            })
+            .to_string()
+        };
+        for (date, is_final, aapl, msft) in [
+            ("2025-10-14", true, 1, 9),
+            ("2025-10-15", false, 3, 1),
+            ("2025-10-15", false, 6, 1),
+        ] {
+            handle_outbound(
+                &from_engine(&report(date, is_final, aapl, msft)),
+                &app.state,
+            );
+        }
+
+        let (status, body) = send(&app, "GET", "/admin/capacity?top=1&sort=trades", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["dates"],
+            serde_json::json!(["2025-10-14", "2025-10-15"])
+        );
+        assert_eq!(body["symbols"].as_array().unwrap().len(), 1);
+        assert_eq!(body["symbols"][0]["symbol"], "MSFT");
+        assert_eq!(body["symbols"][0]["trades"], 10);
+
+        let (_, body) = send(&app, "GET", "/admin/capacity?days=1", None).await;
+        assert_eq!(body["symbols"][0]["symbol"], "AAPL");
+        assert_eq!(body["symbols"][0]["volume"], "60");
+
+        let (status, _) = send(&app, "GET", "/admin/capacity?sort=bogus", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(app.state.payload_guard.counts().invalid, 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_and_nested_request_bodies() {
+        let app = TestAppState::new();
+        let mut order = order_json("a@test.com");
+        order["pad"] = serde_json::Value::String("x".repeat(guard::MAX_BODY_BYTES));
+        let (status, _) = send(&app, "POST", "/place_order", Some(order)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // serde's own recursion limit turns this away without blowing the stack
+        let request = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("[".repeat(50_000)))
+            .unwrap();
+        let response = app.router().oneshot(request).await.unwrap();
+        assert!(response.status().is_client_error());
+        assert!(sent_to_engine(&app).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_retry_and_discard() {
+        let app = TestAppState::new();
+        signup(&app, "buyer@test.com").await;
+        let event = TradeEvent {
+            trade_id: 1,
+            buyer: "buyer@test.com".to_string(),
+            seller: "seller@test.com".to_string(),
+            symbol: "AAPL".to_string(),
+            quantity: Qty::shares(4),
+            price: Price::cents(10150),
+            taker_side: Side::Buy,
+            timestamp: 0,
+            maker_received_at: 0,
+            maker_order_id: 0,
+            maker_remaining: Qty::ZERO,
+            taker_order_id: 0,
+            buyer_tags: Vec::new(),
+            seller_tags: Vec::new(),
+        };
+        let error =
+            settlement::settle_trade(app.users.as_ref(), &app.state.fees, &event).unwrap_err();
+        let first = app.state.dead_letters.push(event.clone(), &error);
+        let second = app.state.dead_letters.push(event, &error);
+
+        let (_, body) = send(&app, "GET", "/admin/settlement/dlq", None).await;
+        assert_eq!(body["items"][0]["reason"], "unknown user seller@test.com");
+        assert_eq!(body["items"][0]["status"], "pending");
+
+        let retry = format!("/admin/settlement/dlq/{}/retry", first);
+        let (status, _) = send(&app, "POST", &retry, None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        signup(&app, "seller@test.com").await;
+        let (status, _) = send(&app, "POST", &retry, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            app.users.get("seller@test.com").unwrap().current_balance,
+            Money::usd(540600)
+        );
+
+        let discard = format!("/admin/settlement/dlq/{}/discard", second);
+        let (status, _) = send(&app, "POST", &discard, Some(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, body) = send(
+            &app,
+            "POST",
+            &discard,
+            Some(serde_json::json!({ "reason": "already settled" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["discard_reason"], "already settled");
+
+        let (_, body) = send(&app, "GET", "/admin/settlement/dlq?status=discarded", None).await;
+        assert_eq!(body["items"].as_array().unwrap().len(), 1);
+        assert_eq!(body["items"][0]["id"], second);
+        let (_, body) = send(&app, "GET", "/admin/settlement/dlq?user=nobody", None).await;
+        assert_eq!(body["items"], serde_json::json!([]));
+        let (status, _) = send(&app, "GET", "/admin/settlement/dlq?cursor=x", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = send(&app, "POST", "/admin/settlement/dlq/99/retry", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_user_history_marks_positions() {
+        let app = TestAppState::new();
+        signup(&app, "a@test.com").await;
+        app.users.update("a@test.com", &mut |user| {
+            user.stocks.insert("AAPL".to_string(), Qty::shares(10));
+        });
+
+        for (date, price) in [
+            ("2025-10-15", Price::cents(10000)),
+            ("2025-10-16", Price::cents(12000)),
+        ] {
+            app.market_data.record_trade("AAPL", price);
+            history::roll_day(
+                date,
+                app.users.as_ref(),
+                app.market_data.as_ref(),
+                &app.state.history,
+            );
+        }
+
+        let (status, body) = send(&app, "GET", "/user/a@test.com/history?days=1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["date"], "2025-10-16");
+        assert_eq!(body[0]["equity"]["amount"], "6200.00");
+
+        let (status, _) = send(&app, "GET", "/user/nobody@test.com/history", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reference_prices_value_positions_before_first_trade() {
+        let app = TestAppState::new();
+        signup(&app, "a@test.com").await;
+        app.users.update("a@test.com", &mut |user| {
+            user.stocks.insert("AAPL".to_string(), Qty::shares(10));
+        });
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/admin/reference_prices",
+            Some(serde_json::json!({ "AAPL": 10000 })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["applied"], serde_json::json!(["AAPL"]));
+
+        let (_, body) = send(&app, "GET", "/prices", None).await;
+        assert_eq!(
+            body["AAPL"],
+            serde_json::json!({ "price": "100", "source": "reference" })
+        );
+
+        history::roll_day(
+            "2025-10-15",
+            app.users.as_ref(),
+            app.market_data.as_ref(),
+            &app.state.history,
+        );
+        let (_, body) = send(&app, "GET", "/user/a@test.com/history", None).await;
+        assert_eq!(body[0]["equity"]["amount"], "6000.00");
+
+        // the first trade replaces the reference and it cannot be re-seeded
+        app.market_data.record_trade("AAPL", Price::cents(10100));
+        let (_, body) = send(
+            &app,
+            "POST",
+            "/admin/reference_prices",
+            Some(serde_json::json!({ "AAPL": 10000 })),
+        )
+        .await;
+        assert_eq!(body["skipped"], serde_json::json!(["AAPL"]));
+        let (_, body) = send(&app, "GET", "/prices", None).await;
+        assert_eq!(
+            body["AAPL"],
+            serde_json::json!({ "price": "101", "source": "traded" })
+        );
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/admin/reference_prices",
+            Some(serde_json::json!({ "AAPL": -5 })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_rfq_lifecycle() {
+        let app = TestAppState::new();
+        signup(&app, "fund@test.com").await;
+        signup(&app, "mm@test.com").await;
+        app.users.update("mm@test.com", &mut |user| {
+            user.stocks.insert("AAPL".to_string(), Qty::shares(100));
+        });
+
+        let request = serde_json::json!({
+            "requester": "fund@test.com",
+            "symbol": "AAPL",
+            "side": "Buy",
+            "quantity": 40,
+        });
+        let (status, rfq) = send(&app, "POST", "/rfq", Some(request)).await;
+        assert_eq!(status, StatusCode::OK);
+        let id = rfq["id"].as_u64().unwrap();
+
+        let quote = serde_json::json!({ "maker": "mm@test.com", "price": 10000 });
+        let quotes = format!("/rfq/{}/quotes", id);
+        let (status, _) = send(&app, "POST", &quotes, Some(quote.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let maker = serde_json::json!({ "email": "mm@test.com", "enabled": true });
+        let (status, _) = send(&app, "POST", "/admin/market_makers", Some(maker)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, "POST", &quotes, Some(quote)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, open) = send(&app, "GET", "/rfq", None).await;
+        assert_eq!(open["items"][0]["quotes"][0]["price"], "100");
+
+        let accept = format!("/rfq/{}/accept", id);
+        let body = serde_json::json!({ "requester": "fund@test.com", "maker": "mm@test.com" });
+        let (status, filled) = send(&app, "POST", &accept, Some(body.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(filled["off_book"], true);
+        assert_eq!(filled["trade"]["buyer"], "fund@test.com");
+        assert_eq!(
+            app.users.get("fund@test.com").unwrap().current_balance,
+            Money::usd(100000)
+        );
+        assert_eq!(
+            app.users.get("mm@test.com").unwrap().stocks["AAPL"],
+            Qty::shares(60)
+        );
+        // the block never touches the order book
+        assert!(sent_to_engine(&app).is_empty());
+
+        let (status, _) = send(&app, "POST", &accept, Some(body)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_rfq_expires() {
+        let app = TestAppState::new();
+        signup(&app, "fund@test.com").await;
+        let request = serde_json::json!({
+            "requester": "fund@test.com",
+            "symbol": "AAPL",
+            "side": "Sell",
+            "quantity": 40,
+        });
+        let (_, rfq) = send(&app, "POST", "/rfq", Some(request)).await;
+
+        app.clock
+            .set(state::test_support::TEST_NOW + rfq::DEFAULT_RFQ_WINDOW_MILLIS);
+        let (_, rfq) = send(&app, "GET", &format!("/rfq/{}", rfq["id"]), None).await;
+        assert_eq!(rfq["status"], "expired");
+        let (_, open) = send(&app, "GET", "/rfq", None).await;
+        assert_eq!(open["items"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_faucet_credits_until_exhausted() {
+        let app = TestAppState::new();
+        signup(&app, "a@test.com").await;
+
+        let (status, body) = send(&app, "POST", "/user/a@test.com/faucet", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["current_balance"]["amount"], "6000.00");
+        assert_eq!(body["faucet_claims_remaining"], 0);
+
+        let (status, _) = send(&app, "POST", "/user/a@test.com/faucet", None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            app.users.get("a@test.com").unwrap().current_balance,
+            Money::usd(600000)
+        );
+
+        // a day later the claim is available again
+        app.clock
+            .set(state::test_support::TEST_NOW + clock::MILLIS_PER_DAY);
+        let (status, _) = send(&app, "POST", "/user/a@test.com/faucet", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_faucet_forbidden_in_production() {
+        let app = TestAppState::with_faucet(FaucetConfig::disabled());
+        signup(&app, "a@test.com").await;
+
+        let (status, _) = send(&app, "POST", "/user/a@test.com/faucet", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_faucet_unknown_user() {
+        let app = TestAppState::new();
+        let (status, _) = send(&app, "POST", "/user/nobody@test.com/faucet", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,604 @@
+use orderbook::{BookDelta, DepthLevel, DepthSnapshot, Price, Side};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+pub const REFERENCE_PRICES_ENV: &str = "REFERENCE_PRICES_FILE";
+pub const VALUATION_SOURCE_ENV: &str = "VALUATION_SOURCE";
+pub const PRICE_COLLAR_ENV: &str = "PRICE_COLLAR_PERCENT";
+pub const DEFAULT_PRICE_COLLAR_PERCENT: u32 = 10;
+// How many levels a side the checksum on each book update covers, as the
+// engine's BOOK_UPDATE_DEPTH.
+pub const CHECKSUM_DEPTH: usize = 10;
+
+// The level changes one engine message made to a book, as published on the
+// market_data channel.
+#[derive(Deserialize, Debug)]
+pub struct BookUpdate {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub symbol: String,
+    pub seq: u64,
+    pub deltas: Vec<BookDelta>,
+    pub checksum: u32,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    // seeded from configuration, no trade has happened yet
+    Reference,
+    Traded,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct LastPrice {
+    pub price: Price,
+    pub source: PriceSource,
+}
+
+// The price positions are marked at. Mid and microprice need both sides of
+// the book and fall back to the last price when either is empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ValuationSource {
+    #[default]
+    Last,
+    Mid,
+    // the mid weighted toward the side with less resting at the best level
+    Microprice,
+}
+
+impl FromStr for ValuationSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last" => Ok(ValuationSource::Last),
+            "mid" => Ok(ValuationSource::Mid),
+            "microprice" => Ok(ValuationSource::Microprice),
+            _ => Err(format!(
+                "unknown valuation source {:?}, expected last, mid or microprice",
+                s
+            )),
+        }
+    }
+}
+
+pub fn mid(bid: &DepthLevel, ask: &DepthLevel) -> Price {
+    Price::from_units((bid.price.units() + ask.price.units()).div_euclid(2))
+}
+
+// (bid * ask size + ask * bid size) / (bid size + ask size): a heavy bid
+// pulls the price up toward the ask, a heavy ask down toward the bid.
+pub fn microprice(bid: &DepthLevel, ask: &DepthLevel) -> Price {
+    let (bid_qty, ask_qty) = (bid.quantity.units() as i128, ask.quantity.units() as i128);
+    let weighted = bid.price.units() as i128 * ask_qty + ask.price.units() as i128 * bid_qty;
+    Price::from_units(weighted.div_euclid(bid_qty + ask_qty) as i64)
+}
+
+// A symbol's price under `source`, from its last price and the best level on
+// each side of its book.
+pub fn valuation_price(
+    source: ValuationSource,
+    last: Option<Price>,
+    best_bid: Option<&DepthLevel>,
+    best_ask: Option<&DepthLevel>,
+) -> Option<Price> {
+    let quoted = match (source, best_bid, best_ask) {
+        (ValuationSource::Mid, Some(bid), Some(ask)) => Some(mid(bid, ask)),
+        (ValuationSource::Microprice, Some(bid), Some(ask)) => Some(microprice(bid, ask)),
+        _ => None,
+    };
+    quoted.or(last)
+}
+
+// The best price on the other side of the book when a limit order is priced
+// more than `percent` through it: a buy that far above the best ask or a
+// sell that far below the best bid. None if it's within the collar or that
+// side is empty.
+pub fn outside_collar(
+    side: &Side,
+    price: Price,
+    book: &DepthSnapshot,
+    percent: u32,
+) -> Option<Price> {
+    let percent = percent as i128;
+    let price = price.units() as i128 * 100;
+    match side {
+        Side::Buy => book
+            .asks
+            .first()
+            .filter(|ask| price > ask.price.units() as i128 * (100 + percent)),
+        Side::Sell => book
+            .bids
+            .first()
+            .filter(|bid| price < bid.price.units() as i128 * (100 - percent)),
+    }
+    .map(|touch| touch.price)
+}
+
+// Market state the gateway derives from the engine's trade stream and book
+// updates.
+pub trait MarketData: Send + Sync {
+    fn record_trade(&self, symbol: &str, price: Price);
+    // Applies an update to the copy of its book; false if the update doesn't
+    // follow the last one applied, or the copy no longer matches its
+    // checksum. The update is applied either way.
+    fn apply_book_update(&self, update: &BookUpdate) -> bool;
+    // The copy of a book, up to `levels` a side, with its levels merged into
+    // bands `aggregation` wide when given; None before any update for the
+    // symbol.
+    fn depth(
+        &self,
+        symbol: &str,
+        levels: usize,
+        aggregation: Option<Price>,
+    ) -> Option<DepthSnapshot>;
+    // Fills in a price for a symbol that has not traded yet; false if a real
+    // trade already set one.
+    fn seed_reference(&self, symbol: &str, price: Price) -> bool;
+    fn prices(&self) -> HashMap<String, LastPrice>;
+    // Every symbol with a price under the configured ValuationSource.
+    fn valuation_prices(&self) -> HashMap<String, Price>;
+
+    fn last_prices(&self) -> HashMap<String, Price> {
+        self.prices()
+            .into_iter()
+            .map(|(symbol, last)| (symbol, last.price))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryMarketData {
+    last_prices: Mutex<HashMap<String, LastPrice>>,
+    books: Mutex<HashMap<String, Ladder>>,
+    valuation: ValuationSource,
+}
+
+impl InMemoryMarketData {
+    pub fn with_valuation(valuation: ValuationSource) -> Self {
+        Self {
+            valuation,
+            ..Self::default()
+        }
+    }
+}
+
+// A book's displayed levels, as rebuilt from its updates.
+#[derive(Default)]
+struct Ladder {
+    bids: BTreeMap<Price, DepthLevel>,
+    asks: BTreeMap<Price, DepthLevel>,
+    seq: u64,
+}
+
+impl Ladder {
+    fn apply(&mut self, delta: &BookDelta) {
+        let side = match delta.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        if delta.quantity.is_zero() {
+            side.remove(&delta.price);
+        } else {
+            side.insert(
+                delta.price,
+                DepthLevel {
+                    price: delta.price,
+                    quantity: delta.quantity,
+                    orders: delta.orders,
+                },
+            );
+        }
+    }
+
+    // Best first on both sides.
+    fn sides(
+        &self,
+    ) -> (
+        impl Iterator<Item = &DepthLevel>,
+        impl Iterator<Item = &DepthLevel>,
+    ) {
+        (self.bids.values().rev(), self.asks.values())
+    }
+}
+
+// Merges levels, best first, into bands `band` wide, summing their
+// quantities and order counts. A bid band takes the price at its bottom and
+// an ask band the one at its top, so each band's price is one the whole band
+// would trade at: with 0.25 bands, bids at 10.10 and 10.05 show as 10.00 and
+// asks at 10.05 and 10.10 as 10.25. Bands with no orders in them aren't
+// shown.
+pub fn aggregate<'a>(
+    levels: impl Iterator<Item = &'a DepthLevel>,
+    side: Side,
+    band: Price,
+) -> Vec<DepthLevel> {
+    let mut bands: Vec<DepthLevel> = Vec::new();
+    for level in levels {
+        let units = level.price.units();
+        let floor = units - units.rem_euclid(band.units());
+        let price = match side {
+            Side::Sell if floor != units => Price::from_units(floor + band.units()),
+            _ => Price::from_units(floor),
+        };
+        match bands.last_mut() {
+            Some(last) if last.price == price => {
+                last.quantity += level.quantity;
+                last.orders += level.orders;
+            }
+            _ => bands.push(DepthLevel { price, ..*level }),
+        }
+    }
+    bands
+}
+
+impl MarketData for InMemoryMarketData {
+    fn record_trade(&self, symbol: &str, price: Price) {
+        self.last_prices.lock().unwrap().insert(
+            symbol.to_string(),
+            LastPrice {
+                price,
+                source: PriceSource::Traded,
+            },
+        );
+    }
+
+    fn apply_book_update(&self, update: &BookUpdate) -> bool {
+        let mut books = self.books.lock().unwrap();
+        let ladder = books.entry(update.symbol.clone()).or_default();
+        let in_sequence = update.seq == ladder.seq + 1;
+        for delta in &update.deltas {
+            ladder.apply(delta);
+        }
+        ladder.seq = update.seq;
+
+        let (bids, asks) = ladder.sides();
+        let bids: Vec<DepthLevel> = bids.take(CHECKSUM_DEPTH).cloned().collect();
+        let asks: Vec<DepthLevel> = asks.take(CHECKSUM_DEPTH).cloned().collect();
+        in_sequence && orderbook::depth_checksum(&bids, &asks) == update.checksum
+    }
+
+    fn depth(
+        &self,
+        symbol: &str,
+        levels: usize,
+        aggregation: Option<Price>,
+    ) -> Option<DepthSnapshot> {
+        let books = self.books.lock().unwrap();
+        let ladder = books.get(symbol)?;
+        let (bids, asks) = ladder.sides();
+        let (mut bids, mut asks): (Vec<DepthLevel>, Vec<DepthLevel>) = match aggregation {
+            Some(band) => (
+                aggregate(bids, Side::Buy, band),
+                aggregate(asks, Side::Sell, band),
+            ),
+            None => (bids.cloned().collect(), asks.cloned().collect()),
+        };
+        bids.truncate(levels);
+        asks.truncate(levels);
+        Some(DepthSnapshot {
+            symbol: symbol.to_string(),
+            checksum: orderbook::depth_checksum(&bids, &asks),
+            bids,
+            asks,
+        })
+    }
+
+    fn seed_reference(&self, symbol: &str, price: Price) -> bool {
+        let mut last_prices = self.last_prices.lock().unwrap();
+        if last_prices
+            .get(symbol)
+            .is_some_and(|last| last.source == PriceSource::Traded)
+        {
+            return false;
+        }
+        last_prices.insert(
+            symbol.to_string(),
+            LastPrice {
+                price,
+                source: PriceSource::Reference,
+            },
+        );
+        true
+    }
+
+    fn prices(&self) -> HashMap<String, LastPrice> {
+        self.last_prices.lock().unwrap().clone()
+    }
+
+    fn valuation_prices(&self) -> HashMap<String, Price> {
+        let last_prices = self.last_prices();
+        let books = self.books.lock().unwrap();
+        let symbols: HashSet<&String> = last_prices.keys().chain(books.keys()).collect();
+        symbols
+            .into_iter()
+            .filter_map(|symbol| {
+                let (best_bid, best_ask) = match books.get(symbol) {
+                    Some(ladder) => {
+                        let (mut bids, mut asks) = ladder.sides();
+                        (bids.next(), asks.next())
+                    }
+                    None => (None, None),
+                };
+                let last = last_prices.get(symbol).copied();
+                valuation_price(self.valuation, last, best_bid, best_ask)
+                    .map(|price| (symbol.clone(), price))
+            })
+            .collect()
+    }
+}
+
+// Reference prices are a JSON object of symbol to price, read the way orders
+// read theirs: {"AAPL": "150.25"}, or {"AAPL": 15025} in cents.
+pub fn parse_reference_prices(contents: &str) -> Result<HashMap<String, Price>, String> {
+    let prices: HashMap<String, Price> =
+        serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    if let Some((symbol, price)) = prices.iter().find(|(_, price)| !price.is_positive()) {
+        return Err(format!(
+            "reference price for {} must be positive, got {}",
+            symbol, price
+        ));
+    }
+    Ok(prices)
+}
+
+pub fn load_reference_prices(path: &Path) -> Result<HashMap<String, Price>, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_reference_prices(&contents)
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orderbook::Qty;
+
+    #[test]
+    fn test_reference_flips_to_traded() {
+        let market_data = InMemoryMarketData::default();
+        assert!(market_data.seed_reference("AAPL", Price::cents(15000)));
+        assert_eq!(
+            market_data.prices()["AAPL"],
+            LastPrice {
+                price: Price::cents(15000),
+                source: PriceSource::Reference
+            }
+        );
+        // re-seeding before the first trade is allowed
+        assert!(market_data.seed_reference("AAPL", Price::cents(15100)));
+
+        market_data.record_trade("AAPL", Price::cents(15250));
+        assert!(!market_data.seed_reference("AAPL", Price::cents(15000)));
+        assert_eq!(
+            market_data.prices()["AAPL"],
+            LastPrice {
+                price: Price::cents(15250),
+                source: PriceSource::Traded
+            }
+        );
+        assert_eq!(market_data.last_prices()["AAPL"], Price::cents(15250));
+    }
+
+    fn level(price: &str, shares: u64, orders: usize) -> DepthLevel {
+        DepthLevel {
+            price: price.parse().unwrap(),
+            quantity: Qty::shares(shares),
+            orders,
+        }
+    }
+
+    fn update(seq: u64, deltas: Vec<(Side, &str, u64, usize)>, checksum: u32) -> BookUpdate {
+        BookUpdate {
+            kind: String::from("book_delta"),
+            symbol: String::from("AAPL"),
+            seq,
+            deltas: deltas
+                .into_iter()
+                .map(|(side, price, shares, orders)| BookDelta {
+                    side,
+                    price: price.parse().unwrap(),
+                    quantity: Qty::shares(shares),
+                    orders,
+                })
+                .collect(),
+            checksum,
+        }
+    }
+
+    #[test]
+    fn test_depth_rebuilt_from_updates() {
+        let market_data = InMemoryMarketData::default();
+        assert!(market_data.depth("AAPL", 10, None).is_none());
+
+        let bids = [level("100", 5, 2), level("99.5", 3, 1)];
+        let asks = [level("101", 4, 1)];
+        let first = update(
+            1,
+            vec![
+                (Side::Buy, "100", 5, 2),
+                (Side::Buy, "99.5", 3, 1),
+                (Side::Sell, "101", 4, 1),
+                (Side::Sell, "102", 1, 1),
+            ],
+            0,
+        );
+        market_data.apply_book_update(&first);
+        let taken = update(
+            2,
+            vec![(Side::Sell, "102", 0, 0)],
+            orderbook::depth_checksum(&bids, &asks),
+        );
+        assert!(market_data.apply_book_update(&taken));
+        let depth = market_data.depth("AAPL", 10, None).unwrap();
+        assert_eq!((depth.bids, depth.asks), (bids.to_vec(), asks.to_vec()));
+        assert_eq!(
+            market_data.depth("AAPL", 1, None).unwrap().bids,
+            vec![level("100", 5, 2)]
+        );
+
+        // a skipped update is caught even when the checksum agrees
+        let skipped = update(4, vec![], orderbook::depth_checksum(&bids, &asks));
+        assert!(!market_data.apply_book_update(&skipped));
+        let corrupt = update(5, vec![], 1);
+        assert!(!market_data.apply_book_update(&corrupt));
+    }
+
+    #[test]
+    fn test_levels_merged_into_bands() {
+        // a 1-cent book grouped into 25-cent bands
+        let bids = [
+            level("10.10", 1, 1),
+            level("10.00", 2, 1),
+            level("9.99", 3, 2),
+            level("9.76", 4, 1),
+            // nothing from 9.50 to 9.74
+            level("9.49", 5, 1),
+        ];
+        assert_eq!(
+            aggregate(bids.iter(), Side::Buy, Price::cents(25)),
+            vec![
+                level("10.00", 3, 2),
+                level("9.75", 7, 3),
+                level("9.25", 5, 1),
+            ]
+        );
+
+        let asks = [
+            level("10.00", 1, 1),
+            level("10.01", 2, 1),
+            level("10.25", 3, 1),
+            level("10.26", 4, 2),
+            // nothing from 10.51 to 10.75
+            level("10.80", 5, 1),
+        ];
+        assert_eq!(
+            aggregate(asks.iter(), Side::Sell, Price::cents(25)),
+            vec![
+                level("10.00", 1, 1),
+                level("10.25", 5, 2),
+                level("10.50", 4, 2),
+                level("11.00", 5, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_valuation_sources_fall_back_to_last() {
+        let (bid, ask) = (level("100", 1, 1), level("101", 3, 1));
+        let last = Some(Price::cents(9000));
+        let cases = [
+            // both sides quoted
+            (
+                ValuationSource::Last,
+                last,
+                Some(&bid),
+                Some(&ask),
+                Some("90"),
+            ),
+            (
+                ValuationSource::Mid,
+                last,
+                Some(&bid),
+                Some(&ask),
+                Some("100.5"),
+            ),
+            // three shares offered against one bid leans toward the bid
+            (
+                ValuationSource::Microprice,
+                last,
+                Some(&bid),
+                Some(&ask),
+                Some("100.25"),
+            ),
+            // one side empty
+            (ValuationSource::Mid, last, Some(&bid), None, Some("90")),
+            (
+                ValuationSource::Microprice,
+                last,
+                None,
+                Some(&ask),
+                Some("90"),
+            ),
+            // one side empty and never traded
+            (ValuationSource::Mid, None, Some(&bid), None, None),
+            (ValuationSource::Microprice, None, None, None, None),
+            (ValuationSource::Last, None, Some(&bid), Some(&ask), None),
+        ];
+        for (source, last, best_bid, best_ask, expected) in cases {
+            assert_eq!(
+                valuation_price(source, last, best_bid, best_ask),
+                expected.map(|price| price.parse().unwrap()),
+                "{:?} with last {:?}, bid {:?}, ask {:?}",
+                source,
+                last,
+                best_bid,
+                best_ask
+            );
+        }
+        assert_eq!("microprice".parse(), Ok(ValuationSource::Microprice));
+        assert!("vwap".parse::<ValuationSource>().is_err());
+    }
+
+    #[test]
+    fn test_valuation_prices_from_the_book() {
+        let market_data = InMemoryMarketData::with_valuation(ValuationSource::Microprice);
+        market_data.record_trade("AAPL", Price::cents(9000));
+        market_data.record_trade("MSFT", Price::cents(40000));
+        market_data.apply_book_update(&update(
+            1,
+            vec![
+                (Side::Buy, "100", 3, 1),
+                (Side::Buy, "99", 9, 1),
+                (Side::Sell, "101", 1, 1),
+            ],
+            0,
+        ));
+        let prices = market_data.valuation_prices();
+        assert_eq!(prices["AAPL"], "100.75".parse().unwrap());
+        assert_eq!(prices["MSFT"], Price::cents(40000));
+
+        market_data.apply_book_update(&update(2, vec![(Side::Sell, "101", 0, 0)], 0));
+        assert_eq!(market_data.valuation_prices()["AAPL"], Price::cents(9000));
+    }
+
+    #[test]
+    fn test_collar_measured_from_the_other_side() {
+        let book = DepthSnapshot {
+            symbol: String::from("AAPL"),
+            bids: vec![level("100", 1, 1)],
+            asks: vec![level("101", 1, 1)],
+            checksum: 0,
+        };
+        let collar = |side, price: &str| outside_collar(&side, price.parse().unwrap(), &book, 10);
+        assert_eq!(collar(Side::Buy, "111.1"), None);
+        assert_eq!(collar(Side::Buy, "111.11"), Some(Price::whole(101)));
+        assert_eq!(collar(Side::Sell, "90"), None);
+        assert_eq!(collar(Side::Sell, "89.99"), Some(Price::whole(100)));
+
+        let one_sided = DepthSnapshot {
+            asks: Vec::new(),
+            ..book.clone()
+        };
+        assert_eq!(
+            outside_collar(&Side::Buy, Price::whole(500), &one_sided, 10),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_reference_prices() {
+        let prices = parse_reference_prices(r#"{"AAPL": "150.25", "MSFT": 41000}"#).unwrap();
+        assert_eq!(prices["AAPL"], Price::cents(15025));
+        assert_eq!(prices["MSFT"], Price::cents(41000));
+        assert!(parse_reference_prices(r#"{"AAPL": 0}"#).is_err());
+        assert!(parse_reference_prices(r#"{"AAPL": "-1"}"#).is_err());
+        assert!(parse_reference_prices(r#"{"AAPL": "150.25001"}"#).is_err());
+    }
+}
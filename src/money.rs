@@ -0,0 +1,388 @@
+use orderbook::{Price, Qty, price::PRICE_SCALE, qty::QTY_SCALE};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Currency {
+    #[serde(rename = "USD")]
+    Usd,
+}
+
+impl Currency {
+    // number of decimal places represented by one minor unit
+    pub fn exponent(&self) -> u32 {
+        match self {
+            Currency::Usd => 2,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Currency::Usd => f.write_str("USD"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    CurrencyMismatch(Currency, Currency),
+    Invalid(String),
+    TooPrecise(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => f.write_str("money amount overflowed"),
+            MoneyError::CurrencyMismatch(a, b) => write!(f, "currency mismatch: {a} vs {b}"),
+            MoneyError::Invalid(s) => write!(f, "invalid money amount: {s:?}"),
+            MoneyError::TooPrecise(s) => {
+                write!(
+                    f,
+                    "money amount {s:?} has more decimals than the currency allows"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+// An amount of money held as an integer count of minor units (cents for USD),
+// so every balance and notional computation stays exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    amount: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: i64, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    pub fn usd(amount: i64) -> Self {
+        Self::new(amount, Currency::Usd)
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.amount < 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        self.amount
+            .checked_add(other.amount)
+            .map(|amount| Money::new(amount, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        self.amount
+            .checked_sub(other.amount)
+            .map(|amount| Money::new(amount, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    // price per share times a quantity, e.g. the notional of a fill, worked
+    // out exactly and rounded once to the nearest minor unit, halves away
+    // from zero
+    pub fn notional(price: Price, quantity: Qty, currency: Currency) -> Result<Money, MoneyError> {
+        let exact = price.units() as i128 * quantity.units() as i128;
+        let per_minor_unit =
+            PRICE_SCALE as i128 * QTY_SCALE as i128 / 10i128.pow(currency.exponent());
+        let (whole, rest) = (exact / per_minor_unit, exact % per_minor_unit);
+        let amount = if rest.abs() * 2 >= per_minor_unit {
+            whole + exact.signum()
+        } else {
+            whole
+        };
+        i64::try_from(amount)
+            .map(|amount| Money::new(amount, currency))
+            .map_err(|_| MoneyError::Overflow)
+    }
+
+    // This amount times `bps` basis points, rounded once to the nearest minor
+    // unit, halves away from zero.
+    pub fn bps(self, bps: i64) -> Result<Money, MoneyError> {
+        let exact = self.amount as i128 * bps as i128;
+        let (whole, rest) = (exact / 10_000, exact % 10_000);
+        let amount = if rest.abs() * 2 >= 10_000 {
+            whole + exact.signum()
+        } else {
+            whole
+        };
+        i64::try_from(amount)
+            .map(|amount| Money::new(amount, self.currency))
+            .map_err(|_| MoneyError::Overflow)
+    }
+
+    // Parses a plain decimal string ("101.25", "-3", "0.5") in the given currency.
+    // Amounts with more decimals than the currency supports are rejected rather
+    // than rounded.
+    pub fn parse(input: &str, currency: Currency) -> Result<Money, MoneyError> {
+        let invalid = || MoneyError::Invalid(input.to_string());
+
+        let (negative, unsigned) = match input.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+        let (whole, fraction) = match unsigned.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (unsigned, None),
+        };
+
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let fraction = match fraction {
+            Some(f) if f.is_empty() || !f.bytes().all(|b| b.is_ascii_digit()) => {
+                return Err(invalid());
+            }
+            Some(f) => f,
+            None => "",
+        };
+
+        let exponent = currency.exponent() as usize;
+        if fraction.len() > exponent {
+            return Err(MoneyError::TooPrecise(input.to_string()));
+        }
+
+        let scale = 10i64.pow(exponent as u32);
+        let whole: i64 = whole.parse().map_err(|_| MoneyError::Overflow)?;
+        let fraction: i64 = if fraction.is_empty() {
+            0
+        } else {
+            let padded = format!("{fraction:0<exponent$}");
+            padded.parse().map_err(|_| invalid())?
+        };
+
+        let magnitude = whole
+            .checked_mul(scale)
+            .and_then(|m| m.checked_add(fraction))
+            .ok_or(MoneyError::Overflow)?;
+        let amount = if negative { -magnitude } else { magnitude };
+        Ok(Money::new(amount, currency))
+    }
+
+    // Formats the amount as a plain decimal string, independent of locale.
+    pub fn to_decimal_string(self) -> String {
+        let exponent = self.currency.exponent();
+        let scale = 10u64.pow(exponent);
+        let magnitude = self.amount.unsigned_abs();
+        let sign = if self.amount < 0 { "-" } else { "" };
+        if exponent == 0 {
+            return format!("{sign}{magnitude}");
+        }
+        format!(
+            "{sign}{}.{:0width$}",
+            magnitude / scale,
+            magnitude % scale,
+            width = exponent as usize
+        )
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_decimal_string())
+    }
+}
+
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    // defaults to USD, the only currency the exchange settles in today
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Money::parse(s, Currency::Usd)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoneyRepr {
+    amount: String,
+    currency: Currency,
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoneyRepr {
+            amount: self.to_decimal_string(),
+            currency: self.currency,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MoneyRepr::deserialize(deserializer)?;
+        Money::parse(&repr.amount, repr.currency).map_err(de::Error::custom)
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_valid_amounts() {
+        let cases = [
+            ("0", 0),
+            ("0.00", 0),
+            ("1", 100),
+            ("101.25", 10125),
+            ("101.2", 10120),
+            ("0.05", 5),
+            ("-0.05", -5),
+            ("-3", -300),
+            ("-101.25", -10125),
+            ("007.10", 710),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                Money::parse(input, Currency::Usd),
+                Ok(Money::usd(expected)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_amounts() {
+        for input in [
+            "", "-", ".", "1.", ".5", "abc", "1,00", "1.2.3", "+1", " 1", "1e3", "--1",
+        ] {
+            assert_eq!(
+                Money::parse(input, Currency::Usd),
+                Err(MoneyError::Invalid(input.to_string())),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_rounding() {
+        for input in ["101.255", "0.001", "-0.009"] {
+            assert_eq!(
+                Money::parse(input, Currency::Usd),
+                Err(MoneyError::TooPrecise(input.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        assert_eq!(
+            Money::parse("92233720368547758.08", Currency::Usd),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::parse("99999999999999999999", Currency::Usd),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::parse("92233720368547758.07", Currency::Usd),
+            Ok(Money::usd(i64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Money::usd(0).to_string(), "0.00");
+        assert_eq!(Money::usd(5).to_string(), "0.05");
+        assert_eq!(Money::usd(-5).to_string(), "-0.05");
+        assert_eq!(Money::usd(10125).to_string(), "101.25");
+        assert_eq!(Money::usd(-10120).to_string(), "-101.20");
+        assert_eq!(Money::usd(i64::MIN).to_string(), "-92233720368547758.08");
+    }
+
+    #[test]
+    fn test_format_parse_round_trip() {
+        for amount in [0, 1, -1, 99, -100, 123456, i64::MAX, i64::MIN + 1] {
+            let money = Money::usd(amount);
+            assert_eq!(money.to_string().parse::<Money>(), Ok(money));
+        }
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let a = Money::usd(10125);
+        assert_eq!(a.checked_add(Money::usd(75)), Ok(Money::usd(10200)));
+        assert_eq!(a.checked_sub(Money::usd(20000)), Ok(Money::usd(-9875)));
+        assert_eq!(
+            Money::usd(i64::MAX).checked_add(Money::usd(1)),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::usd(i64::MIN).checked_sub(Money::usd(1)),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_notional() {
+        let usd = |price: &str, quantity: &str| {
+            Money::notional(
+                price.parse().unwrap(),
+                quantity.parse().unwrap(),
+                Currency::Usd,
+            )
+        };
+        assert_eq!(usd("101.25", "4"), Ok(Money::usd(40500)));
+        assert_eq!(usd("101.25", "0.5"), Ok(Money::usd(5063)));
+        // only the total is rounded: 3 x 0.3333 is 0.9999, which is a dollar
+        assert_eq!(usd("0.3333", "3"), Ok(Money::usd(100)));
+        assert_eq!(usd("0.0049", "1"), Ok(Money::usd(0)));
+        assert_eq!(usd("-101.25", "0.5"), Ok(Money::usd(-5063)));
+        assert_eq!(
+            Money::notional(Price::MAX, Qty::shares(1_000), Currency::Usd),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::notional(Price::MAX, Qty::MAX, Currency::Usd),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_basis_points() {
+        assert_eq!(Money::usd(1_015_000).bps(10), Ok(Money::usd(1015)));
+        // 507.5 cents, rounded away from zero either way
+        assert_eq!(Money::usd(1_015_000).bps(-5), Ok(Money::usd(-508)));
+        assert_eq!(Money::usd(1_015_000).bps(5), Ok(Money::usd(508)));
+        assert_eq!(Money::usd(1_049).bps(1), Ok(Money::usd(0)));
+        assert_eq!(Money::usd(i64::MAX).bps(20_000), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn test_serde_shape() {
+        let json = serde_json::to_value(Money::usd(10125)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"amount": "101.25", "currency": "USD"})
+        );
+
+        let parsed: Money =
+            serde_json::from_value(serde_json::json!({"amount": "-0.50", "currency": "USD"}))
+                .unwrap();
+        assert_eq!(parsed, Money::usd(-50));
+
+        let too_precise = serde_json::from_value::<Money>(
+            serde_json::json!({"amount": "1.005", "currency": "USD"}),
+        );
+        assert!(too_precise.is_err());
+    }
+}
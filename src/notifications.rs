@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{clock::Clock, ids::IdGenerator};
+
+// Oldest entries fall off once a user's inbox holds this many.
+pub const MAX_INBOX_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Trade,
+    OrderRejected,
+    // the engine never answered an order and doesn't have it
+    OrderLost,
+    // an admin reversed one of the user's trades
+    TradeBust,
+    AdminAction,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 5] = [
+        NotificationKind::Trade,
+        NotificationKind::OrderRejected,
+        NotificationKind::OrderLost,
+        NotificationKind::TradeBust,
+        NotificationKind::AdminAction,
+    ];
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Notification {
+    pub id: u64,
+    pub kind: NotificationKind,
+    pub payload: serde_json::Value,
+    pub created_at: i64,
+    pub read: bool,
+}
+
+// Which kinds land in the inbox. The inbox is the only delivery channel so
+// far; others would get their own set here.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NotificationPrefs {
+    pub inbox: BTreeSet<NotificationKind>,
+}
+
+impl Default for NotificationPrefs {
+    fn default() -> Self {
+        Self {
+            inbox: NotificationKind::ALL.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NotFound;
+
+#[derive(Default)]
+struct Inbox {
+    prefs: NotificationPrefs,
+    notifications: VecDeque<Notification>,
+    unread: usize,
+}
+
+// Single entry point for everything that tells a user something happened.
+pub struct Notifier {
+    clock: Arc<dyn Clock>,
+    ids: Arc<IdGenerator>,
+    inboxes: Mutex<HashMap<String, Inbox>>,
+}
+
+impl Notifier {
+    pub fn new(clock: Arc<dyn Clock>, ids: Arc<IdGenerator>) -> Self {
+        Self {
+            clock,
+            ids,
+            inboxes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns the new notification's id, or None when the user's preferences
+    // filtered it out.
+    pub fn dispatch(
+        &self,
+        user: &str,
+        kind: NotificationKind,
+        payload: serde_json::Value,
+    ) -> Option<u64> {
+        let created_at = self.clock.now_millis();
+        let mut inboxes = self.inboxes.lock().unwrap();
+        let inbox = inboxes.entry(user.to_string()).or_default();
+        if !inbox.prefs.inbox.contains(&kind) {
+            return None;
+        }
+
+        let id = self.ids.next();
+        inbox.notifications.push_back(Notification {
+            id,
+            kind,
+            payload,
+            created_at,
+            read: false,
+        });
+        inbox.unread += 1;
+        if inbox.notifications.len() > MAX_INBOX_SIZE
+            && let Some(dropped) = inbox.notifications.pop_front()
+            && !dropped.read
+        {
+            inbox.unread -= 1;
+        }
+        Some(id)
+    }
+
+    pub fn list(&self, user: &str) -> Vec<Notification> {
+        self.inboxes
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|inbox| inbox.notifications.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn unread(&self, user: &str) -> usize {
+        let inboxes = self.inboxes.lock().unwrap();
+        inboxes.get(user).map_or(0, |inbox| inbox.unread)
+    }
+
+    // Marking an already-read notification is a no-op.
+    pub fn mark_read(&self, user: &str, id: u64) -> Result<(), NotFound> {
+        let mut inboxes = self.inboxes.lock().unwrap();
+        let inbox = inboxes.get_mut(user).ok_or(NotFound)?;
+        let notification = inbox
+            .notifications
+            .iter_mut()
+            .find(|n| n.id == id)
+            .ok_or(NotFound)?;
+        if !notification.read {
+            notification.read = true;
+            inbox.unread -= 1;
+        }
+        Ok(())
+    }
+
+    pub fn prefs(&self, user: &str) -> NotificationPrefs {
+        self.inboxes
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|inbox| inbox.prefs.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_prefs(&self, user: &str, prefs: NotificationPrefs) {
+        let mut inboxes = self.inboxes.lock().unwrap();
+        inboxes.entry(user.to_string()).or_default().prefs = prefs;
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::test_support::FakeClock;
+    use serde_json::json;
+
+    fn notifier() -> Notifier {
+        let clock = Arc::new(FakeClock::new(1_760_486_400_000));
+        Notifier::new(clock.clone(), Arc::new(IdGenerator::new(clock, 0)))
+    }
+
+    #[test]
+    fn test_preferences_filter_inbox() {
+        let notifier = notifier();
+        notifier.set_prefs(
+            "a",
+            NotificationPrefs {
+                inbox: BTreeSet::from([NotificationKind::Trade]),
+            },
+        );
+
+        assert!(
+            notifier
+                .dispatch("a", NotificationKind::OrderRejected, json!({}))
+                .is_none()
+        );
+        assert!(
+            notifier
+                .dispatch("a", NotificationKind::Trade, json!({ "quantity": 5 }))
+                .is_some()
+        );
+        // other users keep the defaults
+        assert!(
+            notifier
+                .dispatch("b", NotificationKind::OrderRejected, json!({}))
+                .is_some()
+        );
+
+        let inbox = notifier.list("a");
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].kind, NotificationKind::Trade);
+        assert_eq!(inbox[0].created_at, 1_760_486_400_000);
+        assert_eq!(notifier.prefs("b"), NotificationPrefs::default());
+    }
+
+    #[test]
+    fn test_unread_bookkeeping() {
+        let notifier = notifier();
+        let first = notifier
+            .dispatch("a", NotificationKind::Trade, json!({}))
+            .unwrap();
+        notifier.dispatch("a", NotificationKind::AdminAction, json!({}));
+        assert_eq!(notifier.unread("a"), 2);
+
+        notifier.mark_read("a", first).unwrap();
+        notifier.mark_read("a", first).unwrap();
+        assert_eq!(notifier.unread("a"), 1);
+        assert_eq!(notifier.mark_read("b", first), Err(NotFound));
+        assert_eq!(notifier.mark_read("a", 99), Err(NotFound));
+
+        // an unread notification aging out of a full inbox leaves the count right
+        for _ in 0..MAX_INBOX_SIZE {
+            notifier.dispatch("a", NotificationKind::Trade, json!({}));
+        }
+        assert_eq!(notifier.list("a").len(), MAX_INBOX_SIZE);
+        assert_eq!(notifier.unread("a"), MAX_INBOX_SIZE);
+    }
+}
@@ -0,0 +1,218 @@
+use orderbook::{Order, Qty, TimeInForce, TradeEvent};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+// Closed orders are kept this long, oldest dropped first, so a request about
+// one that just finished is still answered.
+pub const RETAINED_CLOSED_ORDERS: usize = 10_000;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Open,
+    Closed,
+}
+
+// An order as the engine acked it, `order.quantity` being what it has left.
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackedOrder {
+    pub order: Order,
+    pub status: OrderStatus,
+}
+
+impl TrackedOrder {
+    // Market, IOC and FOK orders are done on arrival and never rest.
+    pub fn can_rest(&self) -> bool {
+        self.order.price.is_some() && self.order.time_in_force == TimeInForce::Gtc
+    }
+}
+
+#[derive(Default)]
+struct Orders {
+    by_id: HashMap<u64, TrackedOrder>,
+    // oldest first
+    closed: VecDeque<u64>,
+}
+
+impl Orders {
+    fn close(&mut self, order_id: u64) {
+        let Some(tracked) = self.by_id.get_mut(&order_id) else {
+            return;
+        };
+        if tracked.status == OrderStatus::Closed {
+            return;
+        }
+        tracked.status = OrderStatus::Closed;
+        self.closed.push_back(order_id);
+        if self.closed.len() > RETAINED_CLOSED_ORDERS
+            && let Some(oldest) = self.closed.pop_front()
+        {
+            self.by_id.remove(&oldest);
+        }
+    }
+}
+
+// What the gateway knows of each order from the engine's events: acks open
+// one, trades take from what it has left, amends resize it, and cancels,
+// expiries and its last fill close it.
+#[derive(Default)]
+pub struct OrderStore {
+    orders: Mutex<Orders>,
+}
+
+impl OrderStore {
+    pub fn accepted(&self, order: &Order) {
+        let tracked = TrackedOrder {
+            order: order.clone(),
+            status: OrderStatus::Open,
+        };
+        self.orders
+            .lock()
+            .unwrap()
+            .by_id
+            .insert(order.order_id, tracked);
+    }
+
+    pub fn traded(&self, trade: &TradeEvent) {
+        let mut orders = self.orders.lock().unwrap();
+        for order_id in [trade.maker_order_id, trade.taker_order_id] {
+            let Some(tracked) = orders.by_id.get_mut(&order_id) else {
+                continue;
+            };
+            let left = tracked.order.quantity.saturating_sub(trade.quantity);
+            tracked.order.quantity = left;
+            if left.is_zero() {
+                orders.close(order_id);
+            }
+        }
+    }
+
+    pub fn amended(&self, order_id: u64, quantity: Qty) {
+        if let Some(tracked) = self.orders.lock().unwrap().by_id.get_mut(&order_id) {
+            tracked.order.quantity = quantity;
+        }
+    }
+
+    pub fn closed(&self, order_id: u64) {
+        self.orders.lock().unwrap().close(order_id);
+    }
+
+    pub fn get(&self, order_id: u64) -> Option<TrackedOrder> {
+        self.orders.lock().unwrap().by_id.get(&order_id).cloned()
+    }
+
+    // A user's orders still resting, with what each has left, oldest id
+    // first.
+    pub fn resting(&self, user: &str) -> Vec<Order> {
+        let orders = self.orders.lock().unwrap();
+        let mut resting: Vec<Order> = orders
+            .by_id
+            .values()
+            .filter(|t| t.order.user == user && t.status == OrderStatus::Open && t.can_rest())
+            .map(|t| t.order.clone())
+            .collect();
+        resting.sort_by_key(|order| order.order_id);
+        resting
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orderbook::{Price, Side};
+
+    fn order(order_id: u64, quantity: u64) -> Order {
+        Order {
+            order_id,
+            ..Order::new_limit_order(
+                Qty::shares(quantity),
+                Some(Price::cents(100)),
+                Side::Buy,
+                String::from("AAPL"),
+                String::from("a"),
+            )
+        }
+    }
+
+    fn trade(maker: u64, taker: u64, quantity: u64) -> TradeEvent {
+        TradeEvent {
+            trade_id: 1,
+            buyer: String::from("a"),
+            seller: String::from("b"),
+            symbol: String::from("AAPL"),
+            quantity: Qty::shares(quantity),
+            price: Price::cents(100),
+            taker_side: Side::Sell,
+            timestamp: 0,
+            maker_received_at: 0,
+            maker_order_id: maker,
+            maker_remaining: Qty::ZERO,
+            taker_order_id: taker,
+            buyer_tags: Vec::new(),
+            seller_tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_order_follows_its_events() {
+        let store = OrderStore::default();
+        store.accepted(&order(1, 10));
+        store.traded(&trade(1, 2, 4));
+        let tracked = store.get(1).unwrap();
+        assert_eq!(
+            (tracked.order.quantity, tracked.status),
+            (Qty::shares(6), OrderStatus::Open)
+        );
+
+        store.amended(1, Qty::shares(3));
+        store.traded(&trade(1, 3, 3));
+        assert_eq!(store.get(1).unwrap().status, OrderStatus::Closed);
+
+        store.accepted(&order(4, 10));
+        store.closed(4);
+        assert_eq!(store.get(4).unwrap().status, OrderStatus::Closed);
+        assert!(store.get(5).is_none());
+    }
+
+    #[test]
+    fn test_resting_orders_for_a_user() {
+        let store = OrderStore::default();
+        store.accepted(&order(2, 10));
+        store.accepted(&order(1, 10));
+        store.accepted(&Order {
+            user: String::from("b"),
+            ..order(3, 10)
+        });
+        store.accepted(&Order {
+            time_in_force: TimeInForce::Ioc,
+            ..order(4, 10)
+        });
+        store.accepted(&order(5, 10));
+        store.closed(5);
+        store.traded(&trade(2, 6, 4));
+
+        let resting = store.resting("a");
+        assert_eq!(
+            resting
+                .iter()
+                .map(|order| (order.order_id, order.quantity))
+                .collect::<Vec<_>>(),
+            vec![(1, Qty::shares(10)), (2, Qty::shares(6))]
+        );
+    }
+
+    #[test]
+    fn test_oldest_closed_orders_dropped() {
+        let store = OrderStore::default();
+        for id in 1..=RETAINED_CLOSED_ORDERS as u64 + 1 {
+            store.accepted(&order(id, 1));
+            store.closed(id);
+        }
+        assert!(store.get(1).is_none());
+        assert!(store.get(2).is_some());
+    }
+}
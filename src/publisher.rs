@@ -0,0 +1,106 @@
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct PublishError(pub String);
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to publish: {}", self.0)
+    }
+}
+
+impl From<redis::RedisError> for PublishError {
+    fn from(e: redis::RedisError) -> Self {
+        PublishError(e.to_string())
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PublisherMetrics {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub batches: u64,
+    pub messages: u64,
+    pub largest_batch: usize,
+    // publishes turned away because the queue was full
+    pub rejected: u64,
+}
+
+// Outbound side of the message bus between the gateway and the engine.
+pub trait Publisher: Send + Sync {
+    fn publish(
+        &self,
+        channel: &'static str,
+        payload: String,
+    ) -> BoxFuture<'_, Result<(), PublishError>>;
+
+    // Only queueing publishers have anything to report.
+    fn metrics(&self) -> Option<PublisherMetrics> {
+        None
+    }
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    // Records everything published instead of sending it anywhere.
+    #[derive(Default)]
+    pub struct RecordingPublisher {
+        pub published: Mutex<Vec<(String, String)>>,
+        failing: AtomicBool,
+    }
+
+    impl RecordingPublisher {
+        pub fn fail(&self) {
+            self.failing.store(true, Ordering::SeqCst);
+        }
+
+        // The payloads published on `channel`, out of their envelopes.
+        pub fn messages(&self, channel: &str) -> Vec<serde_json::Value> {
+            self.envelopes(channel)
+                .into_iter()
+                .map(|mut envelope| envelope["payload"].take())
+                .collect()
+        }
+
+        pub fn envelopes(&self, channel: &str) -> Vec<serde_json::Value> {
+            self.published
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(c, _)| c == channel)
+                .map(|(_, raw)| {
+                    let envelope = orderbook::envelope::open(raw).unwrap();
+                    assert_eq!(envelope.v, orderbook::envelope::ENVELOPE_VERSION);
+                    serde_json::from_str(raw).unwrap()
+                })
+                .collect()
+        }
+    }
+
+    impl Publisher for RecordingPublisher {
+        fn publish(
+            &self,
+            channel: &'static str,
+            payload: String,
+        ) -> BoxFuture<'_, Result<(), PublishError>> {
+            Box::pin(async move {
+                if self.failing.load(Ordering::SeqCst) {
+                    return Err(PublishError("connection refused".to_string()));
+                }
+                self.published
+                    .lock()
+                    .unwrap()
+                    .push((channel.to_string(), payload));
+                Ok(())
+            })
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use orderbook::{Order, Price, Qty, Side};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+// One resting order a market maker wants in its ladder.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rung {
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Qty,
+}
+
+// What it takes to turn a user's resting orders into the ladder they asked
+// for, one order at a time.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Step {
+    // already resting as asked
+    Keep {
+        order_id: u64,
+    },
+    // resting at a price no rung wants
+    Cancel {
+        order_id: u64,
+    },
+    // smaller at the same price: reduced in place, keeping its priority
+    Reduce {
+        order_id: u64,
+        by: Qty,
+        quantity: Qty,
+    },
+    // larger at the same price: amended, which sends it to the back
+    Amend {
+        order_id: u64,
+        quantity: Qty,
+    },
+    Place {
+        rung: Rung,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DuplicateRung {
+    pub side: Side,
+    pub price: Price,
+}
+
+// Diffs `current`, a user's resting orders in one symbol with what each has
+// left, against the `desired` ladder. Each rung is matched to the oldest
+// order at its side and price; any other order there is cancelled with the
+// rest. Cancels come first, then resizes, then new orders, so what the user
+// has in the book shrinks before it grows.
+//
+// An order that fills while this is on its way to the engine makes its
+// step fail there; the next update finds it gone and places the rung again.
+pub fn plan(current: &[Order], desired: &[Rung]) -> Result<Vec<Step>, DuplicateRung> {
+    // a side and price, as something a map can be keyed on
+    let key = |side: &Side, price: Price| (*side == Side::Buy, price);
+
+    let mut seen = HashSet::new();
+    for rung in desired {
+        if !seen.insert(key(&rung.side, rung.price)) {
+            return Err(DuplicateRung {
+                side: rung.side.clone(),
+                price: rung.price,
+            });
+        }
+    }
+
+    // oldest first at each side and price
+    let mut resting: BTreeMap<(bool, Price), Vec<&Order>> = BTreeMap::new();
+    let mut by_id: Vec<&Order> = current.iter().filter(|o| o.price.is_some()).collect();
+    by_id.sort_by_key(|order| order.order_id);
+    for order in by_id {
+        resting
+            .entry(key(&order.side, order.price.unwrap()))
+            .or_default()
+            .push(order);
+    }
+
+    let (mut resizes, mut places) = (Vec::new(), Vec::new());
+    for rung in desired {
+        let matched = resting
+            .get_mut(&key(&rung.side, rung.price))
+            .filter(|orders| !orders.is_empty())
+            .map(|orders| orders.remove(0));
+        let Some(order) = matched else {
+            places.push(Step::Place { rung: rung.clone() });
+            continue;
+        };
+        let order_id = order.order_id;
+        resizes.push(if rung.quantity == order.quantity {
+            Step::Keep { order_id }
+        } else if rung.quantity < order.quantity {
+            Step::Reduce {
+                order_id,
+                by: order.quantity - rung.quantity,
+                quantity: rung.quantity,
+            }
+        } else {
+            Step::Amend {
+                order_id,
+                quantity: rung.quantity,
+            }
+        });
+    }
+    let mut unwanted: Vec<u64> = resting
+        .into_values()
+        .flatten()
+        .map(|order| order.order_id)
+        .collect();
+    unwanted.sort();
+    let cancels = unwanted
+        .into_iter()
+        .map(|order_id| Step::Cancel { order_id });
+
+    Ok(cancels.chain(resizes).chain(places).collect())
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(order_id: u64, side: Side, price: &str, quantity: u64) -> Order {
+        Order {
+            order_id,
+            ..Order::new_limit_order(
+                Qty::shares(quantity),
+                Some(price.parse().unwrap()),
+                side,
+                String::from("AAPL"),
+                String::from("mm"),
+            )
+        }
+    }
+
+    fn rung(side: Side, price: &str, quantity: u64) -> Rung {
+        Rung {
+            side,
+            price: price.parse().unwrap(),
+            quantity: Qty::shares(quantity),
+        }
+    }
+
+    fn ladder() -> Vec<Order> {
+        vec![
+            order(1, Side::Buy, "99", 10),
+            order(2, Side::Buy, "98", 10),
+            order(3, Side::Sell, "101", 10),
+            order(4, Side::Sell, "102", 10),
+        ]
+    }
+
+    #[test]
+    fn test_unchanged_ladder_kept() {
+        let desired = [
+            rung(Side::Buy, "99", 10),
+            rung(Side::Buy, "98", 10),
+            rung(Side::Sell, "101", 10),
+            rung(Side::Sell, "102", 10),
+        ];
+        assert_eq!(
+            plan(&ladder(), &desired).unwrap(),
+            (1..=4)
+                .map(|order_id| Step::Keep { order_id })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_rungs_added_and_removed() {
+        // the ladder shifts up a dollar
+        let desired = [
+            rung(Side::Buy, "100", 10),
+            rung(Side::Buy, "99", 10),
+            rung(Side::Sell, "102", 10),
+            rung(Side::Sell, "103", 10),
+        ];
+        assert_eq!(
+            plan(&ladder(), &desired).unwrap(),
+            vec![
+                Step::Cancel { order_id: 2 },
+                Step::Cancel { order_id: 3 },
+                Step::Keep { order_id: 1 },
+                Step::Keep { order_id: 4 },
+                Step::Place {
+                    rung: rung(Side::Buy, "100", 10)
+                },
+                Step::Place {
+                    rung: rung(Side::Sell, "103", 10)
+                },
+            ]
+        );
+
+        // pulling every quote
+        assert_eq!(
+            plan(&ladder(), &[]).unwrap(),
+            (1..=4)
+                .map(|order_id| Step::Cancel { order_id })
+                .collect::<Vec<_>>()
+        );
+        // and putting them back
+        assert_eq!(plan(&[], &[rung(Side::Buy, "99", 10)]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rungs_resized() {
+        let current = vec![
+            order(1, Side::Buy, "99", 10),
+            // partly filled since it was placed
+            order(3, Side::Sell, "101", 4),
+        ];
+        let desired = [rung(Side::Buy, "99", 6), rung(Side::Sell, "101", 10)];
+        assert_eq!(
+            plan(&current, &desired).unwrap(),
+            vec![
+                Step::Reduce {
+                    order_id: 1,
+                    by: Qty::shares(4),
+                    quantity: Qty::shares(6),
+                },
+                Step::Amend {
+                    order_id: 3,
+                    quantity: Qty::shares(10),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filled_rung_replaced_and_extras_cancelled() {
+        // 1 filled and is gone; two orders share 101, the older one kept
+        let current = vec![
+            order(5, Side::Sell, "101", 3),
+            order(3, Side::Sell, "101", 10),
+        ];
+        let desired = [rung(Side::Buy, "99", 10), rung(Side::Sell, "101", 10)];
+        assert_eq!(
+            plan(&current, &desired).unwrap(),
+            vec![
+                Step::Cancel { order_id: 5 },
+                Step::Keep { order_id: 3 },
+                Step::Place {
+                    rung: rung(Side::Buy, "99", 10)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_rungs_refused() {
+        let desired = [rung(Side::Buy, "99", 10), rung(Side::Buy, "99", 5)];
+        assert_eq!(
+            plan(&[], &desired),
+            Err(DuplicateRung {
+                side: Side::Buy,
+                price: "99".parse().unwrap(),
+            })
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::{HashMap, VecDeque};
+
+// Allows at most `max_events` per key within any rolling window of
+// `window_millis`. Callers pass the current time so it can be driven by a
+// Clock.
+pub struct RollingWindowLimiter {
+    max_events: usize,
+    window_millis: i64,
+    events: HashMap<String, VecDeque<i64>>,
+}
+
+impl RollingWindowLimiter {
+    pub fn new(max_events: usize, window_millis: i64) -> Self {
+        Self {
+            max_events,
+            window_millis,
+            events: HashMap::new(),
+        }
+    }
+
+    // Records an event for `key` if the window has room, returning how many
+    // remain afterwards.
+    pub fn try_acquire(&mut self, key: &str, now: i64) -> Option<usize> {
+        let events = self.events.entry(key.to_string()).or_default();
+        Self::evict(events, now, self.window_millis);
+        if events.len() >= self.max_events {
+            return None;
+        }
+        events.push_back(now);
+        Some(self.max_events - events.len())
+    }
+
+    pub fn remaining(&self, key: &str, now: i64) -> usize {
+        let used = self.events.get(key).map_or(0, |events| {
+            events
+                .iter()
+                .filter(|&&at| at > now - self.window_millis)
+                .count()
+        });
+        self.max_events.saturating_sub(used)
+    }
+
+    fn evict(events: &mut VecDeque<i64>, now: i64, window_millis: i64) {
+        while let Some(&oldest) = events.front() {
+            if oldest > now - window_millis {
+                break;
+            }
+            events.pop_front();
+        }
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_rolls() {
+        let mut limiter = RollingWindowLimiter::new(2, 1000);
+        assert_eq!(limiter.try_acquire("a", 0), Some(1));
+        assert_eq!(limiter.try_acquire("a", 500), Some(0));
+        assert_eq!(limiter.try_acquire("a", 999), None);
+        assert_eq!(limiter.remaining("a", 999), 0);
+
+        // the first event leaves the window exactly `window_millis` later
+        assert_eq!(limiter.remaining("a", 1000), 1);
+        assert_eq!(limiter.try_acquire("a", 1000), Some(0));
+        assert_eq!(limiter.try_acquire("a", 1499), None);
+        assert_eq!(limiter.try_acquire("a", 1500), Some(0));
+    }
+
+    #[test]
+    fn test_keys_are_independent() {
+        let mut limiter = RollingWindowLimiter::new(1, 1000);
+        assert_eq!(limiter.try_acquire("a", 0), Some(0));
+        assert_eq!(limiter.try_acquire("b", 0), Some(0));
+        assert_eq!(limiter.try_acquire("a", 10), None);
+        assert_eq!(limiter.remaining("c", 10), 1);
+    }
+}
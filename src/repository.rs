@@ -0,0 +1,42 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::User;
+
+// Storage for user accounts. Handlers and background jobs only go through
+// this trait so the backing store can be swapped out.
+pub trait UserRepository: Send + Sync {
+    fn insert(&self, user: User);
+    fn get(&self, email: &str) -> Option<User>;
+    fn all(&self) -> Vec<User>;
+    // Applies `update` to the stored user in place; false when the user is unknown.
+    fn update(&self, email: &str, update: &mut dyn FnMut(&mut User)) -> bool;
+}
+
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: Mutex<HashMap<String, User>>,
+}
+
+impl UserRepository for InMemoryUserRepository {
+    fn insert(&self, user: User) {
+        self.users.lock().unwrap().insert(user.email.clone(), user);
+    }
+
+    fn get(&self, email: &str) -> Option<User> {
+        self.users.lock().unwrap().get(email).cloned()
+    }
+
+    fn all(&self) -> Vec<User> {
+        self.users.lock().unwrap().values().cloned().collect()
+    }
+
+    fn update(&self, email: &str, update: &mut dyn FnMut(&mut User)) -> bool {
+        match self.users.lock().unwrap().get_mut(email) {
+            Some(user) => {
+                update(user);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use orderbook::{Price, Qty, Side};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    TradeEvent,
+    clock::Clock,
+    fees::Fees,
+    ids::IdGenerator,
+    repository::UserRepository,
+    settlement::{self, SettlementError},
+};
+
+pub const RFQ_WINDOW_ENV: &str = "RFQ_WINDOW_SECS";
+pub const DEFAULT_RFQ_WINDOW_MILLIS: i64 = 30_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RfqSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RfqStatus {
+    Open,
+    Accepted,
+    Expired,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub maker: String,
+    pub price: Price,
+    pub quoted_at: i64,
+}
+
+// A request for quotes on a block too large for the book. Market makers
+// quote until `expires_at`; the requester can accept any live quote.
+#[derive(Serialize, Debug, Clone)]
+pub struct Rfq {
+    pub id: u64,
+    pub requester: String,
+    pub symbol: String,
+    pub side: RfqSide,
+    pub quantity: Qty,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub status: RfqStatus,
+    pub quotes: Vec<Quote>,
+    pub accepted: Option<Quote>,
+}
+
+impl Rfq {
+    fn refresh(&mut self, now: i64) {
+        if self.status == RfqStatus::Open && now >= self.expires_at {
+            self.status = RfqStatus::Expired;
+        }
+    }
+
+    fn ensure_open(&self) -> Result<(), RfqError> {
+        match self.status {
+            RfqStatus::Open => Ok(()),
+            status => Err(RfqError::Closed(status)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RfqError {
+    NotFound,
+    Closed(RfqStatus),
+    NotRequester,
+    SelfQuote,
+    NoSuchQuote,
+    Settlement(SettlementError),
+}
+
+pub struct RfqDesk {
+    clock: Arc<dyn Clock>,
+    ids: Arc<IdGenerator>,
+    window_millis: i64,
+    rfqs: Mutex<BTreeMap<u64, Rfq>>,
+}
+
+impl RfqDesk {
+    pub fn new(clock: Arc<dyn Clock>, ids: Arc<IdGenerator>, window_millis: i64) -> Self {
+        Self {
+            clock,
+            ids,
+            window_millis,
+            rfqs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn request(&self, requester: &str, symbol: &str, side: RfqSide, quantity: Qty) -> Rfq {
+        let now = self.clock.now_millis();
+        let rfq = Rfq {
+            id: self.ids.next(),
+            requester: requester.to_string(),
+            symbol: symbol.to_string(),
+            side,
+            quantity,
+            created_at: now,
+            expires_at: now + self.window_millis,
+            status: RfqStatus::Open,
+            quotes: vec![],
+            accepted: None,
+        };
+        self.rfqs.lock().unwrap().insert(rfq.id, rfq.clone());
+        rfq
+    }
+
+    pub fn get(&self, id: u64) -> Option<Rfq> {
+        let now = self.clock.now_millis();
+        let mut rfqs = self.rfqs.lock().unwrap();
+        let rfq = rfqs.get_mut(&id)?;
+        rfq.refresh(now);
+        Some(rfq.clone())
+    }
+
+    // Requests still accepting quotes, for market makers to poll.
+    pub fn open(&self) -> Vec<Rfq> {
+        let now = self.clock.now_millis();
+        let mut rfqs = self.rfqs.lock().unwrap();
+        rfqs.values_mut()
+            .filter_map(|rfq| {
+                rfq.refresh(now);
+                (rfq.status == RfqStatus::Open).then(|| rfq.clone())
+            })
+            .collect()
+    }
+
+    // Adds or replaces `maker`'s quote.
+    pub fn quote(&self, id: u64, maker: &str, price: Price) -> Result<Rfq, RfqError> {
+        self.with_open(id, |rfq, now| {
+            if rfq.requester == maker {
+                return Err(RfqError::SelfQuote);
+            }
+            rfq.quotes.retain(|q| q.maker != maker);
+            rfq.quotes.push(Quote {
+                maker: maker.to_string(),
+                price,
+                quoted_at: now,
+            });
+            Ok(())
+        })
+    }
+
+    pub fn withdraw(&self, id: u64, maker: &str) -> Result<Rfq, RfqError> {
+        self.with_open(id, |rfq, _| {
+            let before = rfq.quotes.len();
+            rfq.quotes.retain(|q| q.maker != maker);
+            if rfq.quotes.len() == before {
+                return Err(RfqError::NoSuchQuote);
+            }
+            Ok(())
+        })
+    }
+
+    // Settles the block against `maker`'s quote. The desk stays locked while
+    // settling, so of two racing accepts exactly one wins.
+    pub fn accept(
+        &self,
+        id: u64,
+        requester: &str,
+        maker: &str,
+        users: &dyn UserRepository,
+        fees: &Fees,
+    ) -> Result<(Rfq, TradeEvent), RfqError> {
+        let mut trade = None;
+        let rfq = self.with_open(id, |rfq, now| {
+            if rfq.requester != requester {
+                return Err(RfqError::NotRequester);
+            }
+            let quote = rfq
+                .quotes
+                .iter()
+                .find(|q| q.maker == maker)
+                .cloned()
+                .ok_or(RfqError::NoSuchQuote)?;
+
+            let (buyer, seller) = match rfq.side {
+                RfqSide::Buy => (rfq.requester.clone(), quote.maker.clone()),
+                RfqSide::Sell => (quote.maker.clone(), rfq.requester.clone()),
+            };
+            let event = TradeEvent {
+                trade_id: 0,
+                buyer,
+                seller,
+                symbol: rfq.symbol.clone(),
+                quantity: rfq.quantity,
+                price: quote.price,
+                // the requester takes the maker's quote
+                taker_side: match rfq.side {
+                    RfqSide::Buy => Side::Buy,
+                    RfqSide::Sell => Side::Sell,
+                },
+                timestamp: now,
+                maker_received_at: 0,
+                maker_order_id: 0,
+                maker_remaining: Qty::ZERO,
+                taker_order_id: 0,
+                buyer_tags: Vec::new(),
+                seller_tags: Vec::new(),
+            };
+            settlement::settle_trade(users, fees, &event).map_err(RfqError::Settlement)?;
+
+            rfq.status = RfqStatus::Accepted;
+            rfq.accepted = Some(quote);
+            trade = Some(event);
+            Ok(())
+        })?;
+        Ok((rfq, trade.unwrap()))
+    }
+
+    fn with_open(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut Rfq, i64) -> Result<(), RfqError>,
+    ) -> Result<Rfq, RfqError> {
+        let now = self.clock.now_millis();
+        let mut rfqs = self.rfqs.lock().unwrap();
+        let rfq = rfqs.get_mut(&id).ok_or(RfqError::NotFound)?;
+        rfq.refresh(now);
+        rfq.ensure_open()?;
+        f(rfq, now)?;
+        Ok(rfq.clone())
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        User, clock::test_support::FakeClock, money::Money, repository::InMemoryUserRepository,
+    };
+    use std::collections::HashMap;
+
+    const NOW: i64 = 1_760_486_400_000;
+
+    fn setup() -> (Arc<FakeClock>, RfqDesk, InMemoryUserRepository) {
+        let clock = Arc::new(FakeClock::new(NOW));
+        let ids = Arc::new(IdGenerator::new(clock.clone(), 0));
+        let desk = RfqDesk::new(clock.clone(), ids, DEFAULT_RFQ_WINDOW_MILLIS);
+        let users = InMemoryUserRepository::default();
+        for (email, balance, shares) in
+            [("fund", 10_000_000, 0), ("mm1", 0, 5000), ("mm2", 0, 5000)]
+        {
+            users.insert(User {
+                email: email.to_string(),
+                current_balance: Money::usd(balance),
+                stocks: HashMap::from([("AAPL".to_string(), Qty::shares(shares))]),
+                faucet_claims_remaining: 0,
+                market_maker: email != "fund",
+            });
+        }
+        (clock, desk, users)
+    }
+
+    #[test]
+    fn test_request_quote_accept() {
+        let (clock, desk, users) = setup();
+        let rfq = desk.request("fund", "AAPL", RfqSide::Buy, Qty::shares(1000));
+        assert_eq!(rfq.expires_at, NOW + DEFAULT_RFQ_WINDOW_MILLIS);
+
+        desk.quote(rfq.id, "mm1", Price::cents(10100)).unwrap();
+        desk.quote(rfq.id, "mm2", Price::cents(10050)).unwrap();
+        // a maker can improve their quote, replacing the old one
+        clock.set(NOW + 1000);
+        let quoted = desk.quote(rfq.id, "mm1", Price::cents(10000)).unwrap();
+        assert_eq!(quoted.quotes.len(), 2);
+        assert_eq!(quoted.quotes[1].price, Price::cents(10000));
+        assert_eq!(
+            desk.quote(rfq.id, "fund", Price::cents(9000)).unwrap_err(),
+            RfqError::SelfQuote
+        );
+
+        assert_eq!(
+            desk.accept(rfq.id, "mm2", "mm1", &users, &Fees::default())
+                .unwrap_err(),
+            RfqError::NotRequester
+        );
+        let (accepted, trade) = desk
+            .accept(rfq.id, "fund", "mm1", &users, &Fees::default())
+            .unwrap();
+        assert_eq!(accepted.status, RfqStatus::Accepted);
+        assert_eq!(trade.seller, "mm1");
+        assert_eq!(trade.price, Price::cents(10000));
+
+        assert_eq!(users.get("fund").unwrap().current_balance, Money::usd(0));
+        assert_eq!(users.get("mm1").unwrap().stocks["AAPL"], Qty::shares(4000));
+
+        // the losing accept sees the request closed
+        assert_eq!(
+            desk.accept(rfq.id, "fund", "mm2", &users, &Fees::default())
+                .unwrap_err(),
+            RfqError::Closed(RfqStatus::Accepted)
+        );
+        assert!(desk.open().is_empty());
+    }
+
+    #[test]
+    fn test_expiry_and_withdrawal() {
+        let (clock, desk, users) = setup();
+        let rfq = desk.request("fund", "AAPL", RfqSide::Sell, Qty::shares(10));
+        desk.quote(rfq.id, "mm1", Price::cents(9900)).unwrap();
+        desk.withdraw(rfq.id, "mm1").unwrap();
+        assert_eq!(
+            desk.withdraw(rfq.id, "mm1").unwrap_err(),
+            RfqError::NoSuchQuote
+        );
+        assert_eq!(
+            desk.accept(rfq.id, "fund", "mm1", &users, &Fees::default())
+                .unwrap_err(),
+            RfqError::NoSuchQuote
+        );
+
+        desk.quote(rfq.id, "mm2", Price::cents(9900)).unwrap();
+        clock.set(NOW + DEFAULT_RFQ_WINDOW_MILLIS);
+        assert_eq!(desk.get(rfq.id).unwrap().status, RfqStatus::Expired);
+        assert!(desk.open().is_empty());
+        assert_eq!(
+            desk.accept(rfq.id, "fund", "mm2", &users, &Fees::default())
+                .unwrap_err(),
+            RfqError::Closed(RfqStatus::Expired)
+        );
+        assert_eq!(
+            desk.quote(rfq.id, "mm1", Price::cents(9950)).unwrap_err(),
+            RfqError::Closed(RfqStatus::Expired)
+        );
+    }
+
+    #[test]
+    fn test_failed_settlement_keeps_rfq_open() {
+        let (_, desk, users) = setup();
+        let rfq = desk.request("fund", "AAPL", RfqSide::Buy, Qty::shares(1_000_000));
+        desk.quote(rfq.id, "mm1", Price::cents(10000)).unwrap();
+        assert_eq!(
+            desk.accept(rfq.id, "fund", "mm1", &users, &Fees::default())
+                .unwrap_err(),
+            RfqError::Settlement(SettlementError::InsufficientFunds("fund".to_string()))
+        );
+        assert_eq!(desk.get(rfq.id).unwrap().status, RfqStatus::Open);
+    }
+}
@@ -0,0 +1,683 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    Qty, TradeEvent, User,
+    fees::{Fees, TradeFees},
+    ids::IdGenerator,
+    money::{Currency, Money, MoneyError},
+    repository::UserRepository,
+};
+
+// Pending dead letters above this are worth shouting about.
+pub const DLQ_WARN_DEPTH: usize = 100;
+// Settled trades kept for busting, oldest dropped first.
+pub const RETAINED_TRADES: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettlementError {
+    UnknownUser(String),
+    InsufficientFunds(String),
+    // a bust would take back shares the buyer no longer holds
+    InsufficientShares(String),
+    Overflow,
+}
+
+impl fmt::Display for SettlementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettlementError::UnknownUser(email) => write!(f, "unknown user {}", email),
+            SettlementError::InsufficientFunds(email) => {
+                write!(f, "{} cannot cover the trade notional", email)
+            }
+            SettlementError::InsufficientShares(email) => {
+                write!(f, "{} no longer holds the shares to give back", email)
+            }
+            SettlementError::Overflow => f.write_str("money amount overflowed"),
+        }
+    }
+}
+
+impl From<MoneyError> for SettlementError {
+    fn from(_: MoneyError) -> Self {
+        SettlementError::Overflow
+    }
+}
+
+// Moves cash and shares between the two sides of a trade, and each side's
+// fee or rebate between it and the fee account. Both sides are checked
+// before either is touched, so a failed trade leaves no trace.
+pub fn settle_trade(
+    users: &dyn UserRepository,
+    fees: &Fees,
+    event: &TradeEvent,
+) -> Result<TradeFees, SettlementError> {
+    let notional = Money::notional(event.price, event.quantity, Currency::Usd)?;
+    let due = fees.for_trade(event, notional)?;
+
+    let buyer = lookup(users, &event.buyer)?;
+    let seller = lookup(users, &event.seller)?;
+    let buyer_balance = buyer
+        .current_balance
+        .checked_sub(notional)?
+        .checked_sub(due.buyer)?;
+    if buyer_balance.is_negative() {
+        return Err(SettlementError::InsufficientFunds(event.buyer.clone()));
+    }
+    seller
+        .current_balance
+        .checked_add(notional)?
+        .checked_sub(due.seller)?;
+    fees.collect(event, due)?;
+
+    users.update(&event.buyer, &mut |buyer| {
+        // Buyer spends money
+        buyer.current_balance = buyer_balance;
+        // Buyer gains stock
+        *buyer.stocks.entry(event.symbol.clone()).or_default() += event.quantity;
+    });
+    users.update(&event.seller, &mut |seller| {
+        // Seller receives money
+        if let Ok(balance) = seller
+            .current_balance
+            .checked_add(notional)
+            .and_then(|balance| balance.checked_sub(due.seller))
+        {
+            seller.current_balance = balance;
+        }
+
+        // Seller loses stock, so subtract the quantity
+        if let Some(current_quantity) = seller.stocks.get_mut(&event.symbol) {
+            *current_quantity = current_quantity.saturating_sub(event.quantity);
+        }
+    });
+    Ok(due)
+}
+
+// Undoes a settled trade: the shares go back to the seller, the cash to the
+// buyer, and the fees both paid come back out of the fee account. Refused,
+// leaving no trace, once the buyer no longer holds the shares or the seller
+// can't repay the cash.
+pub fn reverse_trade(
+    users: &dyn UserRepository,
+    fees: &Fees,
+    event: &TradeEvent,
+    paid: TradeFees,
+) -> Result<(), SettlementError> {
+    let notional = Money::notional(event.price, event.quantity, Currency::Usd)?;
+
+    let buyer = lookup(users, &event.buyer)?;
+    let seller = lookup(users, &event.seller)?;
+    let held = buyer
+        .stocks
+        .get(&event.symbol)
+        .copied()
+        .unwrap_or(Qty::ZERO);
+    if held < event.quantity {
+        return Err(SettlementError::InsufficientShares(event.buyer.clone()));
+    }
+    let seller_balance = seller
+        .current_balance
+        .checked_sub(notional)?
+        .checked_add(paid.seller)?;
+    if seller_balance.is_negative() {
+        return Err(SettlementError::InsufficientFunds(event.seller.clone()));
+    }
+    buyer
+        .current_balance
+        .checked_add(notional)?
+        .checked_add(paid.buyer)?;
+    fees.refund(event, paid)?;
+
+    users.update(&event.seller, &mut |seller| {
+        seller.current_balance = seller_balance;
+        *seller.stocks.entry(event.symbol.clone()).or_default() += event.quantity;
+    });
+    users.update(&event.buyer, &mut |buyer| {
+        if let Ok(balance) = buyer
+            .current_balance
+            .checked_add(notional)
+            .and_then(|balance| balance.checked_add(paid.buyer))
+        {
+            buyer.current_balance = balance;
+        }
+        if let Some(current_quantity) = buyer.stocks.get_mut(&event.symbol) {
+            *current_quantity = current_quantity.saturating_sub(event.quantity);
+        }
+    });
+    Ok(())
+}
+
+fn lookup(users: &dyn UserRepository, email: &str) -> Result<User, SettlementError> {
+    users
+        .get(email)
+        .ok_or_else(|| SettlementError::UnknownUser(email.to_string()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterStatus {
+    Pending,
+    Discarded,
+}
+
+// A trade event settlement could not apply, parked until an admin retries or
+// discards it.
+#[derive(Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub id: u64,
+    pub event: TradeEvent,
+    pub reason: String,
+    pub attempts: u32,
+    pub status: DeadLetterStatus,
+    pub discard_reason: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DeadLetterError {
+    NotFound,
+    NotPending,
+    Settlement(SettlementError),
+}
+
+pub struct DeadLetterQueue {
+    ids: Arc<IdGenerator>,
+    letters: Mutex<BTreeMap<u64, DeadLetter>>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(ids: Arc<IdGenerator>) -> Self {
+        Self {
+            ids,
+            letters: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn push(&self, event: TradeEvent, error: &SettlementError) -> u64 {
+        let id = self.ids.next();
+        self.letters.lock().unwrap().insert(
+            id,
+            DeadLetter {
+                id,
+                event,
+                reason: error.to_string(),
+                attempts: 1,
+                status: DeadLetterStatus::Pending,
+                discard_reason: None,
+            },
+        );
+        id
+    }
+
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().values().cloned().collect()
+    }
+
+    // Number of events still waiting on an admin.
+    pub fn depth(&self) -> usize {
+        self.letters
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|letter| letter.status == DeadLetterStatus::Pending)
+            .count()
+    }
+
+    // Runs the event back through settlement. On success the entry is removed;
+    // on failure it stays pending with the latest reason.
+    // Hands back the event, and the fees it cost, once it settles.
+    pub fn retry(
+        &self,
+        id: u64,
+        users: &dyn UserRepository,
+        fees: &Fees,
+    ) -> Result<(TradeEvent, TradeFees), DeadLetterError> {
+        let mut letters = self.letters.lock().unwrap();
+        let letter = letters.get_mut(&id).ok_or(DeadLetterError::NotFound)?;
+        if letter.status != DeadLetterStatus::Pending {
+            return Err(DeadLetterError::NotPending);
+        }
+
+        match settle_trade(users, fees, &letter.event) {
+            Ok(due) => Ok((letters.remove(&id).unwrap().event, due)),
+            Err(e) => {
+                letter.attempts += 1;
+                letter.reason = e.to_string();
+                Err(DeadLetterError::Settlement(e))
+            }
+        }
+    }
+
+    pub fn discard(&self, id: u64, reason: String) -> Result<DeadLetter, DeadLetterError> {
+        let mut letters = self.letters.lock().unwrap();
+        let letter = letters.get_mut(&id).ok_or(DeadLetterError::NotFound)?;
+        if letter.status != DeadLetterStatus::Pending {
+            return Err(DeadLetterError::NotPending);
+        }
+        letter.status = DeadLetterStatus::Discarded;
+        letter.discard_reason = Some(reason);
+        Ok(letter.clone())
+    }
+}
+
+// A trade as it settled, with the fees each side paid on it.
+#[derive(Serialize, Debug, Clone)]
+pub struct SettledTrade {
+    pub event: TradeEvent,
+    pub fees: TradeFees,
+    pub busted: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BustError {
+    NotFound,
+    AlreadyBusted,
+    Settlement(SettlementError),
+}
+
+// The most recent trades off the engine's tape, by trade id, for an admin to
+// bust. Off-book trades carry no trade id and aren't kept.
+#[derive(Default)]
+pub struct SettledTrades {
+    trades: Mutex<BTreeMap<u64, SettledTrade>>,
+}
+
+impl SettledTrades {
+    pub fn record(&self, event: TradeEvent, fees: TradeFees) {
+        if event.trade_id == 0 {
+            return;
+        }
+        let mut trades = self.trades.lock().unwrap();
+        trades.insert(
+            event.trade_id,
+            SettledTrade {
+                event,
+                fees,
+                busted: false,
+            },
+        );
+        if trades.len() > RETAINED_TRADES {
+            trades.pop_first();
+        }
+    }
+
+    // Reverses the trade's settlement and marks it busted, so it can't be
+    // busted twice.
+    pub fn bust(
+        &self,
+        trade_id: u64,
+        users: &dyn UserRepository,
+        fees: &Fees,
+    ) -> Result<SettledTrade, BustError> {
+        let mut trades = self.trades.lock().unwrap();
+        let trade = trades.get_mut(&trade_id).ok_or(BustError::NotFound)?;
+        if trade.busted {
+            return Err(BustError::AlreadyBusted);
+        }
+        reverse_trade(users, fees, &trade.event, trade.fees).map_err(BustError::Settlement)?;
+        trade.busted = true;
+        Ok(trade.clone())
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        fees::{EntryKind, FeeSchedule},
+        repository::InMemoryUserRepository,
+    };
+    use orderbook::{Price, Qty, Side};
+    use std::collections::HashMap;
+
+    fn queue() -> DeadLetterQueue {
+        DeadLetterQueue::new(Arc::new(IdGenerator::new(Arc::new(SystemClock), 0)))
+    }
+
+    fn user(email: &str, balance: i64) -> User {
+        User {
+            email: email.to_string(),
+            current_balance: Money::usd(balance),
+            stocks: HashMap::from([("AAPL".to_string(), Qty::shares(10))]),
+            faucet_claims_remaining: 0,
+            market_maker: false,
+        }
+    }
+
+    fn trade(shares: u64, price: Price) -> TradeEvent {
+        TradeEvent {
+            trade_id: 1,
+            buyer: "buyer@test.com".to_string(),
+            seller: "seller@test.com".to_string(),
+            symbol: "AAPL".to_string(),
+            quantity: Qty::shares(shares),
+            price,
+            taker_side: Side::Buy,
+            timestamp: 0,
+            maker_received_at: 0,
+            maker_order_id: 0,
+            maker_remaining: Qty::ZERO,
+            taker_order_id: 0,
+            buyer_tags: Vec::new(),
+            seller_tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_failed_settlement_leaves_both_sides_untouched() {
+        let users = InMemoryUserRepository::default();
+        users.insert(user("buyer@test.com", 1000));
+        users.insert(user("seller@test.com", 0));
+
+        assert_eq!(
+            settle_trade(&users, &Fees::default(), &trade(2, Price::cents(10150))),
+            Err(SettlementError::InsufficientFunds(
+                "buyer@test.com".to_string()
+            ))
+        );
+        assert_eq!(
+            settle_trade(
+                &users,
+                &Fees::default(),
+                &TradeEvent {
+                    quantity: Qty::MAX,
+                    ..trade(1, Price::MAX)
+                }
+            ),
+            Err(SettlementError::Overflow)
+        );
+        assert_eq!(
+            settle_trade(&users, &Fees::default(), &trade(1_000, Price::MAX)),
+            Err(SettlementError::Overflow)
+        );
+        assert_eq!(
+            users.get("seller@test.com").unwrap().current_balance,
+            Money::usd(0)
+        );
+        assert_eq!(
+            users.get("seller@test.com").unwrap().stocks["AAPL"],
+            Qty::shares(10)
+        );
+    }
+
+    #[test]
+    fn test_fractional_trade_settles_to_the_cent() {
+        let users = InMemoryUserRepository::default();
+        users.insert(user("buyer@test.com", 10000));
+        users.insert(user("seller@test.com", 0));
+
+        let half = TradeEvent {
+            quantity: "0.5".parse().unwrap(),
+            ..trade(0, Price::cents(10125))
+        };
+        settle_trade(&users, &Fees::default(), &half).unwrap();
+        let buyer = users.get("buyer@test.com").unwrap();
+        let seller = users.get("seller@test.com").unwrap();
+        assert_eq!(buyer.current_balance, Money::usd(4937));
+        assert_eq!(seller.current_balance, Money::usd(5063));
+        assert_eq!(buyer.stocks["AAPL"], "10.5".parse().unwrap());
+        assert_eq!(seller.stocks["AAPL"], "9.5".parse().unwrap());
+    }
+
+    #[test]
+    fn test_fees_and_rebates_balance_out() {
+        let fees = Fees::new(FeeSchedule {
+            maker_bps: -2,
+            taker_bps: 10,
+        });
+        // the seller makes markets on a tier of their own
+        fees.set_tier(
+            "seller@test.com",
+            Some(FeeSchedule {
+                maker_bps: -5,
+                taker_bps: 5,
+            }),
+        );
+
+        // a buyer lifting the seller's offer, then the seller hitting the
+        // buyer's bid
+        for (taker_side, buyer_fee, seller_fee) in
+            [(Side::Buy, 1015, -508), (Side::Sell, -203, 508)]
+        {
+            let users = InMemoryUserRepository::default();
+            users.insert(user("buyer@test.com", 2_000_000));
+            users.insert(user("seller@test.com", 0));
+            let before = fees.balance();
+
+            let event = TradeEvent {
+                taker_side,
+                ..trade(100, Price::cents(10150))
+            };
+            settle_trade(&users, &fees, &event).unwrap();
+            let buyer = users.get("buyer@test.com").unwrap().current_balance;
+            let seller = users.get("seller@test.com").unwrap().current_balance;
+            assert_eq!(buyer, Money::usd(2_000_000 - 1_015_000 - buyer_fee));
+            assert_eq!(seller, Money::usd(1_015_000 - seller_fee));
+
+            // every cent the users lost, the fee account gained
+            let moved = buyer
+                .checked_add(seller)
+                .unwrap()
+                .checked_sub(Money::usd(2_000_000))
+                .unwrap();
+            let collected = fees.balance().checked_sub(before).unwrap();
+            assert_eq!(moved.checked_add(collected).unwrap(), Money::usd(0));
+        }
+
+        let kinds: Vec<(String, EntryKind, Money)> = fees
+            .ledger()
+            .into_iter()
+            .map(|entry| (entry.user, entry.kind, entry.amount))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    "buyer@test.com".to_string(),
+                    EntryKind::Fee,
+                    Money::usd(1015)
+                ),
+                (
+                    "seller@test.com".to_string(),
+                    EntryKind::Rebate,
+                    Money::usd(508)
+                ),
+                (
+                    "buyer@test.com".to_string(),
+                    EntryKind::Rebate,
+                    Money::usd(203)
+                ),
+                (
+                    "seller@test.com".to_string(),
+                    EntryKind::Fee,
+                    Money::usd(508)
+                ),
+            ]
+        );
+        assert_eq!(fees.balance(), Money::usd(812));
+    }
+
+    #[test]
+    fn test_fee_counts_against_the_buyers_cash() {
+        let fees = Fees::new(FeeSchedule {
+            maker_bps: 0,
+            taker_bps: 10,
+        });
+        let users = InMemoryUserRepository::default();
+        // enough for the shares but not the fee on them
+        users.insert(user("buyer@test.com", 10150));
+        users.insert(user("seller@test.com", 0));
+
+        assert_eq!(
+            settle_trade(&users, &fees, &trade(1, Price::cents(10150))),
+            Err(SettlementError::InsufficientFunds(
+                "buyer@test.com".to_string()
+            ))
+        );
+        assert_eq!(fees.balance(), Money::usd(0));
+        assert!(fees.ledger().is_empty());
+    }
+
+    #[test]
+    fn test_bust_puts_both_sides_back() {
+        let fees = Fees::new(FeeSchedule {
+            maker_bps: -2,
+            taker_bps: 10,
+        });
+        let users = InMemoryUserRepository::default();
+        users.insert(user("buyer@test.com", 2_000_000));
+        users.insert(user("seller@test.com", 0));
+        let trades = SettledTrades::default();
+
+        // all ten of the seller's shares
+        let event = trade(10, Price::cents(10150));
+        let paid = settle_trade(&users, &fees, &event).unwrap();
+        trades.record(event, paid);
+        let busted = trades.bust(1, &users, &fees).unwrap();
+        assert!(busted.busted);
+
+        for (email, balance) in [("buyer@test.com", 2_000_000), ("seller@test.com", 0)] {
+            let user = users.get(email).unwrap();
+            assert_eq!(user.current_balance, Money::usd(balance));
+            assert_eq!(user.stocks["AAPL"], Qty::shares(10));
+        }
+        assert_eq!(fees.balance(), Money::usd(0));
+        let reversals: Vec<(EntryKind, Money)> = fees
+            .ledger()
+            .into_iter()
+            .skip(2)
+            .map(|entry| (entry.kind, entry.amount))
+            .collect();
+        assert_eq!(
+            reversals,
+            vec![
+                (EntryKind::Reversal, Money::usd(102)),
+                (EntryKind::Reversal, Money::usd(-20)),
+            ]
+        );
+
+        assert_eq!(
+            trades.bust(1, &users, &fees).unwrap_err(),
+            BustError::AlreadyBusted
+        );
+        assert_eq!(
+            trades.bust(2, &users, &fees).unwrap_err(),
+            BustError::NotFound
+        );
+    }
+
+    #[test]
+    fn test_bust_refused_once_the_shares_are_gone() {
+        let users = InMemoryUserRepository::default();
+        users.insert(user("buyer@test.com", 100_000));
+        users.insert(user("seller@test.com", 0));
+        let trades = SettledTrades::default();
+        let event = trade(5, Price::cents(10000));
+        let paid = settle_trade(&users, &Fees::default(), &event).unwrap();
+        trades.record(event, paid);
+
+        // the buyer has since sold on all 15
+        users.update("buyer@test.com", &mut |buyer| {
+            buyer.stocks.insert("AAPL".to_string(), Qty::ZERO);
+        });
+        assert_eq!(
+            trades.bust(1, &users, &Fees::default()).unwrap_err(),
+            BustError::Settlement(SettlementError::InsufficientShares(
+                "buyer@test.com".to_string()
+            ))
+        );
+        assert_eq!(
+            users.get("seller@test.com").unwrap().current_balance,
+            Money::usd(50_000)
+        );
+
+        // and may bust once they hold them again
+        users.update("buyer@test.com", &mut |buyer| {
+            buyer.stocks.insert("AAPL".to_string(), Qty::shares(5));
+        });
+        assert!(trades.bust(1, &users, &Fees::default()).is_ok());
+        assert_eq!(
+            users.get("seller@test.com").unwrap().stocks["AAPL"],
+            Qty::shares(10)
+        );
+
+        // off-book trades carry no trade id to bust by
+        trades.record(
+            TradeEvent {
+                trade_id: 0,
+                ..trade(1, Price::cents(10000))
+            },
+            paid,
+        );
+        assert_eq!(
+            trades.bust(0, &users, &Fees::default()).unwrap_err(),
+            BustError::NotFound
+        );
+    }
+
+    #[test]
+    fn test_retry_after_fixing_the_cause() {
+        let users = InMemoryUserRepository::default();
+        let dlq = queue();
+        users.insert(user("buyer@test.com", 1000));
+
+        let event = trade(2, Price::cents(10150));
+        let error = settle_trade(&users, &Fees::default(), &event).unwrap_err();
+        assert_eq!(
+            error,
+            SettlementError::UnknownUser("seller@test.com".to_string())
+        );
+        let id = dlq.push(event, &error);
+
+        // creating the seller is not enough, the buyer is still short
+        users.insert(user("seller@test.com", 0));
+        assert_eq!(
+            dlq.retry(id, &users, &Fees::default()),
+            Err(DeadLetterError::Settlement(
+                SettlementError::InsufficientFunds("buyer@test.com".to_string())
+            ))
+        );
+        assert_eq!(dlq.list()[0].attempts, 2);
+        assert_eq!(dlq.depth(), 1);
+
+        users.update("buyer@test.com", &mut |buyer| {
+            buyer.current_balance = Money::usd(100000)
+        });
+        assert_eq!(
+            dlq.retry(id, &users, &Fees::default()).unwrap().0.quantity,
+            Qty::shares(2)
+        );
+        assert_eq!(dlq.depth(), 0);
+        assert_eq!(
+            users.get("seller@test.com").unwrap().current_balance,
+            Money::usd(20300)
+        );
+        assert_eq!(
+            dlq.retry(id, &users, &Fees::default()),
+            Err(DeadLetterError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_discarded_letters_stay_listed() {
+        let dlq = queue();
+        let id = dlq.push(trade(1, Price::cents(100)), &SettlementError::Overflow);
+
+        let letter = dlq
+            .discard(id, "duplicate of a manual fix".to_string())
+            .unwrap();
+        assert_eq!(letter.status, DeadLetterStatus::Discarded);
+        assert_eq!(dlq.depth(), 0);
+        assert_eq!(dlq.list().len(), 1);
+        assert_eq!(
+            dlq.retry(id, &InMemoryUserRepository::default(), &Fees::default()),
+            Err(DeadLetterError::NotPending)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use orderbook::PlacedOrder;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+use crate::ids::IdGenerator;
+
+// How long a request waits for the engine to finish streaming a snapshot.
+pub const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// One run of a snapshot's resting orders, as the engine streams them.
+#[derive(Deserialize, Debug)]
+pub struct SnapshotChunk {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub symbol: String,
+    pub request_id: u64,
+    pub index: usize,
+    pub orders: Vec<PlacedOrder>,
+}
+
+// The engine's last message for a snapshot: how many chunks it sent and the
+// orderbook::orders_checksum of their orders.
+#[derive(Deserialize, Debug)]
+pub struct SnapshotEnd {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub symbol: String,
+    pub request_id: u64,
+    pub found: bool,
+    pub chunks: usize,
+    pub checksum: u32,
+    pub book_update_seq: u64,
+    pub last_order_id: u64,
+}
+
+// Every resting order in a book, as reassembled from the engine's chunks.
+#[derive(Serialize, Debug, Clone)]
+pub struct L3Snapshot {
+    pub symbol: String,
+    // the last BookUpdate the book reflects
+    pub book_update_seq: u64,
+    pub last_order_id: u64,
+    pub orders: Vec<PlacedOrder>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    UnknownSymbol,
+    // chunks the end message counted that never arrived
+    MissingChunks { expected: usize, received: usize },
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnknownSymbol => write!(f, "the engine has no such book"),
+            SnapshotError::MissingChunks { expected, received } => {
+                write!(f, "snapshot arrived in {} of {} chunks", received, expected)
+            }
+            SnapshotError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "snapshot checksum {} does not match the engine's {}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+pub type SnapshotReply = oneshot::Receiver<Result<L3Snapshot, SnapshotError>>;
+
+struct Pending {
+    request_id: u64,
+    chunks: BTreeMap<usize, Vec<PlacedOrder>>,
+    waiters: Vec<oneshot::Sender<Result<L3Snapshot, SnapshotError>>>,
+}
+
+// Snapshots on their way from the engine, one per symbol: every request for
+// a symbol that comes while one is streaming waits on that one instead of
+// asking again.
+pub struct BookSnapshots {
+    ids: Arc<IdGenerator>,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl BookSnapshots {
+    pub fn new(ids: Arc<IdGenerator>) -> Self {
+        Self {
+            ids,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Waits on the snapshot already coming for `symbol`, or starts one. The
+    // id is Some for a new request, which the caller then sends to the
+    // engine.
+    pub fn subscribe(&self, symbol: &str) -> (Option<u64>, SnapshotReply) {
+        let (sender, reply) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(streaming) = pending.get_mut(symbol) {
+            streaming.waiters.push(sender);
+            return (None, reply);
+        }
+        let request_id = self.ids.next();
+        pending.insert(
+            symbol.to_string(),
+            Pending {
+                request_id,
+                chunks: BTreeMap::new(),
+                waiters: vec![sender],
+            },
+        );
+        (Some(request_id), reply)
+    }
+
+    // Forgets a request the engine never answered, so the next one starts
+    // afresh; whoever was waiting on it gets nothing.
+    pub fn abandon(&self, symbol: &str, request_id: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending
+            .get(symbol)
+            .is_some_and(|p| p.request_id == request_id)
+        {
+            pending.remove(symbol);
+        }
+    }
+
+    // Chunks of a request this gateway didn't make, or gave up on, are
+    // dropped.
+    pub fn chunk(&self, chunk: SnapshotChunk) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(streaming) = pending.get_mut(&chunk.symbol)
+            && streaming.request_id == chunk.request_id
+        {
+            streaming.chunks.insert(chunk.index, chunk.orders);
+        }
+    }
+
+    // Puts the chunks back together, checks them against `end` and hands
+    // the result to everyone waiting.
+    pub fn end(&self, end: SnapshotEnd) {
+        let streaming = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get(&end.symbol) {
+                Some(streaming) if streaming.request_id == end.request_id => {
+                    pending.remove(&end.symbol).unwrap()
+                }
+                _ => return,
+            }
+        };
+        let result = reassemble(&end, streaming.chunks);
+        for waiter in streaming.waiters {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+fn reassemble(
+    end: &SnapshotEnd,
+    chunks: BTreeMap<usize, Vec<PlacedOrder>>,
+) -> Result<L3Snapshot, SnapshotError> {
+    if !end.found {
+        return Err(SnapshotError::UnknownSymbol);
+    }
+    let received = chunks.keys().filter(|&&index| index < end.chunks).count();
+    if received != end.chunks {
+        return Err(SnapshotError::MissingChunks {
+            expected: end.chunks,
+            received,
+        });
+    }
+    let orders: Vec<PlacedOrder> = chunks.into_values().take(end.chunks).flatten().collect();
+    let actual = orderbook::orders_checksum(&orders);
+    if actual != end.checksum {
+        return Err(SnapshotError::ChecksumMismatch {
+            expected: end.checksum,
+            actual,
+        });
+    }
+    Ok(L3Snapshot {
+        symbol: end.symbol.clone(),
+        book_update_seq: end.book_update_seq,
+        last_order_id: end.last_order_id,
+        orders,
+    })
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::test_support::FakeClock;
+    use orderbook::{Order, Price, Qty, Side};
+
+    const CHUNK: usize = 100;
+
+    fn snapshots() -> BookSnapshots {
+        BookSnapshots::new(Arc::new(IdGenerator::new(
+            Arc::new(FakeClock::new(1_760_486_400_000)),
+            0,
+        )))
+    }
+
+    // A book of `count` one-share bids, one a tick apart.
+    fn book(count: usize) -> Vec<PlacedOrder> {
+        (1..=count)
+            .map(|i| PlacedOrder {
+                price: Price::cents(i as i64),
+                position: 0,
+                order: Order {
+                    order_id: i as u64,
+                    ..Order::new_limit_order(
+                        Qty::shares(1),
+                        Some(Price::cents(i as i64)),
+                        Side::Buy,
+                        String::from("AAPL"),
+                        String::from("a"),
+                    )
+                },
+            })
+            .collect()
+    }
+
+    fn stream(snapshots: &BookSnapshots, request_id: u64, orders: &[PlacedOrder]) -> SnapshotEnd {
+        for (index, chunk) in orders.chunks(CHUNK).enumerate() {
+            snapshots.chunk(SnapshotChunk {
+                kind: String::from("snapshot_chunk"),
+                symbol: String::from("AAPL"),
+                request_id,
+                index,
+                orders: chunk.to_vec(),
+            });
+        }
+        SnapshotEnd {
+            kind: String::from("snapshot_end"),
+            symbol: String::from("AAPL"),
+            request_id,
+            found: true,
+            chunks: orders.len().div_ceil(CHUNK),
+            checksum: orderbook::orders_checksum(orders),
+            book_update_seq: 42,
+            last_order_id: orders.len() as u64,
+        }
+    }
+
+    #[test]
+    fn test_large_book_reassembled_for_every_waiter() {
+        let snapshots = snapshots();
+        let orders = book(25_000);
+        let (request_id, mut first) = snapshots.subscribe("AAPL");
+        let request_id = request_id.unwrap();
+        let (coalesced, mut second) = snapshots.subscribe("AAPL");
+        assert_eq!(coalesced, None);
+
+        let end = stream(&snapshots, request_id, &orders);
+        snapshots.end(end);
+        for reply in [&mut first, &mut second] {
+            let snapshot = reply.try_recv().unwrap().unwrap();
+            assert_eq!(snapshot.orders.len(), 25_000);
+            assert_eq!(snapshot.book_update_seq, 42);
+            assert_eq!(
+                orderbook::orders_checksum(&snapshot.orders),
+                orderbook::orders_checksum(&orders)
+            );
+        }
+
+        // the next request after one finishes goes to the engine again
+        assert!(snapshots.subscribe("AAPL").0.is_some());
+    }
+
+    #[test]
+    fn test_corrupt_or_incomplete_snapshot_refused() {
+        let snapshots = snapshots();
+        let orders = book(1_000);
+
+        let (request_id, mut reply) = snapshots.subscribe("AAPL");
+        let mut end = stream(&snapshots, request_id.unwrap(), &orders);
+        end.checksum ^= 1;
+        let expected = end.checksum;
+        snapshots.end(end);
+        assert_eq!(
+            reply.try_recv().unwrap().unwrap_err(),
+            SnapshotError::ChecksumMismatch {
+                expected,
+                actual: orderbook::orders_checksum(&orders),
+            }
+        );
+
+        let (request_id, mut reply) = snapshots.subscribe("AAPL");
+        let mut end = stream(&snapshots, request_id.unwrap(), &orders[..CHUNK * 3]);
+        end.chunks = 4;
+        snapshots.end(end);
+        assert_eq!(
+            reply.try_recv().unwrap().unwrap_err(),
+            SnapshotError::MissingChunks {
+                expected: 4,
+                received: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stray_and_abandoned_streams_ignored() {
+        let snapshots = snapshots();
+        let orders = book(150);
+        let (request_id, mut reply) = snapshots.subscribe("AAPL");
+        let request_id = request_id.unwrap();
+
+        // another gateway's stream for the same symbol
+        let stray = stream(&snapshots, request_id + 1, &orders);
+        snapshots.end(stray);
+        assert!(reply.try_recv().is_err());
+
+        snapshots.abandon("AAPL", request_id);
+        snapshots.end(stream(&snapshots, request_id, &orders));
+        assert!(reply.try_recv().is_err());
+        assert!(snapshots.subscribe("AAPL").0.is_some());
+    }
+}
@@ -0,0 +1,260 @@
+use orderbook::SymbolRules;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    audit::{AuditLog, DEV_AUDIT_KEY},
+    capacity::CapacityStore,
+    clock::{Clock, SystemClock},
+    exposure::OpenOrderQueries,
+    faucet::{Faucet, FaucetConfig},
+    fees::{FeeSchedule, Fees},
+    guard::{PayloadGuard, PayloadLimits},
+    history::History,
+    ids::IdGenerator,
+    market_data::{DEFAULT_PRICE_COLLAR_PERCENT, InMemoryMarketData, MarketData},
+    notifications::Notifier,
+    orders::OrderStore,
+    publisher::Publisher,
+    repository::{InMemoryUserRepository, UserRepository},
+    rfq::{DEFAULT_RFQ_WINDOW_MILLIS, RfqDesk},
+    settlement::{DeadLetterQueue, SettledTrades},
+    snapshots::BookSnapshots,
+    watchdog::{AckWatchdog, DEFAULT_ACK_TIMEOUT_MILLIS},
+};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub users: Arc<dyn UserRepository>,
+    pub publisher: Arc<dyn Publisher>,
+    pub market_data: Arc<dyn MarketData>,
+    pub history: History,
+    pub faucet: Arc<Faucet>,
+    pub dead_letters: Arc<DeadLetterQueue>,
+    pub trades: Arc<SettledTrades>,
+    pub rfqs: Arc<RfqDesk>,
+    pub payload_guard: Arc<PayloadGuard>,
+    pub capacity: Arc<CapacityStore>,
+    pub notifier: Arc<Notifier>,
+    pub audit: Arc<AuditLog>,
+    pub orders: Arc<OrderStore>,
+    pub snapshots: Arc<BookSnapshots>,
+    pub watchdog: Arc<AckWatchdog>,
+    pub open_order_queries: Arc<OpenOrderQueries>,
+    pub fees: Arc<Fees>,
+    pub ids: Arc<IdGenerator>,
+    pub clock: Arc<dyn Clock>,
+    // signs audit exports
+    pub audit_key: Arc<[u8]>,
+    // what orders are checked against before they are published
+    pub symbol_rules: Arc<HashMap<String, SymbolRules>>,
+    // how far, in percent, a limit order may be priced through the other
+    // side's best price before it is refused as a likely typo
+    pub price_collar_percent: u32,
+}
+
+impl AppState {
+    pub fn builder() -> AppStateBuilder {
+        AppStateBuilder::default()
+    }
+
+    // The default rules for a symbol without its own.
+    pub fn rules(&self, symbol: &str) -> SymbolRules {
+        self.symbol_rules.get(symbol).copied().unwrap_or_default()
+    }
+}
+
+// Everything but the publisher has an in-memory default; the faucet is off
+// unless one is supplied.
+#[derive(Default)]
+pub struct AppStateBuilder {
+    users: Option<Arc<dyn UserRepository>>,
+    publisher: Option<Arc<dyn Publisher>>,
+    market_data: Option<Arc<dyn MarketData>>,
+    faucet: Option<Faucet>,
+    clock: Option<Arc<dyn Clock>>,
+    rfq_window_millis: Option<i64>,
+    ack_timeout_millis: Option<i64>,
+    payload_limits: Option<PayloadLimits>,
+    audit_key: Option<Vec<u8>>,
+    instance_id: u16,
+    symbol_rules: HashMap<String, SymbolRules>,
+    price_collar_percent: Option<u32>,
+    fee_schedule: FeeSchedule,
+}
+
+impl AppStateBuilder {
+    pub fn with_repository(mut self, users: Arc<dyn UserRepository>) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    pub fn with_publisher(mut self, publisher: Arc<dyn Publisher>) -> Self {
+        self.publisher = Some(publisher);
+        self
+    }
+
+    pub fn with_market_data(mut self, market_data: Arc<dyn MarketData>) -> Self {
+        self.market_data = Some(market_data);
+        self
+    }
+
+    pub fn with_faucet(mut self, faucet: Faucet) -> Self {
+        self.faucet = Some(faucet);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn with_rfq_window(mut self, window_millis: i64) -> Self {
+        self.rfq_window_millis = Some(window_millis);
+        self
+    }
+
+    // How long an order may go unanswered by the engine before it is looked
+    // for in the user's open orders.
+    pub fn with_ack_timeout(mut self, timeout_millis: i64) -> Self {
+        self.ack_timeout_millis = Some(timeout_millis);
+        self
+    }
+
+    pub fn with_payload_limits(mut self, limits: PayloadLimits) -> Self {
+        self.payload_limits = Some(limits);
+        self
+    }
+
+    pub fn with_audit_key(mut self, key: Vec<u8>) -> Self {
+        self.audit_key = Some(key);
+        self
+    }
+
+    pub fn with_symbol_rules(
+        mut self,
+        rules: impl IntoIterator<Item = (String, SymbolRules)>,
+    ) -> Self {
+        self.symbol_rules.extend(rules);
+        self
+    }
+
+    pub fn with_price_collar(mut self, percent: u32) -> Self {
+        self.price_collar_percent = Some(percent);
+        self
+    }
+
+    // Defaults to no fees at all.
+    pub fn with_fee_schedule(mut self, schedule: FeeSchedule) -> Self {
+        self.fee_schedule = schedule;
+        self
+    }
+
+    // Must differ between replicas so their ids can't collide; defaults to 0.
+    pub fn with_instance_id(mut self, instance_id: u16) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    pub fn build(self) -> AppState {
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let faucet = self
+            .faucet
+            .unwrap_or_else(|| Faucet::new(FaucetConfig::disabled(), clock.clone()));
+        let rfq_window_millis = self.rfq_window_millis.unwrap_or(DEFAULT_RFQ_WINDOW_MILLIS);
+        let ids = Arc::new(IdGenerator::new(clock.clone(), self.instance_id));
+        AppState {
+            users: self
+                .users
+                .unwrap_or_else(|| Arc::new(InMemoryUserRepository::default())),
+            publisher: self.publisher.expect("AppState requires a publisher"),
+            market_data: self
+                .market_data
+                .unwrap_or_else(|| Arc::new(InMemoryMarketData::default())),
+            history: Arc::new(Mutex::new(HashMap::new())),
+            faucet: Arc::new(faucet),
+            dead_letters: Arc::new(DeadLetterQueue::new(ids.clone())),
+            trades: Arc::new(SettledTrades::default()),
+            rfqs: Arc::new(RfqDesk::new(clock.clone(), ids.clone(), rfq_window_millis)),
+            payload_guard: Arc::new(PayloadGuard::new(self.payload_limits.unwrap_or_default())),
+            capacity: Arc::new(CapacityStore::default()),
+            notifier: Arc::new(Notifier::new(clock.clone(), ids.clone())),
+            audit: Arc::new(AuditLog::new(clock.clone())),
+            orders: Arc::new(OrderStore::default()),
+            snapshots: Arc::new(BookSnapshots::new(ids.clone())),
+            watchdog: Arc::new(AckWatchdog::new(
+                clock.clone(),
+                self.ack_timeout_millis
+                    .unwrap_or(DEFAULT_ACK_TIMEOUT_MILLIS),
+            )),
+            open_order_queries: Arc::new(OpenOrderQueries::default()),
+            fees: Arc::new(Fees::new(self.fee_schedule)),
+            ids,
+            clock,
+            audit_key: self
+                .audit_key
+                .unwrap_or_else(|| DEV_AUDIT_KEY.as_bytes().to_vec())
+                .into(),
+            symbol_rules: Arc::new(self.symbol_rules),
+            price_collar_percent: self
+                .price_collar_percent
+                .unwrap_or(DEFAULT_PRICE_COLLAR_PERCENT),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use crate::{
+        clock::test_support::FakeClock, money::Money, publisher::test_support::RecordingPublisher,
+    };
+
+    pub const TEST_NOW: i64 = 1_760_486_400_000;
+
+    // AppState wired to in-memory fakes, keeping handles on them for assertions.
+    pub struct TestAppState {
+        pub state: AppState,
+        pub users: Arc<InMemoryUserRepository>,
+        pub publisher: Arc<RecordingPublisher>,
+        pub market_data: Arc<InMemoryMarketData>,
+        pub clock: Arc<FakeClock>,
+    }
+
+    impl TestAppState {
+        pub fn new() -> Self {
+            Self::with_faucet(FaucetConfig {
+                enabled: true,
+                amount: Money::usd(100000),
+                claims_per_day: 1,
+            })
+        }
+
+        pub fn with_faucet(faucet: FaucetConfig) -> Self {
+            let users = Arc::new(InMemoryUserRepository::default());
+            let publisher = Arc::new(RecordingPublisher::default());
+            let market_data = Arc::new(InMemoryMarketData::default());
+            let clock = Arc::new(FakeClock::new(TEST_NOW));
+            let state = AppState::builder()
+                .with_repository(users.clone())
+                .with_publisher(publisher.clone())
+                .with_market_data(market_data.clone())
+                .with_faucet(Faucet::new(faucet, clock.clone()))
+                .with_clock(clock.clone())
+                .build();
+            Self {
+                state,
+                users,
+                publisher,
+                market_data,
+                clock,
+            }
+        }
+
+        pub fn router(&self) -> axum::Router {
+            crate::router(self.state.clone())
+        }
+    }
+}
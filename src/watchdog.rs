@@ -0,0 +1,239 @@
+use orderbook::{Order, OrderView};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::clock::Clock;
+
+pub const ACK_TIMEOUT_ENV: &str = "ACK_TIMEOUT_SECS";
+pub const DEFAULT_ACK_TIMEOUT_MILLIS: i64 = 30_000;
+
+struct Submitted {
+    order: Order,
+    submitted_at: i64,
+    // the user's open orders have been asked for on its account
+    queried: bool,
+}
+
+// What the engine's open orders said about an order it never answered.
+#[derive(Debug)]
+pub enum Resolution {
+    // it rests in the book: the order as the engine holds it
+    Found(Order),
+    // the engine never saw it, or it no longer rests there
+    Lost(Order),
+}
+
+// Orders published to the engine that it hasn't yet accepted or rejected,
+// by the client_order_id place_order gave them. Once one has waited longer
+// than the timeout its user's open orders are asked for, and the answer
+// settles what became of it. An order that filled on arrival and whose ack
+// went missing isn't in the book either, and is reported lost; its trades
+// still settle.
+pub struct AckWatchdog {
+    clock: Arc<dyn Clock>,
+    timeout_millis: i64,
+    submitted: Mutex<HashMap<u64, Submitted>>,
+    lost: AtomicU64,
+}
+
+impl AckWatchdog {
+    pub fn new(clock: Arc<dyn Clock>, timeout_millis: i64) -> Self {
+        Self {
+            clock,
+            timeout_millis,
+            submitted: Mutex::new(HashMap::new()),
+            lost: AtomicU64::new(0),
+        }
+    }
+
+    pub fn timeout_millis(&self) -> i64 {
+        self.timeout_millis
+    }
+
+    // `order` must carry its client_order_id.
+    pub fn submitted(&self, order: Order) {
+        let Some(client_order_id) = order.client_order_id else {
+            return;
+        };
+        let submitted = Submitted {
+            order,
+            submitted_at: self.clock.now_millis(),
+            queried: false,
+        };
+        self.submitted
+            .lock()
+            .unwrap()
+            .insert(client_order_id, submitted);
+    }
+
+    // The engine accepted or rejected the order.
+    pub fn answered(&self, client_order_id: u64) {
+        self.submitted.lock().unwrap().remove(&client_order_id);
+    }
+
+    // Users with an order past its deadline that hasn't been asked about
+    // yet; each is counted as asked about from here.
+    pub fn overdue(&self) -> Vec<String> {
+        let deadline = self.clock.now_millis() - self.timeout_millis;
+        let mut users: Vec<String> = Vec::new();
+        for submitted in self.submitted.lock().unwrap().values_mut() {
+            if submitted.queried || submitted.submitted_at > deadline {
+                continue;
+            }
+            submitted.queried = true;
+            if !users.contains(&submitted.order.user) {
+                users.push(submitted.order.user.clone());
+            }
+        }
+        users.sort();
+        users
+    }
+
+    // For when asking after `user`'s orders didn't go out: they are asked
+    // about again next time.
+    pub fn requery(&self, user: &str) {
+        for submitted in self.submitted.lock().unwrap().values_mut() {
+            if submitted.order.user == user {
+                submitted.queried = false;
+            }
+        }
+    }
+
+    // Settles every order of `user`'s that was asked about against the
+    // engine's answer. Orders submitted since the question went out wait
+    // for the next one.
+    pub fn resolve(&self, user: &str, open: &[OrderView]) -> Vec<Resolution> {
+        let mut submitted = self.submitted.lock().unwrap();
+        let asked: Vec<u64> = submitted
+            .iter()
+            .filter(|(_, s)| s.queried && s.order.user == user)
+            .map(|(&client_order_id, _)| client_order_id)
+            .collect();
+
+        let mut resolutions = Vec::new();
+        for client_order_id in asked {
+            let order = submitted.remove(&client_order_id).unwrap().order;
+            let view = open
+                .iter()
+                .find(|view| view.client_order_id == Some(client_order_id));
+            match view {
+                Some(view) => resolutions.push(Resolution::Found(Order {
+                    order_id: view.order_id,
+                    price: Some(view.price),
+                    quantity: view.remaining_quantity,
+                    received_at: view.received_at,
+                    state: view.state.clone(),
+                    expires_at: view.expires_at,
+                    ..order
+                })),
+                None => {
+                    self.lost.fetch_add(1, Ordering::Relaxed);
+                    resolutions.push(Resolution::Lost(order));
+                }
+            }
+        }
+        resolutions.sort_by_key(|resolution| match resolution {
+            Resolution::Found(order) | Resolution::Lost(order) => order.client_order_id,
+        });
+        resolutions
+    }
+
+    // How many orders have been given up as lost since startup.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+// ---------------------------------------------TESTS---------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::test_support::FakeClock;
+    use orderbook::{OrderState, Price, Qty, Side, TimeInForce};
+
+    const NOW: i64 = 1_760_486_400_000;
+
+    fn order(client_order_id: u64, user: &str) -> Order {
+        Order {
+            client_order_id: Some(client_order_id),
+            ..Order::new_limit_order(
+                Qty::shares(10),
+                Some(Price::cents(10000)),
+                Side::Buy,
+                String::from("AAPL"),
+                String::from(user),
+            )
+        }
+    }
+
+    // The engine's open orders for one resting order, as a fake responder
+    // would answer.
+    fn resting(client_order_id: u64, order_id: u64, remaining: u64) -> OrderView {
+        OrderView {
+            order_id,
+            user: String::from("a"),
+            symbol: String::from("AAPL"),
+            side: Side::Buy,
+            price: Price::cents(10000),
+            remaining_quantity: Qty::shares(remaining),
+            state: OrderState::Open,
+            time_in_force: TimeInForce::Gtc,
+            received_at: NOW,
+            expires_at: None,
+            queue_position: 0,
+            client_order_id: Some(client_order_id),
+        }
+    }
+
+    #[test]
+    fn test_overdue_orders_asked_about_once() {
+        let clock = Arc::new(FakeClock::new(NOW));
+        let watchdog = AckWatchdog::new(clock.clone(), 1_000);
+        watchdog.submitted(order(1, "a"));
+        watchdog.submitted(order(2, "b"));
+        watchdog.submitted(order(3, "a"));
+        watchdog.answered(2);
+        assert!(watchdog.overdue().is_empty());
+
+        clock.set(NOW + 1_000);
+        assert_eq!(watchdog.overdue(), vec![String::from("a")]);
+        assert!(watchdog.overdue().is_empty());
+        watchdog.requery("a");
+        assert_eq!(watchdog.overdue(), vec![String::from("a")]);
+    }
+
+    #[test]
+    fn test_resolved_from_open_orders() {
+        let clock = Arc::new(FakeClock::new(NOW));
+        let watchdog = AckWatchdog::new(clock.clone(), 1_000);
+        watchdog.submitted(order(1, "a"));
+        watchdog.submitted(order(2, "a"));
+        clock.set(NOW + 1_000);
+        watchdog.overdue();
+        // submitted after the question went out
+        watchdog.submitted(order(3, "a"));
+
+        match watchdog.resolve("a", &[resting(2, 7, 4)]).as_slice() {
+            [Resolution::Lost(lost), Resolution::Found(found)] => {
+                assert_eq!(lost.client_order_id, Some(1));
+                assert_eq!(
+                    (found.client_order_id, found.order_id, found.quantity),
+                    (Some(2), 7, Qty::shares(4))
+                );
+                assert_eq!(found.received_at, NOW);
+            }
+            other => panic!("unexpected resolutions: {:?}", other),
+        }
+        assert_eq!(watchdog.lost(), 1);
+
+        // only the later order is left, and it isn't overdue yet
+        assert!(watchdog.resolve("a", &[]).is_empty());
+        clock.set(NOW + 2_000);
+        assert_eq!(watchdog.overdue(), vec![String::from("a")]);
+    }
+}